@@ -0,0 +1,9 @@
+-- Record processed payments so redelivered updates don't credit the user twice
+CREATE TABLE IF NOT EXISTS payments (
+    telegram_payment_charge_id TEXT PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    invoice_payload TEXT NOT NULL,
+    total_amount INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
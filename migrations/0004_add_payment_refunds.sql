@@ -0,0 +1,3 @@
+-- Track granted days and refunds so a payment can be reversed
+ALTER TABLE payments ADD COLUMN days INTEGER;
+ALTER TABLE payments ADD COLUMN refunded_at INTEGER;
@@ -0,0 +1,2 @@
+-- Store which tier a subscription was bought for (NULL = default tier)
+ALTER TABLE subscriptions ADD COLUMN tier TEXT;
@@ -0,0 +1,2 @@
+-- Remember which pending conversions were already nudged before expiry
+ALTER TABLE pending_conversions ADD COLUMN reminded INTEGER NOT NULL DEFAULT 0;
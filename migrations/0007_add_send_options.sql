@@ -0,0 +1,10 @@
+-- Per-user defaults for sending results
+CREATE TABLE IF NOT EXISTS user_settings (
+    user_id INTEGER PRIMARY KEY,
+    spoiler INTEGER NOT NULL DEFAULT 0,
+    protect_content INTEGER NOT NULL DEFAULT 0
+);
+
+-- Send options chosen on the format selection step
+ALTER TABLE pending_downloads ADD COLUMN spoiler INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE pending_downloads ADD COLUMN protect_content INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- Dialogue state (e.g. waiting for a format for an uploaded video), survives restarts
+CREATE TABLE IF NOT EXISTS dialogues (
+    chat_id INTEGER PRIMARY KEY,
+    state TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
@@ -0,0 +1,13 @@
+-- Delete sent media after a delay (0 keeps it)
+ALTER TABLE user_settings ADD COLUMN auto_delete_minutes INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE pending_downloads ADD COLUMN auto_delete_minutes INTEGER NOT NULL DEFAULT 0;
+
+-- Sent messages waiting to be deleted, so a restart doesn't forget them
+CREATE TABLE IF NOT EXISTS scheduled_deletions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    delete_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_deletions_delete_at ON scheduled_deletions(delete_at);
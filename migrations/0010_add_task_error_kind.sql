@@ -0,0 +1,2 @@
+-- Failure category of failed tasks (download_failed, too_large, ...)
+ALTER TABLE tasks ADD COLUMN error_kind TEXT;
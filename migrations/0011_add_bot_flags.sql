@@ -0,0 +1,5 @@
+-- Operator switches that survive restarts (e.g. maintenance mode)
+CREATE TABLE IF NOT EXISTS bot_flags (
+    name TEXT PRIMARY KEY,
+    enabled INTEGER NOT NULL DEFAULT 0
+);
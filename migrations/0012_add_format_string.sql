@@ -0,0 +1,2 @@
+-- Power users' own yt-dlp format expression (NULL uses the quality picker)
+ALTER TABLE user_settings ADD COLUMN format_string TEXT;
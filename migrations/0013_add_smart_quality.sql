@@ -0,0 +1,2 @@
+-- Pick the video quality from its length instead of asking
+ALTER TABLE user_settings ADD COLUMN smart_quality INTEGER NOT NULL DEFAULT 0;
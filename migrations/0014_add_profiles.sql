@@ -0,0 +1,8 @@
+-- Named snapshots of user settings (JSON), applied with /profile use
+CREATE TABLE IF NOT EXISTS profiles (
+    user_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    settings TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (user_id, name)
+);
@@ -0,0 +1,11 @@
+-- Completed tasks shown in /history (tasks rows are deleted once done)
+CREATE TABLE IF NOT EXISTS download_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    format TEXT NOT NULL,
+    url TEXT,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_download_history_chat_id ON download_history(chat_id, created_at);
@@ -0,0 +1,9 @@
+-- Everything needed to run a failed download again from the retry button
+ALTER TABLE tasks ADD COLUMN audio_language TEXT;
+ALTER TABLE tasks ADD COLUMN format_string TEXT;
+ALTER TABLE tasks ADD COLUMN spoiler INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tasks ADD COLUMN protect_content INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tasks ADD COLUMN auto_delete_minutes INTEGER NOT NULL DEFAULT 0;
+
+-- How many times the user retried this download
+ALTER TABLE tasks ADD COLUMN retries INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Burn subtitles into downloaded videos
+ALTER TABLE user_settings ADD COLUMN subtitles INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE pending_downloads ADD COLUMN subtitles INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tasks ADD COLUMN subtitles INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- Completed downloads of free users since they last saw the Premium upsell
+CREATE TABLE IF NOT EXISTS upsell_state (
+    chat_id INTEGER PRIMARY KEY,
+    downloads INTEGER NOT NULL DEFAULT 0,
+    last_shown_at INTEGER
+);
@@ -0,0 +1,2 @@
+-- The user's message a task's result is sent in reply to
+ALTER TABLE tasks ADD COLUMN reply_to_message_id INTEGER;
@@ -0,0 +1,3 @@
+-- Naming template for downloaded files, set with /name
+ALTER TABLE user_settings ADD COLUMN filename_template TEXT;
+ALTER TABLE tasks ADD COLUMN filename_template TEXT;
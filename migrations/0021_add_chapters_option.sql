@@ -0,0 +1,4 @@
+-- Send the video's chapter list after the media
+ALTER TABLE user_settings ADD COLUMN chapters INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE pending_downloads ADD COLUMN chapters INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tasks ADD COLUMN chapters INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,5 @@
+-- Users' own ffmpeg filters, set with /filter
+ALTER TABLE user_settings ADD COLUMN video_filter TEXT;
+ALTER TABLE user_settings ADD COLUMN audio_filter TEXT;
+ALTER TABLE tasks ADD COLUMN video_filter TEXT;
+ALTER TABLE tasks ADD COLUMN audio_filter TEXT;
@@ -0,0 +1,3 @@
+-- Chat or channel results are also posted to, set with /channel
+ALTER TABLE user_settings ADD COLUMN target_chat_id INTEGER;
+ALTER TABLE tasks ADD COLUMN target_chat_id INTEGER;
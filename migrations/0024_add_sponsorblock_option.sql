@@ -0,0 +1,4 @@
+-- Cut sponsor, self-promotion and intro segments out of YouTube downloads
+ALTER TABLE user_settings ADD COLUMN sponsorblock INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE pending_downloads ADD COLUMN sponsorblock INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tasks ADD COLUMN sponsorblock INTEGER NOT NULL DEFAULT 0;
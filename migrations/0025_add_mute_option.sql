@@ -0,0 +1,4 @@
+-- Send videos without their audio track
+ALTER TABLE user_settings ADD COLUMN mute INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE pending_downloads ADD COLUMN mute INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tasks ADD COLUMN mute INTEGER NOT NULL DEFAULT 0;
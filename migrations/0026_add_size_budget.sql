@@ -0,0 +1,2 @@
+-- Download the best streams that fit a file size instead of a chosen quality
+ALTER TABLE tasks ADD COLUMN size_budget INTEGER;
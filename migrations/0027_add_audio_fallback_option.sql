@@ -0,0 +1,4 @@
+-- Send the audio when a video can't be downloaded as video
+ALTER TABLE user_settings ADD COLUMN audio_fallback INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE pending_downloads ADD COLUMN audio_fallback INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tasks ADD COLUMN audio_fallback INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Trim silence out of audio output (meant for speech)
+ALTER TABLE user_settings ADD COLUMN trim_silence INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE pending_downloads ADD COLUMN trim_silence INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tasks ADD COLUMN trim_silence INTEGER NOT NULL DEFAULT 0;
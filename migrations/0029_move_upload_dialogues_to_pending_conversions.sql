@@ -0,0 +1,8 @@
+-- Uploads used to wait for a format in the dialogue state. They go through pending
+-- conversions now, where /pending offers them again.
+INSERT OR IGNORE INTO pending_conversions (short_id, filename, thumbnail_path, chat_id, message_id, created_at)
+SELECT lower(hex(randomblob(4))), json_extract(state, '$.ReceiveFormat.filename'), NULL, chat_id, 0, updated_at
+FROM dialogues
+WHERE json_extract(state, '$.ReceiveFormat.filename') IS NOT NULL;
+
+DELETE FROM dialogues WHERE json_extract(state, '$.ReceiveFormat') IS NOT NULL;
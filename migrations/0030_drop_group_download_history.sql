@@ -0,0 +1,2 @@
+-- History is only kept for private chats now, a group's rows mixed everyone's downloads
+DELETE FROM download_history WHERE chat_id < 0;
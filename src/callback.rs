@@ -0,0 +1,425 @@
+//! Callback data of the bot's buttons.
+//!
+//! Telegram hands callback data back verbatim, so it's whatever a client sends and
+//! has to be checked like any other input. Every button, of the download flow, under its
+//! results and of the commands' lists and settings, is a `Callback`: `to_string()` builds
+//! its data and `parse` turns it back, rejecting unknown actions and malformed fields
+//! before a handler sees them.
+
+use std::fmt;
+
+use strum::IntoEnumIterator;
+
+use crate::commands::QueueFilter;
+use crate::errors::{BotError, BotResult};
+use crate::settings::{SendOption, is_valid_profile_name};
+use crate::utils::MediaFormatType;
+
+/// Longest short ID accepted, `ShortId` makes 8 characters
+const MAX_SHORT_ID_LEN: usize = 16;
+
+/// Longest task ID accepted, `TaskId` makes 36-character UUIDs
+const MAX_TASK_ID_LEN: usize = 36;
+
+/// Longest YouTube video ID accepted, they're 11 characters
+const MAX_VIDEO_ID_LEN: usize = 16;
+
+/// Longest audio track language accepted (e.g. "en-US", "pt-BR")
+const MAX_LANGUAGE_LEN: usize = 16;
+
+/// Telegram's limit on callback data
+const MAX_CALLBACK_LEN: usize = 64;
+
+/// Callback key of the auto-delete setting (cycles through the presets)
+const AUTO_DELETE_KEY: &str = "autodelete";
+
+/// Callback key of the smart quality setting
+const SMART_QUALITY_KEY: &str = "smartquality";
+
+/// What a /settings button changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Setting {
+    /// Default of a send option
+    SendOption(SendOption),
+    /// Next of the auto-delete presets
+    AutoDelete,
+    /// Quality picked by the video's length
+    SmartQuality,
+}
+
+impl Setting {
+    fn key(&self) -> &'static str {
+        match self {
+            Setting::SendOption(option) => option.key(),
+            Setting::AutoDelete => AUTO_DELETE_KEY,
+            Setting::SmartQuality => SMART_QUALITY_KEY,
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        match key {
+            AUTO_DELETE_KEY => Some(Setting::AutoDelete),
+            SMART_QUALITY_KEY => Some(Setting::SmartQuality),
+            key => SendOption::from_key(key).map(Setting::SendOption),
+        }
+    }
+}
+
+/// A button press
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callback {
+    /// Format of an uploaded video: fmt:format_index:short_id
+    SelectFormat { format: MediaFormatType, short_id: String },
+    /// Format of a link, before anything is downloaded: ff:format_index:short_id
+    SelectFormatFirst { format: MediaFormatType, short_id: String },
+    /// Quality of a link's download: q:short_id:height
+    SelectQuality { short_id: String, height: u32 },
+    /// Best quality of a link's download that fits the upload limit: qs:short_id
+    SelectSizeBudget { short_id: String },
+    /// Resolution of an uploaded video: uq:short_id:height, height 0 keeps the original
+    SelectUploadQuality { short_id: String, height: Option<u32> },
+    /// Audio track of a dubbed video: al:short_id:language
+    SelectAudioTrack { short_id: String, language: String },
+    /// Send option toggle of a link: opt:option_key:short_id
+    ToggleSendOption { option: SendOption, short_id: String },
+    /// Description and details of a downloaded YouTube video: desc:video_id
+    Description { video_id: String },
+    /// Run a failed task again: retry:task_id
+    Retry { task_id: String },
+    /// Send the details of a failed task to the admins: report:task_id
+    Report { task_id: String },
+    /// Premium purchase: buy_premium shows the tiers, buy_premium:tier_id buys one
+    BuyPremium { tier_id: Option<String> },
+    /// A /settings toggle: set:key
+    ToggleSetting { setting: Setting },
+    /// Apply a saved profile from /profile: prof:name
+    ApplyProfile { name: String },
+    /// Page of /history: hist:page
+    HistoryPage { page: u32 },
+    /// Show the format choice of an upload in /pending: pend:fmt:short_id
+    PendingFormat { short_id: String },
+    /// Discard an upload in /pending: pend:del:short_id
+    PendingDiscard { short_id: String },
+    /// Filter and page of /queue: queue:filter:page
+    QueuePage { filter: QueueFilter, page: u32 },
+    /// Page of the admins' /subs list: subs:page
+    SubsPage { page: u32 },
+}
+
+impl Callback {
+    /// Parse the data of a button press
+    pub fn parse(data: &str) -> BotResult<Self> {
+        let invalid = || BotError::ParseError(format!("Invalid callback data: {:?}", data));
+
+        if data.len() > MAX_CALLBACK_LEN {
+            return Err(invalid());
+        }
+        if data == "buy_premium" {
+            return Ok(Self::BuyPremium { tier_id: None });
+        }
+
+        let (action, rest) = data.split_once(':').ok_or_else(invalid)?;
+        let callback = match action {
+            "buy_premium" => {
+                // Tier IDs come from the config, find_tier decides whether one exists
+                if rest.is_empty() || rest.chars().any(char::is_control) {
+                    return Err(invalid());
+                }
+                Self::BuyPremium { tier_id: Some(rest.to_string()) }
+            }
+            "fmt" | "ff" => {
+                let (index, short_id) = rest.split_once(':').ok_or_else(invalid)?;
+                let format = parse_number(index)
+                    .and_then(|index| MediaFormatType::iter().nth(index as usize))
+                    .ok_or_else(invalid)?;
+                let short_id = valid_short_id(short_id).ok_or_else(invalid)?;
+                if action == "fmt" {
+                    Self::SelectFormat { format, short_id }
+                } else {
+                    Self::SelectFormatFirst { format, short_id }
+                }
+            }
+            "q" => {
+                let (short_id, height) = rest.split_once(':').ok_or_else(invalid)?;
+                Self::SelectQuality {
+                    short_id: valid_short_id(short_id).ok_or_else(invalid)?,
+                    height: parse_number(height).filter(|&h| h > 0).ok_or_else(invalid)?,
+                }
+            }
+            "qs" => Self::SelectSizeBudget { short_id: valid_short_id(rest).ok_or_else(invalid)? },
+            "uq" => {
+                let (short_id, height) = rest.split_once(':').ok_or_else(invalid)?;
+                let height = parse_number(height).ok_or_else(invalid)?;
+                Self::SelectUploadQuality {
+                    short_id: valid_short_id(short_id).ok_or_else(invalid)?,
+                    height: Some(height).filter(|&h| h > 0),
+                }
+            }
+            "al" => {
+                let (short_id, language) = rest.split_once(':').ok_or_else(invalid)?;
+                let is_language = !language.is_empty()
+                    && language.len() <= MAX_LANGUAGE_LEN
+                    && language.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+                if !is_language {
+                    return Err(invalid());
+                }
+                Self::SelectAudioTrack {
+                    short_id: valid_short_id(short_id).ok_or_else(invalid)?,
+                    language: language.to_string(),
+                }
+            }
+            "opt" => {
+                let (key, short_id) = rest.split_once(':').ok_or_else(invalid)?;
+                Self::ToggleSendOption {
+                    option: SendOption::from_key(key).ok_or_else(invalid)?,
+                    short_id: valid_short_id(short_id).ok_or_else(invalid)?,
+                }
+            }
+            "desc" => Self::Description {
+                video_id: valid_id(rest, MAX_VIDEO_ID_LEN, &['-', '_']).ok_or_else(invalid)?,
+            },
+            "retry" => Self::Retry {
+                task_id: valid_id(rest, MAX_TASK_ID_LEN, &['-']).ok_or_else(invalid)?,
+            },
+            "report" => Self::Report {
+                task_id: valid_id(rest, MAX_TASK_ID_LEN, &['-']).ok_or_else(invalid)?,
+            },
+            "set" => Self::ToggleSetting { setting: Setting::from_key(rest).ok_or_else(invalid)? },
+            "prof" => {
+                if !is_valid_profile_name(rest) {
+                    return Err(invalid());
+                }
+                Self::ApplyProfile { name: rest.to_string() }
+            }
+            "hist" => Self::HistoryPage { page: parse_number(rest).ok_or_else(invalid)? },
+            "pend" => {
+                let (action, short_id) = rest.split_once(':').ok_or_else(invalid)?;
+                let short_id = valid_short_id(short_id).ok_or_else(invalid)?;
+                match action {
+                    "fmt" => Self::PendingFormat { short_id },
+                    "del" => Self::PendingDiscard { short_id },
+                    _ => return Err(invalid()),
+                }
+            }
+            "queue" => {
+                use std::str::FromStr;
+
+                let (filter, page) = rest.split_once(':').ok_or_else(invalid)?;
+                Self::QueuePage {
+                    filter: QueueFilter::from_str(filter).map_err(|_| invalid())?,
+                    page: parse_number(page).ok_or_else(invalid)?,
+                }
+            }
+            "subs" => Self::SubsPage { page: parse_number(rest).ok_or_else(invalid)? },
+            _ => return Err(invalid()),
+        };
+
+        Ok(callback)
+    }
+}
+
+impl fmt::Display for Callback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SelectFormat { format, short_id } => {
+                write!(f, "fmt:{}:{}", format_index(format), short_id)
+            }
+            Self::SelectFormatFirst { format, short_id } => {
+                write!(f, "ff:{}:{}", format_index(format), short_id)
+            }
+            Self::SelectQuality { short_id, height } => write!(f, "q:{}:{}", short_id, height),
+            Self::SelectSizeBudget { short_id } => write!(f, "qs:{}", short_id),
+            Self::SelectUploadQuality { short_id, height } => {
+                write!(f, "uq:{}:{}", short_id, height.unwrap_or(0))
+            }
+            Self::SelectAudioTrack { short_id, language } => write!(f, "al:{}:{}", short_id, language),
+            Self::ToggleSendOption { option, short_id } => {
+                write!(f, "opt:{}:{}", option.key(), short_id)
+            }
+            Self::Description { video_id } => write!(f, "desc:{}", video_id),
+            Self::Retry { task_id } => write!(f, "retry:{}", task_id),
+            Self::Report { task_id } => write!(f, "report:{}", task_id),
+            Self::BuyPremium { tier_id: None } => write!(f, "buy_premium"),
+            Self::BuyPremium { tier_id: Some(tier_id) } => write!(f, "buy_premium:{}", tier_id),
+            Self::ToggleSetting { setting } => write!(f, "set:{}", setting.key()),
+            Self::ApplyProfile { name } => write!(f, "prof:{}", name),
+            Self::HistoryPage { page } => write!(f, "hist:{}", page),
+            Self::PendingFormat { short_id } => write!(f, "pend:fmt:{}", short_id),
+            Self::PendingDiscard { short_id } => write!(f, "pend:del:{}", short_id),
+            Self::QueuePage { filter, page } => write!(f, "queue:{}:{}", filter.as_ref(), page),
+            Self::SubsPage { page } => write!(f, "subs:{}", page),
+        }
+    }
+}
+
+/// Position of the format in `MediaFormatType::iter()`, which is what the buttons carry
+fn format_index(format: &MediaFormatType) -> usize {
+    MediaFormatType::iter()
+        .position(|f| &f == format)
+        .expect("every format is iterated")
+}
+
+/// Digits only, parse() alone would accept a leading '+'
+fn parse_number(value: &str) -> Option<u32> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+fn valid_short_id(short_id: &str) -> Option<String> {
+    valid_id(short_id, MAX_SHORT_ID_LEN, &['-'])
+}
+
+/// A non-empty ID of ASCII letters, digits and the `extra` characters
+fn valid_id(id: &str, max_len: usize, extra: &[char]) -> Option<String> {
+    let valid = !id.is_empty()
+        && id.len() <= max_len
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || extra.contains(&c));
+    valid.then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_callbacks() -> Vec<Callback> {
+        let short_id = "a1B2-c3D".to_string();
+        let mut callbacks = Vec::new();
+        for format in MediaFormatType::iter() {
+            callbacks.push(Callback::SelectFormat { format: format.clone(), short_id: short_id.clone() });
+            callbacks.push(Callback::SelectFormatFirst { format, short_id: short_id.clone() });
+        }
+        for option in SendOption::ALL {
+            callbacks.push(Callback::ToggleSendOption { option, short_id: short_id.clone() });
+            callbacks.push(Callback::ToggleSetting { setting: Setting::SendOption(option) });
+        }
+        for filter in QueueFilter::iter() {
+            callbacks.push(Callback::QueuePage { filter, page: 3 });
+        }
+        callbacks.extend([
+            Callback::SelectQuality { short_id: short_id.clone(), height: 1080 },
+            Callback::SelectSizeBudget { short_id: short_id.clone() },
+            Callback::SelectUploadQuality { short_id: short_id.clone(), height: Some(480) },
+            Callback::SelectUploadQuality { short_id: short_id.clone(), height: None },
+            Callback::SelectAudioTrack { short_id: short_id.clone(), language: "pt-BR".to_string() },
+            Callback::Description { video_id: "dQw4w9WgXcQ".to_string() },
+            Callback::Description { video_id: "a-b_c".to_string() },
+            Callback::Retry { task_id: uuid::Uuid::new_v4().to_string() },
+            Callback::Report { task_id: uuid::Uuid::new_v4().to_string() },
+            Callback::BuyPremium { tier_id: None },
+            Callback::BuyPremium { tier_id: Some("pro".to_string()) },
+            Callback::ToggleSetting { setting: Setting::AutoDelete },
+            Callback::ToggleSetting { setting: Setting::SmartQuality },
+            Callback::ApplyProfile { name: "work_1".to_string() },
+            Callback::ApplyProfile { name: "подкасты".to_string() },
+            Callback::HistoryPage { page: 0 },
+            Callback::HistoryPage { page: 12 },
+            Callback::PendingFormat { short_id: short_id.clone() },
+            Callback::PendingDiscard { short_id: short_id.clone() },
+            Callback::SubsPage { page: 7 },
+        ]);
+        callbacks
+    }
+
+    #[test]
+    fn every_callback_round_trips() {
+        for callback in all_callbacks() {
+            let data = callback.to_string();
+            assert!(data.len() <= MAX_CALLBACK_LEN, "{} is too long", data);
+            assert_eq!(Callback::parse(&data).unwrap(), callback, "{}", data);
+        }
+    }
+
+    #[test]
+    fn malformed_data_is_rejected() {
+        for data in [
+            "",
+            ":",
+            "fmt",
+            "fmt:",
+            "fmt:0",
+            "fmt:999:abc",
+            "fmt:+1:abc",
+            "fmt:-1:abc",
+            "fmt:0:",
+            "fmt:0:abc/def",
+            "q:abc",
+            "q:abc:0",
+            "q:abc:high",
+            "q:abc:99999999999",
+            "qs:",
+            "uq:abc:",
+            "al:abc:",
+            "al:abc:en US",
+            "opt:unknown:abc",
+            "opt:spoiler",
+            "opt:spoiler:",
+            "desc:",
+            "desc:../etc",
+            "desc:abcdefghijklmnopq",
+            "retry:",
+            "retry:task id",
+            "retry:0123456789012345678901234567890123456",
+            "report:",
+            "report:a:b",
+            "buy_premium:",
+            "buy_premium:\n",
+            "set:",
+            "set:unknown",
+            "prof:",
+            "prof:a b",
+            "prof:a:b",
+            "prof:abcdefghijklmnopqrstu",
+            "hist:",
+            "hist:-1",
+            "hist:next",
+            "pend:fmt",
+            "pend:fmt:",
+            "pend:show:abc",
+            "queue:",
+            "queue:all",
+            "queue:all:",
+            "queue:done:0",
+            "queue:All:0",
+            "subs:",
+            "subs:+1",
+            "unknown:abc",
+            "FMT:0:abc",
+        ] {
+            assert!(Callback::parse(data).is_err(), "{:?} was accepted", data);
+        }
+        assert!(Callback::parse(&format!("qs:{}", "a".repeat(MAX_CALLBACK_LEN))).is_err());
+    }
+
+    #[test]
+    fn garbage_never_panics_and_accepted_data_round_trips() {
+        const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789:-_+ \n/\\.fmtqsuoptal";
+        let prefixes = [
+            "", "fmt:", "ff:", "q:", "qs:", "uq:", "al:", "opt:", "desc:", "retry:", "report:", "buy_premium:", "set:",
+            "prof:", "hist:", "pend:fmt:", "pend:del:", "queue:all:", "queue:", "subs:",
+        ];
+
+        // Deterministic xorshift, so a failure is reproducible
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..20_000 {
+            let prefix = prefixes[next() as usize % prefixes.len()];
+            let len = next() as usize % 40;
+            let tail: String = (0..len)
+                .map(|_| ALPHABET[next() as usize % ALPHABET.len()] as char)
+                .collect();
+            let data = format!("{}{}", prefix, tail);
+
+            if let Ok(callback) = Callback::parse(&data) {
+                assert_eq!(Callback::parse(&callback.to_string()).unwrap(), callback, "{:?}", data);
+            }
+        }
+    }
+}
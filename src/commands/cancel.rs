@@ -1,22 +1,43 @@
+use std::sync::Arc;
+
 use teloxide::prelude::*;
-use tokio::fs;
 
-use crate::{errors::HandlerResult, schema::MyDialogue};
+use crate::{errors::HandlerResult, queue::TaskQueue, schema::MyDialogue};
+
+/// Cancel whatever the user is doing: an uploaded video waiting for a format
+/// and/or their latest queued or running task
+pub async fn cancel(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    task_queue: Arc<TaskQueue>,
+) -> HandlerResult {
+    let mut cancelled = false;
 
-pub async fn cancel(bot: Bot, dialogue: MyDialogue, msg: Message) -> HandlerResult {
-    bot.send_message(msg.chat.id, "Загрузка отменена.").await?;
     let state_or_none = dialogue.get().await?;
     if let Some(state) = state_or_none {
         match state {
             crate::schema::State::Start => (),
-            crate::schema::State::ReceiveFormat { filename } => {
-                let _ = fs::remove_file(filename).await;
-            }
+            crate::schema::State::ReceivePassword { .. } => cancelled = true,
         }
     }
     dialogue
         .exit()
         .await
         .map_err(|e| crate::errors::BotError::general(format!("Failed to exit dialogue: {}", e)))?;
+
+    if let Some(status_message) = task_queue.cancel_latest_task(msg.chat.id).await {
+        let _ = bot
+            .edit_message_text(msg.chat.id, status_message, "🚫 Задача отменена.")
+            .await;
+        cancelled = true;
+    }
+
+    let reply = if cancelled {
+        "Загрузка отменена."
+    } else {
+        "Нечего отменять."
+    };
+    bot.send_message(msg.chat.id, reply).await?;
     Ok(())
 }
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{ParseMode, Recipient},
+    utils::html::escape,
+};
+
+use crate::{
+    errors::HandlerResult,
+    settings::SettingsManager,
+};
+
+const USAGE: &str = "Использование:\n\
+    <code>/channel @mychannel</code> или <code>/channel -1001234567890</code> — публиковать результаты ещё и в этот канал\n\
+    <code>/channel off</code> — больше не публиковать\n\n\
+    Добавьте бота в канал администратором с правом публикации. \
+    Вы сами должны быть администратором канала. \
+    Файлы по-прежнему приходят сюда, а в канал публикуется их копия.";
+
+/// Handle /channel command - chat or channel results are also posted to
+/// Usage: /channel <@username|chat id>|off
+pub async fn channel(bot: Bot, msg: Message, settings_manager: Arc<SettingsManager>) -> HandlerResult {
+    let user_id = msg.from.as_ref().map(|u| u.id).unwrap_or(UserId(0));
+    let mut settings = settings_manager.get(user_id.0 as i64).await;
+
+    let text = msg.text().unwrap_or("");
+    let arg = text.split_once(char::is_whitespace).map(|(_, rest)| rest.trim()).unwrap_or("");
+
+    let reply = match arg {
+        "" => match settings.target_chat {
+            Some(id) => format!("Результаты публикуются в {}.\n\n{}", chat_name(&bot, ChatId(id)).await, USAGE),
+            None => format!("Канал не задан.\n\n{}", USAGE),
+        },
+        "off" => {
+            settings.target_chat = None;
+            settings_manager.update(user_id.0 as i64, &settings).await?;
+            "✅ Результаты больше не публикуются в канале.".to_string()
+        }
+        target => match check_target(&bot, target, user_id).await {
+            Ok(chat_id) => {
+                settings.target_chat = Some(chat_id.0);
+                settings_manager.update(user_id.0 as i64, &settings).await?;
+                format!(
+                    "✅ Теперь результаты будут публиковаться и в {}.",
+                    chat_name(&bot, chat_id).await
+                )
+            }
+            Err(e) => format!("❌ {}\n\n{}", e, USAGE),
+        },
+    };
+
+    bot.send_message(msg.chat.id, reply)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
+/// Resolve a `@username` or chat ID and check that both the user and the bot may post
+/// there, so nobody can point the bot at a channel they don't run
+async fn check_target(bot: &Bot, target: &str, user_id: UserId) -> Result<ChatId, String> {
+    let recipient = match target.parse::<i64>() {
+        Ok(id) => Recipient::Id(ChatId(id)),
+        Err(_) if target.starts_with('@') => Recipient::ChannelUsername(target.to_string()),
+        Err(_) => return Err("Укажите @username канала или его числовой ID.".to_string()),
+    };
+
+    let chat = bot.get_chat(recipient).await.map_err(|e| {
+        log::info!("Target chat {} not available: {}", target, e);
+        "Канал не найден. Проверьте адрес и что бот добавлен в канал.".to_string()
+    })?;
+
+    let is_admin = bot
+        .get_chat_member(chat.id, user_id)
+        .await
+        .map(|member| member.is_privileged())
+        .unwrap_or(false);
+    if !is_admin {
+        return Err("Вы не администратор этого канала.".to_string());
+    }
+
+    let me = bot.get_me().await.map_err(|e| e.to_string())?;
+    let bot_member = bot
+        .get_chat_member(chat.id, me.id)
+        .await
+        .map_err(|_| "Бот не добавлен в этот канал.".to_string())?;
+    let can_post = if chat.is_channel() {
+        bot_member.can_post_messages()
+    } else {
+        bot_member.is_present()
+    };
+    if !can_post {
+        return Err("У бота нет права публиковать сообщения в этом канале.".to_string());
+    }
+
+    Ok(chat.id)
+}
+
+/// The chat's title for replies, its ID if it can't be looked up
+async fn chat_name(bot: &Bot, chat_id: ChatId) -> String {
+    match bot.get_chat(chat_id).await {
+        Ok(chat) => match (chat.title(), chat.username()) {
+            (Some(title), Some(username)) => format!("«{}» (@{})", escape(title), username),
+            (Some(title), None) => format!("«{}»", escape(title)),
+            _ => format!("<code>{}</code>", chat_id),
+        },
+        Err(_) => format!("<code>{}</code>", chat_id),
+    }
+}
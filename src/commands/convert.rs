@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use teloxide::{prelude::*, types::FileMeta};
+
+use crate::{
+    errors::HandlerResult, handlers::receive_upload, queue::TaskQueue, subscription::SubscriptionManager,
+    utils::reply_to,
+};
+
+/// Handle /convert command - sent as a reply to a video, audio or media file already
+/// in the chat, it takes that file through the same format choice as an upload
+pub async fn convert(
+    bot: Bot,
+    msg: Message,
+    task_queue: Arc<TaskQueue>,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> HandlerResult {
+    let Some(source) = msg.reply_to_message() else {
+        bot.send_message(
+            msg.chat.id,
+            "↩️ Ответьте командой /convert на сообщение с видео, аудио или видеофайлом, и я предложу форматы.",
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let Some((file, duration)) = convertible_media(source) else {
+        bot.send_message(
+            msg.chat.id,
+            "❌ В этом сообщении нет видео или аудио. /convert работает с видео, кружочками, GIF, \
+             аудио, голосовыми и файлами с видео или звуком.",
+        )
+        .reply_parameters(reply_to(msg.id))
+        .await?;
+        return Ok(());
+    };
+
+    receive_upload(&bot, &msg, source, file, duration, &task_queue, &subscription_manager).await
+}
+
+/// The file of a message that can be converted, with its length if Telegram knows
+/// it's a video. Audio and documents are probed once they're saved.
+fn convertible_media(msg: &Message) -> Option<(&FileMeta, Option<u32>)> {
+    if let Some(video) = msg.video() {
+        return Some((&video.file, Some(video.duration.seconds())));
+    }
+    if let Some(note) = msg.video_note() {
+        return Some((&note.file, Some(note.duration.seconds())));
+    }
+    if let Some(animation) = msg.animation() {
+        return Some((&animation.file, Some(animation.duration.seconds())));
+    }
+    if let Some(audio) = msg.audio() {
+        return Some((&audio.file, None));
+    }
+    if let Some(voice) = msg.voice() {
+        return Some((&voice.file, None));
+    }
+
+    // Videos sent "as a file" come as documents, only their MIME type tells them apart
+    let document = msg.document()?;
+    let media = document
+        .mime_type
+        .as_ref()
+        .is_some_and(|mime| matches!(mime.type_().as_str(), "video" | "audio"));
+    media.then_some((&document.file, None))
+}
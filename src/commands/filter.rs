@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode},
+    utils::html::escape,
+};
+
+use crate::{
+    callback::Callback,
+    errors::HandlerResult,
+    settings::{CustomFilters, SettingsManager, validate_filter},
+    subscription::SubscriptionManager,
+};
+
+const USAGE: &str = "Использование:\n\
+    <code>/filter video eq=contrast=1.2,hflip</code> — свой фильтр ffmpeg <code>-vf</code>\n\
+    <code>/filter audio atempo=1.25</code> — свой фильтр ffmpeg <code>-af</code>\n\
+    <code>/filter video off</code>, <code>/filter audio off</code> — убрать один фильтр\n\
+    <code>/filter off</code> — убрать оба\n\n\
+    Фильтры применяются перед каждой конвертацией. Вы используете их на свой риск: \
+    бот не гарантирует, что результат примет Telegram, а ошибку ffmpeg покажет как есть.";
+
+/// Handle /filter command - premium only
+/// Usage: /filter video|audio <graph>|off, /filter off
+pub async fn filter(
+    bot: Bot,
+    msg: Message,
+    settings_manager: Arc<SettingsManager>,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> HandlerResult {
+    let user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+
+    if !subscription_manager.is_subscribed(user_id).await {
+        let keyboard = InlineKeyboardMarkup::new(vec![vec![
+            InlineKeyboardButton::callback(
+                "Купить Premium",
+                Callback::BuyPremium { tier_id: None }.to_string(),
+            ),
+        ]]);
+        bot.send_message(
+            msg.chat.id,
+            "Свои фильтры ffmpeg доступны только с Premium-подпиской.",
+        )
+        .reply_markup(keyboard)
+        .await?;
+        return Ok(());
+    }
+
+    let mut settings = settings_manager.get(user_id).await;
+
+    // `/filter <kind> <graph>`: the graph is everything after the kind, spaces included
+    let text = msg.text().unwrap_or("");
+    let args = text.split_once(char::is_whitespace).map(|(_, rest)| rest.trim()).unwrap_or("");
+    let (kind, graph) = args
+        .split_once(char::is_whitespace)
+        .map(|(kind, graph)| (kind, graph.trim()))
+        .unwrap_or((args, ""));
+
+    let reply = match (kind, graph) {
+        ("", _) => format!("{}\n\n{}", current_filters(&settings.filters), USAGE),
+        ("off", "") => {
+            settings.filters = CustomFilters::default();
+            settings_manager.update(user_id, &settings).await?;
+            "✅ Фильтры отключены.".to_string()
+        }
+        ("video" | "audio", "") => format!("❌ Укажите фильтр.\n\n{}", USAGE),
+        ("video" | "audio", graph) => {
+            let slot = if kind == "video" {
+                &mut settings.filters.video
+            } else {
+                &mut settings.filters.audio
+            };
+            if graph == "off" {
+                *slot = None;
+                settings_manager.update(user_id, &settings).await?;
+                format!("✅ Фильтр убран.\n\n{}", current_filters(&settings.filters))
+            } else {
+                match validate_filter(graph) {
+                    Ok(()) => {
+                        *slot = Some(graph.to_string());
+                        settings_manager.update(user_id, &settings).await?;
+                        format!("✅ Фильтр сохранён.\n\n{}", current_filters(&settings.filters))
+                    }
+                    Err(e) => format!("❌ {}\n\n{}", escape(&e), USAGE),
+                }
+            }
+        }
+        _ => USAGE.to_string(),
+    };
+
+    bot.send_message(msg.chat.id, reply)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
+/// The user's filters, one line each
+fn current_filters(filters: &CustomFilters) -> String {
+    if filters.is_empty() {
+        return "Свои фильтры не заданы.".to_string();
+    }
+
+    [("🎞 Видео", &filters.video), ("🔊 Аудио", &filters.audio)]
+        .into_iter()
+        .filter_map(|(label, graph)| {
+            graph
+                .as_ref()
+                .map(|graph| format!("{}: <code>{}</code>", label, escape(graph)))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
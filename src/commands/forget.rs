@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use crate::{
+    config::format_size,
+    errors::HandlerResult,
+    queue::{TaskQueue, TaskStatus},
+};
+
+/// Handle /forget command - delete the files and records the bot keeps for this chat
+/// between tasks, right away instead of when they expire
+pub async fn forget(bot: Bot, msg: Message, task_queue: Arc<TaskQueue>) -> HandlerResult {
+    let summary = task_queue.forget_chat(msg.chat.id).await;
+
+    let mut lines = Vec::new();
+    if summary.uploads > 0 {
+        lines.push(format!("• видео, ждущих выбора формата: {}", summary.uploads));
+    }
+    if summary.links > 0 {
+        lines.push(format!("• ссылок, ждущих выбора: {}", summary.links));
+    }
+    if summary.failed_tasks > 0 {
+        lines.push(format!("• неудачных задач: {}", summary.failed_tasks));
+    }
+    if summary.history > 0 {
+        lines.push(format!("• записей истории загрузок: {}", summary.history));
+    }
+
+    let mut text = if lines.is_empty() {
+        "🧹 Бот не хранит ваших файлов.".to_string()
+    } else {
+        format!(
+            "🧹 Удалено:\n{}\n\nОсвобождено: {}",
+            lines.join("\n"),
+            format_size(summary.bytes)
+        )
+    };
+
+    let active = task_queue
+        .get_user_tasks(msg.chat.id)
+        .await
+        .iter()
+        .filter(|t| matches!(t.status, TaskStatus::Queued { .. } | TaskStatus::Processing))
+        .count();
+    if active > 0 {
+        text.push_str(&format!(
+            "\n\n⏳ Задачи в очереди и в работе ({}) не тронуты, их файлы удалятся сразу после отправки.",
+            active
+        ));
+    }
+
+    bot.send_message(msg.chat.id, text).await?;
+
+    Ok(())
+}
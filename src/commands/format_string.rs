@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode},
+    utils::html::escape,
+};
+
+use crate::{
+    callback::Callback,
+    errors::HandlerResult,
+    settings::{SettingsManager, validate_format_string},
+    subscription::SubscriptionManager,
+};
+
+const USAGE: &str = "Использование:\n\
+    <code>/format_string bv*[height&lt;=1080][ext=mp4]+ba/b</code> — своё выражение yt-dlp <code>-f</code>\n\
+    <code>/format_string off</code> — снова выбирать качество\n\n\
+    С выражением шаг выбора качества пропускается. Если yt-dlp не сможет его разобрать, \
+    загрузка завершится ошибкой yt-dlp.";
+
+/// Handle /format_string command - premium only
+/// Usage: /format_string <expr>|off
+pub async fn format_string(
+    bot: Bot,
+    msg: Message,
+    settings_manager: Arc<SettingsManager>,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> HandlerResult {
+    let user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+
+    if !subscription_manager.is_subscribed(user_id).await {
+        let keyboard = InlineKeyboardMarkup::new(vec![vec![
+            InlineKeyboardButton::callback(
+                "Купить Premium",
+                Callback::BuyPremium { tier_id: None }.to_string(),
+            ),
+        ]]);
+        bot.send_message(
+            msg.chat.id,
+            "Свой формат yt-dlp доступен только с Premium-подпиской.",
+        )
+        .reply_markup(keyboard)
+        .await?;
+        return Ok(());
+    }
+
+    let mut settings = settings_manager.get(user_id).await;
+
+    // Everything after the command is the expression
+    let text = msg.text().unwrap_or("");
+    let expr = text.split_once(char::is_whitespace).map(|(_, rest)| rest.trim()).unwrap_or("");
+
+    let reply = match expr {
+        "" => match &settings.format_string {
+            Some(current) => format!(
+                "Текущий формат: <code>{}</code>\n\n{}",
+                escape(current),
+                USAGE
+            ),
+            None => format!("Свой формат не задан.\n\n{}", USAGE),
+        },
+        "off" => {
+            settings.format_string = None;
+            settings_manager.update(user_id, &settings).await?;
+            "✅ Свой формат отключён, качество снова выбирается вручную.".to_string()
+        }
+        expr => match validate_format_string(expr) {
+            Ok(()) => {
+                settings.format_string = Some(expr.to_string());
+                settings_manager.update(user_id, &settings).await?;
+                format!(
+                    "✅ Формат сохранён: <code>{}</code>\nДля видео шаг выбора качества будет пропущен.",
+                    escape(expr)
+                )
+            }
+            Err(e) => format!("❌ {}\n\n{}", e, USAGE),
+        },
+    };
+
+    bot.send_message(msg.chat.id, reply)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
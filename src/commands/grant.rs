@@ -2,17 +2,17 @@ use std::sync::Arc;
 
 use teloxide::prelude::*;
 
-use crate::{errors::HandlerResult, subscription::SubscriptionManager};
-
-/// Get admin user ID from environment
-fn get_admin_id() -> Option<i64> {
-    std::env::var("ADMIN_ID")
-        .ok()
-        .and_then(|s| s.parse().ok())
-}
+use crate::{
+    errors::HandlerResult,
+    subscription::{
+        premium::{default_tier, find_tier},
+        SubscriptionManager,
+    },
+    utils::is_admin,
+};
 
 /// Handle /grant command - admin only
-/// Usage: /grant <user_id> <days>
+/// Usage: /grant <user_id> <days> [tier]
 pub async fn grant(
     bot: Bot,
     msg: Message,
@@ -21,8 +21,7 @@ pub async fn grant(
     let from_user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
 
     // Check if user is admin
-    let admin_id = get_admin_id();
-    if admin_id.is_none() || admin_id != Some(from_user_id) {
+    if !is_admin(from_user_id) {
         // Silently ignore for non-admins
         return Ok(());
     }
@@ -31,10 +30,10 @@ pub async fn grant(
     let text = msg.text().unwrap_or("");
     let parts: Vec<&str> = text.split_whitespace().collect();
 
-    if parts.len() != 3 {
+    if parts.len() != 3 && parts.len() != 4 {
         bot.send_message(
             msg.chat.id,
-            "Usage: /grant <user_id> <days>\nExample: /grant 578503618 30",
+            "Usage: /grant <user_id> <days> [tier]\nExample: /grant 578503618 30 pro",
         )
         .await?;
         return Ok(());
@@ -58,15 +57,28 @@ pub async fn grant(
         }
     };
 
+    let tier = match parts.get(3) {
+        Some(id) => match find_tier(id) {
+            Some(tier) => tier,
+            None => {
+                bot.send_message(msg.chat.id, format!("Unknown tier: {}", id))
+                    .await?;
+                return Ok(());
+            }
+        },
+        None => default_tier(),
+    };
+
     // Grant subscription
     match subscription_manager
-        .add_subscription(target_user_id, days)
+        .add_subscription(target_user_id, days, tier)
         .await
     {
         Ok(expires_at) => {
             let text = format!(
-                "Subscription granted!\n\nUser: {}\nDays: {}\nExpires: {}",
+                "Subscription granted!\n\nUser: {}\nTier: {}\nDays: {}\nExpires: {}",
                 target_user_id,
+                tier.id,
                 days,
                 expires_at.format("%d.%m.%Y %H:%M UTC")
             );
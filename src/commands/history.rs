@@ -0,0 +1,137 @@
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, MaybeInaccessibleMessage, ParseMode},
+    utils::html::escape,
+};
+
+use crate::{callback::Callback, db::TaskDb, errors::HandlerResult, utils::no_link_preview};
+
+/// Downloads shown per page
+const PAGE_SIZE: i64 = 10;
+
+/// Shown for /history outside a private chat
+const PRIVATE_ONLY_MESSAGE: &str = "📜 История загрузок доступна только в личном чате с ботом.";
+
+/// Handle /history command - your recent completed downloads, kept for private chats only
+/// Usage: /history [clear]
+pub async fn history(bot: Bot, msg: Message, task_db: TaskDb) -> HandlerResult {
+    if !msg.chat.is_private() {
+        bot.send_message(msg.chat.id, PRIVATE_ONLY_MESSAGE).await?;
+        return Ok(());
+    }
+
+    let text = msg.text().unwrap_or("");
+    let parts: Vec<&str> = text.split_whitespace().collect();
+
+    if parts.get(1) == Some(&"clear") {
+        let reply = match task_db.clear_history(msg.chat.id.0).await {
+            Ok(0) => "История и так пуста.".to_string(),
+            Ok(n) => format!("🗑 История очищена ({} записей).", n),
+            Err(e) => {
+                log::error!("{}", e);
+                "❌ Не удалось очистить историю, попробуйте позже.".to_string()
+            }
+        };
+        bot.send_message(msg.chat.id, reply).await?;
+        return Ok(());
+    }
+
+    let (text, keyboard) = history_page(&task_db, msg.chat.id, 0).await;
+
+    bot.send_message(msg.chat.id, text)
+        .parse_mode(ParseMode::Html)
+        .link_preview_options(no_link_preview())
+        .reply_markup(keyboard)
+        .await?;
+
+    Ok(())
+}
+
+/// Handle page navigation in the /history list
+/// Callback format: hist:page
+pub async fn handle_history_callback(bot: Bot, query: CallbackQuery, task_db: TaskDb) -> HandlerResult {
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    let page = match query.data.as_deref().map(Callback::parse) {
+        Some(Ok(Callback::HistoryPage { page })) => page as i64,
+        _ => 0,
+    };
+
+    // Only the chat the list was sent to, so nobody can page through another chat's history
+    if let Some(MaybeInaccessibleMessage::Regular(m)) = &query.message
+        && m.chat.is_private()
+    {
+        let (text, keyboard) = history_page(&task_db, m.chat.id, page).await;
+        bot.edit_message_text(m.chat.id, m.id, text)
+            .parse_mode(ParseMode::Html)
+            .link_preview_options(no_link_preview())
+            .reply_markup(keyboard)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Text and navigation buttons for one page of the chat's history
+async fn history_page(task_db: &TaskDb, chat_id: ChatId, page: i64) -> (String, InlineKeyboardMarkup) {
+    let total = match task_db.count_history(chat_id.0).await {
+        Ok(total) => total,
+        Err(e) => {
+            log::error!("{}", e);
+            return ("❌ Не удалось загрузить историю.".to_string(), InlineKeyboardMarkup::default());
+        }
+    };
+
+    if total == 0 {
+        return ("📭 История загрузок пуста.".to_string(), InlineKeyboardMarkup::default());
+    }
+
+    let pages = (total + PAGE_SIZE - 1) / PAGE_SIZE;
+    let page = page.clamp(0, pages - 1);
+
+    let entries = match task_db.get_history(chat_id.0, PAGE_SIZE, page * PAGE_SIZE).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("{}", e);
+            return ("❌ Не удалось загрузить историю.".to_string(), InlineKeyboardMarkup::default());
+        }
+    };
+
+    let lines: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            let title = match &entry.url {
+                Some(url) => format!("<a href=\"{}\">{}</a>", escape(url), escape(&entry.title)),
+                None => escape(&entry.title),
+            };
+            let date = chrono::DateTime::from_timestamp(entry.created_at, 0)
+                .map(|d| d.format("%d.%m.%Y %H:%M").to_string())
+                .unwrap_or_default();
+            format!("• {}\n  {} · {} UTC", title, escape(&entry.format), date)
+        })
+        .collect();
+
+    let text = format!(
+        "📜 <b>История загрузок</b> ({}, стр. {}/{})\n\n{}\n\nОчистить: /history clear",
+        total,
+        page + 1,
+        pages,
+        lines.join("\n")
+    );
+
+    let mut buttons = Vec::new();
+    if page > 0 {
+        buttons.push(InlineKeyboardButton::callback(
+            "◀️",
+            Callback::HistoryPage { page: page as u32 - 1 }.to_string(),
+        ));
+    }
+    if page + 1 < pages {
+        buttons.push(InlineKeyboardButton::callback(
+            "▶️",
+            Callback::HistoryPage { page: page as u32 + 1 }.to_string(),
+        ));
+    }
+
+    (text, InlineKeyboardMarkup::new([buttons]))
+}
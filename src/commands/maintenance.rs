@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use crate::{errors::HandlerResult, queue::TaskQueue, utils::is_admin};
+
+/// Handle /maintenance command - admin only
+/// Usage: /maintenance on|off
+pub async fn maintenance(bot: Bot, msg: Message, task_queue: Arc<TaskQueue>) -> HandlerResult {
+    let from_user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+
+    // Check if user is admin
+    if !is_admin(from_user_id) {
+        // Silently ignore for non-admins
+        return Ok(());
+    }
+
+    // Parse command arguments
+    let text = msg.text().unwrap_or("");
+    let parts: Vec<&str> = text.split_whitespace().collect();
+
+    let enabled = match parts.get(1).copied() {
+        Some("on") => true,
+        Some("off") => false,
+        _ => {
+            let state = if task_queue.is_maintenance() { "on" } else { "off" };
+            bot.send_message(
+                msg.chat.id,
+                format!("Maintenance mode is {}.\n\nUsage: /maintenance on|off", state),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    if let Err(e) = task_queue.set_maintenance(enabled).await {
+        log::error!("Failed to persist maintenance mode: {}", e);
+    }
+    log::info!("Maintenance mode set to {} by admin {}", enabled, from_user_id);
+
+    let reply = if enabled {
+        format!(
+            "Maintenance mode on. New tasks are rejected, {} queued task(s) will still run.",
+            task_queue.pending_count()
+        )
+    } else {
+        "Maintenance mode off. Accepting new tasks.".to_string()
+    };
+    bot.send_message(msg.chat.id, reply).await?;
+
+    Ok(())
+}
@@ -1,11 +1,45 @@
 mod cancel;
+mod channel;
+mod convert;
+mod filter;
+mod forget;
+mod format_string;
 mod grant;
+mod history;
+mod maintenance;
+mod name;
+mod pending;
 mod premium;
+mod profile;
 mod queue;
+mod refund;
+mod reload;
+mod revoke;
+mod selftest;
+mod settings;
 mod start;
+mod subs;
+mod whoami;
 
 pub use cancel::cancel;
+pub use channel::channel;
+pub use convert::convert;
+pub use filter::filter;
+pub use forget::forget;
+pub use format_string::format_string;
 pub use grant::grant;
+pub use history::{handle_history_callback, history};
+pub use maintenance::maintenance;
+pub use name::name;
+pub use pending::{handle_pending_callback, pending};
 pub use premium::{handle_buy_premium_callback, premium};
-pub use queue::queue;
-pub use start::start;
+pub use profile::{handle_profile_callback, profile};
+pub use queue::{QueueFilter, handle_queue_callback, queue};
+pub use refund::refund;
+pub use reload::reload;
+pub use revoke::revoke;
+pub use selftest::selftest;
+pub use settings::{handle_settings_callback, settings};
+pub use start::{CONVERT_START_PREFIX, start};
+pub use subs::{handle_subs_callback, subs};
+pub use whoami::whoami;
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use teloxide::{prelude::*, types::ParseMode, utils::html::escape};
+
+use crate::{
+    errors::HandlerResult,
+    settings::{SettingsManager, validate_filename_template},
+};
+
+const USAGE: &str = "Использование:\n\
+    <code>/name {title} [{quality}]</code> — шаблон имени скачанных файлов\n\
+    <code>/name off</code> — имя по умолчанию\n\n\
+    Подстановки: <code>{title}</code> — название, <code>{uploader}</code> — автор, \
+    <code>{quality}</code> — качество, <code>{id}</code> — ID видео. \
+    Недопустимые в имени файла символы заменяются на <code>_</code>, расширение добавляется само.";
+
+/// Handle /name command - naming template for downloaded files
+/// Usage: /name <template>|off
+pub async fn name(bot: Bot, msg: Message, settings_manager: Arc<SettingsManager>) -> HandlerResult {
+    let user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+    let mut settings = settings_manager.get(user_id).await;
+
+    // Everything after the command is the template
+    let text = msg.text().unwrap_or("");
+    let template = text.split_once(char::is_whitespace).map(|(_, rest)| rest.trim()).unwrap_or("");
+
+    let reply = match template {
+        "" => match &settings.filename_template {
+            Some(current) => format!(
+                "Текущий шаблон: <code>{}</code>\n\n{}",
+                escape(current),
+                USAGE
+            ),
+            None => format!("Шаблон имени не задан.\n\n{}", USAGE),
+        },
+        "off" => {
+            settings.filename_template = None;
+            settings_manager.update(user_id, &settings).await?;
+            "✅ Шаблон отключён, файлы снова называются по умолчанию.".to_string()
+        }
+        template => match validate_filename_template(template) {
+            Ok(()) => {
+                settings.filename_template = Some(template.to_string());
+                settings_manager.update(user_id, &settings).await?;
+                format!(
+                    "✅ Шаблон сохранён: <code>{}</code>\nОн применяется к видео и аудио, скачанным по ссылке.",
+                    escape(template)
+                )
+            }
+            Err(e) => format!("❌ {}\n\n{}", escape(&e), USAGE),
+        },
+    };
+
+    bot.send_message(msg.chat.id, reply)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
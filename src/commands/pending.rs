@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, MaybeInaccessibleMessage, MessageId},
+};
+
+use crate::{
+    callback::Callback,
+    config::format_size,
+    db::{PendingConversionRow, TaskDb},
+    errors::HandlerResult,
+    handlers::pending_upload_keyboard,
+    queue::TaskQueue,
+    utils::reply_to,
+    video::{VideoInfo, youtube::format_duration},
+};
+
+/// Handle /pending command - uploaded videos of this chat still waiting for a format
+pub async fn pending(bot: Bot, msg: Message, task_db: TaskDb) -> HandlerResult {
+    let (text, keyboard) = pending_list(&task_db, msg.chat.id).await;
+
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(keyboard)
+        .await?;
+
+    Ok(())
+}
+
+/// Handle the buttons of the /pending list
+/// Callback format: pend:fmt:short_id (show the format choice) or pend:del:short_id
+pub async fn handle_pending_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    task_db: TaskDb,
+    task_queue: Arc<TaskQueue>,
+) -> HandlerResult {
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    let Some(Ok(callback)) = query.data.as_deref().map(Callback::parse) else {
+        return Ok(());
+    };
+
+    // Only the chat the list was sent to, so nobody can touch another chat's files
+    let Some(MaybeInaccessibleMessage::Regular(m)) = &query.message else {
+        return Ok(());
+    };
+
+    match callback {
+        Callback::PendingFormat { short_id } => {
+            let rows = match task_db.get_pending_conversions_for_chat(m.chat.id.0).await {
+                Ok(rows) => rows,
+                Err(e) => {
+                    log::error!("{}", e);
+                    Vec::new()
+                }
+            };
+            let Some(row) = rows.iter().find(|row| row.short_id == short_id) else {
+                bot.send_message(m.chat.id, "Это видео уже обработано или удалено.").await?;
+                return Ok(());
+            };
+
+            task_queue.touch_pending_conversion(&short_id).await;
+            bot.send_message(m.chat.id, "Выбери формат для этого видео:")
+                .reply_parameters(reply_to(MessageId(row.message_id)))
+                .reply_markup(pending_upload_keyboard(&short_id, &row.filename).await)
+                .await?;
+        }
+        Callback::PendingDiscard { short_id } => {
+            if task_queue.discard_pending_conversion(m.chat.id, &short_id).await {
+                let (text, keyboard) = pending_list(&task_db, m.chat.id).await;
+                bot.edit_message_text(m.chat.id, m.id, format!("🗑 Видео удалено.\n\n{}", text))
+                    .reply_markup(keyboard)
+                    .await?;
+            } else {
+                bot.send_message(m.chat.id, "Это видео уже обработано или удалено.").await?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Text and buttons listing the chat's videos waiting for a format
+async fn pending_list(task_db: &TaskDb, chat_id: ChatId) -> (String, InlineKeyboardMarkup) {
+    let rows = match task_db.get_pending_conversions_for_chat(chat_id.0).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            log::error!("{}", e);
+            return ("❌ Не удалось загрузить список.".to_string(), InlineKeyboardMarkup::default());
+        }
+    };
+
+    if rows.is_empty() {
+        return (
+            "📭 Нет видео, ждущих выбора формата.".to_string(),
+            InlineKeyboardMarkup::default(),
+        );
+    }
+
+    let mut lines = Vec::with_capacity(rows.len());
+    let mut buttons = Vec::with_capacity(rows.len());
+    for (index, row) in rows.iter().enumerate() {
+        let number = index + 1;
+        lines.push(format!("{}. {}", number, describe(row).await));
+        buttons.push(vec![
+            InlineKeyboardButton::callback(
+                format!("🎬 {}. Выбрать формат", number),
+                Callback::PendingFormat { short_id: row.short_id.clone() }.to_string(),
+            ),
+            InlineKeyboardButton::callback(
+                format!("🗑 {}. Удалить", number),
+                Callback::PendingDiscard { short_id: row.short_id.clone() }.to_string(),
+            ),
+        ]);
+    }
+
+    let text = format!(
+        "⏳ Видео, ждущие выбора формата ({}):\n\n{}",
+        rows.len(),
+        lines.join("\n")
+    );
+
+    (text, InlineKeyboardMarkup::new(buttons))
+}
+
+/// "1920×1080, 3:25, 45 МБ · удалится через 5 ч 10 мин"
+async fn describe(row: &PendingConversionRow) -> String {
+    let mut parts = Vec::new();
+    if let Ok(info) = VideoInfo::from_file(&row.filename).await {
+        parts.push(format!("{}×{}", info.width, info.height));
+        parts.push(format_duration(info.duration as u32));
+    }
+    if let Ok(metadata) = tokio::fs::metadata(&row.filename).await {
+        parts.push(format_size(metadata.len()));
+    }
+    if parts.is_empty() {
+        parts.push("видео".to_string());
+    }
+
+    let left = (row.expires_at() - chrono::Utc::now().timestamp()).max(0);
+    format!(
+        "{} · удалится через {} ч {} мин",
+        parts.join(", "),
+        left / 3600,
+        left % 3600 / 60
+    )
+}
@@ -6,9 +6,10 @@ use teloxide::{
 };
 
 use crate::{
+    callback::Callback,
     errors::HandlerResult,
     subscription::{
-        premium::{SUBSCRIPTION_DAYS, SUBSCRIPTION_PRICE_STARS},
+        premium::{find_tier, invoice_payload, tiers},
         SubscriptionInfo, SubscriptionManager,
     },
 };
@@ -23,18 +24,19 @@ pub async fn premium(
 
     let (status_text, show_buy_button) = match info {
         SubscriptionInfo::Active {
+            tier,
             expires_at,
             days_left,
         } => {
             let text = format!(
-                "<b>Premium-подписка активна</b>\n\n\
+                "<b>Подписка {} активна</b>\n\n\
                 Осталось дней: <b>{}</b>\n\
                 Действует до: {}\n\n\
-                <b>Доступные функции:</b>\n\
-                - Конвертация в кружочки\n\
-                - Конвертация в войсы",
+                <b>Доступные функции:</b>\n{}",
+                tier.name,
                 days_left,
-                expires_at.format("%d.%m.%Y %H:%M UTC")
+                expires_at.format("%d.%m.%Y %H:%M UTC"),
+                tier.features()
             );
             (text, false) // Cannot buy while active
         }
@@ -42,33 +44,27 @@ pub async fn premium(
             let text = format!(
                 "<b>Подписка истекла</b>\n\n\
                 Истекла: {}\n\n\
-                Продлите подписку, чтобы получить доступ к:\n\
-                - Конвертация в кружочки\n\
-                - Конвертация в войсы",
-                expired_at.format("%d.%m.%Y %H:%M UTC")
+                Продлите подписку:\n\n{}",
+                expired_at.format("%d.%m.%Y %H:%M UTC"),
+                tiers_description()
             );
             (text, true)
         }
         SubscriptionInfo::None => {
-            let text = "<b>У вас нет Premium-подписки</b>\n\n\
-                Оформите подписку, чтобы получить доступ к:\n\
-                - Конвертация в кружочки\n\
-                - Конвертация в войсы"
-                .to_string();
+            let text = format!(
+                "<b>У вас нет Premium-подписки</b>\n\n\
+                Оформите подписку:\n\n{}",
+                tiers_description()
+            );
             (text, true)
         }
     };
 
-    let mut keyboard_buttons = vec![];
-
-    if show_buy_button {
-        keyboard_buttons.push(vec![InlineKeyboardButton::callback(
-            format!("Купить за {} Stars ({} дней)", SUBSCRIPTION_PRICE_STARS, SUBSCRIPTION_DAYS),
-            "buy_premium",
-        )]);
-    }
-
-    let keyboard = InlineKeyboardMarkup::new(keyboard_buttons);
+    let keyboard = if show_buy_button {
+        tiers_keyboard()
+    } else {
+        InlineKeyboardMarkup::new(Vec::<Vec<InlineKeyboardButton>>::new())
+    };
 
     bot.send_message(msg.chat.id, status_text)
         .parse_mode(ParseMode::Html)
@@ -78,7 +74,37 @@ pub async fn premium(
     Ok(())
 }
 
-/// Handle the buy_premium callback - send invoice
+/// Description of every tier with its features
+fn tiers_description() -> String {
+    tiers()
+        .iter()
+        .map(|tier| {
+            format!(
+                "<b>{}</b> — {} Stars / {} дней\n{}",
+                tier.name,
+                tier.price_stars,
+                tier.days,
+                tier.features()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// One buy button per tier
+fn tiers_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(tiers().iter().map(|tier| {
+        vec![InlineKeyboardButton::callback(
+            format!(
+                "{} — {} Stars ({} дней)",
+                tier.name, tier.price_stars, tier.days
+            ),
+            Callback::BuyPremium { tier_id: Some(tier.id.clone()) }.to_string(),
+        )]
+    }))
+}
+
+/// Handle the buy_premium callback - show tiers, or send invoice for a chosen tier
 pub async fn handle_buy_premium_callback(
     bot: Bot,
     query: CallbackQuery,
@@ -105,29 +131,40 @@ pub async fn handle_buy_premium_callback(
         return Ok(());
     }
 
-    if let Some(chat_id) = chat_id {
-        // Create invoice payload with user_id
-        let payload = format!("premium_sub_{}", user_id);
+    let Some(chat_id) = chat_id else {
+        return Ok(());
+    };
 
-        // Send invoice with Telegram Stars
-        let prices = vec![LabeledPrice::new(
-            "Premium-подписка",
-            SUBSCRIPTION_PRICE_STARS as u32,
-        )];
+    // Plain "buy_premium" (from upsell messages) - let the user pick a tier
+    let tier = match query.data.as_deref().map(Callback::parse) {
+        Some(Ok(Callback::BuyPremium { tier_id: Some(tier_id) })) => find_tier(&tier_id),
+        _ => None,
+    };
 
-        bot.send_invoice(
-            chat_id,
-            "Premium-подписка",
-            format!(
-                "Доступ к премиум-функциям на {} дней:\n- Конвертация в кружочки\n- Конвертация в войсы",
-                SUBSCRIPTION_DAYS
-            ),
-            payload,
-            "XTR", // Telegram Stars currency
-            prices,
-        )
-        .await?;
-    }
+    let Some(tier) = tier else {
+        bot.send_message(chat_id, format!("<b>Выберите подписку:</b>\n\n{}", tiers_description()))
+            .parse_mode(ParseMode::Html)
+            .reply_markup(tiers_keyboard())
+            .await?;
+        return Ok(());
+    };
+
+    // Send invoice with Telegram Stars
+    let prices = vec![LabeledPrice::new(tier.name.clone(), tier.price_stars)];
+
+    bot.send_invoice(
+        chat_id,
+        tier.name.clone(),
+        format!(
+            "Доступ к премиум-функциям на {} дней:\n{}",
+            tier.days,
+            tier.features()
+        ),
+        invoice_payload(tier, user_id),
+        "XTR", // Telegram Stars currency
+        prices,
+    )
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode},
+    utils::html::escape,
+};
+
+use crate::{
+    callback::Callback,
+    errors::{BotError, HandlerResult},
+    settings::{
+        MAX_PROFILE_NAME_CHARS, MAX_PROFILES, SendOption, SettingsManager, UserSettings,
+        is_valid_profile_name,
+    },
+};
+
+const USAGE: &str = "Профили — сохранённые наборы настроек (/settings, /format_string, /filter и /name).\n\n\
+    <code>/profile save имя</code> — сохранить текущие настройки\n\
+    <code>/profile use имя</code> — применить профиль\n\
+    <code>/profile delete имя</code> — удалить профиль\n\
+    <code>/profile</code> — список профилей";
+
+/// Handle /profile command
+/// Usage: /profile [save|use|delete <name>]
+pub async fn profile(
+    bot: Bot,
+    msg: Message,
+    settings_manager: Arc<SettingsManager>,
+) -> HandlerResult {
+    let user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+
+    // Parse command arguments
+    let text = msg.text().unwrap_or("");
+    let parts: Vec<&str> = text.split_whitespace().collect();
+
+    let reply = match (parts.get(1).copied(), parts.get(2).copied()) {
+        (None | Some("list"), _) => {
+            return send_profile_list(&bot, msg.chat.id, user_id, &settings_manager).await;
+        }
+        (Some("save"), Some(name)) => save_profile(user_id, name, &settings_manager).await?,
+        (Some("use"), Some(name)) => apply_profile(user_id, name, &settings_manager).await?,
+        (Some("delete"), Some(name)) => {
+            if settings_manager.delete_profile(user_id, name).await? {
+                format!("🗑 Профиль <b>{}</b> удалён.", escape(name))
+            } else {
+                format!("❌ Профиль <b>{}</b> не найден.", escape(name))
+            }
+        }
+        _ => USAGE.to_string(),
+    };
+
+    bot.send_message(msg.chat.id, reply)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
+/// Handle profile button from the /profile list
+/// Callback format: prof:name
+pub async fn handle_profile_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    settings_manager: Arc<SettingsManager>,
+) -> HandlerResult {
+    let data = query
+        .data
+        .as_ref()
+        .ok_or_else(|| BotError::general("No callback data"))?;
+
+    let Ok(Callback::ApplyProfile { name }) = Callback::parse(data) else {
+        return Err(BotError::general(format!("Invalid profile callback: {}", data)));
+    };
+
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    let user_id = query.from.id.0 as i64;
+    let reply = apply_profile(user_id, &name, &settings_manager).await?;
+
+    if let Some(message) = &query.message {
+        bot.send_message(message.chat().id, reply)
+            .parse_mode(ParseMode::Html)
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn send_profile_list(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: i64,
+    settings_manager: &SettingsManager,
+) -> HandlerResult {
+    let names = settings_manager.list_profiles(user_id).await?;
+    if names.is_empty() {
+        bot.send_message(chat_id, format!("У вас пока нет профилей.\n\n{}", USAGE))
+            .parse_mode(ParseMode::Html)
+            .await?;
+        return Ok(());
+    }
+
+    // One button per profile to apply it
+    let keyboard = InlineKeyboardMarkup::new(names.iter().map(|name| {
+        vec![InlineKeyboardButton::callback(
+            format!("▶️ {}", name),
+            Callback::ApplyProfile { name: name.clone() }.to_string(),
+        )]
+    }));
+
+    bot.send_message(
+        chat_id,
+        format!("📋 Ваши профили — нажмите, чтобы применить:\n\n{}", USAGE),
+    )
+    .parse_mode(ParseMode::Html)
+    .reply_markup(keyboard)
+    .await?;
+
+    Ok(())
+}
+
+async fn save_profile(
+    user_id: i64,
+    name: &str,
+    settings_manager: &SettingsManager,
+) -> Result<String, BotError> {
+    if !is_valid_profile_name(name) {
+        return Ok(format!(
+            "❌ Имя профиля — до {} букв, цифр, «-» или «_».",
+            MAX_PROFILE_NAME_CHARS
+        ));
+    }
+
+    let existing = settings_manager.list_profiles(user_id).await?;
+    if existing.len() >= MAX_PROFILES && !existing.iter().any(|n| n == name) {
+        return Ok(format!(
+            "❌ Можно хранить не больше {} профилей, удалите ненужный: <code>/profile delete имя</code>",
+            MAX_PROFILES
+        ));
+    }
+
+    let settings = settings_manager.get(user_id).await;
+    settings_manager.save_profile(user_id, name, &settings).await?;
+
+    Ok(format!(
+        "✅ Профиль <b>{}</b> сохранён:\n{}",
+        escape(name),
+        profile_summary(&settings)
+    ))
+}
+
+async fn apply_profile(
+    user_id: i64,
+    name: &str,
+    settings_manager: &SettingsManager,
+) -> Result<String, BotError> {
+    let Some(settings) = settings_manager.get_profile(user_id, name).await? else {
+        return Ok(format!("❌ Профиль <b>{}</b> не найден.", escape(name)));
+    };
+
+    settings_manager.update(user_id, &settings).await?;
+
+    Ok(format!(
+        "✅ Профиль <b>{}</b> применён:\n{}",
+        escape(name),
+        profile_summary(&settings)
+    ))
+}
+
+/// What a profile sets, one line per setting
+fn profile_summary(settings: &UserSettings) -> String {
+    let mut lines: Vec<String> = SendOption::ALL
+        .iter()
+        .map(|&option| settings.send_options.button_label(option))
+        .collect();
+    lines.push(settings.send_options.auto_delete_label());
+    lines.push(settings.smart_quality_label());
+    if let Some(format_string) = &settings.format_string {
+        lines.push(format!("🎛 Формат yt-dlp: <code>{}</code>", escape(format_string)));
+    }
+    if let Some(graph) = &settings.filters.video {
+        lines.push(format!("🎞 Фильтр видео: <code>{}</code>", escape(graph)));
+    }
+    if let Some(graph) = &settings.filters.audio {
+        lines.push(format!("🔊 Фильтр аудио: <code>{}</code>", escape(graph)));
+    }
+    if let Some(template) = &settings.filename_template {
+        lines.push(format!("📝 Имя файла: <code>{}</code>", escape(template)));
+    }
+    if let Some(chat_id) = settings.target_chat {
+        lines.push(format!("📢 Канал: <code>{}</code>", chat_id));
+    }
+
+    lines.join("\n")
+}
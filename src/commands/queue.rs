@@ -1,17 +1,116 @@
 use std::sync::Arc;
 
-use teloxide::prelude::*;
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, MaybeInaccessibleMessage},
+};
 
-use crate::{errors::HandlerResult, queue::{TaskQueue, TaskStatus}};
+use crate::{
+    callback::Callback,
+    errors::HandlerResult,
+    queue::{QueuedTaskInfo, TaskQueue, TaskStatus},
+    utils::create_progress_bar,
+};
 
+/// Tasks shown per page
+const PAGE_SIZE: usize = 10;
+
+/// Which of the user's tasks /queue lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumIter, strum::AsRefStr, strum::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum QueueFilter {
+    All,
+    Queued,
+    Processing,
+    Failed,
+}
+
+impl QueueFilter {
+    fn label(&self) -> &'static str {
+        match self {
+            QueueFilter::All => "Все",
+            QueueFilter::Queued => "⏳ В очереди",
+            QueueFilter::Processing => "🔄 В работе",
+            QueueFilter::Failed => "❌ Ошибки",
+        }
+    }
+
+    fn matches(&self, status: &TaskStatus) -> bool {
+        match self {
+            QueueFilter::All => !matches!(status, TaskStatus::Completed),
+            QueueFilter::Queued => matches!(status, TaskStatus::Queued { .. }),
+            QueueFilter::Processing => matches!(status, TaskStatus::Processing),
+            QueueFilter::Failed => matches!(status, TaskStatus::Failed(_)),
+        }
+    }
+}
+
+/// Handle /queue command - global queue size and the user's tasks
 pub async fn queue(bot: Bot, msg: Message, task_queue: Arc<TaskQueue>) -> HandlerResult {
-    let pending = task_queue.pending_count();
-    let user_tasks = task_queue.get_user_tasks(msg.chat.id).await;
+    let (text, keyboard) = queue_page(&task_queue, msg.chat.id, QueueFilter::All, 0).await;
+
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(keyboard)
+        .await?;
+
+    Ok(())
+}
+
+/// Handle filter and page navigation in the /queue list
+/// Callback format: queue:filter:page
+pub async fn handle_queue_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    task_queue: Arc<TaskQueue>,
+) -> HandlerResult {
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    let (filter, page) = match query.data.as_deref().map(Callback::parse) {
+        Some(Ok(Callback::QueuePage { filter, page })) => (filter, page as usize),
+        _ => (QueueFilter::All, 0),
+    };
+
+    // Only the chat the list was sent to, so nobody can look at another chat's tasks
+    if let Some(MaybeInaccessibleMessage::Regular(m)) = &query.message {
+        let (text, keyboard) = queue_page(&task_queue, m.chat.id, filter, page).await;
+        // Editing to identical content fails, which happens when refreshing an unchanged list
+        let _ = bot
+            .edit_message_text(m.chat.id, m.id, text)
+            .reply_markup(keyboard)
+            .await;
+    }
+
+    Ok(())
+}
+
+/// The chat's tasks: queued and running ones from memory, failed ones from the database
+async fn user_tasks(task_queue: &TaskQueue, chat_id: ChatId) -> Vec<QueuedTaskInfo> {
+    let mut tasks = task_queue.get_user_tasks(chat_id).await;
 
-    // Filter only active tasks (queued or processing)
-    let active_tasks: Vec<_> = user_tasks
+    // Failed tasks stay in memory only for a minute, the database keeps them until they expire
+    for failed in task_queue.get_failed_tasks(chat_id).await {
+        if !tasks.iter().any(|t| t.task_id == failed.task_id) {
+            tasks.push(failed);
+        }
+    }
+
+    tasks
+}
+
+/// Text and buttons for one page of the chat's tasks
+async fn queue_page(
+    task_queue: &TaskQueue,
+    chat_id: ChatId,
+    filter: QueueFilter,
+    page: usize,
+) -> (String, InlineKeyboardMarkup) {
+    use strum::IntoEnumIterator;
+
+    let pending = task_queue.pending_count();
+    let tasks: Vec<_> = user_tasks(task_queue, chat_id)
+        .await
         .into_iter()
-        .filter(|t| matches!(t.status, TaskStatus::Queued { .. } | TaskStatus::Processing))
+        .filter(|t| filter.matches(&t.status))
         .collect();
 
     let mut response = String::new();
@@ -23,21 +122,94 @@ pub async fn queue(bot: Bot, msg: Message, task_queue: Arc<TaskQueue>) -> Handle
         response.push_str("📊 Очередь пуста\n\n");
     }
 
-    // User's active tasks
-    if active_tasks.is_empty() {
-        response.push_str("У вас нет активных задач.");
+    let pages = tasks.len().div_ceil(PAGE_SIZE).max(1);
+    let page = page.min(pages - 1);
+    let page_tasks = tasks.iter().skip(page * PAGE_SIZE).take(PAGE_SIZE);
+
+    let mut retry_buttons = Vec::new();
+
+    if tasks.is_empty() {
+        response.push_str(match filter {
+            QueueFilter::All => "У вас нет активных задач.",
+            QueueFilter::Queued => "У вас нет задач в очереди.",
+            QueueFilter::Processing => "Сейчас ничего не обрабатывается.",
+            QueueFilter::Failed => "У вас нет задач с ошибками.",
+        });
     } else {
-        response.push_str("Ваши задачи:\n");
-        for task in active_tasks {
-            let status = match &task.status {
-                TaskStatus::Queued { position } => format!("⏳ #{}", position),
-                TaskStatus::Processing => "🔄 обработка".to_string(),
-                _ => continue,
+        if pages > 1 {
+            response.push_str(&format!("Ваши задачи (стр. {}/{}):\n", page + 1, pages));
+        } else {
+            response.push_str("Ваши задачи:\n");
+        }
+
+        for (i, task) in page_tasks.enumerate() {
+            let number = page * PAGE_SIZE + i + 1;
+            let status = match (&task.status, task.progress) {
+                (TaskStatus::Queued { position }, _) => format!("⏳ #{}", position),
+                (TaskStatus::Processing, Some(progress)) => format!(
+                    "🔄 {} {}%",
+                    create_progress_bar(progress as f32),
+                    progress
+                ),
+                (TaskStatus::Processing, None) => "🔄 обработка".to_string(),
+                (TaskStatus::Failed(e), _) => format!("❌ {} {}", e.kind.emoji(), e.kind.message()),
+                (TaskStatus::Completed, _) => continue,
             };
-            response.push_str(&format!("• {} — {}\n", task.task_type, status));
+            response.push_str(&format!(
+                "{}. {} {} — {}\n",
+                number,
+                task.description.emoji(),
+                task.description,
+                status
+            ));
+
+            if matches!(task.status, TaskStatus::Failed(_)) {
+                retry_buttons.push(InlineKeyboardButton::callback(
+                    format!("🔄 {}", number),
+                    Callback::Retry { task_id: task.task_id.to_string() }.to_string(),
+                ));
+            }
         }
     }
 
-    bot.send_message(msg.chat.id, response).await?;
-    Ok(())
+    let mut keyboard = InlineKeyboardMarkup::default();
+
+    let filter_buttons: Vec<_> = QueueFilter::iter()
+        .map(|f| {
+            let label = if f == filter {
+                format!("• {}", f.label())
+            } else {
+                f.label().to_string()
+            };
+            InlineKeyboardButton::callback(label, Callback::QueuePage { filter: f, page: 0 }.to_string())
+        })
+        .collect();
+    for chunk in filter_buttons.chunks(2) {
+        keyboard = keyboard.append_row(chunk.to_vec());
+    }
+
+    for chunk in retry_buttons.chunks(5) {
+        keyboard = keyboard.append_row(chunk.to_vec());
+    }
+
+    let mut nav = Vec::new();
+    if page > 0 {
+        nav.push(InlineKeyboardButton::callback(
+            "◀️",
+            Callback::QueuePage { filter, page: page as u32 - 1 }.to_string(),
+        ));
+    }
+    nav.push(InlineKeyboardButton::callback(
+        "🔃",
+        Callback::QueuePage { filter, page: page as u32 }.to_string(),
+    ));
+    if page + 1 < pages {
+        nav.push(InlineKeyboardButton::callback(
+            "▶️",
+            Callback::QueuePage { filter, page: page as u32 + 1 }.to_string(),
+        ));
+    }
+    keyboard = keyboard.append_row(nav);
+
+    (response, keyboard)
 }
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use teloxide::{prelude::*, types::UserId};
+
+use crate::{errors::HandlerResult, subscription::SubscriptionManager, utils::is_admin};
+
+/// Handle /refund command - admin only
+/// Usage: /refund <user_id|charge_id>
+pub async fn refund(
+    bot: Bot,
+    msg: Message,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> HandlerResult {
+    let from_user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+
+    // Check if user is admin
+    if !is_admin(from_user_id) {
+        // Silently ignore for non-admins
+        return Ok(());
+    }
+
+    // Parse command arguments
+    let text = msg.text().unwrap_or("");
+    let parts: Vec<&str> = text.split_whitespace().collect();
+
+    if parts.len() != 2 {
+        bot.send_message(
+            msg.chat.id,
+            "Usage: /refund <user_id|charge_id>\nExample: /refund 578503618",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let Some(payment) = subscription_manager.find_refundable_payment(parts[1]).await else {
+        bot.send_message(msg.chat.id, "No refundable payment found.")
+            .await?;
+        return Ok(());
+    };
+
+    // Refund the Stars first, only then take back the subscription
+    if let Err(e) = bot
+        .refund_star_payment(UserId(payment.user_id as u64), payment.charge_id.clone().into())
+        .await
+    {
+        log::error!("Failed to refund payment {}: {}", payment.charge_id, e);
+        bot.send_message(msg.chat.id, format!("Refund failed: {}", e))
+            .await?;
+        return Ok(());
+    }
+
+    match subscription_manager.revoke_payment(&payment).await {
+        Ok(expires_at) => {
+            let text = format!(
+                "Payment refunded!\n\nUser: {}\nStars: {}\nCharge: {}\nSubscription until: {}",
+                payment.user_id,
+                payment.total_amount,
+                payment.charge_id,
+                expires_at.format("%d.%m.%Y %H:%M UTC")
+            );
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Err(e) => {
+            bot.send_message(
+                msg.chat.id,
+                format!("Stars refunded, but failed to update subscription: {}", e),
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use teloxide::prelude::*;
+
+use crate::{config, errors::HandlerResult, utils::is_admin};
+
+/// Handle /reload command - admin only
+/// Re-reads the configuration without restarting the bot
+pub async fn reload(bot: Bot, msg: Message) -> HandlerResult {
+    let from_user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+
+    // Check if user is admin
+    if !is_admin(from_user_id) {
+        // Silently ignore for non-admins
+        return Ok(());
+    }
+
+    let config = config::reload();
+    log::info!("Configuration reloaded by admin {}", from_user_id);
+
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "Configuration reloaded!\n\n\
+            Tiers: {}\n\
+            Transcription: {}\n\n\
+            Applied now: {}\n\
+            Requires restart: {}",
+            config.subscription_tiers.len(),
+            if config.transcription.is_some() { "on" } else { "off" },
+            config::HOT_RELOADABLE.join(", "),
+            config::RESTART_REQUIRED.join(", "),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
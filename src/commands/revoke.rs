@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use crate::{errors::HandlerResult, subscription::SubscriptionManager, utils::is_admin};
+
+/// Handle /revoke command - admin only
+/// Usage: /revoke <user_id>
+pub async fn revoke(
+    bot: Bot,
+    msg: Message,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> HandlerResult {
+    let from_user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+
+    // Check if user is admin
+    if !is_admin(from_user_id) {
+        // Silently ignore for non-admins
+        return Ok(());
+    }
+
+    // Parse command arguments
+    let text = msg.text().unwrap_or("");
+    let parts: Vec<&str> = text.split_whitespace().collect();
+
+    if parts.len() != 2 {
+        bot.send_message(
+            msg.chat.id,
+            "Usage: /revoke <user_id>\nExample: /revoke 578503618",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let target_user_id: i64 = match parts[1].parse() {
+        Ok(id) => id,
+        Err(_) => {
+            bot.send_message(msg.chat.id, "Invalid user_id. Must be a number.")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    match subscription_manager.revoke(target_user_id).await {
+        Ok(true) => {
+            bot.send_message(
+                msg.chat.id,
+                format!("Subscription revoked!\n\nUser: {}", target_user_id),
+            )
+            .await?;
+        }
+        Ok(false) => {
+            bot.send_message(msg.chat.id, "User has no active subscription.")
+                .await?;
+        }
+        Err(e) => {
+            bot.send_message(msg.chat.id, format!("Error: {}", e))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use teloxide::prelude::*;
+
+use crate::{errors::HandlerResult, utils::is_admin, video::toolchain};
+
+/// Handle /selftest command - admin only
+/// Shows the tool versions and runs a tiny conversion end to end
+pub async fn selftest(bot: Bot, msg: Message) -> HandlerResult {
+    let from_user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+
+    // Check if user is admin
+    if !is_admin(from_user_id) {
+        // Silently ignore for non-admins
+        return Ok(());
+    }
+
+    let status = bot.send_message(msg.chat.id, "Running self-test...").await?;
+
+    let mut text = String::from("Tools:\n");
+    for (name, version) in toolchain::versions().await {
+        match version {
+            Ok(version) => text.push_str(&format!("✅ {}: {}\n", name, version)),
+            Err(e) => text.push_str(&format!("❌ {}: {}\n", name, e)),
+        }
+    }
+
+    text.push_str("\nConversion:\n");
+    let steps = toolchain::self_test().await;
+    for (name, result) in &steps {
+        match result {
+            Ok(elapsed) => text.push_str(&format!("✅ {} ({:.1}s)\n", name, elapsed.as_secs_f64())),
+            Err(e) => text.push_str(&format!("❌ {}: {}\n", name, e)),
+        }
+    }
+
+    let passed = steps.iter().all(|(_, result)| result.is_ok());
+    log::info!("Self-test run by admin {}: {}", from_user_id, if passed { "passed" } else { "failed" });
+    text.push_str(if passed { "\nAll good." } else { "\nSelf-test failed." });
+
+    bot.edit_message_text(msg.chat.id, status.id, text).await?;
+
+    Ok(())
+}
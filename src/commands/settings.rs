@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, MaybeInaccessibleMessage},
+};
+
+use crate::{
+    callback::{Callback, Setting},
+    errors::{BotError, HandlerResult},
+    settings::{SendOption, SettingsManager, UserSettings},
+};
+
+const SETTINGS_TEXT: &str = "⚙️ Настройки по умолчанию для новых загрузок.\n\
+    Их можно поменять для конкретного видео при выборе формата.\n\
+    Автоудаление удаляет отправленный файл через выбранное время.\n\
+    С качеством по длине видео шаг выбора качества пропускается: \
+    короткие видео скачиваются в высоком качестве, длинные — в более низком.\n\
+    Чтобы результаты публиковались ещё и в вашем канале, используйте /channel.";
+
+pub async fn settings(
+    bot: Bot,
+    msg: Message,
+    settings_manager: Arc<SettingsManager>,
+) -> HandlerResult {
+    let user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+    let settings = settings_manager.get(user_id).await;
+
+    bot.send_message(msg.chat.id, SETTINGS_TEXT)
+        .reply_markup(settings_keyboard(&settings))
+        .await?;
+
+    Ok(())
+}
+
+/// Handle settings toggle callback
+/// Callback format: set:option_key
+pub async fn handle_settings_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    settings_manager: Arc<SettingsManager>,
+) -> HandlerResult {
+    let data = query
+        .data
+        .as_ref()
+        .ok_or_else(|| BotError::general("No callback data"))?;
+
+    let Ok(Callback::ToggleSetting { setting }) = Callback::parse(data) else {
+        return Err(BotError::general(format!("Invalid settings callback: {}", data)));
+    };
+
+    let user_id = query.from.id.0 as i64;
+    let mut settings = settings_manager.get(user_id).await;
+
+    let label = match setting {
+        Setting::AutoDelete => {
+            settings.send_options.cycle_auto_delete();
+            settings.send_options.auto_delete_label()
+        }
+        Setting::SmartQuality => {
+            settings.smart_quality = !settings.smart_quality;
+            settings.smart_quality_label()
+        }
+        Setting::SendOption(option) => {
+            settings.send_options.toggle(option);
+            settings.send_options.button_label(option)
+        }
+    };
+    settings_manager.update(user_id, &settings).await?;
+
+    bot.answer_callback_query(query.id.clone())
+        .text(label)
+        .await?;
+
+    if let Some(MaybeInaccessibleMessage::Regular(m)) = &query.message {
+        bot.edit_message_reply_markup(m.chat.id, m.id)
+            .reply_markup(settings_keyboard(&settings))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// One toggle button per setting, plus the auto-delete delay and smart quality
+fn settings_keyboard(settings: &UserSettings) -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = SendOption::ALL
+        .map(|option| {
+            vec![InlineKeyboardButton::callback(
+                settings.send_options.button_label(option),
+                Callback::ToggleSetting { setting: Setting::SendOption(option) }.to_string(),
+            )]
+        })
+        .to_vec();
+    rows.push(vec![InlineKeyboardButton::callback(
+        settings.send_options.auto_delete_label(),
+        Callback::ToggleSetting { setting: Setting::AutoDelete }.to_string(),
+    )]);
+    rows.push(vec![InlineKeyboardButton::callback(
+        settings.smart_quality_label(),
+        Callback::ToggleSetting { setting: Setting::SmartQuality }.to_string(),
+    )]);
+
+    InlineKeyboardMarkup::new(rows)
+}
@@ -1,8 +1,26 @@
+use std::sync::Arc;
+
 use teloxide::prelude::*;
 
-use crate::errors::HandlerResult;
+use crate::{
+    errors::HandlerResult,
+    handlers::pending_upload_keyboard,
+    queue::TaskQueue,
+    utils::reply_to,
+};
+
+/// Start payload that brings back the format choice of a pending conversion,
+/// `t.me/<bot>?start=convert_<short_id>`
+pub const CONVERT_START_PREFIX: &str = "convert_";
+
+/// Handle /start command
+/// Usage: /start [convert_<short_id>]
+pub async fn start(bot: Bot, msg: Message, task_queue: Arc<TaskQueue>) -> HandlerResult {
+    let payload = msg.text().and_then(|text| text.split_whitespace().nth(1));
+    if let Some(short_id) = payload.and_then(|p| p.strip_prefix(CONVERT_START_PREFIX)) {
+        return resume_conversion(&bot, &msg, &task_queue, short_id).await;
+    }
 
-pub async fn start(bot: Bot, msg: Message) -> HandlerResult {
     bot.send_message(
         msg.chat.id,
         "Привет 👋\n\nОтправь мне ссылку на YouTube видео, и я превращу его в любой формат, который ты захочешь.",
@@ -10,3 +28,27 @@ pub async fn start(bot: Bot, msg: Message) -> HandlerResult {
     .await?;
     Ok(())
 }
+
+/// Show the format choice of an uploaded video again, for a message that scrolled away
+async fn resume_conversion(bot: &Bot, msg: &Message, task_queue: &TaskQueue, short_id: &str) -> HandlerResult {
+    // Only the chat the video was uploaded to can pick up its conversion
+    let pending = task_queue
+        .get_pending_conversion(short_id)
+        .await
+        .filter(|pending| pending.chat_id == msg.chat.id);
+    let Some(pending) = pending else {
+        bot.send_message(
+            msg.chat.id,
+            "⌛ Это видео уже обработано или удалено по истечении срока. Отправьте его ещё раз.",
+        )
+        .await?;
+        return Ok(());
+    };
+
+    task_queue.touch_pending_conversion(short_id).await;
+    bot.send_message(msg.chat.id, "Выбери формат для этого видео:")
+        .reply_parameters(reply_to(pending.message_id))
+        .reply_markup(pending_upload_keyboard(short_id, &pending.filename).await)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, MaybeInaccessibleMessage},
+};
+
+use crate::{
+    callback::Callback,
+    errors::HandlerResult,
+    subscription::SubscriptionManager,
+    utils::is_admin,
+};
+
+/// Subscribers shown per page
+const PAGE_SIZE: i64 = 20;
+
+/// Handle /subs command - admin only
+/// Lists active subscriptions with pagination
+pub async fn subs(
+    bot: Bot,
+    msg: Message,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> HandlerResult {
+    let from_user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+
+    // Check if user is admin
+    if !is_admin(from_user_id) {
+        // Silently ignore for non-admins
+        return Ok(());
+    }
+
+    let (text, keyboard) = subs_page(&subscription_manager, 0).await;
+
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(keyboard)
+        .await?;
+
+    Ok(())
+}
+
+/// Handle page navigation in the /subs list
+/// Callback format: subs:page
+pub async fn handle_subs_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> HandlerResult {
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    // Check if user is admin
+    if !is_admin(query.from.id.0 as i64) {
+        return Ok(());
+    }
+
+    let page = match query.data.as_deref().map(Callback::parse) {
+        Some(Ok(Callback::SubsPage { page })) => page as i64,
+        _ => 0,
+    };
+
+    let (text, keyboard) = subs_page(&subscription_manager, page).await;
+
+    if let Some(MaybeInaccessibleMessage::Regular(m)) = &query.message {
+        bot.edit_message_text(m.chat.id, m.id, text)
+            .reply_markup(keyboard)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Text and navigation buttons for one page of active subscriptions
+async fn subs_page(
+    subscription_manager: &SubscriptionManager,
+    page: i64,
+) -> (String, InlineKeyboardMarkup) {
+    let total = match subscription_manager.count_active().await {
+        Ok(total) => total,
+        Err(e) => return (format!("Error: {}", e), InlineKeyboardMarkup::default()),
+    };
+
+    if total == 0 {
+        return ("No active subscriptions.".to_string(), InlineKeyboardMarkup::default());
+    }
+
+    let pages = (total + PAGE_SIZE - 1) / PAGE_SIZE;
+    let page = page.clamp(0, pages - 1);
+
+    let subscriptions = match subscription_manager
+        .list_active(PAGE_SIZE, page * PAGE_SIZE)
+        .await
+    {
+        Ok(subscriptions) => subscriptions,
+        Err(e) => return (format!("Error: {}", e), InlineKeyboardMarkup::default()),
+    };
+
+    let lines: Vec<String> = subscriptions
+        .iter()
+        .map(|s| {
+            format!(
+                "{} — {} until {}",
+                s.user_id,
+                s.tier.id,
+                s.expires_at.format("%d.%m.%Y %H:%M UTC")
+            )
+        })
+        .collect();
+
+    let text = format!(
+        "Active subscriptions: {} (page {}/{})\n\n{}",
+        total,
+        page + 1,
+        pages,
+        lines.join("\n")
+    );
+
+    let mut buttons = Vec::new();
+    if page > 0 {
+        buttons.push(InlineKeyboardButton::callback(
+            "◀️",
+            Callback::SubsPage { page: page as u32 - 1 }.to_string(),
+        ));
+    }
+    if page + 1 < pages {
+        buttons.push(InlineKeyboardButton::callback(
+            "▶️",
+            Callback::SubsPage { page: page as u32 + 1 }.to_string(),
+        ));
+    }
+
+    (text, InlineKeyboardMarkup::new([buttons]))
+}
@@ -0,0 +1,29 @@
+use teloxide::prelude::*;
+
+use crate::{errors::HandlerResult, i18n::Language};
+
+/// Handle /whoami command - the user's ID and the language the bot picked for them
+pub async fn whoami(bot: Bot, msg: Message) -> HandlerResult {
+    let Some(user) = msg.from.as_ref() else {
+        return Ok(());
+    };
+
+    let language_code = user.language_code.as_deref();
+    let language = Language::resolve(language_code);
+
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "🪪 ID: {}\n\
+            Язык Telegram: {}\n\
+            Язык бота: {} ({})",
+            user.id,
+            language_code.unwrap_or("не указан"),
+            language.name(),
+            language.as_ref(),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
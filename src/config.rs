@@ -0,0 +1,705 @@
+//! Runtime configuration loaded from environment variables.
+//!
+//! Every setting has a sensible default, so the bot runs with only
+//! `TELOXIDE_TOKEN` set. Complex values (lists, tables) are passed as JSON.
+//!
+//! The configuration can be reloaded at runtime with `/reload`, see [`reload`].
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::i18n::Language;
+use crate::subscription::premium::{SubscriptionTier, default_tiers};
+use crate::video::hwaccel::HwEncoder;
+use crate::video::transcribe::TranscriptionBackend;
+use crate::utils::{DEFAULT_CONVERT_MESSAGES, DEFAULT_DOWNLOAD_MESSAGES, MediaFormatType};
+
+/// Current configuration. Every loaded config is leaked so that `get()` can keep
+/// handing out `&'static` references; reloads are rare manual operations, so the
+/// leaked memory stays negligible.
+static CONFIG: RwLock<Option<&'static Config>> = RwLock::new(None);
+
+/// Settings that take effect immediately after `/reload`
+pub const HOT_RELOADABLE: &[&str] = &[
+    "SUBSCRIPTION_TIERS",
+    "LOADING_INTERVAL_SECS",
+    "LOADING_*_MESSAGES",
+    "TRANSCRIPTION_*",
+    "WHISPER_CPP_*",
+    "YTDLP_PROXY",
+    "YTDLP_SOCKET_TIMEOUT",
+    "YTDLP_RETRIES",
+    "BOT_API_DATA_*",
+    "UPLOAD_LIMIT_MB",
+    "FREE_MAX_HEIGHT",
+    "SMART_QUALITY_STEPS",
+    "*PENDING_CONVERSIONS_*_LIMIT",
+    "*MAX_ACTIVE_TASKS_PER_USER",
+    "QUEUE_WAIT_*_MINUTES",
+    "FLOW_IDLE_MINUTES",
+    "ARCHIVE_*",
+    "DEFAULT_LANG",
+    "YTDLP_SELF_UPDATE",
+    "METADATA_TIMEOUT_SECS",
+    "ADMIN_ID",
+    "ADMIN_IDS",
+    "DB_MAINTENANCE_INTERVAL_HOURS",
+    "UPSELL_AFTER_DOWNLOADS",
+    "UPSELL_INTERVAL_DAYS",
+    "*SUBMIT_COOLDOWN_SECS",
+    "ENABLED_FORMATS",
+    "TASK_QUEUE_CAPACITY",
+];
+
+/// Settings that are only read at startup
+pub const RESTART_REQUIRED: &[&str] = &[
+    "PENDING_REMINDER_LEAD_HOURS",
+    "DATABASE_URL",
+    "TELOXIDE_TOKEN",
+    "TELOXIDE_API_URL",
+    "REQUIRE_TOOLS",
+    "METADATA_CONCURRENCY",
+    "METRICS_PORT",
+    "FFMPEG_HWACCEL",
+    "FFMPEG_VAAPI_DEVICE",
+];
+
+/// One step of the "smart quality" table: videos up to `max_minutes` long
+/// are downloaded in up to `height`p. A step without `max_minutes` covers the rest.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub struct SmartQualityStep {
+    pub max_minutes: Option<u32>,
+    pub height: u32,
+}
+
+fn default_smart_quality_steps() -> Vec<SmartQualityStep> {
+    vec![
+        SmartQualityStep { max_minutes: Some(5), height: 1080 },
+        SmartQualityStep { max_minutes: Some(20), height: 720 },
+        SmartQualityStep { max_minutes: None, height: 480 },
+    ]
+}
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// Which Bot API server the bot talks to; it decides how big files can be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotApiMode {
+    /// api.telegram.org: uploads up to 50 MB, `getFile` up to 20 MB
+    Cloud,
+    /// Self-hosted telegram-bot-api in `--local` mode: up to 2000 MB both ways,
+    /// files are read straight from its data directory
+    Local,
+}
+
+impl BotApiMode {
+    /// Any `TELOXIDE_API_URL` other than the official server means a local Bot API
+    fn from_env(env: &Env) -> Self {
+        let Some(url) = env
+            .var("TELOXIDE_API_URL")
+            .filter(|url| !url.trim().is_empty())
+        else {
+            return BotApiMode::Cloud;
+        };
+
+        match reqwest::Url::parse(url.trim()) {
+            Ok(url) if url.host_str() == Some("api.telegram.org") => BotApiMode::Cloud,
+            Ok(_) => BotApiMode::Local,
+            Err(e) => {
+                log::warn!("Ignoring invalid TELOXIDE_API_URL: {}", e);
+                BotApiMode::Cloud
+            }
+        }
+    }
+
+    /// Largest file the bot can send
+    pub fn max_upload(&self) -> u64 {
+        match self {
+            BotApiMode::Cloud => 50 * MEGABYTE,
+            BotApiMode::Local => 2000 * MEGABYTE,
+        }
+    }
+
+    /// Largest user-sent file the bot can fetch
+    pub fn max_download(&self) -> u64 {
+        match self {
+            BotApiMode::Cloud => 20 * MEGABYTE,
+            BotApiMode::Local => 2000 * MEGABYTE,
+        }
+    }
+}
+
+/// Human-readable size for user messages, e.g. "50 МБ"
+pub fn format_size(bytes: u64) -> String {
+    format!("{} МБ", bytes / MEGABYTE)
+}
+
+/// Per-user caps on downloaded files still waiting for a format choice
+#[derive(Debug, Clone, Copy)]
+pub struct PendingConversionLimits {
+    /// Warn the user once a new file takes them past this many
+    pub soft: usize,
+    /// Refuse new files once the user has this many
+    pub hard: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Subscription tiers offered in /premium (`SUBSCRIPTION_TIERS`, JSON array)
+    pub subscription_tiers: Vec<SubscriptionTier>,
+    /// How often the loading animation changes (`LOADING_INTERVAL_SECS`)
+    pub loading_interval: Duration,
+    /// Loading messages while downloading (`LOADING_DOWNLOAD_MESSAGES`, JSON array)
+    pub download_messages: Vec<String>,
+    /// Loading messages while converting (`LOADING_CONVERT_MESSAGES`, JSON array)
+    pub convert_messages: Vec<String>,
+    /// How long before expiry to remind about a pending conversion (`PENDING_REMINDER_LEAD_HOURS`, 0 disables),
+    /// capped at half the TTL when the reminders start
+    pub pending_reminder_lead: Duration,
+    /// Speech-to-text backend (`TRANSCRIPTION_BACKEND`), None disables transcripts
+    pub transcription: Option<TranscriptionBackend>,
+    /// How many tasks may wait in the queue before new ones are rejected (`TASK_QUEUE_CAPACITY`)
+    pub task_queue_capacity: usize,
+    /// Proxy for all yt-dlp requests (`YTDLP_PROXY`, e.g. `socks5://host:1080`)
+    pub ytdlp_proxy: Option<String>,
+    /// Seconds yt-dlp waits on a stalled connection (`YTDLP_SOCKET_TIMEOUT`, default 5)
+    pub ytdlp_socket_timeout: u32,
+    /// How many times yt-dlp retries a failed request (`YTDLP_RETRIES`, default 3)
+    pub ytdlp_retries: u32,
+    /// Pending conversion caps (`PENDING_CONVERSIONS_SOFT_LIMIT`, `PENDING_CONVERSIONS_HARD_LIMIT`)
+    pub pending_conversion_limits: PendingConversionLimits,
+    /// Pending conversion caps for subscribers (`PREMIUM_PENDING_CONVERSIONS_*`)
+    pub premium_pending_conversion_limits: PendingConversionLimits,
+    /// Cloud or local Bot API, detected from `TELOXIDE_API_URL`
+    pub bot_api_mode: BotApiMode,
+    /// Where a local Bot API server keeps files, the prefix of the paths it returns
+    /// (`BOT_API_DATA_DIR`, default `/var/lib/telegram-bot-api`)
+    pub bot_api_data_dir: String,
+    /// The same directory as mounted for the bot (`BOT_API_DATA_MOUNT`, default `/bot-api-data`)
+    pub bot_api_data_mount: String,
+    /// Files bigger than this are compressed before sending, in bytes
+    /// (`UPLOAD_LIMIT_MB`, defaults to and can't exceed the Bot API limit)
+    pub upload_limit: u64,
+    /// Highest video quality for users without a subscription, also the size their
+    /// videos are compressed to (`FREE_MAX_HEIGHT`, e.g. 720; unset or 0 doesn't cap).
+    /// Tiers set their own with `max_height` in `SUBSCRIPTION_TIERS`.
+    pub free_max_height: Option<u32>,
+    /// Quality picked by video length for users with smart quality on
+    /// (`SMART_QUALITY_STEPS`, JSON array)
+    pub smart_quality_steps: Vec<SmartQualityStep>,
+    /// How often expired rows are cleaned up and the database is compacted
+    /// (`DB_MAINTENANCE_INTERVAL_HOURS`, default daily)
+    pub db_maintenance_interval: Duration,
+    /// Hardware H.264 encoder (`FFMPEG_HWACCEL`: `nvenc`, `vaapi` or `qsv`),
+    /// None encodes in software. Checked at startup, changes need a restart.
+    pub hw_encoder: Option<HwEncoder>,
+    /// Completed downloads after which free users are offered Premium
+    /// (`UPSELL_AFTER_DOWNLOADS`, 0 disables)
+    pub upsell_after_downloads: u32,
+    /// Minimum time between two Premium offers to the same user (`UPSELL_INTERVAL_DAYS`)
+    pub upsell_interval: Duration,
+    /// Minimum time between two links or videos from a user (`SUBMIT_COOLDOWN_SECS`, 0 disables)
+    pub submit_cooldown: Duration,
+    /// The same for subscribers (`PREMIUM_SUBMIT_COOLDOWN_SECS`, off by default)
+    pub premium_submit_cooldown: Duration,
+    /// Tell users their task is still queued after this long (`QUEUE_WAIT_NOTICE_MINUTES`,
+    /// default 30, 0 disables)
+    pub queue_wait_notice: Duration,
+    /// Cancel tasks that have been queued this long (`QUEUE_WAIT_LIMIT_MINUTES`,
+    /// default 120, 0 disables)
+    pub queue_wait_limit: Duration,
+    /// Drop links and uploads left without a choice this long, warning the user first
+    /// (`FLOW_IDLE_MINUTES`, off by default)
+    pub flow_idle_timeout: Duration,
+    /// How many of a user's tasks may run at once (`MAX_ACTIVE_TASKS_PER_USER`, default 1)
+    pub max_active_tasks_per_user: usize,
+    /// The same for subscribers (`PREMIUM_MAX_ACTIVE_TASKS_PER_USER`, default 2)
+    pub premium_max_active_tasks_per_user: usize,
+    /// Output formats offered to users (`ENABLED_FORMATS`, JSON array, all by default)
+    pub enabled_formats: Vec<MediaFormatType>,
+    /// Refuse to start when yt-dlp, ffmpeg or ffprobe is missing (`REQUIRE_TOOLS`,
+    /// default true); false only logs an error
+    pub require_tools: bool,
+    /// Language for users whose Telegram language isn't supported or unknown
+    /// (`DEFAULT_LANG`, default `ru`, the only language with texts so far)
+    pub default_language: Language,
+    /// Run `yt-dlp -U` and retry once when a download fails the way outdated yt-dlp
+    /// versions do (`YTDLP_SELF_UPDATE`, default false)
+    pub ytdlp_self_update: bool,
+    /// How long to wait for video metadata before asking the user to try again
+    /// (`METADATA_TIMEOUT_SECS`, default 20), waiting for a free slot included
+    pub metadata_timeout: Duration,
+    /// How many metadata fetches may run at once (`METADATA_CONCURRENCY`, default 4)
+    pub metadata_concurrency: usize,
+    /// Port to serve Prometheus metrics on at `/metrics` (`METRICS_PORT`, off by default).
+    /// Needs a build with the `metrics` feature.
+    pub metrics_port: Option<u16>,
+    /// Move sent files into this folder instead of deleting them (`ARCHIVE_DIR`, off by default)
+    pub archive_dir: Option<String>,
+    /// Path of archived files inside `archive_dir` (`ARCHIVE_TEMPLATE`,
+    /// default `{date}/{user}/{title}.{ext}`)
+    pub archive_template: String,
+    /// Users allowed to run admin commands (`ADMIN_IDS`, comma-separated, and the older `ADMIN_ID`)
+    pub admin_ids: Vec<i64>,
+}
+
+impl Config {
+    pub fn from_env(env: &Env) -> Self {
+        let bot_api_mode = BotApiMode::from_env(env);
+
+        Self {
+            subscription_tiers: env
+                .json("SUBSCRIPTION_TIERS")
+                .filter(|tiers: &Vec<SubscriptionTier>| !tiers.is_empty())
+                .unwrap_or_else(default_tiers),
+            loading_interval: Duration::from_secs(
+                env.parsed("LOADING_INTERVAL_SECS")
+                    .filter(|&secs: &u64| secs > 0)
+                    .unwrap_or(3),
+            ),
+            download_messages: env.messages("LOADING_DOWNLOAD_MESSAGES", DEFAULT_DOWNLOAD_MESSAGES),
+            convert_messages: env.messages("LOADING_CONVERT_MESSAGES", DEFAULT_CONVERT_MESSAGES),
+            pending_reminder_lead: Duration::from_secs(
+                env.parsed::<u64>("PENDING_REMINDER_LEAD_HOURS").unwrap_or(2) * 3600,
+            ),
+            transcription: transcription_backend(env),
+            task_queue_capacity: env
+                .parsed("TASK_QUEUE_CAPACITY")
+                .filter(|&capacity: &usize| capacity > 0)
+                .unwrap_or(100),
+            ytdlp_proxy: env
+                .var("YTDLP_PROXY")
+                .map(|proxy| proxy.trim().to_string())
+                .filter(|proxy| !proxy.is_empty()),
+            ytdlp_socket_timeout: env
+                .parsed("YTDLP_SOCKET_TIMEOUT")
+                .filter(|&secs: &u32| secs > 0)
+                .unwrap_or(5),
+            ytdlp_retries: env.parsed("YTDLP_RETRIES").unwrap_or(3),
+            pending_conversion_limits: env.limits(
+                "PENDING_CONVERSIONS_SOFT_LIMIT",
+                "PENDING_CONVERSIONS_HARD_LIMIT",
+                PendingConversionLimits { soft: 3, hard: 5 },
+            ),
+            premium_pending_conversion_limits: env.limits(
+                "PREMIUM_PENDING_CONVERSIONS_SOFT_LIMIT",
+                "PREMIUM_PENDING_CONVERSIONS_HARD_LIMIT",
+                PendingConversionLimits { soft: 8, hard: 10 },
+            ),
+            bot_api_mode,
+            bot_api_data_dir: env
+                .var("BOT_API_DATA_DIR")
+                .filter(|dir| !dir.trim().is_empty())
+                .unwrap_or_else(|| "/var/lib/telegram-bot-api".to_string()),
+            bot_api_data_mount: env
+                .var("BOT_API_DATA_MOUNT")
+                .filter(|dir| !dir.trim().is_empty())
+                .unwrap_or_else(|| "/bot-api-data".to_string()),
+            upload_limit: env
+                .parsed::<u64>("UPLOAD_LIMIT_MB")
+                .filter(|&mb| mb > 0)
+                .map(|mb| (mb * MEGABYTE).min(bot_api_mode.max_upload()))
+                .unwrap_or(bot_api_mode.max_upload()),
+            free_max_height: env.parsed("FREE_MAX_HEIGHT").filter(|&height: &u32| height > 0),
+            smart_quality_steps: env
+                .json("SMART_QUALITY_STEPS")
+                .filter(|steps: &Vec<SmartQualityStep>| !steps.is_empty())
+                .unwrap_or_else(default_smart_quality_steps),
+            db_maintenance_interval: Duration::from_secs(
+                env.parsed("DB_MAINTENANCE_INTERVAL_HOURS")
+                    .filter(|&hours: &u64| hours > 0)
+                    .unwrap_or(24)
+                    * 3600,
+            ),
+            hw_encoder: hw_encoder(env),
+            upsell_after_downloads: env.parsed("UPSELL_AFTER_DOWNLOADS").unwrap_or(10),
+            upsell_interval: Duration::from_secs(
+                env.parsed("UPSELL_INTERVAL_DAYS")
+                    .filter(|&days: &u64| days > 0)
+                    .unwrap_or(7)
+                    * 86400,
+            ),
+            submit_cooldown: Duration::from_secs(env.parsed("SUBMIT_COOLDOWN_SECS").unwrap_or(5)),
+            premium_submit_cooldown: Duration::from_secs(
+                env.parsed("PREMIUM_SUBMIT_COOLDOWN_SECS").unwrap_or(0),
+            ),
+            queue_wait_notice: Duration::from_secs(
+                env.parsed::<u64>("QUEUE_WAIT_NOTICE_MINUTES").unwrap_or(30) * 60,
+            ),
+            queue_wait_limit: Duration::from_secs(
+                env.parsed::<u64>("QUEUE_WAIT_LIMIT_MINUTES").unwrap_or(120) * 60,
+            ),
+            flow_idle_timeout: Duration::from_secs(
+                env.parsed::<u64>("FLOW_IDLE_MINUTES").unwrap_or(0) * 60,
+            ),
+            max_active_tasks_per_user: env
+                .parsed("MAX_ACTIVE_TASKS_PER_USER")
+                .filter(|&n: &usize| n > 0)
+                .unwrap_or(1),
+            premium_max_active_tasks_per_user: env
+                .parsed("PREMIUM_MAX_ACTIVE_TASKS_PER_USER")
+                .filter(|&n: &usize| n > 0)
+                .unwrap_or(2),
+            enabled_formats: env
+                .json("ENABLED_FORMATS")
+                .filter(|formats: &Vec<MediaFormatType>| !formats.is_empty())
+                .unwrap_or_else(|| {
+                    use strum::IntoEnumIterator;
+                    MediaFormatType::iter().collect()
+                }),
+            require_tools: env.parsed("REQUIRE_TOOLS").unwrap_or(true),
+            default_language: env.parsed("DEFAULT_LANG").unwrap_or(Language::Ru),
+            ytdlp_self_update: env.parsed("YTDLP_SELF_UPDATE").unwrap_or(false),
+            metadata_timeout: Duration::from_secs(
+                env.parsed("METADATA_TIMEOUT_SECS")
+                    .filter(|&secs: &u64| secs > 0)
+                    .unwrap_or(20),
+            ),
+            metadata_concurrency: env
+                .parsed("METADATA_CONCURRENCY")
+                .filter(|&n: &usize| n > 0)
+                .unwrap_or(4),
+            metrics_port: env.parsed("METRICS_PORT").filter(|&port: &u16| port > 0),
+            archive_dir: env
+                .var("ARCHIVE_DIR")
+                .map(|dir| dir.trim().to_string())
+                .filter(|dir| !dir.is_empty()),
+            archive_template: env
+                .var("ARCHIVE_TEMPLATE")
+                .filter(|template| !template.trim().is_empty())
+                .unwrap_or_else(|| crate::video::archive::DEFAULT_TEMPLATE.to_string()),
+            admin_ids: admin_ids(env),
+        }
+    }
+
+    /// Maximum height for a video of this length with smart quality on.
+    /// Videos longer than every step get the last step's height.
+    pub fn smart_quality_height(&self, duration_seconds: u32) -> Option<u32> {
+        self.smart_quality_steps
+            .iter()
+            .find(|step| step.max_minutes.is_none_or(|m| duration_seconds <= m * 60))
+            .or(self.smart_quality_steps.last())
+            .map(|step| step.height)
+    }
+
+    /// Pending conversion caps for a user
+    pub fn pending_conversion_limits(&self, premium: bool) -> PendingConversionLimits {
+        if premium {
+            self.premium_pending_conversion_limits
+        } else {
+            self.pending_conversion_limits
+        }
+    }
+
+    /// How many of a user's tasks may run at once
+    pub fn max_active_tasks(&self, premium: bool) -> usize {
+        if premium {
+            self.premium_max_active_tasks_per_user
+        } else {
+            self.max_active_tasks_per_user
+        }
+    }
+
+    /// Minimum time between submissions for a user
+    pub fn submit_cooldown(&self, premium: bool) -> Duration {
+        if premium {
+            self.premium_submit_cooldown
+        } else {
+            self.submit_cooldown
+        }
+    }
+}
+
+/// Get the global configuration, loading it from the environment on first access
+pub fn get() -> &'static Config {
+    if let Some(config) = *CONFIG.read().unwrap() {
+        return config;
+    }
+
+    // Another thread may have loaded it while we were waiting for the lock
+    let mut current = CONFIG.write().unwrap();
+    if let Some(config) = *current {
+        return config;
+    }
+    let config: &'static Config = Box::leak(Box::new(Config::from_env(&Env::default())));
+    *current = Some(config);
+    config
+}
+
+/// Re-read `.env` and swap in a freshly loaded configuration. The file is read into
+/// memory instead of the process environment, which can't be changed safely while
+/// other threads are running. Code that already holds the old config keeps using it
+/// until it calls `get()` again. Variables removed from `.env` fall back to their
+/// values at startup. Subscription statuses cached under the old config, with its
+/// tiers, are looked up again.
+pub fn reload() -> &'static Config {
+    let env = match Env::from_dotenv() {
+        Ok(env) => env,
+        Err(e) => {
+            log::warn!("Failed to re-read .env, using the startup environment: {}", e);
+            Env::default()
+        }
+    };
+
+    let config: &'static Config = Box::leak(Box::new(Config::from_env(&env)));
+    *CONFIG.write().unwrap() = Some(config);
+    config
+}
+
+/// Where settings are read from: the process environment, with the values of a
+/// re-read `.env` taking precedence after `/reload`
+#[derive(Default)]
+pub struct Env {
+    overrides: HashMap<String, String>,
+    /// Every variable looked up so far, to check the reload lists against
+    read: RefCell<HashSet<String>>,
+}
+
+impl Env {
+    /// The process environment with the current `.env` on top
+    fn from_dotenv() -> Result<Self, dotenvy::Error> {
+        let overrides = dotenvy::from_path_iter(".env")?.collect::<Result<_, _>>()?;
+        Ok(Self { overrides, read: Default::default() })
+    }
+
+    /// Raw value of a variable
+    fn var(&self, name: &str) -> Option<String> {
+        self.read.borrow_mut().insert(name.to_string());
+        self.overrides
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+    }
+
+    /// Parse a plain variable, falling back to `None` (with a warning) if it's malformed
+    fn parsed<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+        let raw = self.var(name)?;
+        match raw.trim().parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                log::warn!("Ignoring invalid {}: {}", name, raw);
+                None
+            }
+        }
+    }
+
+    /// Read a soft/hard limit pair; the soft limit never exceeds the hard one
+    fn limits(
+        &self,
+        soft_name: &str,
+        hard_name: &str,
+        defaults: PendingConversionLimits,
+    ) -> PendingConversionLimits {
+        let hard = self
+            .parsed(hard_name)
+            .filter(|&hard: &usize| hard > 0)
+            .unwrap_or(defaults.hard);
+        let soft = self.parsed(soft_name).unwrap_or(defaults.soft).min(hard);
+        PendingConversionLimits { soft, hard }
+    }
+
+    /// Read a non-empty list of messages, falling back to the defaults
+    fn messages(&self, name: &str, defaults: &[&str]) -> Vec<String> {
+        self.json(name)
+            .filter(|messages: &Vec<String>| !messages.is_empty())
+            .unwrap_or_else(|| defaults.iter().map(|m| m.to_string()).collect())
+    }
+
+    /// Parse a JSON variable, falling back to `None` (with a warning) if it's malformed
+    fn json<T: serde::de::DeserializeOwned>(&self, name: &str) -> Option<T> {
+        let raw = self.var(name)?;
+        match serde_json::from_str(&raw) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                log::warn!("Ignoring invalid {}: {}", name, e);
+                None
+            }
+        }
+    }
+}
+
+/// Admin user IDs: the comma-separated `ADMIN_IDS` plus the older single `ADMIN_ID`
+fn admin_ids(env: &Env) -> Vec<i64> {
+    let mut ids = Vec::new();
+    for name in ["ADMIN_IDS", "ADMIN_ID"] {
+        if let Some(list) = env.var(name) {
+            parse_user_ids(name, &list, &mut ids);
+        }
+    }
+    ids
+}
+
+/// Add the IDs of a comma-separated list to `ids`, skipping duplicates.
+/// Entries that aren't user IDs are skipped with a warning.
+fn parse_user_ids(name: &str, list: &str, ids: &mut Vec<i64>) {
+    for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        match entry.parse() {
+            Ok(id) if !ids.contains(&id) => ids.push(id),
+            Ok(_) => {}
+            Err(_) => log::warn!("Ignoring {} entry {:?}, it isn't a user ID", name, entry),
+        }
+    }
+}
+
+/// Read the hardware H.264 encoder from `FFMPEG_HWACCEL`: `nvenc`, `vaapi` (with the
+/// device from `FFMPEG_VAAPI_DEVICE`) or `qsv`; unset or unknown values encode in software
+fn hw_encoder(env: &Env) -> Option<HwEncoder> {
+    let value = env.var("FFMPEG_HWACCEL")?;
+
+    match value.trim() {
+        "" => None,
+        "nvenc" => Some(HwEncoder::Nvenc),
+        "vaapi" => Some(HwEncoder::Vaapi {
+            device: env
+                .var("FFMPEG_VAAPI_DEVICE")
+                .filter(|d| !d.trim().is_empty())
+                .unwrap_or_else(|| "/dev/dri/renderD128".to_string()),
+        }),
+        "qsv" => Some(HwEncoder::Qsv),
+        other => {
+            log::warn!("Ignoring unknown FFMPEG_HWACCEL: {}", other);
+            None
+        }
+    }
+}
+
+/// Read the transcription backend: `whisper_cpp` (local binary) or `api` (OpenAI-compatible)
+fn transcription_backend(env: &Env) -> Option<TranscriptionBackend> {
+    let var = |name: &str| env.var(name).filter(|v| !v.trim().is_empty());
+
+    match var("TRANSCRIPTION_BACKEND")?.trim() {
+        "whisper_cpp" => {
+            let Some(model) = var("WHISPER_CPP_MODEL") else {
+                log::warn!("TRANSCRIPTION_BACKEND=whisper_cpp requires WHISPER_CPP_MODEL, transcripts disabled");
+                return None;
+            };
+            Some(TranscriptionBackend::WhisperCpp {
+                binary: var("WHISPER_CPP_BINARY").unwrap_or_else(|| "whisper-cli".to_string()),
+                model,
+            })
+        }
+        "api" => {
+            let Some(api_key) = var("TRANSCRIPTION_API_KEY") else {
+                log::warn!("TRANSCRIPTION_BACKEND=api requires TRANSCRIPTION_API_KEY, transcripts disabled");
+                return None;
+            };
+            Some(TranscriptionBackend::Api {
+                url: var("TRANSCRIPTION_API_URL")
+                    .unwrap_or_else(|| "https://api.openai.com/v1/audio/transcriptions".to_string()),
+                api_key,
+                model: var("TRANSCRIPTION_API_MODEL").unwrap_or_else(|| "whisper-1".to_string()),
+            })
+        }
+        other => {
+            log::warn!("Ignoring unknown TRANSCRIPTION_BACKEND: {}", other);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Env {
+        let mut overrides: HashMap<String, String> =
+            [("ADMIN_IDS", ""), ("ADMIN_ID", "")].map(|(k, v)| (k.to_string(), v.to_string())).into();
+        overrides.extend(vars.iter().map(|&(k, v)| (k.to_string(), v.to_string())));
+        Env { overrides, ..Default::default() }
+    }
+
+    /// Whether `name` matches a reload list entry, where `*` stands for any run of characters
+    fn matches(pattern: &str, name: &str) -> bool {
+        let mut parts = pattern.split('*');
+        let first = parts.next().unwrap_or_default();
+        let Some(mut rest) = name.strip_prefix(first) else {
+            return false;
+        };
+        let mut parts: Vec<&str> = parts.collect();
+        let Some(last) = parts.pop() else {
+            return rest.is_empty();
+        };
+        for part in parts {
+            match rest.find(part) {
+                Some(at) => rest = &rest[at + part.len()..],
+                None => return false,
+            }
+        }
+        rest.ends_with(last)
+    }
+
+    #[test]
+    fn reload_list_patterns() {
+        assert!(matches("ADMIN_ID", "ADMIN_ID"));
+        assert!(!matches("ADMIN_ID", "ADMIN_IDS"));
+        assert!(matches("TRANSCRIPTION_*", "TRANSCRIPTION_API_KEY"));
+        assert!(matches("*PENDING_CONVERSIONS_*_LIMIT", "PENDING_CONVERSIONS_SOFT_LIMIT"));
+        assert!(matches("*PENDING_CONVERSIONS_*_LIMIT", "PREMIUM_PENDING_CONVERSIONS_HARD_LIMIT"));
+        assert!(!matches("*PENDING_CONVERSIONS_*_LIMIT", "PENDING_CONVERSIONS_LIMITS"));
+        assert!(!matches("LOADING_*_MESSAGES", "LOADING_MESSAGES"));
+    }
+
+    #[test]
+    fn every_variable_is_in_exactly_one_reload_list() {
+        // Some variables are only read for particular values of others
+        let envs = [
+            env(&[]),
+            env(&[("TRANSCRIPTION_BACKEND", "whisper_cpp"), ("WHISPER_CPP_MODEL", "model.bin")]),
+            env(&[("TRANSCRIPTION_BACKEND", "api"), ("TRANSCRIPTION_API_KEY", "key")]),
+            env(&[("FFMPEG_HWACCEL", "vaapi")]),
+        ];
+        let mut names = std::collections::BTreeSet::new();
+        for env in &envs {
+            Config::from_env(env);
+            names.extend(env.read.borrow().iter().cloned());
+        }
+
+        for name in names {
+            let lists = [HOT_RELOADABLE, RESTART_REQUIRED]
+                .iter()
+                .filter(|list| list.iter().any(|pattern| matches(pattern, &name)))
+                .count();
+            assert_eq!(lists, 1, "{} is in {} reload lists", name, lists);
+        }
+    }
+
+    fn parse(list: &str) -> Vec<i64> {
+        let mut ids = Vec::new();
+        parse_user_ids("ADMIN_IDS", list, &mut ids);
+        ids
+    }
+
+    #[test]
+    fn user_id_lists_allow_whitespace() {
+        assert_eq!(parse("1"), vec![1]);
+        assert_eq!(parse("1,2,3"), vec![1, 2, 3]);
+        assert_eq!(parse(" 1 ,\t2\n, 3 "), vec![1, 2, 3]);
+        assert_eq!(parse("1,,2,"), vec![1, 2]);
+        assert_eq!(parse(""), Vec::<i64>::new());
+        assert_eq!(parse(" , "), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn malformed_user_ids_are_skipped() {
+        assert_eq!(parse("1,abc,2"), vec![1, 2]);
+        assert_eq!(parse("@admin, 12 34, 5"), vec![5]);
+        assert_eq!(parse("1;2, 3"), vec![3]);
+        assert_eq!(parse("99999999999999999999, 7"), vec![7]);
+        assert_eq!(parse("1.5, 0x10, 8"), vec![8]);
+    }
+
+    #[test]
+    fn duplicate_user_ids_are_kept_once() {
+        assert_eq!(parse("1,2,1,2,3"), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn single_admin_id_still_works() {
+        assert_eq!(admin_ids(&env(&[("ADMIN_ID", "42")])), vec![42]);
+        assert_eq!(admin_ids(&env(&[("ADMIN_IDS", "1, 2")])), vec![1, 2]);
+        assert_eq!(admin_ids(&env(&[("ADMIN_IDS", "1, 2"), ("ADMIN_ID", "2, 3")])), vec![1, 2, 3]);
+        assert_eq!(admin_ids(&env(&[])), Vec::<i64>::new());
+    }
+}
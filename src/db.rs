@@ -6,8 +6,13 @@ use std::sync::Arc;
 use chrono::Utc;
 use sqlx::{Row, SqlitePool};
 
+use crate::settings::{CustomFilters, SendOptions};
+
 /// TTL for pending tasks in seconds (24 hours)
-const TASK_TTL_SECONDS: i64 = 24 * 60 * 60;
+pub const TASK_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+/// Completed downloads kept per chat for /history, older ones are dropped
+const HISTORY_LIMIT: i64 = 100;
 
 /// Raw pending download row from database
 #[derive(Debug, Clone)]
@@ -17,6 +22,7 @@ pub struct PendingDownloadRow {
     pub chat_id: i64,
     pub message_id: i32,
     pub format: Option<crate::utils::MediaFormatType>,
+    pub options: SendOptions,
 }
 
 /// Raw pending conversion row from database
@@ -27,6 +33,14 @@ pub struct PendingConversionRow {
     pub thumbnail_path: Option<String>,
     pub chat_id: i64,
     pub message_id: i32,
+    pub created_at: i64,
+}
+
+impl PendingConversionRow {
+    /// When the file is dropped if no format is picked, Unix timestamp
+    pub fn expires_at(&self) -> i64 {
+        self.created_at + TASK_TTL_SECONDS
+    }
 }
 
 /// Raw task row from database
@@ -36,6 +50,8 @@ pub struct TaskRow {
     pub task_type: String,
     pub chat_id: i64,
     pub message_id: i32,
+    /// The user's message the result is sent in reply to
+    pub reply_to_message_id: Option<i32>,
     pub unique_file_id: String,
     pub status: String,
     pub url: Option<String>,
@@ -43,6 +59,35 @@ pub struct TaskRow {
     pub filename: Option<String>,
     pub thumbnail_path: Option<String>,
     pub format: Option<String>,
+    pub audio_language: Option<String>,
+    pub format_string: Option<String>,
+    pub filename_template: Option<String>,
+    /// Largest download in bytes, picked instead of a quality
+    pub size_budget: Option<i64>,
+    pub filters: CustomFilters,
+    /// Chat or channel the result is also posted to
+    pub target_chat_id: Option<i64>,
+    pub options: SendOptions,
+    pub retries: u32,
+    /// Failure category, set once the task failed
+    pub error_kind: Option<String>,
+}
+
+/// Raw scheduled message deletion row from database
+#[derive(Debug, Clone)]
+pub struct ScheduledDeletionRow {
+    pub id: i64,
+    pub chat_id: i64,
+    pub message_id: i32,
+}
+
+/// Raw download history row from database
+#[derive(Debug, Clone)]
+pub struct HistoryRow {
+    pub title: String,
+    pub format: String,
+    pub url: Option<String>,
+    pub created_at: i64,
 }
 
 /// Database operations for task queue persistence
@@ -65,17 +110,27 @@ impl TaskDb {
         chat_id: i64,
         message_id: i32,
         format: Option<&str>,
+        options: SendOptions,
     ) -> Result<(), String> {
         let now = Utc::now().timestamp();
 
         sqlx::query(
-            "INSERT INTO pending_downloads (short_id, url, chat_id, message_id, format, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+            "INSERT INTO pending_downloads (short_id, url, chat_id, message_id, format, spoiler, protect_content, auto_delete_minutes, subtitles, chapters, sponsorblock, mute, audio_fallback, trim_silence, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(short_id)
         .bind(url)
         .bind(chat_id)
         .bind(message_id)
         .bind(format)
+        .bind(options.spoiler)
+        .bind(options.protect_content)
+        .bind(options.auto_delete_minutes)
+        .bind(options.subtitles)
+        .bind(options.chapters)
+        .bind(options.sponsorblock)
+        .bind(options.mute)
+        .bind(options.audio_fallback)
+        .bind(options.trim_silence)
         .bind(now)
         .execute(self.pool.as_ref())
         .await
@@ -105,6 +160,36 @@ impl TaskDb {
         Ok(())
     }
 
+    pub async fn update_pending_download_message(&self, short_id: &str, message_id: i32) -> Result<(), String> {
+        sqlx::query("UPDATE pending_downloads SET message_id = ? WHERE short_id = ?")
+            .bind(message_id)
+            .bind(short_id)
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| format!("Failed to update pending download message: {}", e))?;
+
+        Ok(())
+    }
+
+    pub async fn update_pending_download_options(&self, short_id: &str, options: SendOptions) -> Result<(), String> {
+        sqlx::query("UPDATE pending_downloads SET spoiler = ?, protect_content = ?, auto_delete_minutes = ?, subtitles = ?, chapters = ?, sponsorblock = ?, mute = ?, audio_fallback = ?, trim_silence = ? WHERE short_id = ?")
+            .bind(options.spoiler)
+            .bind(options.protect_content)
+            .bind(options.auto_delete_minutes)
+            .bind(options.subtitles)
+            .bind(options.chapters)
+            .bind(options.sponsorblock)
+            .bind(options.mute)
+            .bind(options.audio_fallback)
+            .bind(options.trim_silence)
+            .bind(short_id)
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| format!("Failed to update pending download options: {}", e))?;
+
+        Ok(())
+    }
+
     pub async fn get_all_pending_downloads(&self) -> Result<Vec<PendingDownloadRow>, String> {
         use std::str::FromStr;
         use crate::utils::MediaFormatType;
@@ -112,7 +197,7 @@ impl TaskDb {
         let cutoff = Utc::now().timestamp() - TASK_TTL_SECONDS;
 
         let rows = sqlx::query(
-            "SELECT short_id, url, chat_id, message_id, format FROM pending_downloads WHERE created_at > ?",
+            "SELECT short_id, url, chat_id, message_id, format, spoiler, protect_content, auto_delete_minutes, subtitles, chapters, sponsorblock, mute, audio_fallback, trim_silence FROM pending_downloads WHERE created_at > ?",
         )
         .bind(cutoff)
         .fetch_all(self.pool.as_ref())
@@ -130,11 +215,37 @@ impl TaskDb {
                     chat_id: row.get("chat_id"),
                     message_id: row.get("message_id"),
                     format,
+                    options: SendOptions {
+                        spoiler: row.get("spoiler"),
+                        protect_content: row.get("protect_content"),
+                        auto_delete_minutes: row.get("auto_delete_minutes"),
+                        subtitles: row.get("subtitles"),
+                        chapters: row.get("chapters"),
+                        sponsorblock: row.get("sponsorblock"),
+                        mute: row.get("mute"),
+                        audio_fallback: row.get("audio_fallback"),
+                        trim_silence: row.get("trim_silence"),
+                    },
                 }
             })
             .collect())
     }
 
+    /// Short ID of the newest unexpired pending download of `url` in a chat
+    pub async fn find_pending_download(&self, chat_id: i64, url: &str) -> Result<Option<String>, String> {
+        let cutoff = Utc::now().timestamp() - TASK_TTL_SECONDS;
+
+        sqlx::query_scalar(
+            "SELECT short_id FROM pending_downloads WHERE chat_id = ? AND url = ? AND created_at > ? ORDER BY created_at DESC LIMIT 1",
+        )
+        .bind(chat_id)
+        .bind(url)
+        .bind(cutoff)
+        .fetch_optional(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to find pending download: {}", e))
+    }
+
     pub async fn delete_expired_pending_downloads(&self) -> Result<usize, String> {
         let cutoff = Utc::now().timestamp() - TASK_TTL_SECONDS;
 
@@ -189,23 +300,77 @@ impl TaskDb {
         let cutoff = Utc::now().timestamp() - TASK_TTL_SECONDS;
 
         let rows = sqlx::query(
-            "SELECT short_id, filename, thumbnail_path, chat_id, message_id FROM pending_conversions WHERE created_at > ?",
+            "SELECT short_id, filename, thumbnail_path, chat_id, message_id, created_at FROM pending_conversions WHERE created_at > ?",
         )
         .bind(cutoff)
         .fetch_all(self.pool.as_ref())
         .await
         .map_err(|e| format!("Failed to load pending conversions: {}", e))?;
 
-        Ok(rows
-            .iter()
-            .map(|row| PendingConversionRow {
-                short_id: row.get("short_id"),
-                filename: row.get("filename"),
-                thumbnail_path: row.get("thumbnail_path"),
-                chat_id: row.get("chat_id"),
-                message_id: row.get("message_id"),
-            })
-            .collect())
+        Ok(rows.iter().map(pending_conversion_row).collect())
+    }
+
+    /// Unexpired pending conversions of a chat, oldest first
+    pub async fn get_pending_conversions_for_chat(&self, chat_id: i64) -> Result<Vec<PendingConversionRow>, String> {
+        let cutoff = Utc::now().timestamp() - TASK_TTL_SECONDS;
+
+        let rows = sqlx::query(
+            "SELECT short_id, filename, thumbnail_path, chat_id, message_id, created_at FROM pending_conversions WHERE chat_id = ? AND created_at > ? ORDER BY created_at",
+        )
+        .bind(chat_id)
+        .bind(cutoff)
+        .fetch_all(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to load pending conversions of chat: {}", e))?;
+
+        Ok(rows.iter().map(pending_conversion_row).collect())
+    }
+
+    /// Count unexpired pending conversions of a chat
+    pub async fn count_pending_conversions(&self, chat_id: i64) -> Result<usize, String> {
+        let cutoff = Utc::now().timestamp() - TASK_TTL_SECONDS;
+
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pending_conversions WHERE chat_id = ? AND created_at > ?",
+        )
+        .bind(chat_id)
+        .bind(cutoff)
+        .fetch_one(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to count pending conversions: {}", e))?;
+
+        Ok(count as usize)
+    }
+
+    /// Get pending conversions that expire within `lead_seconds` and weren't reminded about yet
+    pub async fn get_pending_conversions_to_remind(
+        &self,
+        lead_seconds: i64,
+    ) -> Result<Vec<PendingConversionRow>, String> {
+        let now = Utc::now().timestamp();
+        let expired_cutoff = now - TASK_TTL_SECONDS;
+        let remind_cutoff = now - (TASK_TTL_SECONDS - lead_seconds);
+
+        let rows = sqlx::query(
+            "SELECT short_id, filename, thumbnail_path, chat_id, message_id, created_at FROM pending_conversions WHERE reminded = 0 AND created_at > ? AND created_at <= ?",
+        )
+        .bind(expired_cutoff)
+        .bind(remind_cutoff)
+        .fetch_all(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to load pending conversions to remind: {}", e))?;
+
+        Ok(rows.iter().map(pending_conversion_row).collect())
+    }
+
+    pub async fn mark_pending_conversion_reminded(&self, short_id: &str) -> Result<(), String> {
+        sqlx::query("UPDATE pending_conversions SET reminded = 1 WHERE short_id = ?")
+            .bind(short_id)
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| format!("Failed to mark pending conversion reminded: {}", e))?;
+
+        Ok(())
     }
 
     /// Returns filenames of expired conversions for cleanup
@@ -244,12 +409,14 @@ impl TaskDb {
 
     // ==================== Tasks ====================
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn insert_task(
         &self,
         id: &str,
         task_type: &str,
         chat_id: i64,
         message_id: i32,
+        reply_to_message_id: Option<i32>,
         unique_file_id: &str,
         status: &str,
         url: Option<&str>,
@@ -257,13 +424,23 @@ impl TaskDb {
         filename: Option<&str>,
         thumbnail_path: Option<&str>,
         format: Option<&str>,
+        audio_language: Option<&str>,
+        format_string: Option<&str>,
+        filename_template: Option<&str>,
+        size_budget: Option<i64>,
+        filters: &CustomFilters,
+        target_chat_id: Option<i64>,
+        options: SendOptions,
+        retries: u32,
     ) -> Result<(), String> {
         let now = Utc::now().timestamp();
 
         sqlx::query(
             r#"
-            INSERT INTO tasks (id, task_type, chat_id, message_id, unique_file_id, status, url, quality, filename, thumbnail_path, format, created_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO tasks (id, task_type, chat_id, message_id, reply_to_message_id, unique_file_id, status, url, quality, filename,
+                               thumbnail_path, format, audio_language, format_string, filename_template, size_budget, video_filter, audio_filter,
+                               target_chat_id, spoiler, protect_content, auto_delete_minutes, subtitles, chapters, sponsorblock, mute, audio_fallback, trim_silence, retries, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT(id) DO UPDATE SET status = excluded.status
             "#,
         )
@@ -271,6 +448,7 @@ impl TaskDb {
         .bind(task_type)
         .bind(chat_id)
         .bind(message_id)
+        .bind(reply_to_message_id)
         .bind(unique_file_id)
         .bind(status)
         .bind(url)
@@ -278,6 +456,23 @@ impl TaskDb {
         .bind(filename)
         .bind(thumbnail_path)
         .bind(format)
+        .bind(audio_language)
+        .bind(format_string)
+        .bind(filename_template)
+        .bind(size_budget)
+        .bind(&filters.video)
+        .bind(&filters.audio)
+        .bind(target_chat_id)
+        .bind(options.spoiler)
+        .bind(options.protect_content)
+        .bind(options.auto_delete_minutes)
+        .bind(options.subtitles)
+        .bind(options.chapters)
+        .bind(options.sponsorblock)
+        .bind(options.mute)
+        .bind(options.audio_fallback)
+        .bind(options.trim_silence)
+        .bind(retries)
         .bind(now)
         .execute(self.pool.as_ref())
         .await
@@ -297,6 +492,18 @@ impl TaskDb {
         Ok(())
     }
 
+    /// Mark a task as failed and record the failure category
+    pub async fn mark_task_failed(&self, task_id: &str, error_kind: &str) -> Result<(), String> {
+        sqlx::query("UPDATE tasks SET status = 'failed', error_kind = ? WHERE id = ?")
+            .bind(error_kind)
+            .bind(task_id)
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| format!("Failed to mark task failed: {}", e))?;
+
+        Ok(())
+    }
+
     pub async fn delete_task(&self, task_id: &str) -> Result<(), String> {
         sqlx::query("DELETE FROM tasks WHERE id = ?")
             .bind(task_id)
@@ -312,7 +519,9 @@ impl TaskDb {
 
         let rows = sqlx::query(
             r#"
-            SELECT id, task_type, chat_id, message_id, unique_file_id, status, url, quality, filename, thumbnail_path, format
+            SELECT id, task_type, chat_id, message_id, reply_to_message_id, unique_file_id, status, url, quality, filename,
+                   thumbnail_path, format, audio_language, format_string, filename_template, video_filter, audio_filter, target_chat_id, spoiler, protect_content,
+                   auto_delete_minutes, subtitles, chapters, sponsorblock, mute, audio_fallback, trim_silence, size_budget, retries, error_kind
             FROM tasks
             WHERE created_at > ?
             "#,
@@ -322,22 +531,79 @@ impl TaskDb {
         .await
         .map_err(|e| format!("Failed to load tasks: {}", e))?;
 
-        Ok(rows
-            .iter()
-            .map(|row| TaskRow {
-                id: row.get("id"),
-                task_type: row.get("task_type"),
-                chat_id: row.get("chat_id"),
-                message_id: row.get("message_id"),
-                unique_file_id: row.get("unique_file_id"),
-                status: row.get("status"),
-                url: row.get("url"),
-                quality: row.get("quality"),
-                filename: row.get("filename"),
-                thumbnail_path: row.get("thumbnail_path"),
-                format: row.get("format"),
-            })
-            .collect())
+        Ok(rows.iter().map(task_row).collect())
+    }
+
+    /// Whether the chat already has the same download queued or running
+    pub async fn has_active_download(
+        &self,
+        chat_id: i64,
+        url: &str,
+        quality: Option<i32>,
+        format: &str,
+    ) -> Result<bool, String> {
+        let row = sqlx::query(
+            r#"
+            SELECT 1 FROM tasks
+            WHERE chat_id = ? AND task_type = 'download' AND status IN ('queued', 'processing')
+              AND url = ? AND quality IS ? AND format = ?
+            LIMIT 1
+            "#,
+        )
+        .bind(chat_id)
+        .bind(url)
+        .bind(quality)
+        .bind(format)
+        .fetch_optional(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to check for duplicate task: {}", e))?;
+
+        Ok(row.is_some())
+    }
+
+    /// A failed task that hasn't expired yet
+    pub async fn get_failed_task(&self, task_id: &str) -> Result<Option<TaskRow>, String> {
+        let cutoff = Utc::now().timestamp() - TASK_TTL_SECONDS;
+
+        let row = sqlx::query(
+            r#"
+            SELECT id, task_type, chat_id, message_id, reply_to_message_id, unique_file_id, status, url, quality, filename,
+                   thumbnail_path, format, audio_language, format_string, filename_template, video_filter, audio_filter, target_chat_id, spoiler, protect_content,
+                   auto_delete_minutes, subtitles, chapters, sponsorblock, mute, audio_fallback, trim_silence, size_budget, retries, error_kind
+            FROM tasks
+            WHERE id = ? AND status = 'failed' AND created_at > ?
+            "#,
+        )
+        .bind(task_id)
+        .bind(cutoff)
+        .fetch_optional(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to load failed task: {}", e))?;
+
+        Ok(row.as_ref().map(task_row))
+    }
+
+    /// Failed tasks of a chat that haven't expired yet, newest first
+    pub async fn get_failed_tasks(&self, chat_id: i64) -> Result<Vec<TaskRow>, String> {
+        let cutoff = Utc::now().timestamp() - TASK_TTL_SECONDS;
+
+        let rows = sqlx::query(
+            r#"
+            SELECT id, task_type, chat_id, message_id, reply_to_message_id, unique_file_id, status, url, quality, filename,
+                   thumbnail_path, format, audio_language, format_string, filename_template, video_filter, audio_filter, target_chat_id, spoiler, protect_content,
+                   auto_delete_minutes, subtitles, chapters, sponsorblock, mute, audio_fallback, trim_silence, size_budget, retries, error_kind
+            FROM tasks
+            WHERE chat_id = ? AND status = 'failed' AND created_at > ?
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(chat_id)
+        .bind(cutoff)
+        .fetch_all(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to load failed tasks: {}", e))?;
+
+        Ok(rows.iter().map(task_row).collect())
     }
 
     /// Returns filenames of expired tasks for cleanup
@@ -374,6 +640,53 @@ impl TaskDb {
         Ok(files)
     }
 
+    /// Delete the chat's failed tasks. Returns how many there were and the files kept
+    /// for their retry buttons, except those a queued or running task is working on again.
+    /// Both happen in one transaction, so a task failing in between is either counted
+    /// with its files or left alone.
+    pub async fn delete_failed_tasks(&self, chat_id: i64) -> Result<(u64, Vec<String>), String> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+        let rows = sqlx::query(
+            r#"
+            SELECT filename, thumbnail_path FROM tasks
+            WHERE chat_id = ? AND status = 'failed' AND task_type = 'convert'
+              AND filename NOT IN (SELECT filename FROM tasks WHERE status != 'failed' AND filename IS NOT NULL)
+            "#,
+        )
+        .bind(chat_id)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to get failed tasks of chat: {}", e))?;
+
+        let mut files: Vec<String> = rows
+            .iter()
+            .filter_map(|row| row.get::<Option<String>, _>("filename"))
+            .collect();
+
+        for row in &rows {
+            if let Some(thumb) = row.get::<Option<String>, _>("thumbnail_path") {
+                files.push(thumb);
+            }
+        }
+
+        let result = sqlx::query("DELETE FROM tasks WHERE chat_id = ? AND status = 'failed'")
+            .bind(chat_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| format!("Failed to delete failed tasks of chat: {}", e))?;
+
+        tx.commit()
+            .await
+            .map_err(|e| format!("Failed to commit deleting failed tasks: {}", e))?;
+
+        Ok((result.rows_affected(), files))
+    }
+
     /// Get all filenames currently in use (to prevent deletion)
     pub async fn get_active_filenames(&self) -> Result<Vec<String>, String> {
         let mut filenames = Vec::new();
@@ -409,4 +722,276 @@ impl TaskDb {
 
         Ok(filenames)
     }
+
+    // ==================== Bot Flags ====================
+
+    pub async fn get_flag(&self, name: &str) -> Result<bool, String> {
+        let enabled: Option<bool> = sqlx::query_scalar("SELECT enabled FROM bot_flags WHERE name = ?")
+            .bind(name)
+            .fetch_optional(self.pool.as_ref())
+            .await
+            .map_err(|e| format!("Failed to load flag {}: {}", name, e))?;
+
+        Ok(enabled.unwrap_or(false))
+    }
+
+    pub async fn set_flag(&self, name: &str, enabled: bool) -> Result<(), String> {
+        sqlx::query(
+            "INSERT INTO bot_flags (name, enabled) VALUES (?, ?) ON CONFLICT(name) DO UPDATE SET enabled = excluded.enabled",
+        )
+        .bind(name)
+        .bind(enabled)
+        .execute(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to save flag {}: {}", name, e))?;
+
+        Ok(())
+    }
+
+    // ==================== Premium Upsell ====================
+
+    /// Count a completed download towards the Premium offer.
+    /// Returns downloads since the last offer and when it was shown (unix seconds).
+    pub async fn record_upsell_download(&self, chat_id: i64) -> Result<(i64, Option<i64>), String> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO upsell_state (chat_id, downloads) VALUES (?, 1)
+            ON CONFLICT(chat_id) DO UPDATE SET downloads = downloads + 1
+            RETURNING downloads, last_shown_at
+            "#,
+        )
+        .bind(chat_id)
+        .fetch_one(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to record upsell download: {}", e))?;
+
+        Ok((row.get("downloads"), row.get("last_shown_at")))
+    }
+
+    /// Remember that the Premium offer was shown and start counting anew
+    pub async fn mark_upsell_shown(&self, chat_id: i64) -> Result<(), String> {
+        sqlx::query("UPDATE upsell_state SET downloads = 0, last_shown_at = ? WHERE chat_id = ?")
+            .bind(Utc::now().timestamp())
+            .bind(chat_id)
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| format!("Failed to mark upsell shown: {}", e))?;
+
+        Ok(())
+    }
+
+    // ==================== Scheduled Deletions ====================
+
+    pub async fn insert_scheduled_deletion(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        delete_at: i64,
+    ) -> Result<(), String> {
+        sqlx::query(
+            "INSERT INTO scheduled_deletions (chat_id, message_id, delete_at) VALUES (?, ?, ?)",
+        )
+        .bind(chat_id)
+        .bind(message_id)
+        .bind(delete_at)
+        .execute(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to insert scheduled deletion: {}", e))?;
+
+        Ok(())
+    }
+
+    /// Get deletions that are due now
+    pub async fn get_due_deletions(&self) -> Result<Vec<ScheduledDeletionRow>, String> {
+        let now = Utc::now().timestamp();
+
+        let rows = sqlx::query(
+            "SELECT id, chat_id, message_id FROM scheduled_deletions WHERE delete_at <= ? ORDER BY delete_at",
+        )
+        .bind(now)
+        .fetch_all(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to load scheduled deletions: {}", e))?;
+
+        Ok(rows
+            .iter()
+            .map(|row| ScheduledDeletionRow {
+                id: row.get("id"),
+                chat_id: row.get("chat_id"),
+                message_id: row.get("message_id"),
+            })
+            .collect())
+    }
+
+    pub async fn delete_scheduled_deletion(&self, id: i64) -> Result<(), String> {
+        sqlx::query("DELETE FROM scheduled_deletions WHERE id = ?")
+            .bind(id)
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| format!("Failed to delete scheduled deletion: {}", e))?;
+
+        Ok(())
+    }
+
+    // ==================== Download History ====================
+
+    /// Record a completed task, keeping only the chat's latest HISTORY_LIMIT entries
+    pub async fn insert_history(
+        &self,
+        chat_id: i64,
+        title: &str,
+        format: &str,
+        url: Option<&str>,
+    ) -> Result<(), String> {
+        sqlx::query(
+            "INSERT INTO download_history (chat_id, title, format, url, created_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(chat_id)
+        .bind(title)
+        .bind(format)
+        .bind(url)
+        .bind(Utc::now().timestamp())
+        .execute(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to insert history entry: {}", e))?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM download_history WHERE chat_id = ? AND id NOT IN (
+                SELECT id FROM download_history WHERE chat_id = ? ORDER BY id DESC LIMIT ?
+            )
+            "#,
+        )
+        .bind(chat_id)
+        .bind(chat_id)
+        .bind(HISTORY_LIMIT)
+        .execute(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to trim history: {}", e))?;
+
+        Ok(())
+    }
+
+    pub async fn count_history(&self, chat_id: i64) -> Result<i64, String> {
+        let row = sqlx::query("SELECT COUNT(*) as count FROM download_history WHERE chat_id = ?")
+            .bind(chat_id)
+            .fetch_one(self.pool.as_ref())
+            .await
+            .map_err(|e| format!("Failed to count history: {}", e))?;
+
+        Ok(row.get("count"))
+    }
+
+    /// Get a page of the chat's history, newest first
+    pub async fn get_history(&self, chat_id: i64, limit: i64, offset: i64) -> Result<Vec<HistoryRow>, String> {
+        let rows = sqlx::query(
+            "SELECT title, format, url, created_at FROM download_history WHERE chat_id = ? ORDER BY id DESC LIMIT ? OFFSET ?",
+        )
+        .bind(chat_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to load history: {}", e))?;
+
+        Ok(rows
+            .iter()
+            .map(|row| HistoryRow {
+                title: row.get("title"),
+                format: row.get("format"),
+                url: row.get("url"),
+                created_at: row.get("created_at"),
+            })
+            .collect())
+    }
+
+    /// Delete the chat's whole history. Returns the number of removed entries.
+    pub async fn clear_history(&self, chat_id: i64) -> Result<u64, String> {
+        let result = sqlx::query("DELETE FROM download_history WHERE chat_id = ?")
+            .bind(chat_id)
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| format!("Failed to clear history: {}", e))?;
+
+        Ok(result.rows_affected())
+    }
+
+    // ==================== Maintenance ====================
+
+    /// Size of the database file in bytes
+    pub async fn database_size(&self) -> Result<i64, String> {
+        let row = sqlx::query(
+            "SELECT page_count * page_size AS size FROM pragma_page_count(), pragma_page_size()",
+        )
+        .fetch_one(self.pool.as_ref())
+        .await
+        .map_err(|e| format!("Failed to get database size: {}", e))?;
+
+        Ok(row.get("size"))
+    }
+
+    /// Reclaim the space of deleted rows and refresh the query planner statistics
+    pub async fn compact(&self) -> Result<(), String> {
+        sqlx::query("VACUUM")
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| format!("Failed to vacuum database: {}", e))?;
+
+        sqlx::query("PRAGMA optimize")
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| format!("Failed to optimize database: {}", e))?;
+
+        Ok(())
+    }
+}
+
+fn pending_conversion_row(row: &sqlx::sqlite::SqliteRow) -> PendingConversionRow {
+    PendingConversionRow {
+        short_id: row.get("short_id"),
+        filename: row.get("filename"),
+        thumbnail_path: row.get("thumbnail_path"),
+        chat_id: row.get("chat_id"),
+        message_id: row.get("message_id"),
+        created_at: row.get("created_at"),
+    }
+}
+
+fn task_row(row: &sqlx::sqlite::SqliteRow) -> TaskRow {
+    TaskRow {
+        id: row.get("id"),
+        task_type: row.get("task_type"),
+        chat_id: row.get("chat_id"),
+        message_id: row.get("message_id"),
+        reply_to_message_id: row.get("reply_to_message_id"),
+        unique_file_id: row.get("unique_file_id"),
+        status: row.get("status"),
+        url: row.get("url"),
+        quality: row.get("quality"),
+        filename: row.get("filename"),
+        thumbnail_path: row.get("thumbnail_path"),
+        format: row.get("format"),
+        audio_language: row.get("audio_language"),
+        format_string: row.get("format_string"),
+        filename_template: row.get("filename_template"),
+        size_budget: row.get("size_budget"),
+        filters: CustomFilters {
+            video: row.get("video_filter"),
+            audio: row.get("audio_filter"),
+        },
+        target_chat_id: row.get("target_chat_id"),
+        options: SendOptions {
+            spoiler: row.get("spoiler"),
+            protect_content: row.get("protect_content"),
+            auto_delete_minutes: row.get("auto_delete_minutes"),
+            subtitles: row.get("subtitles"),
+            chapters: row.get("chapters"),
+            sponsorblock: row.get("sponsorblock"),
+            mute: row.get("mute"),
+            audio_fallback: row.get("audio_fallback"),
+            trim_silence: row.get("trim_silence"),
+        },
+        retries: row.get("retries"),
+        error_kind: row.get("error_kind"),
+    }
 }
@@ -0,0 +1,110 @@
+//! Dialogue storage backed by the bot's SQLite database.
+//!
+//! Replaces `InMemStorage` so dialogues (e.g. a link waiting for its password) survive
+//! restarts the same way pending downloads and conversions do.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use chrono::Utc;
+use sqlx::{Row, SqlitePool};
+use teloxide::{dispatching::dialogue::Storage, types::ChatId};
+
+use crate::errors::BotError;
+use crate::schema::State;
+
+type StorageFuture<T> = Pin<Box<dyn Future<Output = Result<T, BotError>> + Send>>;
+
+/// Dialogues not touched for this long are dropped on startup (same TTL as queued tasks)
+const DIALOGUE_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+pub struct DialogueStorage {
+    pool: Arc<SqlitePool>,
+}
+
+impl DialogueStorage {
+    pub fn new(pool: Arc<SqlitePool>) -> Arc<Self> {
+        Arc::new(Self { pool })
+    }
+
+    /// Remove stale dialogues. Their uploaded files are no longer referenced
+    /// and get removed by the orphaned files cleanup.
+    pub async fn delete_expired(&self) -> Result<u64, String> {
+        let cutoff = Utc::now().timestamp() - DIALOGUE_TTL_SECONDS;
+
+        sqlx::query("DELETE FROM dialogues WHERE updated_at <= ?")
+            .bind(cutoff)
+            .execute(self.pool.as_ref())
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(|e| format!("Failed to cleanup expired dialogues: {}", e))
+    }
+}
+
+impl Storage<State> for DialogueStorage {
+    type Error = BotError;
+
+    fn remove_dialogue(self: Arc<Self>, chat_id: ChatId) -> StorageFuture<()> {
+        Box::pin(async move {
+            sqlx::query("DELETE FROM dialogues WHERE chat_id = ?")
+                .bind(chat_id.0)
+                .execute(self.pool.as_ref())
+                .await
+                .map_err(|e| BotError::general(format!("Failed to remove dialogue: {}", e)))?;
+
+            Ok(())
+        })
+    }
+
+    fn update_dialogue(self: Arc<Self>, chat_id: ChatId, dialogue: State) -> StorageFuture<()> {
+        Box::pin(async move {
+            // The initial state is the default, no need to store it
+            if matches!(dialogue, State::Start) {
+                return self.remove_dialogue(chat_id).await;
+            }
+
+            let state = serde_json::to_string(&dialogue)?;
+
+            sqlx::query(
+                r#"
+                INSERT INTO dialogues (chat_id, state, updated_at) VALUES (?, ?, ?)
+                ON CONFLICT(chat_id) DO UPDATE SET state = excluded.state, updated_at = excluded.updated_at
+                "#,
+            )
+            .bind(chat_id.0)
+            .bind(state)
+            .bind(Utc::now().timestamp())
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| BotError::general(format!("Failed to save dialogue: {}", e)))?;
+
+            Ok(())
+        })
+    }
+
+    fn get_dialogue(self: Arc<Self>, chat_id: ChatId) -> StorageFuture<Option<State>> {
+        Box::pin(async move {
+            let row = sqlx::query("SELECT state FROM dialogues WHERE chat_id = ?")
+                .bind(chat_id.0)
+                .fetch_optional(self.pool.as_ref())
+                .await
+                .map_err(|e| BotError::general(format!("Failed to load dialogue: {}", e)))?;
+
+            match row {
+                Some(row) => {
+                    let state: String = row.get("state");
+                    match serde_json::from_str(&state) {
+                        Ok(state) => Ok(Some(state)),
+                        Err(e) => {
+                            // Unknown state from an older version - start over
+                            log::warn!("Dropping unreadable dialogue for chat {}: {}", chat_id, e);
+                            Ok(None)
+                        }
+                    }
+                }
+                None => Ok(None),
+            }
+        })
+    }
+}
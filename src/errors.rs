@@ -24,6 +24,8 @@ pub enum BotError {
     },
     // Ошибка памяти бота
     StorageError(InMemStorageError),
+    /// Операция не уложилась в отведённое время
+    Timeout(String),
     /// Общая ошибка с описанием
     General(String),
 }
@@ -49,6 +51,7 @@ impl fmt::Display for BotError {
             }
             BotError::General(msg) => write!(f, "{}", msg),
             BotError::StorageError(e) => write!(f, "Ошибка памяти бота: {}", e),
+            BotError::Timeout(what) => write!(f, "Превышено время ожидания: {}", what),
         }
     }
 }
@@ -155,6 +158,61 @@ impl BotError {
     pub fn general(msg: impl Into<String>) -> Self {
         Self::General(msg.into())
     }
+
+    /// Whether the error comes from a full or read-only disk rather than from the content
+    pub fn storage_problem(&self) -> Option<StorageProblem> {
+        let io_problem = match self {
+            BotError::FileSystemError(e) | BotError::ConversionError(ConversionError::IOError(e)) => {
+                StorageProblem::of_io(e)
+            }
+            _ => None,
+        };
+        io_problem.or_else(|| StorageProblem::in_text(&self.to_string()))
+    }
+}
+
+/// The disk with `videos/` and `converted/` can't take any more files. It's neither the
+/// user's nor the content's fault, the operator has to free space or fix the mount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageProblem {
+    Full,
+    ReadOnly,
+}
+
+/// How the problems read in yt-dlp and ffmpeg output, where only the text is left
+const STORAGE_PROBLEM_SIGNATURES: &[(&str, StorageProblem)] = &[
+    ("No space left on device", StorageProblem::Full),
+    ("Read-only file system", StorageProblem::ReadOnly),
+];
+
+impl StorageProblem {
+    /// Shown to users instead of the task's own error
+    pub const USER_MESSAGE: &str = "⚠️ Сервис временно недоступен, попробуйте позже.";
+
+    pub fn of_io(error: &std::io::Error) -> Option<Self> {
+        match error.kind() {
+            std::io::ErrorKind::StorageFull => Some(StorageProblem::Full),
+            std::io::ErrorKind::ReadOnlyFilesystem => Some(StorageProblem::ReadOnly),
+            _ => None,
+        }
+    }
+
+    /// Find the problem in an error message, e.g. a task's error details
+    pub fn in_text(text: &str) -> Option<Self> {
+        STORAGE_PROBLEM_SIGNATURES
+            .iter()
+            .find(|(signature, _)| text.contains(signature))
+            .map(|&(_, problem)| problem)
+    }
+
+    /// Log a warning the operator can't miss; `context` says what failed
+    pub fn warn(&self, context: &str) {
+        let fix = match self {
+            StorageProblem::Full => "the disk is full, free up space (old files in videos/ and converted/ can go)",
+            StorageProblem::ReadOnly => "the filesystem is read-only, check the mount of videos/ and converted/",
+        };
+        log::error!("!!! {} failed because {}. Tasks will keep failing until this is fixed", context, fix);
+    }
 }
 
 /// Результат операций бота
@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use super::format_first_received::submit_audio_download;
+use crate::{
+    callback::Callback,
+    errors::{BotError, HandlerResult},
+    queue::TaskQueue,
+    settings::SettingsManager,
+    subscription::SubscriptionManager,
+};
+
+/// Handle audio track selection callback for dubbed videos
+/// Callback format: al:short_id:language
+pub async fn audio_track_received(
+    bot: Bot,
+    query: CallbackQuery,
+    task_queue: Arc<TaskQueue>,
+    settings_manager: Arc<SettingsManager>,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> HandlerResult {
+    let data = query
+        .data
+        .as_ref()
+        .ok_or_else(|| BotError::general("No callback data"))?;
+
+    let message = query
+        .message
+        .as_ref()
+        .ok_or_else(|| BotError::general("Couldn't find message"))?;
+
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    let Callback::SelectAudioTrack { short_id, language } = Callback::parse(data)? else {
+        return Err(BotError::general(format!("Invalid audio track callback: {}", data)));
+    };
+
+    log::info!("User selected audio track: {}", language);
+
+    let user_id = query.from.id.0 as i64;
+    let premium = subscription_manager.is_subscribed(user_id).await;
+    let settings = settings_manager.get(user_id).await;
+    submit_audio_download(
+        &bot,
+        &task_queue,
+        message,
+        &short_id,
+        Some(language),
+        &settings,
+        premium,
+    )
+    .await
+}
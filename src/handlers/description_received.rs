@@ -0,0 +1,118 @@
+use teloxide::{
+    prelude::*,
+    types::{InputFile, MaybeInaccessibleMessage},
+};
+
+use crate::{
+    callback::Callback,
+    errors::{BotError, HandlerResult},
+    utils::{MAX_MESSAGE_CHARS, SendWithRetry, reply_to},
+    video::youtube::{VideoDetails, get_video_details},
+};
+
+/// Handle "description" callback under a finished download
+/// Callback format: desc:video_id
+pub async fn description_received(bot: Bot, query: CallbackQuery) -> HandlerResult {
+    let data = query
+        .data
+        .as_ref()
+        .ok_or_else(|| BotError::general("No callback data"))?;
+
+    let message = query
+        .message
+        .as_ref()
+        .ok_or_else(|| BotError::general("Couldn't find message"))?;
+
+    let Callback::Description { video_id } = Callback::parse(data)? else {
+        return Err(BotError::general(format!("Invalid description callback: {}", data)));
+    };
+
+    let url = format!("https://www.youtube.com/watch?v={}", video_id);
+    let details = match get_video_details(&url).await {
+        Ok(details) => details,
+        Err(e) => {
+            log::warn!("Failed to get details of {}: {}", url, e);
+            bot.answer_callback_query(query.id.clone())
+                .text("Не удалось получить описание видео.")
+                .show_alert(true)
+                .await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    let chat_id = message.chat().id;
+    let text = details_text(&details);
+
+    // Long descriptions go as a file instead of being cut off
+    if text.chars().count() > MAX_MESSAGE_CHARS {
+        let mut request = bot.send_document(
+            chat_id,
+            InputFile::memory(text.into_bytes()).file_name("description.txt"),
+        );
+        if let MaybeInaccessibleMessage::Regular(m) = message {
+            request = request.reply_parameters(reply_to(m.id));
+        }
+        request.send_with_retry().await?;
+    } else {
+        let mut request = bot.send_message(chat_id, text);
+        if let MaybeInaccessibleMessage::Regular(m) = message {
+            request = request.reply_parameters(reply_to(m.id));
+        }
+        request.send_with_retry().await?;
+    }
+
+    Ok(())
+}
+
+/// Title, author, date, views and tags, followed by the description
+fn details_text(details: &VideoDetails) -> String {
+    let mut lines = Vec::new();
+
+    if let Some(title) = &details.title {
+        lines.push(format!("🎬 {}", title));
+    }
+    if let Some(uploader) = &details.uploader {
+        lines.push(format!("👤 {}", uploader));
+    }
+    if let Some(date) = details.upload_date.as_deref().and_then(format_upload_date) {
+        lines.push(format!("📅 {}", date));
+    }
+    if let Some(views) = details.view_count {
+        lines.push(format!("👁 {} просмотров", group_digits(views)));
+    }
+    if !details.tags.is_empty() {
+        lines.push(format!("🏷 {}", details.tags.join(", ")));
+    }
+
+    let mut text = lines.join("\n");
+    text.push_str("\n\n");
+    text.push_str(
+        details
+            .description
+            .as_deref()
+            .unwrap_or("Описания у видео нет."),
+    );
+    text
+}
+
+/// YYYYMMDD from yt-dlp as DD.MM.YYYY
+fn format_upload_date(date: &str) -> Option<String> {
+    if date.len() != 8 || !date.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("{}.{}.{}", &date[6..8], &date[4..6], &date[..4]))
+}
+
+/// 1234567 as "1 234 567"
+fn group_digits(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(' ');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
@@ -1,19 +1,21 @@
 use std::sync::Arc;
 
-use strum::IntoEnumIterator;
 use teloxide::{
     prelude::*,
     types::{InlineKeyboardButton, InlineKeyboardMarkup, MaybeInaccessibleMessage, ParseMode},
 };
 
 use crate::{
+    callback::Callback,
     errors::{BotError, HandlerResult},
     queue::{Task, TaskId, TaskQueue, TaskType},
+    settings::{SettingsManager, UserSettings},
     subscription::{
-        premium::{is_premium_format, SUBSCRIPTION_DAYS, SUBSCRIPTION_PRICE_STARS},
+        premium::cheapest_tier,
         SubscriptionManager,
     },
-    utils::MediaFormatType,
+    utils::{FORMAT_DISABLED_MESSAGE, MediaFormatType},
+    video::{VideoInfo, youtube::STANDARD_HEIGHTS},
 };
 
 /// Handle format selection callback from queue-based download
@@ -23,6 +25,7 @@ pub async fn format_callback_received(
     query: CallbackQuery,
     task_queue: Arc<TaskQueue>,
     subscription_manager: Arc<SubscriptionManager>,
+    settings_manager: Arc<SettingsManager>,
 ) -> HandlerResult {
     let data = query
         .data
@@ -38,71 +41,143 @@ pub async fn format_callback_received(
         MaybeInaccessibleMessage::Regular(m) => m.chat.id,
     };
 
-    let message_id = match &message {
-        MaybeInaccessibleMessage::Inaccessible(m) => m.message_id,
-        MaybeInaccessibleMessage::Regular(m) => m.id,
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    let Callback::SelectFormat { format, short_id } = Callback::parse(data)? else {
+        return Err(BotError::general(format!("Invalid format callback: {}", data)));
     };
+    let short_id = short_id.as_str();
 
-    bot.answer_callback_query(query.id.clone()).await?;
+    // Old keyboards can still show a format that has been disabled since
+    if !format.is_available() {
+        bot.send_message(chat_id, FORMAT_DISABLED_MESSAGE).await?;
+        return Ok(());
+    }
 
-    // Parse callback data: fmt:format_index:short_id
-    let stripped = data.strip_prefix("fmt:").ok_or_else(|| {
-        BotError::general(format!("Invalid format callback: {}", data))
-    })?;
+    // Check if the user's subscription tier unlocks this format
+    let user_id = query.from.id.0 as i64;
+    if !subscription_manager.can_use_format(user_id, &format).await {
+        // User doesn't have premium - show upgrade message
+        let text = format!(
+            "<b>Эта функция доступна только с Premium-подпиской</b>\n\n\
+            Конвертация в {} требует подписки.\n\n\
+            Стоимость: от <b>{} Stars</b> за {} дней",
+            format, cheapest_tier().price_stars, cheapest_tier().days
+        );
+
+        let keyboard = InlineKeyboardMarkup::new(vec![vec![
+            InlineKeyboardButton::callback(
+                "Купить Premium",
+                Callback::BuyPremium { tier_id: None }.to_string(),
+            ),
+        ]]);
+
+        if let MaybeInaccessibleMessage::Regular(m) = &message {
+            bot.edit_message_text(chat_id, m.id, text)
+                .parse_mode(ParseMode::Html)
+                .reply_markup(keyboard)
+                .await?;
+        }
+        return Ok(());
+    }
+
+    // Videos are scaled down to the user's tier ceiling
+    let max_height = match format {
+        MediaFormatType::Video => subscription_manager.max_height(user_id).await,
+        _ => None,
+    };
 
-    // Split into parts: format_index:short_id
-    let parts: Vec<&str> = stripped.splitn(2, ':').collect();
-    if parts.len() != 2 {
-        return Err(BotError::general(format!(
-            "Invalid format callback structure: {}",
-            data
-        )));
+    // Videos can be sent smaller than they were uploaded, so ask for the resolution first
+    if format == MediaFormatType::Video
+        && let MaybeInaccessibleMessage::Regular(m) = &message
+        && let Some(pending) = task_queue.get_pending_conversion(short_id).await
+        && let Some(keyboard) = resolution_keyboard(short_id, &pending.filename, max_height).await
+    {
+        task_queue.touch_pending_conversion(short_id).await;
+        bot.edit_message_text(chat_id, m.id, "📐 В каком разрешении отправить видео?")
+            .reply_markup(keyboard)
+            .await?;
+        return Ok(());
     }
 
-    let format_index: usize = parts[0].parse().map_err(|_| {
-        BotError::general(format!("Invalid format index: {}", parts[0]))
-    })?;
-    let short_id = parts[1];
-
-    // Get format from index
-    let format = MediaFormatType::iter()
-        .nth(format_index)
-        .ok_or_else(|| BotError::general(format!("Invalid format index: {}", format_index)))?;
-
-    // Check if this is a premium format and user has subscription
-    if is_premium_format(&format) {
-        let user_id = query.from.id.0 as i64;
-        if !subscription_manager.is_subscribed(user_id).await {
-            // User doesn't have premium - show upgrade message
-            let text = format!(
-                "<b>Эта функция доступна только с Premium-подпиской</b>\n\n\
-                Конвертация в {} требует подписки.\n\n\
-                Стоимость: <b>{} Stars</b> за {} дней",
-                format, SUBSCRIPTION_PRICE_STARS, SUBSCRIPTION_DAYS
-            );
-
-            let keyboard = InlineKeyboardMarkup::new(vec![vec![
-                InlineKeyboardButton::callback("Купить Premium", "buy_premium"),
-            ]]);
-
-            if let MaybeInaccessibleMessage::Regular(m) = &message {
-                bot.edit_message_text(chat_id, m.id, text)
-                    .parse_mode(ParseMode::Html)
-                    .reply_markup(keyboard)
-                    .await?;
-            }
-            return Ok(());
+    let settings = settings_manager.get(user_id).await;
+    let premium = subscription_manager.is_subscribed(user_id).await;
+    submit_conversion(&bot, &task_queue, &message, short_id, format, max_height, &settings, premium).await
+}
+
+/// Resolutions an uploaded video can be scaled down to, from the probed file: every
+/// standard one below the source's height, and the original, as far as `max_height`
+/// allows. None if there's nothing smaller to offer or the file can't be probed.
+async fn resolution_keyboard(short_id: &str, filename: &str, max_height: Option<u32>) -> Option<InlineKeyboardMarkup> {
+    let info = match VideoInfo::from_file(filename).await {
+        Ok(info) => info,
+        Err(e) => {
+            log::warn!("Failed to probe {} for the resolution choice: {}", filename, e);
+            return None;
         }
+    };
+
+    let allowed = |height: u32| max_height.is_none_or(|max| height <= max);
+    let smaller: Vec<u32> = STANDARD_HEIGHTS
+        .into_iter()
+        .filter(|&h| h < info.height && allowed(h))
+        .collect();
+    if smaller.is_empty() {
+        return None;
     }
 
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = smaller
+        .chunks(3)
+        .map(|row| {
+            row.iter()
+                .map(|&height| {
+                    InlineKeyboardButton::callback(
+                        format!("{}p", height),
+                        Callback::SelectUploadQuality {
+                            short_id: short_id.to_string(),
+                            height: Some(height),
+                        }
+                        .to_string(),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+    if allowed(info.height) {
+        buttons.push(vec![InlineKeyboardButton::callback(
+            format!("Оригинал ({}p)", info.height),
+            Callback::SelectUploadQuality { short_id: short_id.to_string(), height: None }.to_string(),
+        )]);
+    }
+
+    Some(InlineKeyboardMarkup::new(buttons))
+}
+
+/// Queue the conversion of an upload waiting under `short_id`.
+/// `max_height` scales a video down before it's sent, None keeps the original.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn submit_conversion(
+    bot: &Bot,
+    task_queue: &TaskQueue,
+    message: &MaybeInaccessibleMessage,
+    short_id: &str,
+    format: MediaFormatType,
+    max_height: Option<u32>,
+    settings: &UserSettings,
+    premium: bool,
+) -> HandlerResult {
+    let chat_id = message.chat().id;
+    let message_id = message.id();
+
     // Get pending conversion data
     let pending = task_queue.take_pending_conversion(short_id).await.ok_or_else(|| {
         BotError::general("Conversion session expired. Please download the video again.")
     })?;
 
     log::info!(
-        "Format callback: format={:?}, filename={}",
+        "Format callback: format={:?}, max_height={:?}, filename={}",
         format,
+        max_height,
         pending.filename
     );
 
@@ -113,30 +188,37 @@ pub async fn format_callback_received(
             filename: pending.filename,
             thumbnail_path: pending.thumbnail_path,
             format,
+            max_height,
         },
         chat_id,
         message_id,
+        // The uploaded video itself
+        reply_to: Some(pending.message_id),
         unique_file_id: format!("chat{}_msg{}", chat_id, message_id),
+        options: settings.send_options,
+        filters: settings.filters_for(premium),
+        target_chat: settings.target_chat.map(ChatId),
+        premium,
     };
 
     // Submit to queue
     match task_queue.submit(task).await {
         Ok(position) => {
-            let queue_msg = if position > 1 {
-                format!("⏳ Задача добавлена в очередь (позиция: {})", position)
+            let queue_msg = if !position.starts_immediately() {
+                format!("⏳ Задача добавлена в очередь ({})", position)
             } else {
                 "📤 Обрабатываем...".to_string()
             };
 
-            if let MaybeInaccessibleMessage::Regular(m) = &message {
+            if let MaybeInaccessibleMessage::Regular(m) = message {
                 let _ = bot.edit_message_text(chat_id, m.id, queue_msg).await;
             }
         }
         Err(e) => {
             log::error!("Failed to submit conversion task: {}", e);
-            if let MaybeInaccessibleMessage::Regular(m) = &message {
+            if let MaybeInaccessibleMessage::Regular(m) = message {
                 let _ = bot
-                    .edit_message_text(chat_id, m.id, "❌ Ошибка добавления в очередь")
+                    .edit_message_text(chat_id, m.id, e.user_message())
                     .await;
             }
         }
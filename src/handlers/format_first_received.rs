@@ -1,22 +1,32 @@
 use std::sync::Arc;
 
-use strum::IntoEnumIterator;
 use teloxide::{
     prelude::*,
     types::{InlineKeyboardButton, InlineKeyboardMarkup, MaybeInaccessibleMessage, ParseMode},
 };
 
 use crate::{
+    callback::Callback,
     errors::{BotError, HandlerResult},
     queue::{Task, TaskId, TaskQueue, TaskType},
+    settings::{SettingsManager, UserSettings},
     subscription::{
-        premium::{is_premium_format, SUBSCRIPTION_DAYS, SUBSCRIPTION_PRICE_STARS},
+        premium::cheapest_tier,
         SubscriptionManager,
     },
-    utils::MediaFormatType,
-    video::youtube::get_available_qualities,
+    utils::{FORMAT_DISABLED_MESSAGE, MediaFormatType, replied_request},
+    video::{
+        convert::fits_after_compression,
+        youtube::{
+            METADATA_TIMEOUT_MESSAGE, VideoQuality, get_audio_tracks, get_available_qualities, get_video_duration,
+        },
+    },
 };
 
+/// Size estimates are approximate, a quality is only flagged as too big when its
+/// estimate is over the upload limit by this factor
+const OVERSIZE_MARGIN: f64 = 1.5;
+
 /// Handle format selection callback (first step after receiving link)
 /// Callback format: ff:format_index:short_id
 pub async fn format_first_received(
@@ -24,6 +34,7 @@ pub async fn format_first_received(
     query: CallbackQuery,
     task_queue: Arc<TaskQueue>,
     subscription_manager: Arc<SubscriptionManager>,
+    settings_manager: Arc<SettingsManager>,
 ) -> HandlerResult {
     let data = query
         .data
@@ -39,60 +50,44 @@ pub async fn format_first_received(
         MaybeInaccessibleMessage::Regular(m) => m.chat.id,
     };
 
-    let message_id = match &message {
-        MaybeInaccessibleMessage::Inaccessible(m) => m.message_id,
-        MaybeInaccessibleMessage::Regular(m) => m.id,
-    };
-
     bot.answer_callback_query(query.id.clone()).await?;
 
-    // Parse callback data: ff:format_index:short_id
-    let stripped = data.strip_prefix("ff:").ok_or_else(|| {
-        BotError::general(format!("Invalid format first callback: {}", data))
-    })?;
+    let Callback::SelectFormatFirst { format, short_id } = Callback::parse(data)? else {
+        return Err(BotError::general(format!("Invalid format first callback: {}", data)));
+    };
+    let short_id = short_id.as_str();
 
-    let parts: Vec<&str> = stripped.splitn(2, ':').collect();
-    if parts.len() != 2 {
-        return Err(BotError::general(format!(
-            "Invalid format first callback structure: {}",
-            data
-        )));
+    // Old keyboards can still show a format that has been disabled since
+    if !format.is_available() {
+        bot.send_message(chat_id, FORMAT_DISABLED_MESSAGE).await?;
+        return Ok(());
     }
 
-    let format_index: usize = parts[0].parse().map_err(|_| {
-        BotError::general(format!("Invalid format index: {}", parts[0]))
-    })?;
-    let short_id = parts[1];
-
-    // Get format from index
-    let format = MediaFormatType::iter()
-        .nth(format_index)
-        .ok_or_else(|| BotError::general(format!("Invalid format index: {}", format_index)))?;
-
-    // Check if this is a premium format and user has subscription
-    if is_premium_format(&format) {
-        let user_id = query.from.id.0 as i64;
-        if !subscription_manager.is_subscribed(user_id).await {
-            // User doesn't have premium - show upgrade message
-            let text = format!(
-                "<b>Эта функция доступна только с Premium-подпиской</b>\n\n\
-                Конвертация в {} требует подписки.\n\n\
-                Стоимость: <b>{} Stars</b> за {} дней",
-                format, SUBSCRIPTION_PRICE_STARS, SUBSCRIPTION_DAYS
-            );
-
-            let keyboard = InlineKeyboardMarkup::new(vec![vec![
-                InlineKeyboardButton::callback("Купить Premium", "buy_premium"),
-            ]]);
+    // Check if the user's subscription tier unlocks this format
+    let user_id = query.from.id.0 as i64;
+    if !subscription_manager.can_use_format(user_id, &format).await {
+        // User doesn't have premium - show upgrade message
+        let text = format!(
+            "<b>Эта функция доступна только с Premium-подпиской</b>\n\n\
+            Конвертация в {} требует подписки.\n\n\
+            Стоимость: от <b>{} Stars</b> за {} дней",
+            format, cheapest_tier().price_stars, cheapest_tier().days
+        );
 
-            if let MaybeInaccessibleMessage::Regular(m) = &message {
-                bot.edit_message_text(chat_id, m.id, text)
-                    .parse_mode(ParseMode::Html)
-                    .reply_markup(keyboard)
-                    .await?;
-            }
-            return Ok(());
+        let keyboard = InlineKeyboardMarkup::new(vec![vec![
+            InlineKeyboardButton::callback(
+                "Купить Premium",
+                Callback::BuyPremium { tier_id: None }.to_string(),
+            ),
+        ]]);
+
+        if let MaybeInaccessibleMessage::Regular(m) = &message {
+            bot.edit_message_text(chat_id, m.id, text)
+                .parse_mode(ParseMode::Html)
+                .reply_markup(keyboard)
+                .await?;
         }
+        return Ok(());
     }
 
     // Get pending download
@@ -109,6 +104,42 @@ pub async fn format_first_received(
     // For Audio and Voice, start download immediately (no quality needed)
     match format {
         MediaFormatType::Video | MediaFormatType::VideoNote => {
+            let settings = settings_manager.get(user_id).await;
+            let premium = subscription_manager.is_subscribed(user_id).await;
+            let max_height = subscription_manager.max_height(user_id).await;
+
+            // Power users with their own format expression skip the quality step
+            if let Some(format_string) = settings.format_string.clone().filter(|_| premium) {
+                return submit_video_download(
+                    &bot,
+                    &task_queue,
+                    &message,
+                    short_id,
+                    VideoChoice::FormatString(format_string),
+                    &settings,
+                    premium,
+                )
+                .await;
+            }
+
+            // So do users who let the video length decide (the duration is cached from
+            // the link step); without a duration fall back to asking
+            if settings.smart_quality
+                && let Ok(duration) = get_video_duration(&pending.url, pending.password.as_ref()).await
+                && let Some(height) = crate::config::get().smart_quality_height(duration)
+            {
+                return submit_video_download(
+                    &bot,
+                    &task_queue,
+                    &message,
+                    short_id,
+                    VideoChoice::SmartQuality(max_height.map_or(height, |max| height.min(max))),
+                    &settings,
+                    premium,
+                )
+                .await;
+            }
+
             // Get available qualities
             if let MaybeInaccessibleMessage::Regular(m) = &message {
                 let _ = bot
@@ -116,94 +147,357 @@ pub async fn format_first_received(
                     .await;
             }
 
-            match get_available_qualities(&pending.url).await {
+            match get_available_qualities(&pending.url, pending.password.as_ref()).await {
                 Ok(qualities) => {
                     log::info!("Found {} quality options", qualities.len());
+                    let offered = qualities.len();
+                    let qualities = cap_qualities(qualities, max_height);
+
+                    // Qualities that would be downloaded only to turn out too big to send
+                    let duration = get_video_duration(&pending.url, pending.password.as_ref()).await.ok();
+                    let oversized: Vec<bool> = qualities
+                        .iter()
+                        .map(|q| is_oversized(q, duration))
+                        .collect();
 
                     // Create quality buttons with short callback: q:short_id:height
                     let buttons: Vec<InlineKeyboardButton> = qualities
                         .iter()
-                        .map(|q| {
-                            let callback = format!("q:{}:{}", short_id, q.height);
-                            InlineKeyboardButton::callback(&q.label, callback)
+                        .zip(&oversized)
+                        .map(|(q, &oversized)| {
+                            let callback = Callback::SelectQuality {
+                                short_id: short_id.to_string(),
+                                height: q.height,
+                            }
+                            .to_string();
+                            let label = match q.estimated_size {
+                                Some(size) if oversized => {
+                                    format!("⚠️ {} ~{}", q.label, crate::config::format_size(size))
+                                }
+                                _ => q.label.clone(),
+                            };
+                            InlineKeyboardButton::callback(label, callback)
                         })
                         .collect();
 
+                    let mut text = if oversized.contains(&true) {
+                        format!(
+                            "🎬 Выбери качество видео:\n\n\
+                             ⚠️ Видео в отмеченном качестве, скорее всего, больше {} \
+                             и не сожмётся до отправки. Лучше выбрать качество пониже.",
+                            crate::config::format_size(crate::config::get().upload_limit)
+                        )
+                    } else {
+                        "🎬 Выбери качество видео:".to_string()
+                    };
+                    if qualities.len() < offered && !premium {
+                        text.push_str("\n\n💎 Качество выше доступно с Premium: /premium");
+                    }
+
                     let mut keyboard = InlineKeyboardMarkup::default();
                     for chunk in buttons.chunks(2) {
                         keyboard = keyboard.append_row(chunk.to_vec());
                     }
+                    // Let yt-dlp pick by size instead, so nothing needs compressing
+                    keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback(
+                        format!(
+                            "📦 Лучшее до {}",
+                            crate::config::format_size(crate::config::get().upload_limit)
+                        ),
+                        Callback::SelectSizeBudget { short_id: short_id.to_string() }.to_string(),
+                    )]);
 
                     if let MaybeInaccessibleMessage::Regular(m) = &message {
                         let _ = bot
-                            .edit_message_text(chat_id, m.id, "🎬 Выбери качество видео:")
+                            .edit_message_text(chat_id, m.id, text)
                             .reply_markup(keyboard)
                             .await;
                     }
                 }
                 Err(e) => {
                     log::error!("Failed to get video qualities: {}", e);
+                    let text = match e {
+                        BotError::Timeout(_) => METADATA_TIMEOUT_MESSAGE,
+                        _ => "❌ Не могу получить информацию о видео, попробуй другую ссылку.",
+                    };
                     if let MaybeInaccessibleMessage::Regular(m) = &message {
-                        let _ = bot
-                            .edit_message_text(
-                                chat_id,
-                                m.id,
-                                "❌ Не могу получить информацию о видео, попробуй другую ссылку.",
-                            )
-                            .await;
+                        let _ = bot.edit_message_text(chat_id, m.id, text).await;
                     }
                 }
             }
         }
-        MediaFormatType::Audio | MediaFormatType::Voice => {
-            // For audio formats, start download immediately without quality selection
-            // Take the pending download (removes it from pending)
-            let pending = task_queue.take_pending_download(short_id).await.ok_or_else(|| {
-                BotError::general("Download session expired. Please send the link again.")
-            })?;
-
-            let unique_file_id = format!("chat{}_msg{}", chat_id, message_id);
-
-            // Create download task with no quality (audio only)
-            let task = Task {
-                id: TaskId::new(),
-                task_type: TaskType::Download {
-                    url: pending.url,
-                    quality: None, // No quality for audio
-                    format,
-                },
-                chat_id,
-                message_id,
-                unique_file_id,
+        MediaFormatType::VideoSticker => {
+            // Only 3 seconds at 512px end up in a sticker, there's no quality to pick
+            let premium = subscription_manager.is_subscribed(user_id).await;
+            let settings = settings_manager.get(user_id).await;
+            submit_video_download(
+                &bot,
+                &task_queue,
+                &message,
+                short_id,
+                VideoChoice::Sticker,
+                &settings,
+                premium,
+            )
+            .await?;
+        }
+        MediaFormatType::Audio | MediaFormatType::Voice | MediaFormatType::Transcript => {
+            // Dubbed videos have several audio tracks - let the user pick one
+            let tracks = match get_audio_tracks(&pending.url, pending.password.as_ref()).await {
+                Ok(tracks) => tracks,
+                Err(e) => {
+                    log::warn!("Failed to get audio tracks, using default: {}", e);
+                    Vec::new()
+                }
             };
 
-            // Submit to queue
-            match task_queue.submit(task).await {
-                Ok(position) => {
-                    let queue_msg = if position > 1 {
-                        format!(
-                            "⏳ Задача добавлена в очередь (позиция: {})\nСкачиваем аудио...",
-                            position
-                        )
-                    } else {
-                        "⏳ Скачиваем аудио...".to_string()
-                    };
+            if tracks.len() > 1 {
+                log::info!("Found {} audio tracks", tracks.len());
 
-                    if let MaybeInaccessibleMessage::Regular(m) = &message {
-                        let _ = bot.edit_message_text(chat_id, m.id, queue_msg).await;
-                    }
+                // Create track buttons with short callback: al:short_id:language
+                let buttons: Vec<InlineKeyboardButton> = tracks
+                    .iter()
+                    .map(|t| {
+                        let callback = Callback::SelectAudioTrack {
+                            short_id: short_id.to_string(),
+                            language: t.language.clone(),
+                        }
+                        .to_string();
+                        InlineKeyboardButton::callback(&t.label, callback)
+                    })
+                    .collect();
+
+                let mut keyboard = InlineKeyboardMarkup::default();
+                for chunk in buttons.chunks(2) {
+                    keyboard = keyboard.append_row(chunk.to_vec());
                 }
-                Err(e) => {
-                    log::error!("Failed to submit task: {}", e);
-                    if let MaybeInaccessibleMessage::Regular(m) = &message {
-                        let _ = bot
-                            .edit_message_text(chat_id, m.id, "❌ Ошибка добавления в очередь")
-                            .await;
-                    }
+
+                if let MaybeInaccessibleMessage::Regular(m) = &message {
+                    let _ = bot
+                        .edit_message_text(chat_id, m.id, "🎧 Выбери звуковую дорожку:")
+                        .reply_markup(keyboard)
+                        .await;
                 }
+            } else {
+                // For audio formats, start download immediately without quality selection
+                let premium = subscription_manager.is_subscribed(user_id).await;
+                let settings = settings_manager.get(user_id).await;
+                submit_audio_download(
+                    &bot,
+                    &task_queue,
+                    &message,
+                    short_id,
+                    None,
+                    &settings,
+                    premium,
+                )
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Queue an audio-only download for a pending link, optionally with a specific audio track
+pub async fn submit_audio_download(
+    bot: &Bot,
+    task_queue: &TaskQueue,
+    message: &MaybeInaccessibleMessage,
+    short_id: &str,
+    audio_language: Option<String>,
+    settings: &UserSettings,
+    premium: bool,
+) -> HandlerResult {
+    let chat_id = message.chat().id;
+    let message_id = message.id();
+
+    // Take the pending download (removes it from pending)
+    let pending = task_queue.take_pending_download(short_id).await.ok_or_else(|| {
+        BotError::general("Download session expired. Please send the link again.")
+    })?;
+
+    let format = pending.format.unwrap_or(MediaFormatType::Audio);
+    let unique_file_id = format!("chat{}_msg{}", chat_id, message_id);
+
+    // Create download task with no quality (audio only)
+    let task = Task {
+        id: TaskId::new(),
+        task_type: TaskType::Download {
+            url: pending.url,
+            quality: None, // No quality for audio
+            format,
+            audio_language,
+            format_string: None,
+            size_budget: None,
+            filename_template: settings.filename_template.clone(),
+            password: pending.password,
+        },
+        chat_id,
+        message_id,
+        reply_to: replied_request(message),
+        unique_file_id,
+        options: pending.options,
+        filters: settings.filters_for(premium),
+        target_chat: settings.target_chat.map(ChatId),
+        premium,
+    };
+
+    // Submit to queue
+    match task_queue.submit(task).await {
+        Ok(position) => {
+            let queue_msg = if !position.starts_immediately() {
+                format!(
+                    "⏳ Задача добавлена в очередь ({})\nСкачиваем аудио...",
+                    position
+                )
+            } else {
+                "⏳ Скачиваем аудио...".to_string()
+            };
+
+            if let MaybeInaccessibleMessage::Regular(m) = message {
+                let _ = bot.edit_message_text(chat_id, m.id, queue_msg).await;
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to submit task: {}", e);
+            if let MaybeInaccessibleMessage::Regular(m) = message {
+                let _ = bot
+                    .edit_message_text(chat_id, m.id, e.user_message())
+                    .await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// How a video download was set up without the quality step
+enum VideoChoice {
+    /// The user's own yt-dlp format expression
+    FormatString(String),
+    /// Maximum height picked from the video length
+    SmartQuality(u32),
+    /// Source for a video sticker, see `STICKER_SOURCE_HEIGHT`
+    Sticker,
+}
+
+/// Qualities up to the user's ceiling (see `SubscriptionManager::max_height`). The lowest
+/// one stays even above it, so a download is always possible.
+fn cap_qualities(qualities: Vec<VideoQuality>, max_height: Option<u32>) -> Vec<VideoQuality> {
+    let Some(max) = max_height else {
+        return qualities;
+    };
+    let capped: Vec<VideoQuality> = qualities.iter().filter(|q| q.height <= max).cloned().collect();
+    if capped.is_empty() {
+        qualities.into_iter().take(1).collect()
+    } else {
+        capped
+    }
+}
+
+/// Stickers are at most 512px, anything above that is downloaded for nothing
+const STICKER_SOURCE_HEIGHT: u32 = 720;
+
+/// Queue a video download for a pending link, skipping the quality selection
+async fn submit_video_download(
+    bot: &Bot,
+    task_queue: &TaskQueue,
+    message: &MaybeInaccessibleMessage,
+    short_id: &str,
+    choice: VideoChoice,
+    settings: &UserSettings,
+    premium: bool,
+) -> HandlerResult {
+    let chat_id = message.chat().id;
+    let message_id = message.id();
+
+    // Take the pending download (removes it from pending)
+    let pending = task_queue.take_pending_download(short_id).await.ok_or_else(|| {
+        BotError::general("Download session expired. Please send the link again.")
+    })?;
+
+    let format = pending.format.unwrap_or(MediaFormatType::Video);
+    let unique_file_id = format!("chat{}_msg{}", chat_id, message_id);
+
+    let (quality, format_string, action) = match choice {
+        VideoChoice::FormatString(format_string) => {
+            log::info!("Using custom format string {:?} for URL: {}", format_string, pending.url);
+            (None, Some(format_string), "Скачиваем видео в вашем формате...".to_string())
+        }
+        VideoChoice::SmartQuality(height) => {
+            log::info!("Smart quality picked {}p for URL: {}", height, pending.url);
+            (
+                Some(height),
+                None,
+                format!(
+                    "Скачиваем видео в {}p (по длине видео, выбирать вручную — /settings)...",
+                    height
+                ),
+            )
+        }
+        VideoChoice::Sticker => (
+            Some(STICKER_SOURCE_HEIGHT),
+            None,
+            "Скачиваем видео для стикера...".to_string(),
+        ),
+    };
+
+    let task = Task {
+        id: TaskId::new(),
+        task_type: TaskType::Download {
+            url: pending.url,
+            quality,
+            format,
+            audio_language: None,
+            format_string,
+            size_budget: None,
+            filename_template: settings.filename_template.clone(),
+            password: pending.password,
+        },
+        chat_id,
+        message_id,
+        reply_to: replied_request(message),
+        unique_file_id,
+        options: pending.options,
+        filters: settings.filters_for(premium),
+        target_chat: settings.target_chat.map(ChatId),
+        premium,
+    };
+
+    // Submit to queue
+    match task_queue.submit(task).await {
+        Ok(position) => {
+            let queue_msg = if !position.starts_immediately() {
+                format!("⏳ Задача добавлена в очередь ({})\n{}", position, action)
+            } else {
+                format!("⏳ {}", action)
+            };
+
+            if let MaybeInaccessibleMessage::Regular(m) = message {
+                let _ = bot.edit_message_text(chat_id, m.id, queue_msg).await;
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to submit task: {}", e);
+            if let MaybeInaccessibleMessage::Regular(m) = message {
+                let _ = bot
+                    .edit_message_text(chat_id, m.id, e.user_message())
+                    .await;
             }
         }
     }
 
     Ok(())
 }
+
+/// Whether a quality is confidently too big to send: its estimated size is well over the
+/// upload limit and the video is too long for compression to fit it. Unknown sizes or
+/// durations are never flagged.
+fn is_oversized(quality: &VideoQuality, duration: Option<u32>) -> bool {
+    let upload_limit = crate::config::get().upload_limit as f64;
+    let (Some(size), Some(duration)) = (quality.estimated_size, duration) else {
+        return false;
+    };
+    size as f64 > upload_limit * OVERSIZE_MARGIN && !fits_after_compression(duration as f64)
+}
@@ -1,41 +1,244 @@
 use std::sync::Arc;
 
-use strum::IntoEnumIterator;
 use teloxide::{
     prelude::*,
-    types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode},
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode},
 };
 
 use crate::{
-    errors::{BotError, HandlerResult},
-    queue::TaskQueue,
-    utils::MediaFormatType,
+    callback::Callback,
+    errors::{BotError, BotResult, HandlerResult},
+    queue::{COOLDOWN_MESSAGE, MAINTENANCE_MESSAGE, PendingDownload, TaskQueue},
+    schema::{MyDialogue, State},
+    settings::{SendOption, SendOptions, SettingsManager},
+    subscription::SubscriptionManager,
+    utils::{
+        UNSAFE_URL_MESSAGE, format_keyboard_rows, is_youtube_video_link, reply_to, resolve_youtube_link,
+        validate_url_safe, youtube_playlist_id,
+    },
     video::youtube::{
-        MAX_VIDEO_DURATION_SECONDS, format_duration, get_video_duration,
-        is_video_too_long,
+        METADATA_TIMEOUT_MESSAGE, VideoPassword, format_duration, get_video_duration, has_chapters, has_subtitles,
+        is_password_error, is_video_too_long,
     },
 };
 
+/// Asked when yt-dlp says the video needs a password
+const PASSWORD_PROMPT: &str = "🔒 Это видео защищено паролем. Отправьте пароль следующим сообщением, \
+он нужен только для этой загрузки и нигде не сохраняется.
+
+/cancel — отменить.";
+
+/// Shown when yt-dlp rejects the password, the next message is tried as the password again
+const WRONG_PASSWORD_MESSAGE: &str = "❌ Неверный пароль. Отправьте пароль ещё раз или /cancel, чтобы отменить.";
+
+/// Shown when yt-dlp finds no video behind a non-YouTube link
+const NO_VIDEO_MESSAGE: &str = "❌ По этой ссылке не нашлось видео, которое можно скачать";
+
 pub async fn link_received(
     bot: Bot,
     msg: Message,
+    dialogue: MyDialogue,
     task_queue: Arc<TaskQueue>,
+    subscription_manager: Arc<SubscriptionManager>,
+    settings_manager: Arc<SettingsManager>,
 ) -> HandlerResult {
     let text = msg.text().ok_or_else(|| {
         BotError::general("Text should be here. It's invalid state")
     })?;
 
-    // Send immediate feedback
+    if task_queue.is_maintenance() {
+        bot.send_message(msg.chat.id, MAINTENANCE_MESSAGE).await?;
+        return Ok(());
+    }
+
+    if !check_cooldown(&bot, &msg, &task_queue, &subscription_manager).await? {
+        return Ok(());
+    }
+
+    // Send immediate feedback. As a reply to the link, so the result can be sent
+    // in reply to it too, see `replied_request`
     let status_msg = bot
         .send_message(msg.chat.id, "🔍 Получаю информацию о видео...")
+        .reply_parameters(reply_to(msg.id))
+        .await?;
+
+    process_link(
+        &bot,
+        &msg,
+        &status_msg,
+        text.trim(),
+        &dialogue,
+        None,
+        &task_queue,
+        &subscription_manager,
+        &settings_manager,
+    )
+    .await
+}
+
+/// Handle a non-YouTube http(s) link. Ones that redirect to a YouTube video are
+/// followed, the rest go to yt-dlp as they are (e.g. Vimeo, which may ask for a password).
+pub async fn short_link_received(
+    bot: Bot,
+    msg: Message,
+    dialogue: MyDialogue,
+    task_queue: Arc<TaskQueue>,
+    subscription_manager: Arc<SubscriptionManager>,
+    settings_manager: Arc<SettingsManager>,
+) -> HandlerResult {
+    let text = msg.text().ok_or_else(|| {
+        BotError::general("Text should be here. It's invalid state")
+    })?;
+
+    if task_queue.is_maintenance() {
+        bot.send_message(msg.chat.id, MAINTENANCE_MESSAGE).await?;
+        return Ok(());
+    }
+
+    if !check_cooldown(&bot, &msg, &task_queue, &subscription_manager).await? {
+        return Ok(());
+    }
+
+    let status_msg = bot
+        .send_message(msg.chat.id, "🔗 Проверяю ссылку...")
+        .reply_parameters(reply_to(msg.id))
+        .await?;
+
+    let url = match resolve_youtube_link(text).await {
+        Some(url) => {
+            log::info!("Resolved link {} -> {}", text.trim(), url);
+            url
+        }
+        None => text.trim().to_string(),
+    };
+
+    bot.edit_message_text(msg.chat.id, status_msg.id, "🔍 Получаю информацию о видео...")
         .await?;
 
-    // Check video duration first
-    match get_video_duration(text).await {
+    // The resolved URL is what gets stored in the pending download
+    process_link(
+        &bot,
+        &msg,
+        &status_msg,
+        &url,
+        &dialogue,
+        None,
+        &task_queue,
+        &subscription_manager,
+        &settings_manager,
+    )
+    .await
+}
+
+/// Handle the password of a protected video, asked for by `process_link`.
+/// The message is deleted right away, the password only lives in the pending download.
+pub async fn password_received(
+    bot: Bot,
+    msg: Message,
+    dialogue: MyDialogue,
+    (url, link_message_id): (String, i32),
+    task_queue: Arc<TaskQueue>,
+    subscription_manager: Arc<SubscriptionManager>,
+    settings_manager: Arc<SettingsManager>,
+) -> HandlerResult {
+    let text = msg.text().ok_or_else(|| {
+        BotError::general("Text should be here. It's invalid state")
+    })?;
+    let password = VideoPassword::new(text.trim());
+
+    // Don't leave the password in the chat history
+    let _ = bot.delete_message(msg.chat.id, msg.id).await;
+
+    let status_msg = bot
+        .send_message(msg.chat.id, "🔑 Проверяю пароль...")
+        .reply_parameters(reply_to(MessageId(link_message_id)))
+        .await?;
+
+    // Wrong passwords keep the dialogue, so the next message is tried instead
+    match get_video_duration(&url, Some(&password)).await {
+        Err(e) if is_password_error(&e) => {
+            bot.edit_message_text(msg.chat.id, status_msg.id, WRONG_PASSWORD_MESSAGE).await?;
+            return Ok(());
+        }
+        Err(BotError::Timeout(_)) => {
+            bot.edit_message_text(msg.chat.id, status_msg.id, METADATA_TIMEOUT_MESSAGE).await?;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    dialogue.exit().await?;
+
+    process_link(
+        &bot,
+        &msg,
+        &status_msg,
+        &url,
+        &dialogue,
+        Some(password),
+        &task_queue,
+        &subscription_manager,
+        &settings_manager,
+    )
+    .await
+}
+
+/// Enforce the submission cooldown before any yt-dlp work is done for a link.
+/// Returns false (after telling the user) if the link came too soon after the last one.
+async fn check_cooldown(
+    bot: &Bot,
+    msg: &Message,
+    task_queue: &TaskQueue,
+    subscription_manager: &SubscriptionManager,
+) -> BotResult<bool> {
+    let user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+    let premium = subscription_manager.is_subscribed(user_id).await;
+    if task_queue.check_submit_cooldown(user_id, premium) {
+        return Ok(true);
+    }
+
+    bot.send_message(msg.chat.id, COOLDOWN_MESSAGE).await?;
+    Ok(false)
+}
+
+/// Check the video duration and show format selection for a link.
+/// Protected videos ask for their password first, see `password_received`.
+#[allow(clippy::too_many_arguments)]
+async fn process_link(
+    bot: &Bot,
+    msg: &Message,
+    status_msg: &Message,
+    text: &str,
+    dialogue: &MyDialogue,
+    password: Option<VideoPassword>,
+    task_queue: &Arc<TaskQueue>,
+    subscription_manager: &SubscriptionManager,
+    settings_manager: &SettingsManager,
+) -> HandlerResult {
+    // Nothing runs on a link that points at the bot's own machine or network
+    if let Err(e) = validate_url_safe(text).await {
+        log::warn!("{}", e);
+        bot.edit_message_text(msg.chat.id, status_msg.id, UNSAFE_URL_MESSAGE).await?;
+        return Ok(());
+    }
+
+    // The same link pasted again while its format choice is still open: bring that
+    // choice down here instead of starting over
+    if let Some((short_id, pending)) = task_queue.find_pending_download(msg.chat.id, text).await {
+        log::info!("Reusing pending download {} for URL: {}", short_id, text);
+        task_queue.move_pending_download(&short_id, status_msg.id).await;
+        return resend_format_message(bot, msg, status_msg, &short_id, &pending).await;
+    }
+
+    // Check video duration first (the limit depends on the user's subscription tier)
+    let user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+    let max_duration_seconds = subscription_manager.max_duration_seconds(user_id).await;
+
+    match get_video_duration(text, password.as_ref()).await {
         Ok(duration) => {
-            if is_video_too_long(duration) {
+            if is_video_too_long(duration, max_duration_seconds) {
                 let formatted_duration = format_duration(duration);
-                let max_duration = format_duration(MAX_VIDEO_DURATION_SECONDS);
+                let max_duration = format_duration(max_duration_seconds);
                 bot.edit_message_text(
                     msg.chat.id,
                     status_msg.id,
@@ -49,6 +252,27 @@ pub async fn link_received(
                 return Ok(());
             }
         }
+        Err(BotError::Timeout(_)) => {
+            // Every other metadata lookup below would wait just as long
+            bot.edit_message_text(msg.chat.id, status_msg.id, METADATA_TIMEOUT_MESSAGE)
+                .await?;
+            return Ok(());
+        }
+        Err(e) if password.is_none() && is_password_error(&e) => {
+            // Only the link goes into the (stored) dialogue, the password never does
+            log::info!("Video needs a password: {}", text);
+            dialogue
+                .update(State::ReceivePassword { url: text.to_string(), link_message_id: msg.id.0 })
+                .await?;
+            bot.edit_message_text(msg.chat.id, status_msg.id, PASSWORD_PROMPT).await?;
+            return Ok(());
+        }
+        Err(_) if !is_youtube_video_link(text) => {
+            // Any link ends up here, most of them aren't videos yt-dlp can download
+            log::info!("No video found at {}", text);
+            bot.edit_message_text(msg.chat.id, status_msg.id, NO_VIDEO_MESSAGE).await?;
+            return Ok(());
+        }
         Err(_) => {
             // If we can't get duration, we'll still try to process the video
             // This handles cases where duration might not be available but video is valid
@@ -56,8 +280,18 @@ pub async fn link_received(
         }
     }
 
-    // Show format selection first
-    send_format_message(&bot, &msg, &status_msg, text, &task_queue).await?;
+    // Show format selection first, with the user's default send options
+    let mut options = settings_manager.get(user_id).await.send_options;
+    if !has_subtitles(text, password.as_ref()).await {
+        options.subtitles = false;
+    }
+    if !has_chapters(text, password.as_ref()).await {
+        options.chapters = false;
+    }
+    if !is_youtube_video_link(text) {
+        options.sponsorblock = false;
+    }
+    send_format_message(bot, msg, status_msg, text, task_queue, options, password).await?;
 
     Ok(())
 }
@@ -69,26 +303,24 @@ async fn send_format_message(
     status_msg: &Message,
     url: &str,
     task_queue: &Arc<TaskQueue>,
+    options: SendOptions,
+    password: Option<VideoPassword>,
 ) -> HandlerResult {
+    let subtitles_available = has_subtitles(url, password.as_ref()).await;
+    let chapters_available = has_chapters(url, password.as_ref()).await;
+
     // Store URL in pending downloads and get short ID (format will be set later)
     let short_id = task_queue
-        .add_pending_download(url.to_string(), msg.chat.id, status_msg.id, None)
+        .add_pending_download(url.to_string(), msg.chat.id, status_msg.id, None, options, password)
         .await;
 
-    // Create format buttons with callback: ff:format_index:short_id
-    // ff = "format first" to distinguish from fmt (format after download)
-    let formats: Vec<InlineKeyboardButton> = MediaFormatType::iter()
-        .enumerate()
-        .map(|(idx, f)| {
-            let label = format!("{}", f);
-            let callback = format!("ff:{}:{}", idx, short_id);
-            InlineKeyboardButton::callback(label, callback)
-        })
-        .collect();
-
-    let keyboard = InlineKeyboardMarkup::default()
-        .append_row([formats[0].clone(), formats[1].clone()])
-        .append_row([formats[2].clone(), formats[3].clone()]);
+    let keyboard = format_selection_keyboard(
+        &short_id.0,
+        options,
+        subtitles_available,
+        chapters_available,
+        is_youtube_video_link(url),
+    );
 
     // Show queue status if there are pending tasks
     let pending = task_queue.pending_count();
@@ -98,13 +330,91 @@ async fn send_format_message(
         String::new()
     };
 
+    // Downloads always use --no-playlist, make that explicit for playlist links
+    let playlist_note = if youtube_playlist_id(url).is_some() {
+        "\n\nℹ️ Ссылка из плейлиста — будет скачано только это видео."
+    } else {
+        ""
+    };
+
+    bot.edit_message_text(
+        msg.chat.id,
+        status_msg.id,
+        format!("🎬 Выбери формат:{}{}", playlist_note, queue_info),
+    )
+    .reply_markup(keyboard)
+    .await?;
+
+    Ok(())
+}
+
+/// Show the format selection of an existing pending download again, under the new message.
+/// The old message loses its buttons so there's only one place to choose.
+async fn resend_format_message(
+    bot: &Bot,
+    msg: &Message,
+    status_msg: &Message,
+    short_id: &str,
+    pending: &PendingDownload,
+) -> HandlerResult {
+    let _ = bot
+        .edit_message_text(pending.chat_id, pending.message_id, "⬇️ Выбор формата перенесён ниже.")
+        .await;
+
+    let keyboard = format_selection_keyboard(
+        short_id,
+        pending.options,
+        has_subtitles(&pending.url, pending.password.as_ref()).await,
+        has_chapters(&pending.url, pending.password.as_ref()).await,
+        is_youtube_video_link(&pending.url),
+    );
+
     bot.edit_message_text(
         msg.chat.id,
         status_msg.id,
-        format!("🎬 Выбери формат:{}", queue_info),
+        "🎬 Эта ссылка уже ждёт выбора формата, видео не будет скачано заново.
+
+Выбери формат:",
     )
     .reply_markup(keyboard)
     .await?;
 
     Ok(())
 }
+
+/// Format buttons plus send option toggles for a pending download.
+/// The subtitles and chapters toggles are only offered for videos that have them,
+/// SponsorBlock only for YouTube videos.
+pub fn format_selection_keyboard(
+    short_id: &str,
+    options: SendOptions,
+    subtitles_available: bool,
+    chapters_available: bool,
+    sponsorblock_available: bool,
+) -> InlineKeyboardMarkup {
+    // Create format buttons with callback: ff:format_index:short_id
+    // ff = "format first" to distinguish from fmt (format after download)
+    let formats = format_keyboard_rows(|_, format| {
+        Callback::SelectFormatFirst { format: format.clone(), short_id: short_id.to_string() }.to_string()
+    });
+
+    // Option toggles with callback: opt:option_key:short_id
+    let toggles: Vec<InlineKeyboardButton> = SendOption::ALL
+        .into_iter()
+        .filter(|&option| option != SendOption::Subtitles || subtitles_available)
+        .filter(|&option| option != SendOption::Chapters || chapters_available)
+        .filter(|&option| option != SendOption::SponsorBlock || sponsorblock_available)
+        .map(|option| {
+            InlineKeyboardButton::callback(
+                options.button_label(option),
+                Callback::ToggleSendOption { option, short_id: short_id.to_string() }.to_string(),
+            )
+        })
+        .collect();
+
+    let mut keyboard = InlineKeyboardMarkup::new(formats);
+    for row in toggles.chunks(2) {
+        keyboard = keyboard.append_row(row.to_vec());
+    }
+    keyboard
+}
@@ -1,15 +1,25 @@
+mod audio_track_received;
+mod description_received;
 mod format_callback_received;
 mod format_first_received;
-mod format_received;
 mod link_received;
 mod payment;
 mod quality_received;
+mod report_received;
+mod retry_received;
+mod send_option_toggled;
+mod upload_quality_received;
 mod video_received;
 
+pub use audio_track_received::audio_track_received;
+pub use description_received::description_received;
 pub use format_callback_received::format_callback_received;
 pub use format_first_received::format_first_received;
-pub use format_received::format_received;
-pub use link_received::link_received;
+pub use link_received::{link_received, password_received, short_link_received};
 pub use payment::{handle_pre_checkout_query, handle_successful_payment};
 pub use quality_received::quality_received;
-pub use video_received::video_received;
+pub use report_received::report_received;
+pub use retry_received::retry_received;
+pub use send_option_toggled::send_option_toggled;
+pub use upload_quality_received::upload_quality_received;
+pub use video_received::{pending_upload_keyboard, receive_upload, video_received};
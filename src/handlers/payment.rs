@@ -1,17 +1,31 @@
 use std::sync::Arc;
 
-use teloxide::prelude::*;
+use teloxide::{
+    prelude::*,
+    types::{SuccessfulPayment, UserId},
+};
 
 use crate::{
     errors::HandlerResult,
-    subscription::{premium::SUBSCRIPTION_DAYS, SubscriptionManager},
+    subscription::{
+        premium::{parse_invoice_payload, PAYMENT_PAYLOAD_PREFIX},
+        PaymentRecord, SubscriptionManager,
+    },
+    utils::get_admin_ids,
 };
 
 /// Handle pre-checkout query - approve the payment
 pub async fn handle_pre_checkout_query(bot: Bot, query: PreCheckoutQuery) -> HandlerResult {
-    // Verify the payload starts with our prefix
-    if query.invoice_payload.starts_with("premium_sub_") {
+    // Verify the payload is ours and the price matches the tier it was issued for
+    let valid = parse_invoice_payload(&query.invoice_payload)
+        .is_some_and(|(tier, _)| tier.price_stars == query.total_amount);
+
+    if valid {
         bot.answer_pre_checkout_query(query.id.clone(), true).await?;
+    } else if query.invoice_payload.starts_with(PAYMENT_PAYLOAD_PREFIX) {
+        bot.answer_pre_checkout_query(query.id.clone(), false)
+            .error_message("This offer is no longer available")
+            .await?;
     } else {
         bot.answer_pre_checkout_query(query.id.clone(), false)
             .error_message("Unknown payment type")
@@ -26,38 +40,156 @@ pub async fn handle_successful_payment(
     msg: Message,
     subscription_manager: Arc<SubscriptionManager>,
 ) -> HandlerResult {
-    if let Some(payment) = msg.successful_payment() {
-        // Extract user_id from payload
-        if let Some(user_id_str) = payment.invoice_payload.strip_prefix("premium_sub_") {
-            if let Ok(user_id) = user_id_str.parse::<i64>() {
-                // Add subscription
-                match subscription_manager
-                    .add_subscription(user_id, SUBSCRIPTION_DAYS)
-                    .await
-                {
-                    Ok(expires_at) => {
-                        let text = format!(
-                            "Спасибо за покупку!\n\n\
-                            Premium-подписка активирована.\n\
-                            Действует до: {}\n\n\
-                            Теперь вам доступны:\n\
-                            - Конвертация в кружочки\n\
-                            - Конвертация в войсы",
-                            expires_at.format("%d.%m.%Y %H:%M UTC")
-                        );
-                        bot.send_message(msg.chat.id, text).await?;
-                    }
-                    Err(e) => {
-                        log::error!("Failed to activate subscription: {}", e);
-                        bot.send_message(
-                            msg.chat.id,
-                            "Произошла ошибка при активации подписки. Обратитесь в поддержку.",
-                        )
-                        .await?;
-                    }
-                }
+    let Some(payment) = msg.successful_payment() else {
+        return Ok(());
+    };
+
+    // Extract tier and user_id from payload
+    // Telegram may redeliver the same update, so the charge id guards against double-crediting
+    let charge_id = &payment.telegram_payment_charge_id.0;
+
+    let Some((tier, user_id)) = parse_invoice_payload(&payment.invoice_payload) else {
+        // The Stars are already taken, e.g. the tier was removed between checkout and payment
+        log::error!(
+            "Unknown payment payload {} for charge {}",
+            payment.invoice_payload,
+            charge_id
+        );
+        let user_id = msg.from.as_ref().map_or(msg.chat.id.0, |u| u.id.0 as i64);
+        refund_unfulfilled_payment(&bot, &msg, payment, user_id, &subscription_manager).await?;
+        return Ok(());
+    };
+
+    match subscription_manager
+        .process_payment(
+            charge_id,
+            user_id,
+            &payment.invoice_payload,
+            payment.total_amount,
+            &payment.currency,
+            tier,
+        )
+        .await
+    {
+        Ok(Some(expires_at)) => {
+            let text = format!(
+                "Спасибо за покупку!\n\n\
+                Подписка {} активирована.\n\
+                Действует до: {}\n\n\
+                Теперь вам доступны:\n{}",
+                tier.name,
+                expires_at.format("%d.%m.%Y %H:%M UTC"),
+                tier.features()
+            );
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Ok(None) => {
+            log::warn!(
+                "Payment {} for user {} was already processed, skipping",
+                charge_id,
+                user_id
+            );
+        }
+        Err(e) => {
+            log::error!("Failed to activate subscription: {}", e);
+            bot.send_message(
+                msg.chat.id,
+                "Произошла ошибка при активации подписки. Обратитесь в поддержку.",
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Record a payment that can't be fulfilled, refund it and let the user and admins know.
+/// If the refund fails, the recorded charge can still be refunded with /refund.
+async fn refund_unfulfilled_payment(
+    bot: &Bot,
+    msg: &Message,
+    payment: &SuccessfulPayment,
+    user_id: i64,
+    subscription_manager: &SubscriptionManager,
+) -> HandlerResult {
+    let charge_id = &payment.telegram_payment_charge_id.0;
+
+    let record = match subscription_manager
+        .record_unfulfilled_payment(
+            charge_id,
+            user_id,
+            &payment.invoice_payload,
+            payment.total_amount,
+            &payment.currency,
+        )
+        .await
+    {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            log::warn!("Payment {} was already recorded, skipping", charge_id);
+            return Ok(());
+        }
+        Err(e) => {
+            // Without the charge row the admins are the only record left
+            log::error!("Failed to record unfulfilled payment {}: {}", charge_id, e);
+            PaymentRecord {
+                charge_id: charge_id.clone(),
+                user_id,
+                total_amount: payment.total_amount as i64,
+                days: 0,
+            }
+        }
+    };
+
+    let refunded = match bot
+        .refund_star_payment(
+            UserId(user_id as u64),
+            payment.telegram_payment_charge_id.clone(),
+        )
+        .await
+    {
+        Ok(_) => {
+            if let Err(e) = subscription_manager.revoke_payment(&record).await {
+                log::error!("Failed to mark payment {} refunded: {}", charge_id, e);
             }
+            true
+        }
+        Err(e) => {
+            log::error!("Failed to refund payment {}: {}", charge_id, e);
+            false
+        }
+    };
+
+    let user_text = if refunded {
+        "Этот тариф больше недоступен, подписка не активирована. Звёзды возвращены."
+    } else {
+        "Этот тариф больше недоступен, подписка не активирована. \
+        Мы вернём звёзды вручную, администратор уже уведомлён."
+    };
+    bot.send_message(msg.chat.id, user_text).await?;
+
+    let admin_text = format!(
+        "Payment for an unknown tier\n\nUser: {}\nStars: {}\nPayload: {}\nCharge: {}\n{}",
+        user_id,
+        payment.total_amount,
+        payment.invoice_payload,
+        charge_id,
+        if refunded {
+            "Refunded automatically.".to_string()
+        } else {
+            format!("Refund failed, use /refund {}", charge_id)
+        }
+    );
+    for admin_id in get_admin_ids() {
+        if let Err(e) = bot.send_message(ChatId(admin_id), &admin_text).await {
+            log::warn!(
+                "Failed to notify admin {} about payment {}: {}",
+                admin_id,
+                charge_id,
+                e
+            );
         }
     }
+
     Ok(())
 }
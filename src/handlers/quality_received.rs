@@ -7,17 +7,22 @@ use teloxide::{
 };
 
 use crate::{
+    callback::Callback,
     errors::{BotError, HandlerResult},
     queue::{Task, TaskId, TaskQueue, TaskType},
-    utils::MediaFormatType,
+    settings::SettingsManager,
+    subscription::SubscriptionManager,
+    utils::{MediaFormatType, replied_request},
 };
 
 /// Handle quality selection callback
-/// Callback format: q:short_id:height
+/// Callback format: q:short_id:height, or qs:short_id for the best quality within the upload limit
 pub async fn quality_received(
     bot: Bot,
     query: CallbackQuery,
     task_queue: Arc<TaskQueue>,
+    settings_manager: Arc<SettingsManager>,
+    subscription_manager: Arc<SubscriptionManager>,
 ) -> HandlerResult {
     let data = query
         .data
@@ -40,59 +45,71 @@ pub async fn quality_received(
 
     bot.answer_callback_query(query.id.clone()).await?;
 
-    // Parse callback data: q:short_id:height
-    let stripped = data.strip_prefix("q:").ok_or_else(|| {
-        BotError::general(format!("Invalid quality callback: {}", data))
-    })?;
-
-    let parts: Vec<&str> = stripped.splitn(2, ':').collect();
-    if parts.len() != 2 {
-        return Err(BotError::general(format!(
-            "Invalid quality callback structure: {}",
-            data
-        )));
-    }
+    let (short_id, height, size_budget) = match Callback::parse(data)? {
+        Callback::SelectQuality { short_id, height } => (short_id, Some(height), None),
+        Callback::SelectSizeBudget { short_id } => (short_id, None, Some(crate::config::get().upload_limit)),
+        _ => return Err(BotError::general(format!("Invalid quality callback: {}", data))),
+    };
 
-    let short_id = parts[0];
-    let height: u32 = parts[1].parse().map_err(|_| {
-        BotError::general(format!("Invalid quality: {}", parts[1]))
-    })?;
+    // Old keyboards may offer more than the user's tier allows now
+    let user_id = query.from.id.0 as i64;
+    let height = match (height, subscription_manager.max_height(user_id).await) {
+        (Some(height), Some(max)) => Some(height.min(max)),
+        (height, max) => height.or(max),
+    };
+    let label = match (height, size_budget) {
+        (_, Some(bytes)) => format!("до {}", crate::config::format_size(bytes)),
+        (Some(height), None) => format!("в {}p", height),
+        (None, None) => String::new(),
+    };
 
     // Get URL and format from pending downloads
-    let pending = task_queue.take_pending_download(short_id).await.ok_or_else(|| {
+    let pending = task_queue.take_pending_download(&short_id).await.ok_or_else(|| {
         BotError::general("Download session expired. Please send the link again.")
     })?;
 
     // Get format from pending (should be set by format_first_received)
     let format = pending.format.unwrap_or(MediaFormatType::Video);
 
-    info!("User selected quality: {}p for URL: {} with format: {:?}", height, pending.url, format);
+    info!("User selected quality: {:?} (budget {:?}) for URL: {} with format: {:?}", height, size_budget, pending.url, format);
 
     let unique_file_id = format!("chat{}_msg{}", chat_id, message_id);
+    let settings = settings_manager.get(user_id).await;
+    let premium = subscription_manager.is_subscribed(user_id).await;
 
     // Create download task with format
     let task = Task {
         id: TaskId::new(),
         task_type: TaskType::Download {
             url: pending.url,
-            quality: Some(height),
+            quality: height,
             format,
+            audio_language: None,
+            format_string: None,
+            size_budget,
+            filename_template: settings.filename_template.clone(),
+            password: pending.password,
         },
         chat_id,
         message_id,
+        reply_to: replied_request(&message),
         unique_file_id,
+        options: pending.options,
+        filters: settings.filters_for(premium),
+        target_chat: settings.target_chat.map(ChatId),
+        premium,
     };
 
     // Submit to queue
     match task_queue.submit(task).await {
         Ok(position) => {
-            let queue_msg = if position > 1 {
+            let queue_msg = if !position.starts_immediately() {
                 format!(
-                    "⏳ Задача добавлена в очередь (позиция: {})\nСкачиваем видео в {}p...",
-                    position, height
+                    "⏳ Задача добавлена в очередь ({})\nСкачиваем видео {}...",
+                    position, label
                 )
             } else {
-                format!("⏳ Скачиваем видео в {}p...", height)
+                format!("⏳ Скачиваем видео {}...", label)
             };
 
             if let MaybeInaccessibleMessage::Regular(m) = &message {
@@ -103,7 +120,7 @@ pub async fn quality_received(
             log::error!("Failed to submit task: {}", e);
             if let MaybeInaccessibleMessage::Regular(m) = &message {
                 let _ = bot
-                    .edit_message_text(chat_id, m.id, "❌ Ошибка добавления в очередь")
+                    .edit_message_text(chat_id, m.id, e.user_message())
                     .await;
             }
         }
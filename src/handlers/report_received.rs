@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardMarkup, MaybeInaccessibleMessage, ParseMode},
+    utils::html::escape,
+};
+use tokio::process;
+
+use crate::{
+    callback::Callback,
+    errors::{BotError, HandlerResult},
+    queue::{FailureReport, TaskQueue},
+    utils::get_admin_ids,
+};
+
+/// Error details longer than this are cut from the start, the cause is usually at the end
+const MAX_DETAILS_CHARS: usize = 3000;
+
+/// Handle "report a problem" callback on a failed task
+/// Callback format: report:task_id
+pub async fn report_received(
+    bot: Bot,
+    query: CallbackQuery,
+    task_queue: Arc<TaskQueue>,
+) -> HandlerResult {
+    let data = query
+        .data
+        .as_ref()
+        .ok_or_else(|| BotError::general("No callback data"))?;
+
+    let message = query
+        .message
+        .as_ref()
+        .ok_or_else(|| BotError::general("Couldn't find message"))?;
+
+    let Callback::Report { task_id } = Callback::parse(data)? else {
+        return Err(BotError::general(format!("Invalid report callback: {}", data)));
+    };
+    let task_id = task_id.as_str();
+
+    let chat_id = message.chat().id;
+
+    // Without admins there's nobody to report to, keep the report
+    let admin_ids = get_admin_ids();
+    let report = if admin_ids.is_empty() {
+        None
+    } else {
+        task_queue.take_failure_report(chat_id, task_id).await
+    };
+    let Some(report) = report else {
+        bot.answer_callback_query(query.id.clone())
+            .text("Отчёт уже отправлен или устарел.")
+            .await?;
+        return Ok(());
+    };
+
+    let text = report_text(&query.from, chat_id, &report).await;
+    for admin_id in admin_ids {
+        if let Err(e) = bot
+            .send_message(ChatId(admin_id), &text)
+            .parse_mode(ParseMode::Html)
+            .await
+        {
+            log::warn!("Failed to send failure report to admin {}: {}", admin_id, e);
+        }
+    }
+    log::info!("Failure report for task {} sent to admins", report.task_id);
+
+    bot.answer_callback_query(query.id.clone())
+        .text("Спасибо! Отчёт отправлен администратору.")
+        .await?;
+
+    // One report per failure
+    if let MaybeInaccessibleMessage::Regular(m) = message {
+        let _ = bot
+            .edit_message_reply_markup(chat_id, m.id)
+            .reply_markup(InlineKeyboardMarkup::default())
+            .await;
+    }
+
+    Ok(())
+}
+
+/// Build the admin message with everything needed to reproduce the failure
+async fn report_text(user: &teloxide::types::User, chat_id: ChatId, report: &FailureReport) -> String {
+    let user_name = user
+        .username
+        .as_ref()
+        .map(|u| format!(" (@{})", u))
+        .unwrap_or_default();
+
+    let (ytdlp, ffmpeg) = tokio::join!(
+        tool_version(process::Command::new("yt-dlp"), "--version"),
+        tool_version(process::Command::new("ffmpeg"), "-version"),
+    );
+
+    let details = &report.error.details;
+    let char_count = details.chars().count();
+    let details = if char_count > MAX_DETAILS_CHARS {
+        let tail: String = details.chars().skip(char_count - MAX_DETAILS_CHARS).collect();
+        format!("…{}", tail)
+    } else {
+        details.clone()
+    };
+
+    format!(
+        "🐞 <b>Failure report</b>\n\n\
+        User: <code>{}</code>{}\n\
+        Chat: <code>{}</code>\n\
+        Task: {} {}\n\
+        URL: {}\n\
+        Error: {}\n\
+        yt-dlp: {}\n\
+        ffmpeg: {}\n\n\
+        <pre>{}</pre>",
+        user.id,
+        escape(&user_name),
+        chat_id,
+        report.description.emoji(),
+        escape(&report.description.to_string()),
+        report.url.as_deref().map(escape).unwrap_or_else(|| "—".to_string()),
+        report.error.kind.as_ref(),
+        escape(&ytdlp),
+        escape(&ffmpeg),
+        escape(&details),
+    )
+}
+
+/// First line of a tool's version output
+async fn tool_version(mut cmd: process::Command, arg: &str) -> String {
+    match cmd.arg(arg).output().await {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .unwrap_or("unknown")
+            .trim()
+            .to_string(),
+        Ok(output) => format!("exited with {}", output.status),
+        Err(e) => format!("not available ({})", e),
+    }
+}
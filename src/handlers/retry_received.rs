@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use teloxide::{prelude::*, types::MaybeInaccessibleMessage};
+
+use crate::{
+    callback::Callback,
+    errors::{BotError, HandlerResult},
+    queue::TaskQueue,
+    subscription::SubscriptionManager,
+};
+
+/// Handle "retry" callback on a failed task
+/// Callback format: retry:task_id
+pub async fn retry_received(
+    bot: Bot,
+    query: CallbackQuery,
+    task_queue: Arc<TaskQueue>,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> HandlerResult {
+    let data = query
+        .data
+        .as_ref()
+        .ok_or_else(|| BotError::general("No callback data"))?;
+
+    let message = query
+        .message
+        .as_ref()
+        .ok_or_else(|| BotError::general("Couldn't find message"))?;
+
+    let Callback::Retry { task_id } = Callback::parse(data)? else {
+        return Err(BotError::general(format!("Invalid retry callback: {}", data)));
+    };
+    let task_id = task_id.as_str();
+
+    let chat_id = message.chat().id;
+    let premium = subscription_manager
+        .is_subscribed(query.from.id.0 as i64)
+        .await;
+
+    match task_queue.retry_failed(chat_id, task_id, premium).await {
+        Ok(position) => {
+            log::info!("Task {} retried by user", task_id);
+            bot.answer_callback_query(query.id.clone()).await?;
+
+            let queue_msg = if !position.starts_immediately() {
+                format!("⏳ Задача добавлена в очередь ({})\nПробуем ещё раз...", position)
+            } else {
+                "⏳ Пробуем ещё раз...".to_string()
+            };
+
+            if let MaybeInaccessibleMessage::Regular(m) = message {
+                let _ = bot.edit_message_text(chat_id, m.id, queue_msg).await;
+            }
+        }
+        Err(e) => {
+            log::info!("Task {} can't be retried: {:?}", task_id, e);
+            bot.answer_callback_query(query.id.clone())
+                .text(e.user_message())
+                .show_alert(true)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use teloxide::{prelude::*, types::MaybeInaccessibleMessage};
+
+use crate::{
+    callback::Callback,
+    errors::{BotError, HandlerResult},
+    handlers::link_received::format_selection_keyboard,
+    queue::TaskQueue,
+    utils::is_youtube_video_link,
+    video::youtube::{has_chapters, has_subtitles},
+};
+
+/// Handle send option toggle on the format selection step
+/// Callback format: opt:option_key:short_id
+pub async fn send_option_toggled(
+    bot: Bot,
+    query: CallbackQuery,
+    task_queue: Arc<TaskQueue>,
+) -> HandlerResult {
+    let data = query
+        .data
+        .as_ref()
+        .ok_or_else(|| BotError::general("No callback data"))?;
+
+    let Callback::ToggleSendOption { option, short_id } = Callback::parse(data)? else {
+        return Err(BotError::general(format!("Invalid option callback: {}", data)));
+    };
+    let short_id = short_id.as_str();
+
+    let Some(pending) = task_queue.get_pending_download(short_id).await else {
+        bot.answer_callback_query(query.id.clone())
+            .text("Сессия истекла, отправьте ссылку заново.")
+            .await?;
+        return Ok(());
+    };
+
+    let mut options = pending.options;
+    options.toggle(option);
+    task_queue.update_pending_download_options(short_id, options).await;
+
+    bot.answer_callback_query(query.id.clone())
+        .text(options.button_label(option))
+        .await?;
+
+    // Re-render the keyboard with the new option state
+    if let Some(MaybeInaccessibleMessage::Regular(m)) = &query.message {
+        bot.edit_message_reply_markup(m.chat.id, m.id)
+            .reply_markup(format_selection_keyboard(
+                short_id,
+                options,
+                has_subtitles(&pending.url, pending.password.as_ref()).await,
+                has_chapters(&pending.url, pending.password.as_ref()).await,
+                is_youtube_video_link(&pending.url),
+            ))
+            .await?;
+    }
+
+    Ok(())
+}
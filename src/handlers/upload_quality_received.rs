@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use super::format_callback_received::submit_conversion;
+use crate::{
+    callback::Callback,
+    errors::{BotError, HandlerResult},
+    queue::TaskQueue,
+    settings::SettingsManager,
+    subscription::SubscriptionManager,
+    utils::MediaFormatType,
+};
+
+/// Handle the resolution choice for an uploaded video sent as video
+/// Callback format: uq:short_id:height, height 0 keeps the original
+pub async fn upload_quality_received(
+    bot: Bot,
+    query: CallbackQuery,
+    task_queue: Arc<TaskQueue>,
+    settings_manager: Arc<SettingsManager>,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> HandlerResult {
+    let data = query
+        .data
+        .as_ref()
+        .ok_or_else(|| BotError::general("No callback data"))?;
+
+    let message = query
+        .message
+        .as_ref()
+        .ok_or_else(|| BotError::general("Couldn't find message"))?;
+
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    let Callback::SelectUploadQuality { short_id, height } = Callback::parse(data)? else {
+        return Err(BotError::general(format!("Invalid upload quality callback: {}", data)));
+    };
+    let user_id = query.from.id.0 as i64;
+
+    // The original or a pick from an old keyboard still stays within the user's tier
+    let max_height = match (height, subscription_manager.max_height(user_id).await) {
+        (Some(height), Some(max)) => Some(height.min(max)),
+        (height, max) => height.or(max),
+    };
+
+    log::info!("User selected upload resolution: {:?}", max_height);
+
+    let premium = subscription_manager.is_subscribed(user_id).await;
+    let settings = settings_manager.get(user_id).await;
+    submit_conversion(
+        &bot,
+        &task_queue,
+        message,
+        &short_id,
+        MediaFormatType::Video,
+        max_height,
+        &settings,
+        premium,
+    )
+    .await
+}
@@ -1,31 +1,111 @@
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::Arc;
 
 use strum::IntoEnumIterator;
-use teloxide::{prelude::*, types::{InlineKeyboardButton, InlineKeyboardMarkup, Video}};
+use teloxide::{
+    prelude::*,
+    types::{FileMeta, InlineKeyboardButton, InlineKeyboardMarkup, Video},
+};
 use tokio::fs;
 
 use crate::{
-    errors::{BotError, HandlerResult},
-    schema::{MyDialogue, State},
-    utils::{get_unique_file_id, replace_path_keep_extension_inplace, MediaFormatType},
+    callback::Callback,
+    config::{BotApiMode, format_size},
+    errors::{BotError, HandlerResult, StorageProblem},
+    queue::{COOLDOWN_MESSAGE, MAINTENANCE_MESSAGE, PendingConversionCheck, TaskQueue},
+    subscription::SubscriptionManager,
+    utils::{MediaFormatType, format_keyboard_rows, get_unique_file_id, replace_path_keep_extension_inplace},
+    video::{VideoInfo, workdir},
 };
 
+/// Videos up to this long get a one-tap "audio only" button
+const QUICK_AUDIO_MAX_SECONDS: u32 = 5 * 60;
+
 pub async fn video_received(
     bot: Bot,
-    dialogue: MyDialogue,
     msg: Message,
     video: Video,
+    task_queue: Arc<TaskQueue>,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> HandlerResult {
+    receive_upload(
+        &bot,
+        &msg,
+        &msg,
+        &video.file,
+        Some(video.duration.seconds()),
+        &task_queue,
+        &subscription_manager,
+    )
+    .await
+}
+
+/// Save a file from the chat and offer its format choice, as for an uploaded video.
+/// `msg` asked for it (the upload itself, or /convert) and `source` has the file;
+/// the choice is kept in reply to `source`. Without a `duration` from Telegram the
+/// file is probed for it, and for whether it has a video stream at all.
+pub async fn receive_upload(
+    bot: &Bot,
+    msg: &Message,
+    source: &Message,
+    file: &FileMeta,
+    duration: Option<u32>,
+    task_queue: &TaskQueue,
+    subscription_manager: &SubscriptionManager,
 ) -> HandlerResult {
-    let file = bot.get_file(video.file.id).await?;
+    if task_queue.is_maintenance() {
+        bot.send_message(msg.chat.id, MAINTENANCE_MESSAGE).await?;
+        return Ok(());
+    }
+
+    // Uploaded videos wait on disk for a format choice, so they count towards the limit
+    let user_id = msg.from.as_ref().map(|u| u.id.0 as i64).unwrap_or(0);
+    let premium = subscription_manager.is_subscribed(user_id).await;
+    if !task_queue.check_submit_cooldown(user_id, premium) {
+        bot.send_message(msg.chat.id, COOLDOWN_MESSAGE).await?;
+        return Ok(());
+    }
+
+    let limit_check = task_queue.check_pending_conversion_limit(msg.chat.id, premium).await;
+    if let PendingConversionCheck::Refused { .. } = limit_check {
+        bot.send_message(msg.chat.id, limit_check.message().unwrap_or_default())
+            .await?;
+        return Ok(());
+    }
+
+    // The cloud Bot API won't hand out big files at all, say so instead of failing
+    let bot_api_mode = crate::config::get().bot_api_mode;
+    if file.size as u64 > bot_api_mode.max_download() {
+        bot.send_message(
+            msg.chat.id,
+            format!(
+                "❌ Видео слишком большое: бот может скачивать файлы до {}.",
+                format_size(bot_api_mode.max_download())
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let file = bot.get_file(file.id.clone()).await?;
 
     let unique_id = get_unique_file_id(msg.clone());
-    let container_path = "/var/lib/telegram-bot-api";
-    let host_path = "/bot-api-data";
-    let local_path = file.path.replace(container_path, host_path);
+    let config = crate::config::get();
+    let local_path = file.path.replacen(&config.bot_api_data_dir, &config.bot_api_data_mount, 1);
     let telegram_path = Path::new(&local_path);
+    let output_dir = match workdir::source_dir(&unique_id).await {
+        Ok(dir) => dir,
+        Err(e) => {
+            if let Some(problem) = e.storage_problem() {
+                problem.warn("Saving an uploaded video");
+                bot.send_message(msg.chat.id, StorageProblem::USER_MESSAGE).await?;
+            }
+            return Err(e);
+        }
+    };
     let output_path = replace_path_keep_extension_inplace(
         telegram_path,
-        "videos",
+        &output_dir.to_string_lossy(),
         &format!("custom_{unique_id}"),
     );
     log::info!(
@@ -34,48 +114,103 @@ pub async fn video_received(
         local_path,
         output_path.display()
     );
-    let download_result = fs::copy(&local_path, &output_path).await;
+    let download_result = match bot_api_mode {
+        // A local Bot API stores the file in its data directory, mounted into our container
+        BotApiMode::Local if fs::try_exists(&local_path).await.unwrap_or(false) => {
+            fs::copy(&local_path, &output_path).await.map(|_| ())
+        }
+        BotApiMode::Local => {
+            log::warn!(
+                "{} doesn't exist, check BOT_API_DATA_DIR and BOT_API_DATA_MOUNT. \
+                 Downloading the file through the Bot API instead",
+                local_path
+            );
+            download_via_api(bot, &file.path, &output_path).await
+        }
+        BotApiMode::Cloud => download_via_api(bot, &file.path, &output_path).await,
+    };
     if let Err(e) = download_result {
         log::error!("Error downloading file from {} to {}: {:?}", local_path, output_path.display(), e);
-        bot.send_message(
-            msg.chat.id,
-            "⚠️ Мы не смогли скачать ваше видео, попробуйте еще раз.",
-        )
-        .await?;
+        let text = match StorageProblem::of_io(&e) {
+            Some(problem) => {
+                problem.warn("Saving an uploaded video");
+                StorageProblem::USER_MESSAGE
+            }
+            None => "⚠️ Мы не смогли скачать ваше видео, попробуйте еще раз.",
+        };
+        bot.send_message(msg.chat.id, text).await?;
         return Err(BotError::general("Error downloading file"));
     }
     log::debug!("Video downloaded");
 
-    send_format_message(bot, dialogue, msg, &output_path).await?;
+    // From here on the upload goes through the same queue as downloaded videos
+    let filename = output_path.to_string_lossy().into_owned();
+    let short_id = task_queue
+        .add_pending_conversion(filename.clone(), None, msg.chat.id, source.id)
+        .await;
+
+    let keyboard = match duration {
+        Some(duration) => upload_format_keyboard(&short_id.0, duration, false),
+        None => pending_upload_keyboard(&short_id.0, &filename).await,
+    };
+
+    let mut text =
+        "Видео загружено. Теперь выбери формат в котором ты хочешь получить это видео".to_string();
+    if let Some(warning) = limit_check.message() {
+        text.push_str("\n\n");
+        text.push_str(&warning);
+    }
+
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(keyboard)
+        .await?;
+
     Ok(())
 }
 
-async fn send_format_message(
-    bot: Bot,
-    dialogue: MyDialogue,
-    msg: Message,
-    filename: impl Into<PathBuf>,
-) -> HandlerResult {
-    let formats: Vec<InlineKeyboardButton> = MediaFormatType::iter()
-        .map(|f| format!("{}", f))
-        .map(|f| InlineKeyboardButton::callback(&f, &f))
-        .collect();
-
-    bot.send_message(
-        msg.chat.id,
-        "Видео загружено. Теперь выбери формат в котором ты хочешь получить это видео",
-    )
-    .reply_markup(
-        InlineKeyboardMarkup::default()
-            .append_row([formats[0].clone(), formats[1].clone()])
-            .append_row([formats[2].clone(), formats[3].clone()]),
-    )
-    .await?;
-    dialogue
-        .update(State::ReceiveFormat {
-            filename: filename.into().to_str().unwrap().to_owned(),
-        })
+/// Format buttons for an uploaded video waiting under `short_id`, with a one-tap
+/// "audio only" button for short videos. Files without video only get audio formats.
+/// Callback format: fmt:format_index:short_id
+pub fn upload_format_keyboard(short_id: &str, duration_secs: u32, audio_only: bool) -> InlineKeyboardMarkup {
+    let callback = |format: MediaFormatType| {
+        Callback::SelectFormat { format, short_id: short_id.to_string() }.to_string()
+    };
+    if audio_only {
+        let buttons: Vec<InlineKeyboardButton> = MediaFormatType::iter()
+            .filter(|f| f.is_available() && f.is_audio_only())
+            .map(|f| InlineKeyboardButton::callback(f.to_string(), callback(f)))
+            .collect();
+        return InlineKeyboardMarkup::new(buttons.chunks(2).map(|row| row.to_vec()));
+    }
+
+    let mut rows = format_keyboard_rows(|_, format| callback(format.clone()));
+    if duration_secs <= QUICK_AUDIO_MAX_SECONDS && MediaFormatType::Audio.is_available() {
+        rows.insert(
+            0,
+            vec![InlineKeyboardButton::callback("⚡ Только звук", callback(MediaFormatType::Audio))],
+        );
+    }
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// `upload_format_keyboard` for a file already on disk, probed for its length and
+/// whether it has a video stream
+pub async fn pending_upload_keyboard(short_id: &str, filename: &str) -> InlineKeyboardMarkup {
+    let duration = VideoInfo::get_duration(filename).await.unwrap_or(0.0);
+    // ffprobe reads the file fine but finds no video stream in it
+    let audio_only = matches!(VideoInfo::from_file(filename).await, Err(BotError::ParseError(_)));
+    upload_format_keyboard(short_id, duration as u32, audio_only)
+}
+
+/// Fetch a file through the Bot API's file endpoint
+async fn download_via_api(bot: &Bot, file_path: &str, output_path: &Path) -> std::io::Result<()> {
+    use teloxide::net::Download;
+
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let mut output = fs::File::create(output_path).await?;
+    bot.download_file(file_path, &mut output)
         .await
-        .map_err(|e| BotError::general(format!("Failed to update dialogue: {}", e)))?;
-    Ok(())
+        .map_err(std::io::Error::other)
 }
@@ -0,0 +1,48 @@
+//! Language of the bot's user-facing texts.
+//!
+//! The texts are only written in Russian so far. This decides which language a user
+//! should get, so translations can be added without working it out in every handler.
+//! A language only gets a variant here once its texts exist.
+
+/// Languages the bot can talk in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumString, strum::AsRefStr)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum Language {
+    Ru,
+}
+
+impl Language {
+    /// The user's Telegram language if it's one of ours, the configured default otherwise
+    /// (missing or unsupported codes, e.g. the long tail of locales)
+    pub fn resolve(language_code: Option<&str>) -> Self {
+        language_code
+            .and_then(Self::from_code)
+            .unwrap_or(crate::config::get().default_language)
+    }
+
+    /// Match an IETF language tag from Telegram (`ru`, `en-US`, `pt-br`) by its primary subtag
+    fn from_code(code: &str) -> Option<Self> {
+        code.split(['-', '_']).next()?.parse().ok()
+    }
+
+    /// Name of the language in itself
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::Ru => "Русский",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_languages_with_texts_are_matched() {
+        assert_eq!(Language::from_code("ru"), Some(Language::Ru));
+        assert_eq!(Language::from_code("RU-ru"), Some(Language::Ru));
+        assert_eq!(Language::from_code("en"), None);
+        assert_eq!(Language::from_code("en-US"), None);
+        assert_eq!(Language::from_code(""), None);
+    }
+}
@@ -1,23 +1,32 @@
+mod callback;
 mod commands;
+mod config;
 pub mod db;
+mod dialogue_storage;
 mod errors;
 mod handlers;
+mod i18n;
+mod metrics;
 mod migrations;
 pub mod queue;
 mod schema;
+mod settings;
 pub mod subscription;
 mod utils;
 mod video;
 
 use std::sync::Arc;
 
-use teloxide::{dispatching::dialogue::InMemStorage, prelude::*};
+use teloxide::prelude::*;
 
 use crate::{
     db::TaskDb,
+    dialogue_storage::DialogueStorage,
     queue::TaskQueue,
-    schema::{State, schema},
+    schema::schema,
+    settings::SettingsManager,
     subscription::SubscriptionManager,
+    video::workdir,
 };
 
 #[tokio::main]
@@ -28,6 +37,23 @@ async fn main() {
 
     let bot = Bot::from_env();
 
+    // Every task needs yt-dlp and ffmpeg, better to find out now than from failed tasks
+    if !video::toolchain::preflight().await {
+        if config::get().require_tools {
+            log::error!("Required tools are missing, exiting. Set REQUIRE_TOOLS=false to start anyway");
+            std::process::exit(1);
+        }
+        log::warn!("Required tools are missing, tasks will fail until they are installed");
+    }
+    log::info!(
+        "yt-dlp socket timeout {}s, {} retries",
+        config::get().ytdlp_socket_timeout,
+        config::get().ytdlp_retries
+    );
+
+    // Find out whether the configured GPU encoder can actually be used
+    video::hwaccel::probe().await;
+
     // Initialize the subscription manager
     let database_url = std::env::var("DATABASE_URL")
         .unwrap_or_else(|_| "sqlite:subscriptions.db?mode=rwc".to_string());
@@ -38,6 +64,8 @@ async fn main() {
     );
     log::info!("Subscription manager initialized");
 
+    let settings_manager = Arc::new(SettingsManager::new(subscription_manager.pool()));
+
     // Initialize the task database and queue
     let task_db = TaskDb::new(subscription_manager.pool());
     let task_queue = TaskQueue::new(bot.clone(), task_db.clone()).await;
@@ -46,79 +74,211 @@ async fn main() {
     // Restore state after restart and notify affected users
     task_queue.restore_on_startup(&bot).await;
 
-    // Clean up orphaned files (not referenced by any pending task)
+    // Dialogues (uploaded videos waiting for a format) are persisted too
+    let dialogue_storage = DialogueStorage::new(subscription_manager.pool());
+    match dialogue_storage.delete_expired().await {
+        Ok(0) => {}
+        Ok(n) => log::info!("Removed {} expired dialogues", n),
+        Err(e) => log::error!("{}", e),
+    }
+
+    // Clean up orphaned files (not referenced by any pending task or dialogue)
     cleanup_orphaned_files(&task_db).await;
 
-    Dispatcher::builder(bot, schema())
+    // Nudge users about pending conversions before they expire
+    task_queue.start_expiry_reminders(bot.clone());
+
+    // Warn about links and uploads left without a choice, then drop them
+    task_queue.start_idle_flow_checks(bot.clone());
+
+    // Delete sent media once the user's auto-delete delay has passed
+    task_queue.start_scheduled_deletions(bot.clone());
+
+    // Keep users with long-waiting tasks informed, give up on tasks stuck too long
+    task_queue.start_queue_wait_checks(bot.clone());
+
+    // Forget submission cooldowns that have run out
+    task_queue.start_cooldown_cleanup();
+
+    // Clean up expired rows and compact the database on long-running instances
+    start_db_maintenance(task_db.clone(), dialogue_storage.clone());
+
+    if let Some(port) = config::get().metrics_port {
+        tokio::spawn(metrics::serve(port, task_queue.clone(), subscription_manager.clone()));
+    }
+
+    let mut dispatcher = Dispatcher::builder(bot, schema())
         .dependencies(dptree::deps![
-            InMemStorage::<State>::new(),
-            task_queue,
-            subscription_manager
+            dialogue_storage,
+            task_queue.clone(),
+            task_db,
+            subscription_manager,
+            settings_manager
         ])
-        .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+        .build();
+
+    // Stop taking updates on Ctrl-C as well as on SIGTERM from `docker stop` or systemd
+    let shutdown_token = dispatcher.shutdown_token();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        log::info!("Shutdown signal received, stopping the dispatcher...");
+        match shutdown_token.shutdown() {
+            Ok(stopped) => stopped.await,
+            Err(e) => log::warn!("Failed to stop the dispatcher: {}", e),
+        }
+    });
+
+    dispatcher.dispatch().await;
+
+    // Give running downloads a chance to finish before the process exits
+    task_queue.shutdown(SHUTDOWN_GRACE_PERIOD).await;
+    log::info!("Bot stopped");
 }
 
-/// Clean up files that are not referenced by any pending task
+/// How long to wait for running tasks on shutdown. Keep it below the container's
+/// stop timeout (`stop_grace_period` in compose.yml), or they get killed anyway.
+const SHUTDOWN_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(25);
+
+/// Wait for SIGINT (Ctrl-C) or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut sigterm = match signal(SignalKind::terminate()) {
+            Ok(sigterm) => sigterm,
+            Err(e) => {
+                log::error!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Periodically delete expired rows (otherwise only removed on startup) and
+/// compact the database. The interval is re-read from the config every time.
+fn start_db_maintenance(db: TaskDb, dialogue_storage: Arc<DialogueStorage>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(config::get().db_maintenance_interval).await;
+            run_db_maintenance(&db, &dialogue_storage).await;
+        }
+    });
+}
+
+async fn run_db_maintenance(db: &TaskDb, dialogue_storage: &DialogueStorage) {
+    let started = std::time::Instant::now();
+    let size_before = db.database_size().await.unwrap_or_else(|e| {
+        log::error!("{}", e);
+        0
+    });
+
+    let mut removed_files = Vec::new();
+
+    let removed_downloads = db.delete_expired_pending_downloads().await.unwrap_or_else(|e| {
+        log::error!("{}", e);
+        0
+    });
+    match db.delete_expired_pending_conversions().await {
+        Ok(files) => removed_files.extend(files),
+        Err(e) => log::error!("{}", e),
+    }
+    match db.delete_expired_tasks().await {
+        Ok(files) => removed_files.extend(files),
+        Err(e) => log::error!("{}", e),
+    }
+    let removed_dialogues = dialogue_storage.delete_expired().await.unwrap_or_else(|e| {
+        log::error!("{}", e);
+        0
+    });
+
+    for file in &removed_files {
+        workdir::remove_task_of(file).await;
+    }
+
+    if let Err(e) = db.compact().await {
+        log::error!("{}", e);
+    }
+
+    let size_after = db.database_size().await.unwrap_or_else(|e| {
+        log::error!("{}", e);
+        0
+    });
+    log::info!(
+        "Database maintenance done in {:?}: removed {} expired pending downloads, {} dialogues \
+        and {} files of expired conversions, size {} -> {} bytes",
+        started.elapsed(),
+        removed_downloads,
+        removed_dialogues,
+        removed_files.len(),
+        size_before,
+        size_after
+    );
+}
+
+/// Clean up task folders and files that are not referenced by any pending task
 async fn cleanup_orphaned_files(db: &TaskDb) {
     use std::collections::HashSet;
     use std::path::Path;
     use tokio::fs;
 
-    let active_files: HashSet<String> = match db.get_active_filenames().await {
-        Ok(files) => files
-            .into_iter()
-            .filter_map(|f| {
-                // Extract just the filename for comparison
-                Path::new(&f)
-                    .file_name()
-                    .map(|name| name.to_string_lossy().to_string())
-            })
-            .collect(),
+    let active_files = match db.get_active_filenames().await {
+        Ok(files) => files,
         Err(e) => {
             log::error!("Failed to get active filenames: {}", e);
             return;
         }
     };
 
-    // Clean videos directory
-    if let Ok(mut entries) = fs::read_dir("videos").await {
-        while let Ok(Some(entry)) = entries.next_entry().await {
-            let path = entry.path();
-            if path.is_file() {
-                let filename = path
-                    .file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_default();
-                if !active_files.contains(&filename) {
-                    if let Err(e) = fs::remove_file(&path).await {
-                        log::warn!("Failed to remove orphaned file {:?}: {}", path, e);
-                    } else {
-                        log::info!("Removed orphaned file: {:?}", path);
-                    }
+    // Files in task folders keep their whole folder, files from the flat layout only themselves
+    let mut active_tasks = HashSet::new();
+    let mut active_flat_files = HashSet::new();
+    for file in &active_files {
+        let path = Path::new(file);
+        match workdir::task_id_of(path) {
+            Some(id) => {
+                active_tasks.insert(id);
+            }
+            None => {
+                if let Some(name) = path.file_name() {
+                    active_flat_files.insert(name.to_string_lossy().to_string());
                 }
             }
         }
     }
 
-    // Clean converted directory
-    if let Ok(mut entries) = fs::read_dir("converted").await {
+    for root in [workdir::SOURCE_ROOT, workdir::OUTPUT_ROOT] {
+        let Ok(mut entries) = fs::read_dir(root).await else {
+            continue;
+        };
         while let Ok(Some(entry)) = entries.next_entry().await {
             let path = entry.path();
-            if path.is_file() {
-                let filename = path
-                    .file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_default();
-                if !active_files.contains(&filename) {
-                    if let Err(e) = fs::remove_file(&path).await {
-                        log::warn!("Failed to remove orphaned file {:?}: {}", path, e);
-                    } else {
-                        log::info!("Removed orphaned file: {:?}", path);
-                    }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let result = if path.is_dir() {
+                if active_tasks.contains(&name) {
+                    continue;
+                }
+                fs::remove_dir_all(&path).await
+            } else {
+                if active_flat_files.contains(&name) {
+                    continue;
                 }
+                fs::remove_file(&path).await
+            };
+            match result {
+                Ok(()) => log::info!("Removed orphaned {:?}", path),
+                Err(e) => log::warn!("Failed to remove orphaned {:?}: {}", path, e),
             }
         }
     }
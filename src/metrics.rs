@@ -0,0 +1,145 @@
+//! Prometheus metrics, built with the `metrics` feature and served when `METRICS_PORT`
+//! is set.
+//!
+//! Tasks, downloads and conversions are counted and timed where they happen. Queue
+//! depth, busy workers and active subscriptions are read when Prometheus scrapes
+//! `/metrics`, so nothing has to keep them up to date. Without the feature every
+//! function here is empty and the calls compile away.
+
+#[cfg(feature = "metrics")]
+pub use enabled::*;
+
+#[cfg(not(feature = "metrics"))]
+pub use disabled::*;
+
+#[cfg(feature = "metrics")]
+mod enabled {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::{Router, extract::State, routing::get};
+    use ::metrics::{counter, gauge, histogram};
+    use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+
+    use crate::queue::{TaskQueue, TaskType};
+    use crate::subscription::SubscriptionManager;
+    use crate::utils::MediaFormatType;
+
+    /// Histogram buckets for task, download and conversion times, in seconds
+    const DURATION_BUCKETS: &[f64] = &[1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 3600.0];
+
+    #[derive(Clone)]
+    struct Scrape {
+        handle: PrometheusHandle,
+        task_queue: Arc<TaskQueue>,
+        subscription_manager: Arc<SubscriptionManager>,
+    }
+
+    /// Install the recorder and serve `/metrics` on `port` until the process exits
+    pub async fn serve(port: u16, task_queue: Arc<TaskQueue>, subscription_manager: Arc<SubscriptionManager>) {
+        let handle = match PrometheusBuilder::new()
+            .set_buckets(DURATION_BUCKETS)
+            .and_then(|builder| builder.install_recorder())
+        {
+            Ok(handle) => handle,
+            Err(e) => {
+                log::error!("Failed to set up metrics: {}", e);
+                return;
+            }
+        };
+
+        let listener = match tokio::net::TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                log::error!("Failed to listen for metrics on port {}: {}", port, e);
+                return;
+            }
+        };
+        log::info!("Serving metrics on port {}", port);
+
+        let app = Router::new().route("/metrics", get(render)).with_state(Scrape {
+            handle,
+            task_queue,
+            subscription_manager,
+        });
+        if let Err(e) = axum::serve(listener, app).await {
+            log::error!("Metrics server stopped: {}", e);
+        }
+    }
+
+    async fn render(State(scrape): State<Scrape>) -> String {
+        gauge!("queue_depth").set(scrape.task_queue.pending_count() as f64);
+        gauge!("workers_busy").set(scrape.task_queue.busy_workers() as f64);
+        gauge!("workers_total").set(TaskQueue::worker_count() as f64);
+        match scrape.subscription_manager.count_active().await {
+            Ok(count) => gauge!("active_subscriptions").set(count as f64),
+            Err(e) => log::warn!("Failed to count subscriptions for metrics: {}", e),
+        }
+        scrape.handle.render()
+    }
+
+    pub fn task_submitted(task_type: &TaskType) {
+        let (kind, format) = labels(task_type);
+        counter!("tasks_submitted_total", "type" => kind, "format" => format).increment(1);
+    }
+
+    /// A task left the worker; `error_kind` is its failure category if it failed
+    pub fn task_finished(task_type: &TaskType, error_kind: Option<&str>, elapsed: Duration) {
+        let (kind, format) = labels(task_type);
+        match error_kind {
+            None => {
+                counter!("tasks_completed_total", "type" => kind, "format" => format.clone()).increment(1);
+                histogram!("task_duration_seconds", "type" => kind, "format" => format).record(elapsed.as_secs_f64());
+            }
+            Some(error_kind) => {
+                counter!(
+                    "tasks_failed_total",
+                    "type" => kind,
+                    "format" => format,
+                    "error" => error_kind.to_string()
+                )
+                .increment(1);
+            }
+        }
+    }
+
+    pub fn download_finished(elapsed: Duration, ok: bool) {
+        let result = if ok { "ok" } else { "error" };
+        histogram!("download_duration_seconds", "result" => result).record(elapsed.as_secs_f64());
+    }
+
+    pub fn conversion_finished(format: &MediaFormatType, elapsed: Duration, ok: bool) {
+        let result = if ok { "ok" } else { "error" };
+        histogram!("conversion_duration_seconds", "format" => format.to_string(), "result" => result)
+            .record(elapsed.as_secs_f64());
+    }
+
+    fn labels(task_type: &TaskType) -> (&'static str, String) {
+        match task_type {
+            TaskType::Download { format, .. } => ("download", format.to_string()),
+            TaskType::Convert { format, .. } => ("convert", format.to_string()),
+        }
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod disabled {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::queue::{TaskQueue, TaskType};
+    use crate::subscription::SubscriptionManager;
+    use crate::utils::MediaFormatType;
+
+    pub async fn serve(_port: u16, _task_queue: Arc<TaskQueue>, _subscription_manager: Arc<SubscriptionManager>) {
+        log::warn!("METRICS_PORT is set, but the bot was built without the `metrics` feature");
+    }
+
+    pub fn task_submitted(_task_type: &TaskType) {}
+
+    pub fn task_finished(_task_type: &TaskType, _error_kind: Option<&str>, _elapsed: Duration) {}
+
+    pub fn download_finished(_elapsed: Duration, _ok: bool) {}
+
+    pub fn conversion_finished(_format: &MediaFormatType, _elapsed: Duration, _ok: bool) {}
+}
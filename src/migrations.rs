@@ -57,7 +57,7 @@ async fn handle_legacy_database(pool: &SqlitePool) -> BotResult<()> {
         let migrations: Vec<_> = MIGRATOR.iter().collect();
 
         // Mark migration 0001 as already applied (tables exist)
-        if let Some(m) = migrations.get(0) {
+        if let Some(m) = migrations.first() {
             sqlx::query(
                 "INSERT OR IGNORE INTO _sqlx_migrations (version, description, success, checksum, execution_time) VALUES (?, ?, ?, ?, ?)"
             )
@@ -74,22 +74,22 @@ async fn handle_legacy_database(pool: &SqlitePool) -> BotResult<()> {
         }
 
         // Check if format column already exists in pending_downloads
-        if column_exists(pool, "pending_downloads", "format").await {
-            if let Some(m) = migrations.get(1) {
-                sqlx::query(
-                    "INSERT OR IGNORE INTO _sqlx_migrations (version, description, success, checksum, execution_time) VALUES (?, ?, ?, ?, ?)"
-                )
-                .bind(m.version)
-                .bind(m.description.as_ref())
-                .bind(true)
-                .bind(m.checksum.as_ref())
-                .bind(0i64)
-                .execute(pool)
-                .await
-                .map_err(|e| BotError::general(format!("Failed to mark migration 0002: {}", e)))?;
-
-                log::info!("Marked migration {} as applied (column exists)", m.description);
-            }
+        if column_exists(pool, "pending_downloads", "format").await
+            && let Some(m) = migrations.get(1)
+        {
+            sqlx::query(
+                "INSERT OR IGNORE INTO _sqlx_migrations (version, description, success, checksum, execution_time) VALUES (?, ?, ?, ?, ?)"
+            )
+            .bind(m.version)
+            .bind(m.description.as_ref())
+            .bind(true)
+            .bind(m.checksum.as_ref())
+            .bind(0i64)
+            .execute(pool)
+            .await
+            .map_err(|e| BotError::general(format!("Failed to mark migration 0002: {}", e)))?;
+
+            log::info!("Marked migration {} as applied (column exists)", m.description);
         }
     }
 
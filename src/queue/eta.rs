@@ -0,0 +1,70 @@
+//! Time estimates for running tasks.
+//!
+//! Every finished task leaves a sample of how long it took and how long its media
+//! was, kept per kind of task (download or upload, and the target format). A new
+//! task is expected to take as long per second of media as the recent ones of its
+//! kind did; without a media length, as long as they took on average. Until a kind
+//! has finished once there's no estimate.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::TaskType;
+
+/// Samples kept per kind of task
+const SAMPLES_PER_KIND: usize = 20;
+
+struct Sample {
+    elapsed: Duration,
+    /// Length of the media, if it was known
+    media_secs: Option<f64>,
+}
+
+/// Recent processing times by kind of task
+#[derive(Default)]
+pub struct ProcessingHistory {
+    samples: Mutex<HashMap<String, VecDeque<Sample>>>,
+}
+
+impl ProcessingHistory {
+    /// Remember how long a successful task took
+    pub fn record(&self, task_type: &TaskType, elapsed: Duration, media_secs: Option<f64>) {
+        let mut samples = self.samples.lock().unwrap();
+        let kind = samples.entry(kind_of(task_type)).or_default();
+        if kind.len() == SAMPLES_PER_KIND {
+            kind.pop_front();
+        }
+        kind.push_back(Sample { elapsed, media_secs });
+    }
+
+    /// Expected total processing time of a task, None before any task of its kind finished
+    pub fn estimate(&self, task_type: &TaskType, media_secs: Option<f64>) -> Option<Duration> {
+        let samples = self.samples.lock().unwrap();
+        let kind = samples.get(&kind_of(task_type))?;
+
+        // Seconds of processing per second of media
+        let rates: Vec<f64> = kind
+            .iter()
+            .filter_map(|s| s.media_secs.filter(|&m| m > 0.0).map(|m| s.elapsed.as_secs_f64() / m))
+            .collect();
+        if let Some(media_secs) = media_secs.filter(|&m| m > 0.0)
+            && !rates.is_empty()
+        {
+            let rate = rates.iter().sum::<f64>() / rates.len() as f64;
+            return Some(Duration::from_secs_f64(rate * media_secs));
+        }
+
+        if kind.is_empty() {
+            return None;
+        }
+        Some(kind.iter().map(|s| s.elapsed).sum::<Duration>() / kind.len() as u32)
+    }
+}
+
+fn kind_of(task_type: &TaskType) -> String {
+    match task_type {
+        TaskType::Download { format, .. } => format!("download:{}", format),
+        TaskType::Convert { format, .. } => format!("convert:{}", format),
+    }
+}
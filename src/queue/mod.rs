@@ -1,17 +1,220 @@
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use teloxide::prelude::*;
-use teloxide::types::{ChatId, MessageId};
-use tokio::sync::{mpsc, Mutex, Semaphore};
+use teloxide::types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, MessageId};
+use tokio::sync::{mpsc, Mutex, Notify, Semaphore};
 
+use crate::callback::Callback;
 use crate::db::TaskDb;
-use crate::utils::MediaFormatType;
+use crate::errors::{BotError, StorageProblem};
+use crate::settings::{CustomFilters, SendOptions};
+use crate::utils::{
+    MediaFormatType, SendWithRetry, edit_status, format_keyboard_rows, LoadingScreen, reply_to,
+    send_transcript, youtube_video_id,
+};
+use crate::video::ProgressInfo;
+use crate::video::workdir;
+use crate::video::youtube::{AudioMetadata, VideoPassword, VideoSelection};
+
+use eta::ProcessingHistory;
+use scheduler::FairQueue;
+
+mod eta;
+mod scheduler;
 
 /// Maximum number of concurrent tasks (downloads + conversions)
 const MAX_CONCURRENT_TASKS: usize = 2;
 
+/// Number of recent task durations used to estimate wait time
+const DURATION_SAMPLES: usize = 20;
+
+/// How often to look for pending conversions that are about to expire
+const REMINDER_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// Name of the persisted maintenance mode flag
+const MAINTENANCE_FLAG: &str = "maintenance";
+
+/// Shown to users when new tasks are rejected because of maintenance
+pub const MAINTENANCE_MESSAGE: &str =
+    "🛠 Бот на техническом обслуживании, попробуйте чуть позже.";
+
+/// Shown to users who send links or videos faster than the submission cooldown allows
+pub const COOLDOWN_MESSAGE: &str = "⏳ Не так быстро, подождите пару секунд и отправьте снова.";
+
+/// Users tracked for the submission cooldown before stale entries are dropped early
+const MAX_COOLDOWN_ENTRIES: usize = 10_000;
+
+/// How often stale submission timestamps are dropped
+const COOLDOWN_CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How often to delete sent media whose auto-delete delay has passed
+const DELETION_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How often links and uploads waiting for a choice are checked against `FLOW_IDLE_MINUTES`
+const IDLE_FLOW_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often waiting tasks are checked against the queue wait limits
+const QUEUE_WAIT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often `shutdown` checks whether running tasks have finished
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How many times a failed download can be retried from its retry button
+pub const MAX_RETRIES: u32 = 3;
+
+/// Result of checking a chat's pending conversions against the limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingConversionCheck {
+    Allowed,
+    /// Allowed, but past the soft limit (`pending` includes the new one)
+    NearLimit { pending: usize, hard: usize },
+    /// At the hard limit, the new one must not be stored
+    Refused { pending: usize },
+}
+
+impl PendingConversionCheck {
+    /// Message to show the user, if any
+    pub fn message(&self) -> Option<String> {
+        match self {
+            PendingConversionCheck::Allowed => None,
+            PendingConversionCheck::NearLimit { pending, hard } => Some(format!(
+                "⚠️ У вас {} из {} видео ждут выбора формата. Выберите формат для старых, чтобы освободить место.",
+                pending, hard
+            )),
+            PendingConversionCheck::Refused { pending } => Some(format!(
+                "❌ У вас уже {} видео ждут выбора формата. Выберите формат для них или отмените их, прежде чем скачивать новые.",
+                pending
+            )),
+        }
+    }
+
+    /// Check whether a chat may store one more pending conversion
+    pub async fn for_chat(db: &TaskDb, chat_id: ChatId, premium: bool) -> Self {
+        let limits = crate::config::get().pending_conversion_limits(premium);
+
+        let pending = match db.count_pending_conversions(chat_id.0).await {
+            Ok(count) => count,
+            Err(e) => {
+                // Don't block users because of a database hiccup
+                log::error!("{}", e);
+                return PendingConversionCheck::Allowed;
+            }
+        };
+
+        if pending >= limits.hard {
+            PendingConversionCheck::Refused { pending }
+        } else if pending + 1 > limits.soft {
+            PendingConversionCheck::NearLimit {
+                pending: pending + 1,
+                hard: limits.hard,
+            }
+        } else {
+            PendingConversionCheck::Allowed
+        }
+    }
+}
+
+/// Why a task wasn't accepted by `submit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmitError {
+    /// Maintenance mode is on
+    Maintenance,
+    /// The queue is at capacity (`TASK_QUEUE_CAPACITY`)
+    QueueFull,
+    /// The chat already has the same download queued or running
+    Duplicate,
+}
+
+impl SubmitError {
+    /// Message to show the user
+    pub fn user_message(&self) -> &'static str {
+        match self {
+            SubmitError::Maintenance => MAINTENANCE_MESSAGE,
+            SubmitError::QueueFull => "⏳ Очередь переполнена, попробуйте через несколько минут.",
+            SubmitError::Duplicate => "⏳ Это видео уже скачивается в этом формате, результат придёт в сообщении выше.",
+        }
+    }
+}
+
+impl std::fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubmitError::Maintenance => write!(f, "bot is under maintenance"),
+            SubmitError::QueueFull => write!(f, "task queue is full"),
+            SubmitError::Duplicate => write!(f, "same download is already in progress"),
+        }
+    }
+}
+
+/// Why a failed task couldn't be retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryError {
+    /// The task expired, was already retried, or its uploaded file is gone
+    NotFound,
+    /// The task was retried MAX_RETRIES times already
+    TooManyRetries,
+    /// The queue didn't accept the task
+    Submit(SubmitError),
+}
+
+impl RetryError {
+    /// Message to show the user
+    pub fn user_message(&self) -> &'static str {
+        match self {
+            RetryError::NotFound => "Эту задачу уже нельзя повторить, отправьте ссылку или видео заново.",
+            RetryError::TooManyRetries => "Попытки закончились, попробуйте позже или отправьте другую ссылку.",
+            RetryError::Submit(e) => e.user_message(),
+        }
+    }
+}
+
+/// Where a submitted task ended up in the queue
+#[derive(Debug, Clone, Copy)]
+pub struct QueuePosition {
+    /// Position among waiting tasks, 1-based
+    pub position: usize,
+    /// Tasks that have to finish before this one gets a free worker
+    pub tasks_ahead: usize,
+    /// Estimated time until the task starts, based on recent task durations
+    pub estimated_wait: Option<Duration>,
+}
+
+impl QueuePosition {
+    /// Whether a worker is free right away
+    pub fn starts_immediately(&self) -> bool {
+        self.tasks_ahead == 0
+    }
+}
+
+impl std::fmt::Display for QueuePosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "позиция: {}", self.position)?;
+        if let Some(wait) = self.estimated_wait {
+            // Round up so short waits don't show as "0 мин"
+            write!(f, ", ожидание ~{} мин", wait.as_secs().div_ceil(60).max(1))?;
+        }
+        Ok(())
+    }
+}
+
+/// What /forget removed for a chat
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ForgetSummary {
+    /// Uploads that were waiting for a format
+    pub uploads: usize,
+    /// Links that were waiting for a format or quality
+    pub links: usize,
+    /// Failed tasks, with the files kept for retrying them
+    pub failed_tasks: u64,
+    /// Entries of the chat's /history
+    pub history: u64,
+    /// Bytes freed on disk
+    pub bytes: u64,
+}
+
 /// Short ID for callback data (8 chars max)
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ShortId(pub String);
@@ -23,6 +226,12 @@ impl ShortId {
     }
 }
 
+impl Default for ShortId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for ShortId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -37,6 +246,14 @@ pub struct PendingDownload {
     pub message_id: MessageId,
     /// Selected format (set after format selection)
     pub format: Option<MediaFormatType>,
+    /// Spoiler/protection toggles chosen on the format selection step
+    pub options: SendOptions,
+    /// Password of a protected video. Never stored, so it's gone after a restart.
+    pub password: Option<VideoPassword>,
+    /// Last time the user did something with it, for `FLOW_IDLE_MINUTES`
+    pub last_activity: Instant,
+    /// Whether the user was warned that it's about to be dropped for inactivity
+    pub idle_warned: bool,
 }
 
 /// Pending conversion waiting for format selection
@@ -46,6 +263,10 @@ pub struct PendingConversion {
     pub thumbnail_path: Option<String>,
     pub chat_id: ChatId,
     pub message_id: MessageId,
+    /// Last time the user did something with it, for `FLOW_IDLE_MINUTES`
+    pub last_activity: Instant,
+    /// Whether the user was warned that it's about to be dropped for inactivity
+    pub idle_warned: bool,
 }
 
 /// Unique task identifier
@@ -62,6 +283,12 @@ impl TaskId {
     }
 }
 
+impl Default for TaskId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for TaskId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -78,15 +305,37 @@ pub enum TaskType {
         quality: Option<u32>,
         /// Target format for conversion after download
         format: MediaFormatType,
+        /// Audio track language for dubbed videos. None for the default track.
+        audio_language: Option<String>,
+        /// User's own yt-dlp format expression, used instead of `quality`
+        format_string: Option<String>,
+        /// Largest download in bytes: the best streams that fit, up to `quality`
+        size_budget: Option<u64>,
+        /// Name for the sent file, see `settings::FILENAME_PLACEHOLDERS`
+        filename_template: Option<String>,
+        /// Password of a protected video, kept in memory only: not saved with the task
+        password: Option<VideoPassword>,
     },
-    /// Convert downloaded video to specific format (legacy, for direct uploads)
+    /// Convert an already downloaded or uploaded video to a specific format
     Convert {
         filename: String,
         thumbnail_path: Option<String>,
         format: MediaFormatType,
+        /// Height to scale the video down to before sending. None keeps it as is.
+        max_height: Option<u32>,
     },
 }
 
+impl TaskType {
+    /// Password the video of a download is fetched with
+    pub fn video_password(&self) -> Option<&VideoPassword> {
+        match self {
+            TaskType::Download { password, .. } => password.as_ref(),
+            TaskType::Convert { .. } => None,
+        }
+    }
+}
+
 /// A task in the queue
 #[derive(Debug, Clone)]
 pub struct Task {
@@ -94,7 +343,17 @@ pub struct Task {
     pub task_type: TaskType,
     pub chat_id: ChatId,
     pub message_id: MessageId,
+    /// The user's request the result is sent in reply to, None if it's not known
+    pub reply_to: Option<MessageId>,
     pub unique_file_id: String,
+    /// How the result should be sent (spoiler, content protection)
+    pub options: SendOptions,
+    /// The user's own ffmpeg filters, run before the conversion
+    pub filters: CustomFilters,
+    /// The user's chat or channel the result is also posted to (see /channel)
+    pub target_chat: Option<ChatId>,
+    /// Submitted by a subscriber, scheduled ahead of other users' tasks
+    pub premium: bool,
 }
 
 /// Task status for tracking
@@ -107,7 +366,160 @@ pub enum TaskStatus {
     /// Completed successfully
     Completed,
     /// Failed with error
-    Failed(String),
+    Failed(TaskError),
+}
+
+/// Category of a task failure, shown to users instead of internal error text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::AsRefStr, strum::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum TaskErrorKind {
+    DownloadFailed,
+    ConversionFailed,
+    SendFailed,
+    Timeout,
+    TooLarge,
+    Cancelled,
+    /// The bot's disk is full or read-only, see `StorageProblem`
+    StorageUnavailable,
+}
+
+impl TaskErrorKind {
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            TaskErrorKind::DownloadFailed => "📥",
+            TaskErrorKind::ConversionFailed => "⚙️",
+            TaskErrorKind::SendFailed => "📤",
+            TaskErrorKind::Timeout => "⏱",
+            TaskErrorKind::TooLarge => "📦",
+            TaskErrorKind::Cancelled => "🚫",
+            TaskErrorKind::StorageUnavailable => "💾",
+        }
+    }
+
+    /// User-facing description of the failure
+    pub fn message(&self) -> &'static str {
+        match self {
+            TaskErrorKind::DownloadFailed => "не удалось скачать",
+            TaskErrorKind::ConversionFailed => "ошибка конвертации",
+            TaskErrorKind::SendFailed => "не удалось отправить",
+            TaskErrorKind::Timeout => "превышено время ожидания",
+            TaskErrorKind::TooLarge => "файл слишком большой",
+            TaskErrorKind::Cancelled => "отменено",
+            TaskErrorKind::StorageUnavailable => "сервис временно недоступен",
+        }
+    }
+}
+
+/// Why a task failed: a category for users and the internal details for logs
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskError {
+    pub kind: TaskErrorKind,
+    pub details: String,
+}
+
+impl TaskError {
+    pub fn new(kind: TaskErrorKind, details: impl ToString) -> Self {
+        Self {
+            kind,
+            details: details.to_string(),
+        }
+    }
+
+    /// Categorize an internal error. `stage` is the category used when the error itself
+    /// doesn't say more, e.g. `DownloadFailed` for errors from the download step.
+    pub fn from_bot_error(stage: TaskErrorKind, error: BotError) -> Self {
+        let kind = match &error {
+            BotError::Timeout(_) => TaskErrorKind::Timeout,
+            BotError::FileTooLarge(_) => TaskErrorKind::TooLarge,
+            _ if error.storage_problem().is_some() => TaskErrorKind::StorageUnavailable,
+            _ => stage,
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl std::fmt::Display for TaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind.as_ref(), self.details)
+    }
+}
+
+/// Short description of what a task does, shown in /queue
+#[derive(Debug, Clone)]
+pub enum TaskDescription {
+    Download {
+        quality: Option<u32>,
+        format: MediaFormatType,
+    },
+    Convert {
+        format: MediaFormatType,
+    },
+}
+
+impl TaskDescription {
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            TaskDescription::Download { .. } => "📥",
+            TaskDescription::Convert { format } => format.emoji(),
+        }
+    }
+}
+
+impl std::fmt::Display for TaskDescription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskDescription::Download {
+                quality: Some(q),
+                format,
+            } => write!(f, "{}p {}", q, format),
+            TaskDescription::Download {
+                quality: None,
+                format,
+            } => write!(f, "{}", format),
+            TaskDescription::Convert { format } => write!(f, "{}", format),
+        }
+    }
+}
+
+impl From<&TaskType> for TaskDescription {
+    fn from(task_type: &TaskType) -> Self {
+        match task_type {
+            TaskType::Download {
+                quality, format, ..
+            } => TaskDescription::Download {
+                quality: *quality,
+                format: format.clone(),
+            },
+            TaskType::Convert { format, .. } => TaskDescription::Convert {
+                format: format.clone(),
+            },
+        }
+    }
+}
+
+/// Context of a chat's last failed task, kept so the user can report it to the admin
+#[derive(Debug, Clone)]
+pub struct FailureReport {
+    pub task_id: TaskId,
+    /// Source link, for downloads
+    pub url: Option<String>,
+    pub description: TaskDescription,
+    pub error: TaskError,
+}
+
+/// When a task entered the queue, for the wait notice and limit
+struct QueueWait {
+    since: Instant,
+    /// The user was told the task is still waiting
+    noticed: bool,
+}
+
+/// A task a worker is busy with
+struct RunningTask {
+    /// Status message of the task
+    message_id: MessageId,
+    /// Signalled to abort the task
+    cancel: Arc<Notify>,
 }
 
 /// Information about a queued task for the user
@@ -115,13 +527,69 @@ pub enum TaskStatus {
 pub struct QueuedTaskInfo {
     pub task_id: TaskId,
     pub status: TaskStatus,
-    pub task_type: String,
+    pub description: TaskDescription,
+    /// Progress of the current phase (download or conversion), 0-100
+    pub progress: Option<u8>,
+}
+
+/// Records progress updates of a running task so /queue can show them
+#[derive(Clone)]
+struct ProgressTracker {
+    task_id: TaskId,
+    task_statuses: Arc<Mutex<HashMap<TaskId, QueuedTaskInfo>>>,
+    /// When the worker picked the task up
+    started_at: Instant,
+    /// Expected time for the whole task from similar finished ones, see `eta`
+    estimate: Option<Duration>,
+}
+
+impl ProgressTracker {
+    /// Create a progress sender for a phase of the task.
+    /// Updates are recorded for /queue and passed on to `forward_to` (e.g. a loading screen).
+    /// Phases that can't tell how long they'll take get the whole task's remaining time.
+    fn sender(
+        &self,
+        forward_to: Option<mpsc::UnboundedSender<ProgressInfo>>,
+    ) -> mpsc::UnboundedSender<ProgressInfo> {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<ProgressInfo>();
+        let tracker = self.clone();
+
+        tokio::spawn(async move {
+            while let Some(mut progress) = receiver.recv().await {
+                if progress.estimated_time_remaining.is_none() {
+                    progress.estimated_time_remaining = tracker.remaining();
+                }
+                tracker.set(progress.percentage).await;
+                if let Some(forward_to) = &forward_to {
+                    let _ = forward_to.send(progress);
+                }
+            }
+        });
+
+        sender
+    }
+
+    /// Time left by the estimate; None without one, or once the task runs longer than expected
+    fn remaining(&self) -> Option<Duration> {
+        self.estimate?
+            .checked_sub(self.started_at.elapsed())
+            .filter(|left| !left.is_zero())
+    }
+
+    async fn set(&self, percentage: f32) {
+        let mut statuses = self.task_statuses.lock().await;
+        if let Some(info) = statuses.get_mut(&self.task_id) {
+            info.progress = Some(percentage.clamp(0.0, 100.0) as u8);
+        }
+    }
 }
 
 /// Global task queue manager
 pub struct TaskQueue {
-    /// Channel sender for submitting tasks
-    sender: mpsc::UnboundedSender<Task>,
+    /// Tasks waiting for a worker, in fair order
+    waiting: std::sync::Mutex<FairQueue>,
+    /// Wakes the worker when a task is submitted or a chat drops below its running limit
+    task_ready: Arc<Notify>,
     /// Semaphore to limit concurrent tasks
     semaphore: Arc<Semaphore>,
     /// Track tasks per user for status queries
@@ -130,10 +598,29 @@ pub struct TaskQueue {
     task_statuses: Arc<Mutex<HashMap<TaskId, QueuedTaskInfo>>>,
     /// Number of tasks waiting in queue (not yet being processed)
     pending_count: Arc<AtomicUsize>,
+    /// Durations of recently finished tasks, for wait time estimates
+    recent_durations: Arc<std::sync::Mutex<VecDeque<Duration>>>,
+    /// Processing times by kind of task, for the remaining time of running tasks
+    processing_history: Arc<ProcessingHistory>,
     /// Pending downloads waiting for quality selection (short_id -> PendingDownload)
     pending_downloads: Arc<Mutex<HashMap<String, PendingDownload>>>,
     /// Pending conversions waiting for format selection (short_id -> PendingConversion)
     pending_conversions: Arc<Mutex<HashMap<String, PendingConversion>>>,
+    /// Last failed task per chat, until the user reports it
+    last_failures: Arc<Mutex<HashMap<ChatId, FailureReport>>>,
+    /// Tasks being processed right now, so they can be cancelled
+    running: Arc<Mutex<HashMap<TaskId, RunningTask>>>,
+    /// When each waiting task was queued
+    queue_waits: std::sync::Mutex<HashMap<TaskId, QueueWait>>,
+    /// Number of running tasks per chat, for the per-user limit
+    active_per_chat: Arc<std::sync::Mutex<HashMap<ChatId, usize>>>,
+    /// When each user last sent a link or video, for the submission cooldown
+    last_submissions: std::sync::Mutex<HashMap<i64, Instant>>,
+    /// Held from the duplicate check until the task is saved, so the same link sent
+    /// twice at once can't pass the check both times
+    saving: Mutex<()>,
+    /// New tasks are rejected while this is set, running ones still finish
+    maintenance: AtomicBool,
     /// Database for persistence
     db: TaskDb,
 }
@@ -141,7 +628,6 @@ pub struct TaskQueue {
 impl TaskQueue {
     /// Create a new task queue and start the worker
     pub async fn new(bot: Bot, db: TaskDb) -> Arc<Self> {
-        let (sender, receiver) = mpsc::unbounded_channel();
         let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_TASKS));
         let user_tasks = Arc::new(Mutex::new(HashMap::new()));
         let task_statuses = Arc::new(Mutex::new(HashMap::new()));
@@ -160,6 +646,11 @@ impl TaskQueue {
                         chat_id: ChatId(row.chat_id),
                         message_id: MessageId(row.message_id),
                         format: row.format,
+                        options: row.options,
+                        password: None,
+                        // Idle time isn't persisted, a restart starts it over
+                        last_activity: Instant::now(),
+                        idle_warned: false,
                     },
                 );
             }
@@ -176,40 +667,72 @@ impl TaskQueue {
                         thumbnail_path: row.thumbnail_path,
                         chat_id: ChatId(row.chat_id),
                         message_id: MessageId(row.message_id),
+                        last_activity: Instant::now(),
+                        idle_warned: false,
                     },
                 );
             }
             log::info!("Loaded {} pending conversions from database", pc.len());
         }
 
+        let maintenance = db.get_flag(MAINTENANCE_FLAG).await.unwrap_or_else(|e| {
+            log::error!("{}", e);
+            false
+        });
+        if maintenance {
+            log::warn!("Maintenance mode is on, new tasks will be rejected");
+        }
+
         let queue = Arc::new(Self {
-            sender,
+            waiting: std::sync::Mutex::new(FairQueue::default()),
+            task_ready: Arc::new(Notify::new()),
             semaphore,
             user_tasks,
             task_statuses,
             pending_count,
+            recent_durations: Arc::new(std::sync::Mutex::new(VecDeque::new())),
+            processing_history: Arc::new(ProcessingHistory::default()),
             pending_downloads,
             pending_conversions,
+            last_failures: Arc::new(Mutex::new(HashMap::new())),
+            running: Arc::new(Mutex::new(HashMap::new())),
+            queue_waits: std::sync::Mutex::new(HashMap::new()),
+            active_per_chat: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            last_submissions: std::sync::Mutex::new(HashMap::new()),
+            saving: Mutex::new(()),
+            maintenance: AtomicBool::new(maintenance),
             db,
         });
 
         // Start the worker
         let queue_clone = queue.clone();
         tokio::spawn(async move {
-            queue_clone.run_worker(receiver, bot).await;
+            queue_clone.run_worker(bot).await;
         });
 
         queue
     }
 
     /// Store a pending download and return short ID for callback
-    pub async fn add_pending_download(&self, url: String, chat_id: ChatId, message_id: MessageId, format: Option<MediaFormatType>) -> ShortId {
+    pub async fn add_pending_download(
+        &self,
+        url: String,
+        chat_id: ChatId,
+        message_id: MessageId,
+        format: Option<MediaFormatType>,
+        options: SendOptions,
+        password: Option<VideoPassword>,
+    ) -> ShortId {
         let short_id = ShortId::new();
         let pending = PendingDownload {
             url: url.clone(),
             chat_id,
             message_id,
             format: format.clone(),
+            options,
+            password,
+            last_activity: Instant::now(),
+            idle_warned: false,
         };
 
         // Save to database
@@ -219,6 +742,7 @@ impl TaskQueue {
             chat_id.0,
             message_id.0,
             format.as_ref().map(|f| f.to_string()).as_deref(),
+            options,
         ).await {
             log::error!("Failed to save pending download to DB: {}", e);
         }
@@ -245,6 +769,8 @@ impl TaskQueue {
         let mut pending_downloads = self.pending_downloads.lock().await;
         if let Some(pending) = pending_downloads.get_mut(short_id) {
             pending.format = Some(format.clone());
+            pending.last_activity = Instant::now();
+            pending.idle_warned = false;
 
             // Update in database
             if let Err(e) = self.db.update_pending_download_format(short_id, &format.to_string()).await {
@@ -257,12 +783,103 @@ impl TaskQueue {
         }
     }
 
+    /// Update send options for a pending download
+    pub async fn update_pending_download_options(&self, short_id: &str, options: SendOptions) -> Option<()> {
+        let mut pending_downloads = self.pending_downloads.lock().await;
+        let pending = pending_downloads.get_mut(short_id)?;
+        pending.options = options;
+        pending.last_activity = Instant::now();
+        pending.idle_warned = false;
+
+        // Update in database
+        if let Err(e) = self.db.update_pending_download_options(short_id, options).await {
+            log::error!("Failed to update pending download options in DB: {}", e);
+        }
+
+        Some(())
+    }
+
+    /// Point a pending download at the message its format selection is now shown in
+    pub async fn move_pending_download(&self, short_id: &str, message_id: MessageId) -> Option<()> {
+        let mut pending_downloads = self.pending_downloads.lock().await;
+        let pending = pending_downloads.get_mut(short_id)?;
+        pending.message_id = message_id;
+        pending.last_activity = Instant::now();
+        pending.idle_warned = false;
+
+        // Update in database
+        if let Err(e) = self.db.update_pending_download_message(short_id, message_id.0).await {
+            log::error!("Failed to update pending download message in DB: {}", e);
+        }
+
+        Some(())
+    }
+
     /// Get a pending download without removing it
     pub async fn get_pending_download(&self, short_id: &str) -> Option<PendingDownload> {
         let pending_downloads = self.pending_downloads.lock().await;
         pending_downloads.get(short_id).cloned()
     }
 
+    /// A pending download of the same link in the chat that's still waiting for a choice
+    pub async fn find_pending_download(&self, chat_id: ChatId, url: &str) -> Option<(String, PendingDownload)> {
+        let short_id = match self.db.find_pending_download(chat_id.0, url).await {
+            Ok(short_id) => short_id?,
+            Err(e) => {
+                log::error!("{}", e);
+                return None;
+            }
+        };
+
+        let pending = self.get_pending_download(&short_id).await?;
+        Some((short_id, pending))
+    }
+
+    /// Check whether a chat may store one more pending conversion.
+    /// Callers should do this before downloading a file that will wait for a format choice.
+    pub async fn check_pending_conversion_limit(&self, chat_id: ChatId, premium: bool) -> PendingConversionCheck {
+        PendingConversionCheck::for_chat(&self.db, chat_id, premium).await
+    }
+
+    /// Check the user's submission cooldown and start a new one if they may submit.
+    /// Returns false if the user's last link or video was too recent.
+    pub fn check_submit_cooldown(&self, user_id: i64, premium: bool) -> bool {
+        let cooldown = crate::config::get().submit_cooldown(premium);
+        if cooldown.is_zero() {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut last_submissions = self.last_submissions.lock().unwrap();
+        if let Some(last) = last_submissions.get(&user_id)
+            && now.duration_since(*last) < cooldown
+        {
+            return false;
+        }
+
+        // Keep the map bounded during floods from many users, even between cleanups
+        if last_submissions.len() >= MAX_COOLDOWN_ENTRIES {
+            prune_submissions(&mut last_submissions, now);
+        }
+        if last_submissions.len() < MAX_COOLDOWN_ENTRIES {
+            last_submissions.insert(user_id, now);
+        }
+
+        true
+    }
+
+    /// Periodically drop submission timestamps whose cooldown has passed
+    pub fn start_cooldown_cleanup(self: &Arc<Self>) {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(COOLDOWN_CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                prune_submissions(&mut queue.last_submissions.lock().unwrap(), Instant::now());
+            }
+        });
+    }
+
     /// Store a pending conversion and return short ID for callback
     pub async fn add_pending_conversion(&self, filename: String, thumbnail_path: Option<String>, chat_id: ChatId, message_id: MessageId) -> ShortId {
         let short_id = ShortId::new();
@@ -271,6 +888,8 @@ impl TaskQueue {
             thumbnail_path: thumbnail_path.clone(),
             chat_id,
             message_id,
+            last_activity: Instant::now(),
+            idle_warned: false,
         };
 
         // Save to database
@@ -290,6 +909,21 @@ impl TaskQueue {
         short_id
     }
 
+    /// Get a pending conversion by short ID, leaving it in place
+    pub async fn get_pending_conversion(&self, short_id: &str) -> Option<PendingConversion> {
+        let pending_conversions = self.pending_conversions.lock().await;
+        pending_conversions.get(short_id).cloned()
+    }
+
+    /// Note that the user is still choosing a format for a pending conversion, so the
+    /// idle timeout starts over
+    pub async fn touch_pending_conversion(&self, short_id: &str) {
+        if let Some(pending) = self.pending_conversions.lock().await.get_mut(short_id) {
+            pending.last_activity = Instant::now();
+            pending.idle_warned = false;
+        }
+    }
+
     /// Get and remove a pending conversion by short ID
     pub async fn take_pending_conversion(&self, short_id: &str) -> Option<PendingConversion> {
         // Delete from database
@@ -301,26 +935,255 @@ impl TaskQueue {
         pending_conversions.remove(short_id)
     }
 
+    /// Drop a chat's pending conversion together with its files, e.g. from /pending.
+    /// Returns false if there's no such conversion in the chat.
+    pub async fn discard_pending_conversion(&self, chat_id: ChatId, short_id: &str) -> bool {
+        match self.get_pending_conversion(short_id).await {
+            Some(pending) if pending.chat_id == chat_id => {}
+            _ => return false,
+        }
+        let Some(pending) = self.take_pending_conversion(short_id).await else {
+            return false;
+        };
+
+        log::info!("Discarding pending conversion {} of chat {}", short_id, chat_id);
+        if let Some(thumbnail) = &pending.thumbnail_path {
+            let _ = tokio::fs::remove_file(thumbnail).await;
+        }
+        crate::video::workdir::remove_task_of(&pending.filename).await;
+        true
+    }
+
+    /// Delete everything the bot keeps for a chat between tasks: uploads waiting for a
+    /// format, links waiting for a choice and files kept for retrying failed tasks.
+    /// Queued and running tasks are left alone, they clean up after themselves.
+    pub async fn forget_chat(&self, chat_id: ChatId) -> ForgetSummary {
+        let mut summary = ForgetSummary::default();
+        let mut files = Vec::new();
+
+        let conversions: Vec<String> = self
+            .pending_conversions
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, pending)| pending.chat_id == chat_id)
+            .map(|(short_id, _)| short_id.clone())
+            .collect();
+        for short_id in conversions {
+            if let Some(pending) = self.take_pending_conversion(&short_id).await {
+                files.push(pending.filename);
+                files.extend(pending.thumbnail_path);
+                summary.uploads += 1;
+            }
+        }
+
+        let downloads: Vec<String> = self
+            .pending_downloads
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, pending)| pending.chat_id == chat_id)
+            .map(|(short_id, _)| short_id.clone())
+            .collect();
+        for short_id in downloads {
+            if self.take_pending_download(&short_id).await.is_some() {
+                summary.links += 1;
+            }
+        }
+
+        match self.db.delete_failed_tasks(chat_id.0).await {
+            Ok((count, failed_files)) => {
+                summary.failed_tasks = count;
+                files.extend(failed_files);
+            }
+            Err(e) => log::error!("{}", e),
+        }
+        self.last_failures.lock().await.remove(&chat_id);
+
+        match self.db.clear_history(chat_id.0).await {
+            Ok(count) => summary.history = count,
+            Err(e) => log::error!("{}", e),
+        }
+
+        // Sources and their thumbnails often share a task folder, count each folder once
+        let mut removed = std::collections::HashSet::new();
+        for file in files {
+            let key = workdir::task_id_of(std::path::Path::new(&file)).unwrap_or_else(|| file.clone());
+            if removed.insert(key) {
+                summary.bytes += workdir::task_size_of(&file).await;
+                workdir::remove_task_of(&file).await;
+            }
+        }
+
+        log::info!("Forgot chat {}: {:?}", chat_id, summary);
+        summary
+    }
+
+    /// Take the failure context for a report, if `task_id` is still the chat's last failure
+    pub async fn take_failure_report(&self, chat_id: ChatId, task_id: &str) -> Option<FailureReport> {
+        let mut failures = self.last_failures.lock().await;
+        if failures.get(&chat_id)?.task_id.0 != task_id {
+            return None;
+        }
+        failures.remove(&chat_id)
+    }
+
+    /// Whether new tasks are currently rejected
+    pub fn is_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::SeqCst)
+    }
+
+    /// Turn maintenance mode on or off (persisted across restarts)
+    pub async fn set_maintenance(&self, enabled: bool) -> Result<(), String> {
+        self.maintenance.store(enabled, Ordering::SeqCst);
+        self.db.set_flag(MAINTENANCE_FLAG, enabled).await
+    }
+
     /// Submit a task to the queue
-    pub async fn submit(&self, task: Task) -> Result<usize, String> {
-        // Position is number of tasks already waiting + 1
-        let position = self.pending_count.fetch_add(1, Ordering::SeqCst) + 1;
+    pub async fn submit(&self, task: Task) -> Result<QueuePosition, SubmitError> {
+        self.enqueue(task, 0).await
+    }
+
+    /// Run a failed task again with the parameters saved in the database.
+    /// Uploads are converted again from the file kept after the failure.
+    /// The new task reports to the same status message as the failed one.
+    pub async fn retry_failed(
+        &self,
+        chat_id: ChatId,
+        task_id: &str,
+        premium: bool,
+    ) -> Result<QueuePosition, RetryError> {
+        use std::str::FromStr;
+
+        let row = match self.db.get_failed_task(task_id).await {
+            Ok(row) => row,
+            Err(e) => {
+                log::error!("{}", e);
+                None
+            }
+        };
+        let Some(row) = row.filter(|r| r.chat_id == chat_id.0) else {
+            return Err(RetryError::NotFound);
+        };
+        let Some(format) = row.format.and_then(|f| MediaFormatType::from_str(&f).ok()) else {
+            return Err(RetryError::NotFound);
+        };
+
+        if row.retries >= MAX_RETRIES {
+            return Err(RetryError::TooManyRetries);
+        }
+
+        let task_type = match (row.task_type.as_str(), row.url, row.filename) {
+            ("download", Some(url), _) => TaskType::Download {
+                url,
+                quality: row.quality.map(|q| q as u32),
+                format,
+                audio_language: row.audio_language,
+                // Custom format expressions are a subscriber feature
+                format_string: row.format_string.filter(|_| premium),
+                filename_template: row.filename_template,
+                size_budget: row.size_budget.map(|b| b as u64),
+                // Passwords aren't kept, a protected video has to be sent again
+                password: None,
+            },
+            // Uploads can't be fetched again, only retried while their file is kept
+            ("convert", _, Some(filename)) if tokio::fs::try_exists(&filename).await.unwrap_or(false) => {
+                TaskType::Convert {
+                    filename,
+                    thumbnail_path: row.thumbnail_path,
+                    format,
+                    max_height: row.quality.map(|q| q as u32),
+                }
+            }
+            _ => return Err(RetryError::NotFound),
+        };
+
+        let task = Task {
+            id: TaskId::new(),
+            task_type,
+            chat_id,
+            message_id: MessageId(row.message_id),
+            reply_to: row.reply_to_message_id.map(MessageId),
+            unique_file_id: row.unique_file_id,
+            options: row.options,
+            filters: if premium { row.filters } else { CustomFilters::default() },
+            target_chat: row.target_chat_id.map(ChatId),
+            premium,
+        };
+
+        let position = self
+            .enqueue(task, row.retries + 1)
+            .await
+            .map_err(RetryError::Submit)?;
+
+        // The new task carries the retry count on, so the button works only once
+        if let Err(e) = self.db.delete_task(&row.id).await {
+            log::error!("Failed to delete retried task: {}", e);
+        }
+
+        Ok(position)
+    }
+
+    /// Save a task and put it in the schedule; `retries` counts earlier failed runs
+    async fn enqueue(&self, task: Task, retries: u32) -> Result<QueuePosition, SubmitError> {
+        if self.is_maintenance() {
+            return Err(SubmitError::Maintenance);
+        }
+
+        // Hold a place first, so a full queue rejects the task before anything is tracked
+        if !self.waiting.lock().unwrap().try_reserve(crate::config::get().task_queue_capacity) {
+            return Err(SubmitError::QueueFull);
+        }
+
+        // Don't do the same work twice when a link is sent again while it's still in progress
+        let saving = self.saving.lock().await;
+        if let TaskType::Download { url, quality, format, .. } = &task.task_type {
+            match self
+                .db
+                .has_active_download(task.chat_id.0, url, quality.map(|q| q as i32), &format.to_string())
+                .await
+            {
+                Ok(true) => {
+                    self.waiting.lock().unwrap().release();
+                    return Err(SubmitError::Duplicate);
+                }
+                Ok(false) => {}
+                Err(e) => log::error!("{}", e),
+            }
+        }
 
         // Save task to database
         let (task_type_str, url, quality, filename, thumbnail_path, format) = match &task.task_type {
-            TaskType::Download { url, quality, format } => {
+            TaskType::Download { url, quality, format, .. } => {
                 ("download", Some(url.as_str()), quality.map(|q| q as i32), None, None, Some(format.to_string()))
             }
-            TaskType::Convert { filename, thumbnail_path, format } => {
-                ("convert", None, None, Some(filename.as_str()), thumbnail_path.as_deref(), Some(format.to_string()))
+            TaskType::Convert { filename, thumbnail_path, format, max_height } => {
+                (
+                    "convert",
+                    None,
+                    max_height.map(|h| h as i32),
+                    Some(filename.as_str()),
+                    thumbnail_path.as_deref(),
+                    Some(format.to_string()),
+                )
             }
         };
+        let (audio_language, format_string, filename_template, size_budget) = match &task.task_type {
+            TaskType::Download { audio_language, format_string, filename_template, size_budget, .. } => (
+                audio_language.as_deref(),
+                format_string.as_deref(),
+                filename_template.as_deref(),
+                size_budget.map(|b| b as i64),
+            ),
+            TaskType::Convert { .. } => (None, None, None, None),
+        };
 
         if let Err(e) = self.db.insert_task(
             &task.id.0,
             task_type_str,
             task.chat_id.0,
             task.message_id.0,
+            task.reply_to.map(|id| id.0),
             &task.unique_file_id,
             "queued",
             url,
@@ -328,9 +1191,18 @@ impl TaskQueue {
             filename,
             thumbnail_path,
             format.as_deref(),
+            audio_language,
+            format_string,
+            filename_template,
+            size_budget,
+            &task.filters,
+            task.target_chat.map(|id| id.0),
+            task.options,
+            retries,
         ).await {
             log::error!("Failed to save task to DB: {}", e);
         }
+        drop(saving);
 
         // Track task for user
         {
@@ -344,30 +1216,93 @@ impl TaskQueue {
         // Track task status
         {
             let mut statuses = self.task_statuses.lock().await;
-            let task_type = match &task.task_type {
-                TaskType::Download { quality, format, .. } => {
-                    match quality {
-                        Some(q) => format!("📥 {}p {}", q, format),
-                        None => format!("📥 {}", format),
-                    }
-                }
-                TaskType::Convert { format, .. } => format!("{} {}", format.emoji(), format),
-            };
             statuses.insert(
                 task.id.clone(),
                 QueuedTaskInfo {
                     task_id: task.id.clone(),
-                    status: TaskStatus::Queued { position },
-                    task_type,
+                    // Set below, once the task has its place in the schedule
+                    status: TaskStatus::Queued { position: 0 },
+                    description: TaskDescription::from(&task.task_type),
+                    progress: None,
                 },
             );
         }
 
-        self.sender
-            .send(task)
-            .map_err(|e| format!("Failed to submit task: {}", e))?;
+        crate::metrics::task_submitted(&task.task_type);
+        let task_id = task.id.clone();
+        self.queue_waits.lock().unwrap().insert(
+            task_id.clone(),
+            QueueWait {
+                since: Instant::now(),
+                noticed: false,
+            },
+        );
+        let position = {
+            let mut waiting = self.waiting.lock().unwrap();
+            waiting.push_reserved(task);
+            waiting.position(&task_id).unwrap_or(waiting.len())
+        };
+        self.pending_count.fetch_add(1, Ordering::SeqCst);
+        self.task_ready.notify_one();
 
-        Ok(position)
+        // The worker may have picked the task up already
+        {
+            let mut statuses = self.task_statuses.lock().await;
+            if let Some(info) = statuses.get_mut(&task_id)
+                && matches!(info.status, TaskStatus::Queued { .. })
+            {
+                info.status = TaskStatus::Queued { position };
+            }
+        }
+
+        Ok(self.estimate_position(position))
+    }
+
+    /// Wait for the next task in the fair schedule whose chat is below its running limit
+    async fn next_task(&self) -> Task {
+        loop {
+            // Register interest before checking, so a submit in between isn't missed
+            let notified = self.task_ready.notified();
+            {
+                let config = crate::config::get();
+                let mut active = self.active_per_chat.lock().unwrap();
+                let task = self.waiting.lock().unwrap().pop(|task| {
+                    active.get(&task.chat_id).copied().unwrap_or(0) < config.max_active_tasks(task.premium)
+                });
+                if let Some(task) = task {
+                    *active.entry(task.chat_id).or_default() += 1;
+                    self.queue_waits.lock().unwrap().remove(&task.id);
+                    return task;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Work out how long a task at `position` among waiting tasks has to wait,
+    /// given that up to MAX_CONCURRENT_TASKS run at once
+    fn estimate_position(&self, position: usize) -> QueuePosition {
+        let running = MAX_CONCURRENT_TASKS - self.semaphore.available_permits();
+
+        // Running tasks and waiting tasks ahead take workers first;
+        // this many of them must finish before a worker is free for us
+        let tasks_ahead = (running + position).saturating_sub(MAX_CONCURRENT_TASKS);
+
+        // Each round of MAX_CONCURRENT_TASKS finished tasks frees one worker for us
+        let average = {
+            let durations = self.recent_durations.lock().unwrap();
+            (!durations.is_empty())
+                .then(|| durations.iter().sum::<Duration>() / durations.len() as u32)
+        };
+        let estimated_wait = average
+            .filter(|_| tasks_ahead > 0)
+            .map(|avg| avg * tasks_ahead.div_ceil(MAX_CONCURRENT_TASKS) as u32);
+
+        QueuePosition {
+            position,
+            tasks_ahead,
+            estimated_wait,
+        }
     }
 
     /// Get number of tasks waiting in queue
@@ -375,10 +1310,22 @@ impl TaskQueue {
         self.pending_count.load(Ordering::SeqCst)
     }
 
+    /// Number of workers processing a task right now
+    pub fn busy_workers(&self) -> usize {
+        MAX_CONCURRENT_TASKS - self.semaphore.available_permits()
+    }
+
+    /// Number of tasks processed at once
+    pub fn worker_count() -> usize {
+        MAX_CONCURRENT_TASKS
+    }
+
     /// Get tasks for a user
     pub async fn get_user_tasks(&self, chat_id: ChatId) -> Vec<QueuedTaskInfo> {
         let user_tasks = self.user_tasks.lock().await;
         let statuses = self.task_statuses.lock().await;
+        // Positions move as other users' tasks come and go, read them from the schedule
+        let order = self.waiting.lock().unwrap().order();
 
         user_tasks
             .get(&chat_id)
@@ -386,15 +1333,125 @@ impl TaskQueue {
                 task_ids
                     .iter()
                     .filter_map(|id| statuses.get(id).cloned())
+                    .map(|mut info| {
+                        if let TaskStatus::Queued { position } = &mut info.status
+                            && let Some(index) = order.iter().position(|id| id == &info.task_id)
+                        {
+                            *position = index + 1;
+                        }
+                        info
+                    })
                     .collect()
             })
             .unwrap_or_default()
     }
 
+    /// Failed tasks of a chat still kept in the database, newest first.
+    /// Unlike `get_user_tasks` these outlive the short in-memory tracking, so they can be retried later.
+    pub async fn get_failed_tasks(&self, chat_id: ChatId) -> Vec<QueuedTaskInfo> {
+        use std::str::FromStr;
+
+        let rows = match self.db.get_failed_tasks(chat_id.0).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                log::error!("{}", e);
+                return Vec::new();
+            }
+        };
+
+        rows.into_iter()
+            .filter_map(|row| {
+                let format = row.format.and_then(|f| MediaFormatType::from_str(&f).ok())?;
+                let description = if row.task_type == "download" {
+                    TaskDescription::Download {
+                        quality: row.quality.map(|q| q as u32),
+                        format,
+                    }
+                } else {
+                    TaskDescription::Convert { format }
+                };
+                let kind = row
+                    .error_kind
+                    .and_then(|k| TaskErrorKind::from_str(&k).ok())
+                    .unwrap_or(TaskErrorKind::DownloadFailed);
+
+                Some(QueuedTaskInfo {
+                    task_id: TaskId(row.id),
+                    status: TaskStatus::Failed(TaskError::new(kind, "")),
+                    description,
+                    progress: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Cancel the chat's most recently submitted task that is still queued or running.
+    /// Returns the status message of the cancelled task.
+    pub async fn cancel_latest_task(&self, chat_id: ChatId) -> Option<MessageId> {
+        let active: Vec<TaskId> = {
+            let user_tasks = self.user_tasks.lock().await;
+            let statuses = self.task_statuses.lock().await;
+            user_tasks
+                .get(&chat_id)
+                .map(|ids| {
+                    ids.iter()
+                        .filter(|id| {
+                            statuses.get(id).is_some_and(|info| {
+                                matches!(info.status, TaskStatus::Queued { .. } | TaskStatus::Processing)
+                            })
+                        })
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        for task_id in active.iter().rev() {
+            if let Some(message_id) = self.cancel_task(task_id).await {
+                return Some(message_id);
+            }
+        }
+        None
+    }
+
+    /// Cancel a queued or running task. Returns the task's status message,
+    /// or None if the task already finished.
+    async fn cancel_task(&self, task_id: &TaskId) -> Option<MessageId> {
+        // Still waiting: take it out of the schedule and forget it
+        if let Some(task) = self.cancel_waiting_task(task_id).await {
+            return Some(task.message_id);
+        }
+
+        // Running: the worker stops it and cleans up
+        let running = self.running.lock().await;
+        let task = running.get(task_id)?;
+        task.cancel.notify_one();
+        log::info!("Task {} cancelled while running", task_id);
+        Some(task.message_id)
+    }
+
+    /// Take a task out of the schedule and forget it, if it hasn't started yet
+    async fn cancel_waiting_task(&self, task_id: &TaskId) -> Option<Task> {
+        let task = self.waiting.lock().unwrap().remove(task_id)?;
+        self.pending_count.fetch_sub(1, Ordering::SeqCst);
+        self.queue_waits.lock().unwrap().remove(task_id);
+        log::info!("Task {} cancelled while queued", task_id);
+
+        remove_task_files(&task, false).await;
+        if let Err(e) = self.db.delete_task(&task_id.0).await {
+            log::error!("Failed to delete cancelled task: {}", e);
+        }
+        if let Some(tasks) = self.user_tasks.lock().await.get_mut(&task.chat_id) {
+            tasks.retain(|id| id != task_id);
+        }
+        self.task_statuses.lock().await.remove(task_id);
+
+        Some(task)
+    }
+
     /// Restore state after bot restart and notify affected users
     pub async fn restore_on_startup(&self, bot: &Bot) {
-        use strum::IntoEnumIterator;
-        use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+        use teloxide::types::InlineKeyboardMarkup;
         use tokio::fs;
 
         log::info!("Starting restore_on_startup...");
@@ -406,13 +1463,13 @@ impl TaskQueue {
 
         if let Ok(expired_files) = self.db.delete_expired_pending_conversions().await {
             for file in expired_files {
-                let _ = fs::remove_file(&file).await;
+                workdir::remove_task_of(&file).await;
             }
         }
 
         if let Ok(expired_files) = self.db.delete_expired_tasks().await {
             for file in expired_files {
-                let _ = fs::remove_file(&file).await;
+                workdir::remove_task_of(&file).await;
             }
         }
 
@@ -432,7 +1489,7 @@ impl TaskQueue {
 
                     // Delete task and associated file
                     if let Some(filename) = &task_row.filename {
-                        let _ = fs::remove_file(filename).await;
+                        workdir::remove_task_of(filename).await;
                     }
                     if let Some(thumbnail) = &task_row.thumbnail_path {
                         let _ = fs::remove_file(thumbnail).await;
@@ -448,7 +1505,7 @@ impl TaskQueue {
                         .await;
 
                     if let Some(filename) = &task_row.filename {
-                        let _ = fs::remove_file(filename).await;
+                        workdir::remove_task_of(filename).await;
                     }
                     if let Some(thumbnail) = &task_row.thumbnail_path {
                         let _ = fs::remove_file(thumbnail).await;
@@ -494,18 +1551,9 @@ impl TaskQueue {
         for (short_id, pending, file_exists) in to_notify {
             if file_exists {
                 // File exists - show format selection again
-                let formats: Vec<InlineKeyboardButton> = MediaFormatType::iter()
-                    .enumerate()
-                    .map(|(idx, f)| {
-                        let label = format!("{}", f);
-                        let callback = format!("fmt:{}:{}", idx, short_id);
-                        InlineKeyboardButton::callback(label, callback)
-                    })
-                    .collect();
-
-                let keyboard = InlineKeyboardMarkup::default()
-                    .append_row([formats[0].clone(), formats[1].clone()])
-                    .append_row([formats[2].clone(), formats[3].clone()]);
+                let keyboard = InlineKeyboardMarkup::new(format_keyboard_rows(|_, format| {
+                    Callback::SelectFormat { format: format.clone(), short_id: short_id.clone() }.to_string()
+                }));
 
                 let _ = bot
                     .send_message(
@@ -535,6 +1583,331 @@ impl TaskQueue {
         log::info!("restore_on_startup completed");
     }
 
+    /// Stop starting queued tasks and wait up to `timeout` for running ones to finish.
+    /// Whatever is left stays in the database and is reported to its users by
+    /// `restore_on_startup`.
+    pub async fn shutdown(&self, timeout: Duration) {
+        let dropped = std::mem::take(&mut *self.waiting.lock().unwrap()).len();
+        if dropped > 0 {
+            log::info!("Dropped {} queued task(s), their users are notified on the next start", dropped);
+        }
+
+        let started_at = Instant::now();
+        loop {
+            let running = self
+                .task_statuses
+                .lock()
+                .await
+                .values()
+                .filter(|info| info.status == TaskStatus::Processing)
+                .count();
+            if running == 0 {
+                log::info!("All running tasks finished");
+                return;
+            }
+            if started_at.elapsed() >= timeout {
+                log::warn!("{} task(s) still running, shutting down anyway", running);
+                return;
+            }
+            tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Periodically remind users about pending conversions that are about to expire
+    pub fn start_expiry_reminders(self: &Arc<Self>, bot: Bot) {
+        let lead = crate::config::get().pending_reminder_lead;
+        if lead.is_zero() {
+            log::info!("Pending conversion reminders are disabled");
+            return;
+        }
+        // A lead close to the TTL would remind right after the video was sent
+        let max_lead = Duration::from_secs(crate::db::TASK_TTL_SECONDS as u64 / 2);
+        let lead = if lead > max_lead {
+            log::warn!("PENDING_REMINDER_LEAD_HOURS is capped at half the pending conversion TTL");
+            max_lead
+        } else {
+            lead
+        };
+
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REMINDER_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                queue.send_expiry_reminders(&bot, lead).await;
+            }
+        });
+    }
+
+    async fn send_expiry_reminders(&self, bot: &Bot, lead: Duration) {
+        let rows = match self.db.get_pending_conversions_to_remind(lead.as_secs() as i64).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                log::error!("Failed to load pending conversions to remind: {}", e);
+                return;
+            }
+        };
+
+        let hours = lead.as_secs().div_ceil(3600);
+
+        // The format message may have scrolled far away, a /start link brings it back
+        let username = if rows.is_empty() {
+            None
+        } else {
+            bot.get_me().await.ok().map(|me| me.username().to_string())
+        };
+
+        for row in rows {
+            log::info!("Reminding chat {} about pending conversion {}", row.chat_id, row.short_id);
+
+            let mut text = format!(
+                "⏰ Ваше видео всё ещё ждёт выбора формата. Оно будет удалено примерно через {} ч.",
+                hours
+            );
+            if let Some(username) = &username {
+                text.push_str(&format!(
+                    "\n\nВыбрать формат: https://t.me/{}?start={}{}",
+                    username,
+                    crate::commands::CONVERT_START_PREFIX,
+                    row.short_id
+                ));
+            }
+
+            let _ = bot
+                .send_message(ChatId(row.chat_id), text)
+                .reply_parameters(reply_to(MessageId(row.message_id)))
+                .link_preview_options(crate::utils::no_link_preview())
+                .await;
+
+            if let Err(e) = self.db.mark_pending_conversion_reminded(&row.short_id).await {
+                log::error!("Failed to mark pending conversion reminded: {}", e);
+            }
+        }
+    }
+
+    /// Periodically warn users about links and uploads they've left without a choice,
+    /// and drop them once they've been idle for `FLOW_IDLE_MINUTES`
+    pub fn start_idle_flow_checks(self: &Arc<Self>, bot: Bot) {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(IDLE_FLOW_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                queue.check_idle_flows(&bot).await;
+            }
+        });
+    }
+
+    async fn check_idle_flows(&self, bot: &Bot) {
+        let timeout = crate::config::get().flow_idle_timeout;
+        if timeout.is_zero() {
+            return;
+        }
+        // Warn a quarter of the timeout ahead, so there's time to come back
+        let warn_after = timeout - timeout / 4;
+        let minutes_left = (timeout / 4).as_secs().div_ceil(60);
+
+        // Decide under the locks, talk to Telegram after them
+        let mut downloads_to_warn = Vec::new();
+        let mut downloads_to_drop = Vec::new();
+        for (short_id, pending) in self.pending_downloads.lock().await.iter_mut() {
+            let idle = pending.last_activity.elapsed();
+            if idle >= timeout {
+                downloads_to_drop.push(short_id.clone());
+            } else if idle >= warn_after && !pending.idle_warned {
+                pending.idle_warned = true;
+                downloads_to_warn.push((pending.chat_id, pending.message_id));
+            }
+        }
+
+        let mut conversions_to_warn = Vec::new();
+        let mut conversions_to_drop = Vec::new();
+        for (short_id, pending) in self.pending_conversions.lock().await.iter_mut() {
+            let idle = pending.last_activity.elapsed();
+            if idle >= timeout {
+                conversions_to_drop.push((short_id.clone(), pending.chat_id));
+            } else if idle >= warn_after && !pending.idle_warned {
+                pending.idle_warned = true;
+                conversions_to_warn.push((pending.chat_id, pending.message_id));
+            }
+        }
+
+        for (chat_id, message_id) in downloads_to_warn {
+            let _ = bot
+                .send_message(
+                    chat_id,
+                    format!(
+                        "⏳ Вы ещё здесь? Если не выбрать формат, примерно через {} мин. выбор закроется.",
+                        minutes_left
+                    ),
+                )
+                .reply_parameters(reply_to(message_id))
+                .await;
+        }
+
+        for (chat_id, message_id) in conversions_to_warn {
+            let _ = bot
+                .send_message(
+                    chat_id,
+                    format!(
+                        "⏳ Вы ещё здесь? Если не выбрать формат, примерно через {} мин. видео будет удалено.",
+                        minutes_left
+                    ),
+                )
+                .reply_parameters(reply_to(message_id))
+                .await;
+        }
+
+        for short_id in downloads_to_drop {
+            let Some(pending) = self.take_pending_download(&short_id).await else {
+                continue;
+            };
+            log::info!("Dropping idle pending download {} of chat {}", short_id, pending.chat_id);
+            // The format message is the bot's own, so its buttons can be taken away
+            edit_status(
+                bot,
+                pending.chat_id,
+                pending.message_id,
+                "⌛ Время выбора истекло. Отправьте ссылку ещё раз.",
+            )
+            .await;
+        }
+
+        for (short_id, chat_id) in conversions_to_drop {
+            let Some(pending) = self.get_pending_conversion(&short_id).await else {
+                continue;
+            };
+            if !self.discard_pending_conversion(chat_id, &short_id).await {
+                continue;
+            }
+            log::info!("Dropped idle pending conversion {} of chat {}", short_id, chat_id);
+            let _ = bot
+                .send_message(chat_id, "⌛ Формат так и не выбран, видео удалено. Отправьте его ещё раз, если оно нужно.")
+                .reply_parameters(reply_to(pending.message_id))
+                .await;
+        }
+    }
+
+    /// Periodically tell users whose tasks have been waiting long that they're still
+    /// queued, and cancel tasks that waited past the limit
+    pub fn start_queue_wait_checks(self: &Arc<Self>, bot: Bot) {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(QUEUE_WAIT_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                queue.check_queue_waits(&bot).await;
+            }
+        });
+    }
+
+    async fn check_queue_waits(&self, bot: &Bot) {
+        let config = crate::config::get();
+
+        // Decide under the lock, talk to Telegram after it
+        let (to_notice, to_cancel) = {
+            let mut waits = self.queue_waits.lock().unwrap();
+            let mut to_notice = Vec::new();
+            let mut to_cancel = Vec::new();
+            for (task_id, wait) in waits.iter_mut() {
+                let waited = wait.since.elapsed();
+                if !config.queue_wait_limit.is_zero() && waited >= config.queue_wait_limit {
+                    to_cancel.push(task_id.clone());
+                } else if !config.queue_wait_notice.is_zero()
+                    && waited >= config.queue_wait_notice
+                    && !wait.noticed
+                {
+                    wait.noticed = true;
+                    to_notice.push(task_id.clone());
+                }
+            }
+            (to_notice, to_cancel)
+        };
+
+        for task_id in to_notice {
+            let (chat_id, message_id, position) = {
+                let waiting = self.waiting.lock().unwrap();
+                let Some(task) = waiting.get(&task_id) else { continue };
+                (task.chat_id, task.message_id, waiting.position(&task_id).unwrap_or(0))
+            };
+            log::info!("Task {} has been queued for long, telling the user", task_id);
+
+            let _ = bot
+                .send_message(
+                    chat_id,
+                    format!(
+                        "⏳ Задача всё ещё в очереди (место {}). Сейчас много работы, спасибо за терпение!",
+                        position
+                    ),
+                )
+                .reply_parameters(reply_to(message_id))
+                .await;
+        }
+
+        for task_id in to_cancel {
+            // Only while still waiting: a task the worker took meanwhile keeps running
+            let Some(task) = self.cancel_waiting_task(&task_id).await else {
+                continue;
+            };
+            log::warn!("Task {} waited in the queue too long, cancelled", task_id);
+
+            edit_status(
+                bot,
+                task.chat_id,
+                task.message_id,
+                "😔 Извините, задача слишком долго ждала в очереди и была отменена. \
+                 Попробуйте отправить её снова чуть позже.",
+            )
+            .await;
+        }
+    }
+
+    /// Periodically delete sent media whose auto-delete delay has passed.
+    /// Deletions are stored in the database, so ones due during downtime run on startup.
+    pub fn start_scheduled_deletions(self: &Arc<Self>, bot: Bot) {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(DELETION_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                queue.run_scheduled_deletions(&bot).await;
+            }
+        });
+    }
+
+    async fn run_scheduled_deletions(&self, bot: &Bot) {
+        use teloxide::{ApiError, RequestError};
+
+        let rows = match self.db.get_due_deletions().await {
+            Ok(rows) => rows,
+            Err(e) => {
+                log::error!("Failed to load scheduled deletions: {}", e);
+                return;
+            }
+        };
+
+        for row in rows {
+            match bot.delete_message(ChatId(row.chat_id), MessageId(row.message_id)).await {
+                Ok(_) => log::info!("Auto-deleted message {} in chat {}", row.message_id, row.chat_id),
+                // The user already deleted it (or it's too old to delete) - nothing left to do
+                Err(RequestError::Api(
+                    ApiError::MessageToDeleteNotFound | ApiError::MessageCantBeDeleted,
+                )) => {}
+                Err(e) => log::warn!(
+                    "Failed to auto-delete message {} in chat {}: {}",
+                    row.message_id,
+                    row.chat_id,
+                    e
+                ),
+            }
+
+            // Don't retry: a failed deletion would otherwise be attempted forever
+            if let Err(e) = self.db.delete_scheduled_deletion(row.id).await {
+                log::error!("Failed to remove scheduled deletion: {}", e);
+            }
+        }
+    }
+
     /// Update task status (in-memory and database)
     async fn update_status(&self, task_id: &TaskId, status: TaskStatus) {
         // Update in-memory
@@ -556,9 +1929,12 @@ impl TaskQueue {
     }
 
     /// Main worker loop
-    async fn run_worker(&self, mut receiver: mpsc::UnboundedReceiver<Task>, bot: Bot) {
-        while let Some(task) = receiver.recv().await {
+    async fn run_worker(&self, bot: Bot) {
+        loop {
+            // Wait for a free worker before choosing, so the choice sees every task
+            // submitted in the meantime
             let permit = self.semaphore.clone().acquire_owned().await.unwrap();
+            let task = self.next_task().await;
             self.pending_count.fetch_sub(1, Ordering::SeqCst);
 
             // Update status to processing
@@ -570,11 +1946,84 @@ impl TaskQueue {
             let user_tasks = self.user_tasks.clone();
             let pending_conversions = self.pending_conversions.clone();
             let db = self.db.clone();
+            let recent_durations = self.recent_durations.clone();
+            let processing_history = self.processing_history.clone();
+            let last_failures = self.last_failures.clone();
+            let running = self.running.clone();
+            let active_per_chat = self.active_per_chat.clone();
+            let task_ready = self.task_ready.clone();
+            let cancel = Arc::new(Notify::new());
+            running.lock().await.insert(
+                task_id.clone(),
+                RunningTask {
+                    message_id: task.message_id,
+                    cancel: cancel.clone(),
+                },
+            );
 
             // Spawn task handler
             tokio::spawn(async move {
                 log::info!("Processing task {}: {:?}", task_id, task.task_type);
-                let result = process_task(&bot_clone, &task, &pending_conversions, &db).await;
+                let started_at = Instant::now();
+                let media_secs = media_duration(&task.task_type).await;
+                let progress = ProgressTracker {
+                    task_id: task_id.clone(),
+                    task_statuses: task_statuses.clone(),
+                    started_at,
+                    estimate: processing_history.estimate(&task.task_type, media_secs),
+                };
+                // Dropping the task's future on cancel also kills its yt-dlp/ffmpeg processes
+                let result = tokio::select! {
+                    result = process_task(&bot_clone, &task, &pending_conversions, &db, &progress) => result,
+                    _ = cancel.notified() => {
+                        remove_task_files(&task, false).await;
+                        Err(TaskError::new(TaskErrorKind::Cancelled, "cancelled by user"))
+                    }
+                };
+                running.lock().await.remove(&task_id);
+
+                // A full or read-only disk isn't the content's fault, don't let the task's
+                // own error suggest trying another video
+                let mut result = result;
+                if let Err(error) = &mut result
+                    && let Some(problem) = StorageProblem::in_text(&error.details)
+                {
+                    problem.warn(&format!("Task {}", task_id));
+                    error.kind = TaskErrorKind::StorageUnavailable;
+                    let _ = bot_clone
+                        .edit_message_text(task.chat_id, task.message_id, StorageProblem::USER_MESSAGE)
+                        .await;
+                }
+
+                // The worker is free now, the rest is just bookkeeping
+                drop(permit);
+                {
+                    let mut active = active_per_chat.lock().unwrap();
+                    if let Some(count) = active.get_mut(&task.chat_id) {
+                        *count -= 1;
+                        if *count == 0 {
+                            active.remove(&task.chat_id);
+                        }
+                    }
+                }
+                // A task of this chat may have been skipped while it was at its limit
+                task_ready.notify_one();
+                {
+                    let mut durations = recent_durations.lock().unwrap();
+                    if durations.len() == DURATION_SAMPLES {
+                        durations.pop_front();
+                    }
+                    durations.push_back(started_at.elapsed());
+                }
+                // Failed tasks usually stop early and would make estimates too short
+                if result.is_ok() {
+                    processing_history.record(&task.task_type, started_at.elapsed(), media_secs);
+                }
+                crate::metrics::task_finished(
+                    &task.task_type,
+                    result.as_ref().err().map(|e| e.kind.as_ref()),
+                    started_at.elapsed(),
+                );
 
                 match &result {
                     Ok(_) => log::info!("Task {} completed successfully", task_id),
@@ -592,9 +2041,92 @@ impl TaskQueue {
                     }
                 }
 
-                // Delete task from database (it's done)
-                if let Err(e) = db.delete_task(&task_id.0).await {
-                    log::error!("Failed to delete task from DB: {}", e);
+                // Delete finished tasks from database, keep failed ones with their
+                // category until they expire
+                let db_result = match &result {
+                    Ok(_) => db.delete_task(&task_id.0).await,
+                    Err(e) if e.kind == TaskErrorKind::Cancelled => db.delete_task(&task_id.0).await,
+                    Err(e) => db.mark_task_failed(&task_id.0, e.kind.as_ref()).await,
+                };
+                if let Err(e) = db_result {
+                    log::error!("Failed to update finished task in DB: {}", e);
+                }
+
+                // Cancelled tasks cleaned up after themselves already
+                if !matches!(&result, Err(e) if e.kind == TaskErrorKind::Cancelled) {
+                    remove_task_files(&task, result.is_err()).await;
+                }
+
+                // Remember completed tasks for /history. Only in private chats: rows are
+                // per chat, and a group's history would show everyone's downloads to everyone
+                if result.is_ok() && task.chat_id.is_user() {
+                    let (title, url) = match &task.task_type {
+                        TaskType::Download { url, .. } => (
+                            crate::video::youtube::get_video_title(url, task.task_type.video_password())
+                                .await
+                                .unwrap_or_else(|_| url.clone()),
+                            Some(url.as_str()),
+                        ),
+                        TaskType::Convert { .. } => ("Загруженное видео".to_string(), None),
+                    };
+                    let description = TaskDescription::from(&task.task_type).to_string();
+                    if let Err(e) = db.insert_history(task.chat_id.0, &title, &description, url).await {
+                        log::error!("Failed to save history entry: {}", e);
+                    }
+                }
+
+                // Offer the video's description and details next to the result
+                if result.is_ok()
+                    && let TaskType::Download { url, .. } = &task.task_type
+                    && let Some(video_id) = youtube_video_id(url)
+                {
+                    let keyboard = InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+                        "📄 Описание",
+                        Callback::Description { video_id }.to_string(),
+                    )]]);
+                    let _ = bot_clone
+                        .edit_message_reply_markup(task.chat_id, task.message_id)
+                        .reply_markup(keyboard)
+                        .await;
+                }
+
+                // Nudge free users who keep coming back towards Premium
+                if result.is_ok() && !task.premium {
+                    offer_premium(&bot_clone, &db, task.chat_id).await;
+                }
+
+                // Offer to send the details to the admin
+                if let Err(error) = &result
+                    && error.kind != TaskErrorKind::Cancelled
+                {
+                    last_failures.lock().await.insert(
+                        task.chat_id,
+                        FailureReport {
+                            task_id: task_id.clone(),
+                            url: match &task.task_type {
+                                TaskType::Download { url, .. } => Some(url.clone()),
+                                TaskType::Convert { .. } => None,
+                            },
+                            description: TaskDescription::from(&task.task_type),
+                            error: error.clone(),
+                        },
+                    );
+
+                    // Uploads are kept after a failure, so every task can run again
+                    let keyboard = InlineKeyboardMarkup::new(vec![vec![
+                        InlineKeyboardButton::callback(
+                            "🔄 Повторить",
+                            Callback::Retry { task_id: task_id.to_string() }.to_string(),
+                        ),
+                        InlineKeyboardButton::callback(
+                            "🐞 Сообщить о проблеме",
+                            Callback::Report { task_id: task_id.to_string() }.to_string(),
+                        ),
+                    ]]);
+                    let _ = bot_clone
+                        .edit_message_reply_markup(task.chat_id, task.message_id)
+                        .reply_markup(keyboard)
+                        .await;
                 }
 
                 // Clean up after a delay
@@ -611,42 +2143,216 @@ impl TaskQueue {
                     let mut statuses = task_statuses.lock().await;
                     statuses.remove(&task_id);
                 }
-
-                drop(permit);
             });
         }
     }
 }
 
+/// Drop submission timestamps older than the longest cooldown
+fn prune_submissions(last_submissions: &mut HashMap<i64, Instant>, now: Instant) {
+    let config = crate::config::get();
+    let longest = config.submit_cooldown.max(config.premium_submit_cooldown);
+    last_submissions.retain(|_, last| now.duration_since(*last) < longest);
+}
+
+/// Show the Premium offer once a free user completed enough downloads,
+/// at most once per `upsell_interval`. Private chats only, groups shouldn't get ads.
+async fn offer_premium(bot: &Bot, db: &TaskDb, chat_id: ChatId) {
+    let config = crate::config::get();
+    if config.upsell_after_downloads == 0 || !chat_id.is_user() {
+        return;
+    }
+
+    let (downloads, last_shown_at) = match db.record_upsell_download(chat_id.0).await {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("{}", e);
+            return;
+        }
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let interval_passed = last_shown_at
+        .is_none_or(|shown| now - shown >= config.upsell_interval.as_secs() as i64);
+    if downloads < config.upsell_after_downloads as i64 || !interval_passed {
+        return;
+    }
+
+    let tier = crate::subscription::premium::cheapest_tier();
+    let text = format!(
+        "💛 Нравится бот?\n\n\
+        С Premium доступны кружочки, войсы, расшифровка речи и приоритет в очереди.\n\n\
+        Стоимость: от {} Stars за {} дней",
+        tier.price_stars, tier.days
+    );
+    let keyboard = InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+        "Купить Premium",
+        Callback::BuyPremium { tier_id: None }.to_string(),
+    )]]);
+
+    if let Err(e) = bot.send_message(chat_id, text).reply_markup(keyboard).await {
+        log::warn!("Failed to send Premium offer to {}: {}", chat_id, e);
+        return;
+    }
+
+    if let Err(e) = db.mark_upsell_shown(chat_id.0).await {
+        log::error!("{}", e);
+    }
+}
+
+/// Length of the task's media in seconds, for time estimates. Downloads use the
+/// metadata cached at the link step, uploads are probed.
+async fn media_duration(task_type: &TaskType) -> Option<f64> {
+    match task_type {
+        TaskType::Download { url, password, .. } => crate::video::youtube::get_video_duration(url, password.as_ref())
+            .await
+            .ok()
+            .map(f64::from),
+        TaskType::Convert { filename, .. } => crate::video::VideoInfo::from_file(filename)
+            .await
+            .ok()
+            .map(|info| info.duration),
+    }
+}
+
 /// Process a single task
 async fn process_task(
     bot: &Bot,
     task: &Task,
     _pending_conversions: &Arc<Mutex<HashMap<String, PendingConversion>>>,
-    _db: &TaskDb,
-) -> Result<(), String> {
-    match &task.task_type {
-        TaskType::Download { url, quality, format } => {
-            process_download_task(bot, task, url, *quality, format.clone()).await
+    db: &TaskDb,
+    progress: &ProgressTracker,
+) -> Result<(), TaskError> {
+    let sent = match &task.task_type {
+        TaskType::Download { url, quality, format, audio_language, format_string, size_budget, .. } => {
+            let selection = match (format_string, size_budget) {
+                (Some(expr), _) => VideoSelection::Custom(expr),
+                (None, Some(bytes)) => VideoSelection::MaxSize { bytes: *bytes, max_height: *quality },
+                (None, None) => VideoSelection::MaxHeight(*quality),
+            };
+            process_download_task(bot, task, db, url, selection, format.clone(), audio_language.as_deref(), progress).await
+        }
+        TaskType::Convert { filename, thumbnail_path, format, .. } => {
+            process_convert_task(bot, task, filename, thumbnail_path.clone(), format.clone(), None, progress).await
         }
-        TaskType::Convert { filename, thumbnail_path, format } => {
-            process_convert_task(bot, task, filename, thumbnail_path.clone(), format.clone()).await
+    }?;
+
+    let copied = match task.target_chat {
+        Some(target) if !sent.is_empty() => copy_to_target_chat(bot, task, target, &sent).await,
+        _ => Vec::new(),
+    };
+
+    if let Some(delay) = task.options.auto_delete_after() {
+        let delete_at = chrono::Utc::now().timestamp() + delay.as_secs() as i64;
+        let messages = sent
+            .into_iter()
+            .map(|id| (task.chat_id, id))
+            .chain(copied.into_iter().map(|id| (task.target_chat.unwrap_or(task.chat_id), id)));
+        for (chat_id, message_id) in messages {
+            if let Err(e) = db
+                .insert_scheduled_deletion(chat_id.0, message_id.0, delete_at)
+                .await
+            {
+                log::error!("Failed to schedule message deletion: {}", e);
+            }
         }
     }
+
+    Ok(())
+}
+
+/// Post copies of the sent messages to the user's chat or channel (see /channel) and
+/// link them in the user's chat. The result stays in the user's chat either way, so a
+/// failed copy is reported but doesn't fail the task. Returns the copies.
+async fn copy_to_target_chat(bot: &Bot, task: &Task, target: ChatId, sent: &[MessageId]) -> Vec<MessageId> {
+    use crate::utils::no_link_preview;
+    use teloxide::{ApiError, RequestError};
+
+    let copied = match bot
+        .copy_messages(target, task.chat_id, sent.to_vec())
+        .protect_content(task.options.protect_content)
+        .send_with_retry()
+        .await
+    {
+        Ok(copied) => copied,
+        Err(e) => {
+            log::warn!("Failed to copy task {} result to chat {}: {}", task.id, target, e);
+            let text = match e {
+                RequestError::Api(ApiError::Unknown(ref reason)) if reason.contains("can't be copied") => {
+                    "⚠️ Защищённые от пересылки файлы нельзя опубликовать в канале. \
+                     Отключите защиту в /settings, чтобы они туда попадали."
+                }
+                RequestError::Api(_) => {
+                    "⚠️ Не удалось опубликовать результат в вашем канале: у бота нет прав отправлять туда сообщения. \
+                     Сделайте бота администратором с правом публикации или смените канал в /channel."
+                }
+                _ => "⚠️ Не удалось опубликовать результат в вашем канале, попробуйте позже.",
+            };
+            let _ = bot
+                .send_message(task.chat_id, text)
+                .reply_parameters(reply_to(sent[0]))
+                .send_with_retry()
+                .await;
+            return Vec::new();
+        }
+    };
+
+    // Public channels are linked by username, private ones through their internal ID
+    let username = match bot.get_chat(target).await {
+        Ok(chat) => chat.username().map(str::to_string),
+        Err(e) => {
+            log::warn!("Failed to get chat {} for the post link: {}", target, e);
+            None
+        }
+    };
+    let text = match copied.first().and_then(|&id| Message::url_of(target, username.as_deref(), id)) {
+        Some(url) => format!("📢 Опубликовано в вашем канале: {}", url),
+        None => "📢 Опубликовано в вашем канале.".to_string(),
+    };
+    let _ = bot
+        .send_message(task.chat_id, text)
+        .reply_parameters(reply_to(sent[0]))
+        .link_preview_options(no_link_preview())
+        .send_with_retry()
+        .await;
+
+    copied
 }
 
 /// Process download task - downloads and immediately converts to target format
+#[allow(clippy::too_many_arguments)]
 async fn process_download_task(
     bot: &Bot,
     task: &Task,
+    db: &TaskDb,
     url: &str,
-    quality: Option<u32>,
+    selection: VideoSelection<'_>,
     format: MediaFormatType,
-) -> Result<(), String> {
-    use crate::video::youtube::download_video;
+    audio_language: Option<&str>,
+    progress: &ProgressTracker,
+) -> Result<Vec<MessageId>, TaskError> {
+    use crate::video::youtube::{
+        DownloadCuts, download_video, get_audio_metadata, is_password_error, is_video_unavailable_error,
+    };
+
+    // Files waiting for a format choice use disk space until they're converted or expire.
+    // A chat at the limit has to deal with those before downloading more.
+    let limit_check = PendingConversionCheck::for_chat(db, task.chat_id, task.premium).await;
+    if let PendingConversionCheck::Refused { .. } = limit_check {
+        log::info!("Refusing download task {}: too many pending conversions", task.id);
+        edit_status(bot, task.chat_id, task.message_id, limit_check.message().unwrap_or_default()).await;
+        return Ok(Vec::new());
+    }
+
+    let password = task.task_type.video_password();
 
-    let quality_str = quality.map(|q| format!("{}p", q)).unwrap_or_else(|| "аудио".to_string());
-    log::info!("Starting download task: {} at {} for {:?}", url, quality_str, format);
+    let quality_str = match selection {
+        VideoSelection::MaxHeight(Some(q)) => format!("{}p", q),
+        VideoSelection::MaxHeight(None) => "аудио".to_string(),
+        VideoSelection::MaxSize { bytes, .. } => format!("видео до {}", crate::config::format_size(bytes)),
+        VideoSelection::Custom(_) => "видео".to_string(),
+    };
+    log::info!("Starting download task: {} with {:?} for {:?}", url, selection, format);
 
     // Update message to show downloading
     let _ = bot
@@ -657,45 +2363,482 @@ async fn process_download_task(
         )
         .await;
 
-    match download_video(url, &task.unique_file_id, quality, &format).await {
-        Ok(result) => {
-            log::info!("Downloaded file: {}", result.video_path);
-
+    // Animate the status message while downloading
+    let (loading_tx, loading_rx) = mpsc::unbounded_channel();
+    let loading = LoadingScreen::start(
+        bot.clone(),
+        task.chat_id,
+        task.message_id,
+        loading_rx,
+        crate::config::get().download_messages.clone(),
+    );
+
+    let download_started = Instant::now();
+    let download_result = download_video(
+        url,
+        password,
+        &task.unique_file_id,
+        selection,
+        &format,
+        audio_language,
+        DownloadCuts { sponsorblock: task.options.sponsorblock },
+        Some(progress.sender(Some(loading_tx))),
+    )
+    .await;
+    crate::metrics::download_finished(download_started.elapsed(), download_result.is_ok());
+
+    drop(loading);
+
+    // Opted in: a video that can't be had as video is sent as audio instead
+    let (download_result, format, fell_back) = match download_result {
+        Err(e) if !format.is_audio_only() && task.options.audio_fallback && is_video_unavailable_error(&e) => {
+            log::warn!("Video of {} is unavailable, falling back to audio: {}", url, e);
+            let _ = bot
+                .edit_message_text(task.chat_id, task.message_id, "🎧 Видео недоступно, скачиваем звук...")
+                .await;
+            let fallback = download_video(
+                url,
+                password,
+                &task.unique_file_id,
+                VideoSelection::MaxHeight(None),
+                &MediaFormatType::Audio,
+                audio_language,
+                DownloadCuts { sponsorblock: task.options.sponsorblock },
+                Some(progress.sender(None)),
+            )
+            .await;
+            (fallback, MediaFormatType::Audio, true)
+        }
+        result => (result, format, false),
+    };
+
+    match download_result {
+        Ok(result) => {
+            log::info!("Downloaded file: {}", result.video_path);
+
+            // Nothing fit the budget and yt-dlp fell back to the smallest streams
+            if let VideoSelection::MaxSize { bytes, .. } = selection
+                && let Ok(metadata) = tokio::fs::metadata(&result.video_path).await
+                && metadata.len() > bytes
+            {
+                log::info!("No format of {} fits {} bytes, got {}", url, bytes, metadata.len());
+                let _ = bot
+                    .send_message(
+                        task.chat_id,
+                        format!(
+                            "⚠️ Ни один вариант не уложился в {}, скачан самый маленький ({}).",
+                            crate::config::format_size(bytes),
+                            crate::config::format_size(metadata.len())
+                        ),
+                    )
+                    .send_with_retry()
+                    .await;
+            }
+
+            let video_path = if format == MediaFormatType::Video && task.options.subtitles {
+                burn_downloaded_subtitles(bot, task, url, audio_language, result.video_path, progress).await
+            } else {
+                result.video_path
+            };
+
+            // The metadata is cached from the link step, so this doesn't run yt-dlp again
+            let audio_metadata = if format == MediaFormatType::Audio {
+                match get_audio_metadata(url, password).await {
+                    Ok(metadata) => Some(metadata),
+                    Err(e) => {
+                        log::warn!("Failed to get audio metadata, sending without it: {}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
             // Immediately convert to target format
-            process_convert_task(bot, task, &result.video_path, result.thumbnail_path.clone(), format).await
+            let mut sent = process_convert_task(
+                bot,
+                task,
+                &video_path,
+                result.thumbnail_path.clone(),
+                format,
+                audio_metadata,
+                progress,
+            )
+            .await?;
+
+            if task.options.chapters
+                && let Some(chapters) = send_chapters(bot, task, url, sent.first().copied()).await
+            {
+                sent.push(chapters);
+            }
+
+            if fell_back
+                && let Some(&media) = sent.first()
+            {
+                let _ = bot
+                    .send_message(task.chat_id, "ℹ️ Видео было недоступно, поэтому отправлен только звук.")
+                    .reply_parameters(reply_to(media))
+                    .send_with_retry()
+                    .await;
+            }
+
+            Ok(sent)
         }
         Err(e) => {
             log::error!("Download error: {}", e);
-            let _ = bot
-                .edit_message_text(
-                    task.chat_id,
-                    task.message_id,
-                    "❌ Не могу скачать это видео, попробуй другое.",
-                )
-                .await;
-            Err(format!("Download failed: {}", e))
+            // E.g. a protected video restored after a restart, which lost its password
+            let text = if is_password_error(&e) {
+                "🔒 Видео защищено паролем. Отправьте ссылку ещё раз и введите пароль."
+            } else {
+                "❌ Не могу скачать это видео, попробуй другое."
+            };
+            edit_status(bot, task.chat_id, task.message_id, text).await;
+            Err(TaskError::from_bot_error(TaskErrorKind::DownloadFailed, e))
+        }
+    }
+}
+
+/// Send the video's chapters with their start times, in reply to the sent media.
+/// Times link to that moment of the video on YouTube. Nothing is sent for videos without chapters.
+async fn send_chapters(bot: &Bot, task: &Task, url: &str, media: Option<MessageId>) -> Option<MessageId> {
+    use crate::utils::{MAX_MESSAGE_CHARS, no_link_preview};
+    use crate::video::youtube::{format_duration, get_chapters};
+    use teloxide::types::ParseMode;
+    use teloxide::utils::html::escape;
+
+    let chapters = match get_chapters(url, task.task_type.video_password()).await {
+        Ok(chapters) if !chapters.is_empty() => chapters,
+        Ok(_) => return None,
+        Err(e) => {
+            log::warn!("Failed to get chapters for {}: {}", url, e);
+            return None;
+        }
+    };
+
+    let video_id = youtube_video_id(url);
+    let mut text = String::from("📑 Главы:\n");
+    for chapter in &chapters {
+        let time = format_duration(chapter.start);
+        let time = match &video_id {
+            Some(id) => format!("<a href=\"https://youtu.be/{}?t={}\">{}</a>", id, chapter.start, time),
+            None => time,
+        };
+        let line = format!("\n{} {}", time, escape(&chapter.title));
+        // Long chapter lists are cut rather than split over several messages
+        if text.chars().count() + line.chars().count() > MAX_MESSAGE_CHARS {
+            break;
+        }
+        text.push_str(&line);
+    }
+
+    let mut request = bot
+        .send_message(task.chat_id, text)
+        .parse_mode(ParseMode::Html)
+        .link_preview_options(no_link_preview())
+        .protect_content(task.options.protect_content);
+    if let Some(id) = media.or(task.reply_to) {
+        request = request.reply_parameters(reply_to(id));
+    }
+
+    match request.await {
+        Ok(message) => Some(message.id),
+        Err(e) => {
+            log::warn!("Failed to send chapters for {}: {}", url, e);
+            None
+        }
+    }
+}
+
+/// Burn the video's subtitles into a downloaded video. Returns the path of the video
+/// to send: without subtitles (or if burning them fails) it's the original download.
+async fn burn_downloaded_subtitles(
+    bot: &Bot,
+    task: &Task,
+    url: &str,
+    audio_language: Option<&str>,
+    video_path: String,
+    progress: &ProgressTracker,
+) -> String {
+    use crate::video::convert::burn_subtitles;
+    use crate::video::youtube::download_subtitles;
+
+    let subtitles = match download_subtitles(url, task.task_type.video_password(), &task.unique_file_id, audio_language).await {
+        Ok(Some(path)) => path,
+        Ok(None) => {
+            log::info!("No subtitles for {}, sending the video without them", url);
+            return video_path;
+        }
+        Err(e) => {
+            log::warn!("Failed to download subtitles for {}: {}", url, e);
+            return video_path;
+        }
+    };
+
+    let _ = bot
+        .edit_message_text(task.chat_id, task.message_id, "💬 Вшиваем субтитры...")
+        .await;
+
+    let result = burn_subtitles(&video_path, &subtitles, Some(progress.sender(None))).await;
+    let _ = tokio::fs::remove_file(&subtitles).await;
+
+    match result {
+        Ok(burned) => {
+            let _ = tokio::fs::remove_file(&video_path).await;
+            burned
+        }
+        Err(e) => {
+            log::warn!("Failed to burn subtitles into {}: {}", video_path, e);
+            video_path
+        }
+    }
+}
+
+/// Name for the sent file from the user's naming template (see /name), without extension.
+/// None for uploads, without a template, or when the metadata can't be fetched.
+async fn custom_file_stem(task: &Task) -> Option<String> {
+    let TaskType::Download { url, quality, filename_template: Some(template), password, .. } = &task.task_type else {
+        return None;
+    };
+
+    match crate::video::youtube::expand_filename_template(url, password.as_ref(), template, *quality).await {
+        Ok(stem) => Some(stem),
+        Err(e) => {
+            log::warn!("Failed to expand file name template for {}: {}", url, e);
+            None
+        }
+    }
+}
+
+/// The file at `path`, uploaded as `stem` plus the extension of the file actually sent
+fn named_file(path: &str, stem: Option<&str>) -> teloxide::types::InputFile {
+    let file = teloxide::types::InputFile::file(path);
+    match stem {
+        Some(stem) => {
+            let name = match std::path::Path::new(path).extension() {
+                Some(ext) => format!("{}.{}", stem, ext.to_string_lossy()),
+                None => stem.to_string(),
+            };
+            file.file_name(name)
+        }
+        None => file,
+    }
+}
+
+/// Get a Telegram-compliant thumbnail for a video: the normalized YouTube thumbnail
+/// if there is one, a frame from the video otherwise. The result is always a fresh
+/// file that the caller removes after sending.
+async fn prepare_thumbnail(youtube_thumbnail: Option<&str>, video_path: &str) -> Option<String> {
+    use crate::video::convert::{is_valid_thumbnail, normalize_thumbnail};
+    use crate::video::generate_thumbnail;
+
+    let thumbnail = match existing_thumbnail(youtube_thumbnail).await {
+        Some(path) => match normalize_thumbnail(path).await {
+            Ok(thumb) => Some(thumb),
+            Err(e) => {
+                log::warn!("Failed to normalize thumbnail {}: {}", path, e);
+                generate_thumbnail(video_path).await.ok()
+            }
+        },
+        None => generate_thumbnail(video_path).await.ok(),
+    }?;
+
+    if is_valid_thumbnail(&thumbnail).await {
+        Some(thumbnail)
+    } else {
+        log::warn!("Thumbnail {} doesn't meet Telegram limits, sending without it", thumbnail);
+        let _ = tokio::fs::remove_file(&thumbnail).await;
+        None
+    }
+}
+
+/// The downloaded thumbnail, unless it's missing or empty. yt-dlp's thumbnail step can
+/// fail silently while the video downloads fine, leaving a path to nothing.
+async fn existing_thumbnail(thumbnail: Option<&str>) -> Option<&str> {
+    let path = thumbnail?;
+    if is_non_empty_file(path).await {
+        Some(path)
+    } else {
+        log::warn!("Thumbnail {} is missing or empty, generating one from the video", path);
+        None
+    }
+}
+
+async fn is_non_empty_file(path: &str) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.is_file() && metadata.len() > 0)
+}
+
+/// Remove the task's folders with whatever it left behind. A failed upload is kept
+/// for the retry button (see `remove_source`), cancelled tasks keep nothing.
+async fn remove_task_files(task: &Task, failed: bool) {
+    match &task.task_type {
+        TaskType::Download { .. } => workdir::remove_task(&task.unique_file_id).await,
+        TaskType::Convert { .. } if failed => {}
+        TaskType::Convert { filename, thumbnail_path, .. } => {
+            workdir::remove_task_of(filename).await;
+            remove_original_thumbnail(thumbnail_path).await;
+        }
+    }
+}
+
+/// Keep a sent file in the archive if archive mode is on (`ARCHIVE_DIR`), named after
+/// the video's title. Uploads have no title, their task's file ID stands in.
+async fn archive_sent_file(task: &Task, path: &str) {
+    if crate::config::get().archive_dir.is_none() {
+        return;
+    }
+
+    let title = match &task.task_type {
+        TaskType::Download { url, password, .. } => {
+            crate::video::youtube::get_video_title(url, password.as_ref()).await.ok()
         }
+        TaskType::Convert { .. } => None,
+    };
+    let title = title.unwrap_or_else(|| task.unique_file_id.clone());
+    crate::video::archive::archive_file(path, task.chat_id.0, &title).await;
+}
+
+/// Remove the thumbnail downloaded alongside the video once it's no longer needed
+async fn remove_original_thumbnail(thumbnail_path: &Option<String>) {
+    if let Some(path) = thumbnail_path {
+        let _ = tokio::fs::remove_file(path).await;
     }
 }
 
-/// Process conversion task
+/// Delete a task's source file and its original thumbnail once they're no longer needed.
+/// Uploads that failed are kept for the retry button: they can't be fetched again,
+/// and `delete_expired_tasks` removes them when the task expires.
+async fn remove_source(task: &Task, filename: &str, thumbnail_path: &Option<String>, succeeded: bool) {
+    if !succeeded && matches!(task.task_type, TaskType::Convert { .. }) {
+        log::info!("Keeping {} for a retry of failed task {}", filename, task.id);
+        return;
+    }
+
+    let _ = tokio::fs::remove_file(filename).await;
+    remove_original_thumbnail(thumbnail_path).await;
+}
+
+/// Parts of a split file that `send_parts` got out, and the ones it couldn't
+#[derive(Debug)]
+struct SentParts {
+    ids: Vec<MessageId>,
+    /// Indexes of the parts that failed to send
+    failed: Vec<usize>,
+}
+
+/// Send `count` parts with `send`, one at a time, each only after the previous one was
+/// delivered, so they arrive in playback order. A part that fails is skipped and the
+/// rest still go out; only when nothing was sent is it an error.
+async fn send_parts<F, Fut>(count: usize, mut send: F) -> Result<SentParts, teloxide::RequestError>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<MessageId, teloxide::RequestError>>,
+{
+    let mut sent = SentParts { ids: Vec::new(), failed: Vec::new() };
+    let mut last_error = None;
+    for index in 0..count {
+        match send(index).await {
+            Ok(id) => sent.ids.push(id),
+            Err(e) => {
+                log::warn!("Failed to send part {}/{}: {}", index + 1, count, e);
+                sent.failed.push(index);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    match last_error {
+        Some(e) if sent.ids.is_empty() => Err(e),
+        _ => Ok(sent),
+    }
+}
+
+/// Process conversion task. Returns the messages with the sent media.
+/// `audio_metadata` fills in the audio player's title and performer, if known.
 async fn process_convert_task(
     bot: &Bot,
     task: &Task,
     filename: &str,
     thumbnail_path: Option<String>,
     format: MediaFormatType,
-) -> Result<(), String> {
-    use crate::video::convert::{convert_audio, convert_video_note};
-    use crate::video::{VideoInfo, compress_video_with_progress, generate_thumbnail};
-    use std::sync::atomic::{AtomicBool, Ordering};
-    use std::sync::Arc;
+    audio_metadata: Option<AudioMetadata>,
+    progress: &ProgressTracker,
+) -> Result<Vec<MessageId>, TaskError> {
+    use crate::video::convert::{
+        apply_custom_filters, convert_audio, convert_video_note, convert_video_sticker, convert_voice, downscale_video,
+        split_audio, strip_audio, verify_output,
+    };
+    use crate::video::{VideoInfo, compress_video_with_progress};
     use teloxide::types::{InputFile, ParseMode};
     use teloxide::{ApiError, RequestError};
     use tokio::fs;
-    use tokio::sync::mpsc;
 
-    use crate::utils::loading_screen_with_progress;
+    let mut sent = Vec::new();
+    let mut failure = None;
+    let file_stem = custom_file_stem(task).await;
+
+    // The user's own filters go first, everything below works on their output
+    let filtered;
+    let filename = if task.filters.is_empty() {
+        filename
+    } else {
+        let _ = bot
+            .edit_message_text(task.chat_id, task.message_id, "🎛 Применяем ваши фильтры...")
+            .await;
+        match apply_custom_filters(filename, &task.filters, Some(progress.sender(None))).await {
+            Ok(path) => {
+                filtered = path;
+                filtered.as_str()
+            }
+            Err(e) => {
+                edit_status(
+                    bot,
+                    task.chat_id,
+                    task.message_id,
+                    format!("❌ Ваш фильтр не сработал, проверьте его в /filter.
+
+{}", e),
+                )
+                .await;
+                return Err(TaskError::new(TaskErrorKind::ConversionFailed, e));
+            }
+        }
+    };
+
+    // Muted videos lose their audio before anything else looks at the file
+    let muted;
+    let filename = if format == MediaFormatType::Video && task.options.mute {
+        let _ = bot
+            .edit_message_text(task.chat_id, task.message_id, "🔇 Убираем звук...")
+            .await;
+        muted = strip_audio(filename, Some(progress.sender(None)))
+            .await
+            .map_err(|e| TaskError::new(TaskErrorKind::ConversionFailed, e))?;
+        muted.as_str()
+    } else {
+        filename
+    };
+
+    // Uploads sent as video can be scaled down to the resolution the user picked
+    let scaled;
+    let filename = match &task.task_type {
+        TaskType::Convert { max_height: Some(height), .. } if format == MediaFormatType::Video => {
+            let source_height = VideoInfo::from_file(filename).await.map(|info| info.height).unwrap_or(0);
+            if *height < source_height {
+                let _ = bot
+                    .edit_message_text(task.chat_id, task.message_id, format!("📐 Уменьшаем до {}p...", height))
+                    .await;
+                scaled = downscale_video(filename, *height, Some(progress.sender(None)))
+                    .await
+                    .map_err(|e| TaskError::new(TaskErrorKind::ConversionFailed, e))?;
+                scaled.as_str()
+            } else {
+                filename
+            }
+        }
+        _ => filename,
+    };
 
     // For Video format, just send without conversion
     if format == MediaFormatType::Video {
@@ -705,27 +2848,41 @@ async fn process_convert_task(
 
         let video_info = VideoInfo::from_file(filename)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| TaskError::new(TaskErrorKind::ConversionFailed, e))?;
 
         // Use YouTube thumbnail if available, otherwise generate one
-        let thumbnail = if thumbnail_path.is_some() {
-            thumbnail_path.clone()
-        } else {
-            generate_thumbnail(filename).await.ok()
-        };
+        let thumbnail = prepare_thumbnail(thumbnail_path.as_deref(), filename).await;
 
         let mut request = bot
-            .send_video(task.chat_id, InputFile::file(filename))
+            .send_video(task.chat_id, named_file(filename, file_stem.as_deref()))
             .width(video_info.width)
             .height(video_info.height)
             .duration(video_info.duration as u32)
-            .supports_streaming(true);
+            .supports_streaming(true)
+            .has_spoiler(task.options.spoiler)
+            .protect_content(task.options.protect_content);
 
         if let Some(ref thumb_path) = thumbnail {
             request = request.thumbnail(InputFile::file(thumb_path));
         }
+        if let Some(id) = task.reply_to {
+            request = request.reply_parameters(reply_to(id));
+        }
 
-        let result = request.await;
+        // Don't spend minutes uploading a file the Bot API is going to reject
+        let upload_limit = crate::config::get().upload_limit;
+        let file_size = fs::metadata(filename).await.map(|m| m.len()).unwrap_or(0);
+        let result = if file_size > upload_limit {
+            log::info!(
+                "Video {} is {} bytes, over the {} bytes upload limit",
+                filename,
+                file_size,
+                upload_limit
+            );
+            Err(RequestError::Api(ApiError::RequestEntityTooLarge))
+        } else {
+            request.send_with_retry().await
+        };
 
         // Clean up thumbnail
         if let Some(thumb_path) = thumbnail {
@@ -733,14 +2890,15 @@ async fn process_convert_task(
         }
 
         match result {
-            Ok(_) => {
-                let _ = bot
-                    .edit_message_text(
-                        task.chat_id,
-                        task.message_id,
-                        "✅ Готово! Ваше видео отправлено!",
-                    )
-                    .await;
+            Ok(message) => {
+                sent.push(message.id);
+                edit_status(
+                    bot,
+                    task.chat_id,
+                    task.message_id,
+                    "✅ Готово! Ваше видео отправлено!",
+                )
+                .await;
             }
             Err(RequestError::Api(ApiError::RequestEntityTooLarge)) => {
                 // Try compression
@@ -748,35 +2906,44 @@ async fn process_convert_task(
                     .edit_message_text(
                         task.chat_id,
                         task.message_id,
-                        "🔧 Видео слишком большое, сжимаем...",
+                        format!(
+                            "🔧 Видео больше {}, сжимаем...",
+                            crate::config::format_size(upload_limit)
+                        ),
                     )
                     .await;
 
-                match compress_video_with_progress(filename, None).await {
+                // Not above the quality the user picked, which their tier already capped
+                let max_height = match &task.task_type {
+                    TaskType::Download { quality, .. } => *quality,
+                    TaskType::Convert { max_height, .. } => *max_height,
+                };
+                match compress_video_with_progress(filename, max_height, Some(progress.sender(None))).await {
                     Ok(compressed) => {
                         let video_info = VideoInfo::from_file(&compressed)
                             .await
-                            .map_err(|e| e.to_string())?;
+                            .map_err(|e| TaskError::new(TaskErrorKind::ConversionFailed, e))?;
 
                         // Use original thumbnail or generate from compressed video
-                        let thumb = if thumbnail_path.is_some() {
-                            thumbnail_path.clone()
-                        } else {
-                            generate_thumbnail(&compressed).await.ok()
-                        };
+                        let thumb = prepare_thumbnail(thumbnail_path.as_deref(), &compressed).await;
 
                         let mut request = bot
-                            .send_video(task.chat_id, InputFile::file(&compressed))
+                            .send_video(task.chat_id, named_file(&compressed, file_stem.as_deref()))
                             .width(video_info.width)
                             .height(video_info.height)
                             .duration(video_info.duration as u32)
-                            .supports_streaming(true);
+                            .supports_streaming(true)
+                            .has_spoiler(task.options.spoiler)
+                            .protect_content(task.options.protect_content);
 
                         if let Some(ref thumb_path) = thumb {
                             request = request.thumbnail(InputFile::file(thumb_path));
                         }
+                        if let Some(id) = task.reply_to {
+                            request = request.reply_parameters(reply_to(id));
+                        }
 
-                        let send_result = request.await;
+                        let send_result = request.send_with_retry().await;
 
                         let _ = fs::remove_file(&compressed).await;
                         if let Some(thumb_path) = thumb {
@@ -784,45 +2951,54 @@ async fn process_convert_task(
                         }
 
                         match send_result {
-                            Ok(_) => {
-                                let _ = bot
-                                    .edit_message_text(
-                                        task.chat_id,
-                                        task.message_id,
-                                        "✅ Видео сжато и отправлено!",
-                                    )
-                                    .await;
+                            Ok(message) => {
+                                sent.push(message.id);
+                                edit_status(
+                                    bot,
+                                    task.chat_id,
+                                    task.message_id,
+                                    "✅ Видео сжато и отправлено!",
+                                )
+                                .await;
                             }
-                            Err(_) => {
-                                let _ = bot
-                                    .edit_message_text(
-                                        task.chat_id,
-                                        task.message_id,
-                                        "❌ Не удалось отправить видео даже после сжатия.",
-                                    )
-                                    .await;
+                            Err(e) => {
+                                edit_status(
+                                    bot,
+                                    task.chat_id,
+                                    task.message_id,
+                                    "❌ Не удалось отправить видео даже после сжатия.",
+                                )
+                                .await;
+                                failure = Some(TaskError::new(TaskErrorKind::TooLarge, e));
                             }
                         }
                     }
-                    Err(_) => {
-                        let _ = bot
-                            .edit_message_text(
-                                task.chat_id,
-                                task.message_id,
-                                "❌ Не удалось сжать видео.",
-                            )
-                            .await;
+                    Err(e) => {
+                        edit_status(
+                            bot,
+                            task.chat_id,
+                            task.message_id,
+                            "❌ Не удалось сжать видео.",
+                        )
+                        .await;
+                        failure = Some(TaskError::new(TaskErrorKind::ConversionFailed, e));
                     }
                 }
             }
             Err(e) => {
-                let _ = fs::remove_file(filename).await;
-                return Err(format!("Send error: {}", e));
+                remove_source(task, filename, &thumbnail_path, false).await;
+                return Err(TaskError::new(TaskErrorKind::SendFailed, e));
             }
         }
 
-        let _ = fs::remove_file(filename).await;
-        return Ok(());
+        if failure.is_none() {
+            archive_sent_file(task, filename).await;
+        }
+        remove_source(task, filename, &thumbnail_path, failure.is_none()).await;
+        return match failure {
+            Some(e) => Err(e),
+            None => Ok(sent),
+        };
     }
 
     // For other formats, need conversion
@@ -835,25 +3011,17 @@ async fn process_convert_task(
         .await;
 
     // Start loading screen
-    let should_stop_loading = Arc::new(AtomicBool::new(false));
-    let (_progress_tx, progress_rx) = mpsc::unbounded_channel();
-    let loading_task = {
-        let bot_clone = bot.clone();
-        let should_stop_clone = should_stop_loading.clone();
-        let chat_id = task.chat_id;
-        let message_id = task.message_id;
-        tokio::spawn(async move {
-            loading_screen_with_progress(
-                bot_clone,
-                chat_id,
-                message_id,
-                should_stop_clone,
-                progress_rx,
-            )
-            .await;
-        })
-    };
-
+    let (progress_tx, progress_rx) = mpsc::unbounded_channel();
+    let progress_tx = progress.sender(Some(progress_tx));
+    let loading = LoadingScreen::start(
+        bot.clone(),
+        task.chat_id,
+        task.message_id,
+        progress_rx,
+        crate::config::get().convert_messages.clone(),
+    );
+
+    let conversion_started = Instant::now();
     let conversion_result = match format {
         MediaFormatType::Video => Ok(filename.to_string()),
         MediaFormatType::VideoNote => {
@@ -864,14 +3032,41 @@ async fn process_convert_task(
                 )
                 .parse_mode(ParseMode::Html)
                 .await;
-            convert_video_note(filename).await
+            convert_video_note(filename, Some(progress_tx)).await
+        }
+        MediaFormatType::Audio => convert_audio(filename, task.options.trim_silence, Some(progress_tx)).await,
+        MediaFormatType::Voice => convert_voice(filename, Some(progress_tx)).await,
+        MediaFormatType::VideoSticker => {
+            let _ = bot
+                .send_message(
+                    task.chat_id,
+                    "<b>⚠️ Внимание</b> стикер будет обрезан до 3 секунд и без звука.",
+                )
+                .parse_mode(ParseMode::Html)
+                .await;
+            convert_video_sticker(filename, Some(progress_tx)).await
+        }
+        MediaFormatType::Transcript => {
+            let _ = bot
+                .edit_message_text(task.chat_id, task.message_id, "📝 Расшифровываем речь...")
+                .await;
+            crate::video::transcribe::transcribe_to_file(filename).await
         }
-        MediaFormatType::Audio | MediaFormatType::Voice => convert_audio(filename).await,
     };
 
     // Stop loading
-    should_stop_loading.store(true, Ordering::Relaxed);
-    loading_task.abort();
+    drop(loading);
+
+    // Don't send (and drop the source for) a broken output; plain video isn't converted
+    let conversion_result = match conversion_result {
+        Ok(converted_file) if converted_file != filename => {
+            verify_output(&converted_file).await.map(|_| converted_file)
+        }
+        other => other,
+    };
+    if format != MediaFormatType::Video {
+        crate::metrics::conversion_finished(&format, conversion_started.elapsed(), conversion_result.is_ok());
+    }
 
     match conversion_result {
         Ok(converted_file) => {
@@ -879,27 +3074,28 @@ async fn process_convert_task(
                 MediaFormatType::Video => {
                     let video_info = VideoInfo::from_file(&converted_file)
                         .await
-                        .map_err(|e| e.to_string())?;
+                        .map_err(|e| TaskError::new(TaskErrorKind::ConversionFailed, e))?;
 
                     // Use original thumbnail or generate from converted video
-                    let thumb = if thumbnail_path.is_some() {
-                        thumbnail_path.clone()
-                    } else {
-                        generate_thumbnail(&converted_file).await.ok()
-                    };
+                    let thumb = prepare_thumbnail(thumbnail_path.as_deref(), &converted_file).await;
 
                     let mut request = bot
-                        .send_video(task.chat_id, InputFile::file(&converted_file))
+                        .send_video(task.chat_id, named_file(&converted_file, file_stem.as_deref()))
                         .width(video_info.width)
                         .height(video_info.height)
                         .duration(video_info.duration as u32)
-                        .supports_streaming(true);
+                        .supports_streaming(true)
+                        .has_spoiler(task.options.spoiler)
+                        .protect_content(task.options.protect_content);
 
                     if let Some(ref thumb_path) = thumb {
                         request = request.thumbnail(InputFile::file(thumb_path));
                     }
+                    if let Some(id) = task.reply_to {
+                        request = request.reply_parameters(reply_to(id));
+                    }
 
-                    let result = request.await.map(|_| ());
+                    let result = request.send_with_retry().await.map(|m| vec![m.id]);
 
                     // Clean up thumbnail
                     if let Some(thumb_path) = thumb {
@@ -908,68 +3104,404 @@ async fn process_convert_task(
 
                     result
                 }
-                MediaFormatType::Audio => bot
-                    .send_audio(task.chat_id, InputFile::file(&converted_file))
-                    .await
-                    .map(|_| ()),
-                MediaFormatType::VideoNote => bot
-                    .send_video_note(task.chat_id, InputFile::file(&converted_file))
-                    .await
-                    .map(|_| ()),
-                MediaFormatType::Voice => bot
-                    .send_voice(task.chat_id, InputFile::file(&converted_file))
-                    .await
-                    .map(|_| ()),
+                MediaFormatType::Audio => {
+                    // Audio over the upload limit (hours-long podcasts) goes out in parts
+                    let parts = match split_audio(&converted_file, crate::config::get().upload_limit).await {
+                        Ok(parts) => parts,
+                        Err(e) => {
+                            log::warn!("Failed to split {}, sending it whole: {}", converted_file, e);
+                            vec![converted_file.clone()]
+                        }
+                    };
+
+                    let metadata = audio_metadata.unwrap_or_default();
+
+                    // Cover art: the video thumbnail, or a frame from the source video
+                    let thumb = prepare_thumbnail(thumbnail_path.as_deref(), filename).await;
+
+                    // "Название (2/3)", for a file name, title and player alike
+                    let numbered = |name: &str, index: usize| match parts.len() {
+                        1 => name.to_string(),
+                        count => format!("{} ({}/{})", name, index + 1, count),
+                    };
+
+                    let (parts, numbered) = (&parts, &numbered);
+                    let (metadata, thumb, file_stem) = (&metadata, &thumb, &file_stem);
+                    let result = send_parts(parts.len(), |index| {
+                        let part = &parts[index];
+                        async move {
+                            let mut request = bot
+                                .send_audio(
+                                    task.chat_id,
+                                    named_file(part, file_stem.as_deref().map(|stem| numbered(stem, index)).as_deref()),
+                                )
+                                .protect_content(task.options.protect_content);
+
+                            match metadata.title.as_deref() {
+                                Some(title) => request = request.title(numbered(title, index)),
+                                None if parts.len() > 1 => request = request.title(numbered("Часть", index)),
+                                None => {}
+                            }
+                            if parts.len() > 1 {
+                                request = request.caption(format!("Часть {}/{}", index + 1, parts.len()));
+                            }
+                            if let Some(performer) = metadata.performer.clone() {
+                                request = request.performer(performer);
+                            }
+                            if let Ok(duration) = VideoInfo::get_duration(part).await {
+                                request = request.duration(duration as u32);
+                            }
+                            if let Some(thumb_path) = thumb {
+                                request = request.thumbnail(InputFile::file(thumb_path));
+                            }
+                            if let Some(id) = task.reply_to {
+                                request = request.reply_parameters(reply_to(id));
+                            }
+                            request.send_with_retry().await.map(|message| message.id)
+                        }
+                    })
+                    .await;
+
+                    // The parts that did go out are kept, the user hears which ones are missing
+                    if let Ok(sent_parts) = &result {
+                        for index in &sent_parts.failed {
+                            let mut request = bot.send_message(
+                                task.chat_id,
+                                format!("⚠️ Часть {}/{} не удалось отправить", index + 1, parts.len()),
+                            );
+                            if let Some(id) = task.reply_to {
+                                request = request.reply_parameters(reply_to(id));
+                            }
+                            let _ = request.await;
+                        }
+                    }
+
+                    if let Some(thumb_path) = thumb {
+                        let _ = fs::remove_file(thumb_path).await;
+                    }
+                    for part in parts.iter().filter(|&part| *part != converted_file) {
+                        let _ = fs::remove_file(part).await;
+                    }
+
+                    result.map(|sent_parts| sent_parts.ids)
+                }
+                MediaFormatType::VideoNote => {
+                    let mut request = bot
+                        .send_video_note(task.chat_id, InputFile::file(&converted_file))
+                        .protect_content(task.options.protect_content);
+                    if let Some(id) = task.reply_to {
+                        request = request.reply_parameters(reply_to(id));
+                    }
+                    request.send_with_retry().await.map(|m| vec![m.id])
+                }
+                MediaFormatType::Voice => {
+                    let mut request = bot
+                        .send_voice(task.chat_id, InputFile::file(&converted_file))
+                        .protect_content(task.options.protect_content);
+                    if let Some(id) = task.reply_to {
+                        request = request.reply_parameters(reply_to(id));
+                    }
+                    request.send_with_retry().await.map(|m| vec![m.id])
+                }
+                MediaFormatType::VideoSticker => {
+                    let mut request = bot
+                        .send_sticker(task.chat_id, InputFile::file(&converted_file))
+                        .protect_content(task.options.protect_content);
+                    if let Some(id) = task.reply_to {
+                        request = request.reply_parameters(reply_to(id));
+                    }
+                    request.send_with_retry().await.map(|m| vec![m.id])
+                }
+                MediaFormatType::Transcript => {
+                    let text = fs::read_to_string(&converted_file).await.unwrap_or_default();
+                    send_transcript(bot, task.chat_id, &text, task.options.protect_content, task.reply_to)
+                        .await
+                        .map(|messages| messages.iter().map(|m| m.id).collect())
+                }
             };
 
             match send_result {
-                Ok(_) => {
-                    let _ = bot
-                        .edit_message_text(
-                            task.chat_id,
-                            task.message_id,
-                            "✅ Готово! Файл отправлен!",
-                        )
-                        .await;
+                Ok(ids) => {
+                    sent = ids;
+                    edit_status(
+                        bot,
+                        task.chat_id,
+                        task.message_id,
+                        "✅ Готово! Файл отправлен!",
+                    )
+                    .await;
                 }
                 Err(RequestError::Api(ApiError::RequestEntityTooLarge)) => {
-                    let _ = bot
-                        .edit_message_text(
-                            task.chat_id,
-                            task.message_id,
-                            "❌ Файл слишком большой для отправки.",
-                        )
-                        .await;
+                    edit_status(
+                        bot,
+                        task.chat_id,
+                        task.message_id,
+                        "❌ Файл слишком большой для отправки.",
+                    )
+                    .await;
+                    failure = Some(TaskError::new(
+                        TaskErrorKind::TooLarge,
+                        ApiError::RequestEntityTooLarge,
+                    ));
                 }
                 Err(e) => {
-                    let _ = bot
-                        .edit_message_text(
-                            task.chat_id,
-                            task.message_id,
-                            format!("❌ Ошибка отправки: {}", e),
-                        )
-                        .await;
+                    edit_status(
+                        bot,
+                        task.chat_id,
+                        task.message_id,
+                        format!("❌ Ошибка отправки: {}", e),
+                    )
+                    .await;
+                    failure = Some(TaskError::new(TaskErrorKind::SendFailed, e));
                 }
             }
 
-            // Cleanup
+            if failure.is_none() {
+                archive_sent_file(task, &converted_file).await;
+            }
+
+            // Cleanup: a retry converts again, so only the source is worth keeping
             if converted_file != filename {
                 let _ = fs::remove_file(&converted_file).await;
             }
-            let _ = fs::remove_file(filename).await;
+            remove_source(task, filename, &thumbnail_path, failure.is_none()).await;
 
-            Ok(())
+            match failure {
+                Some(e) => Err(e),
+                None => Ok(sent),
+            }
         }
         Err(e) => {
-            let _ = bot
-                .edit_message_text(
-                    task.chat_id,
-                    task.message_id,
-                    "❌ Ошибка конвертации. Попробуйте другой формат.",
-                )
-                .await;
-            let _ = fs::remove_file(filename).await;
-            Err(format!("Conversion error: {}", e))
+            let text = match e {
+                BotError::FileTooLarge(_) if format == MediaFormatType::VideoSticker => {
+                    "❌ Не получилось уложить стикер в 256 КБ. Попробуйте видео попроще: \
+                    с меньшим количеством движения и деталей."
+                }
+                _ => "❌ Ошибка конвертации. Попробуйте другой формат.",
+            };
+            edit_status(bot, task.chat_id, task.message_id, text).await;
+            remove_source(task, filename, &thumbnail_path, false).await;
+            Err(TaskError::from_bot_error(TaskErrorKind::ConversionFailed, e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use crate::errors::ConversionError;
+
+    #[test]
+    fn errors_keep_the_stage_category_by_default() {
+        let error = TaskError::from_bot_error(
+            TaskErrorKind::DownloadFailed,
+            BotError::YoutubeError("ERROR: Video unavailable".to_string()),
+        );
+        assert_eq!(error.kind, TaskErrorKind::DownloadFailed);
+        assert!(error.details.contains("Video unavailable"));
+
+        let error = TaskError::from_bot_error(
+            TaskErrorKind::ConversionFailed,
+            BotError::ExternalCommandError {
+                command: "ffmpeg".to_string(),
+                stderr: "Invalid data found when processing input".to_string(),
+            },
+        );
+        assert_eq!(error.kind, TaskErrorKind::ConversionFailed);
+    }
+
+    #[test]
+    fn errors_that_say_more_than_the_stage_win() {
+        for stage in [TaskErrorKind::DownloadFailed, TaskErrorKind::ConversionFailed] {
+            let error = TaskError::from_bot_error(stage, BotError::Timeout("yt-dlp".to_string()));
+            assert_eq!(error.kind, TaskErrorKind::Timeout);
+
+            let error = TaskError::from_bot_error(stage, BotError::FileTooLarge("60 MB".to_string()));
+            assert_eq!(error.kind, TaskErrorKind::TooLarge);
+
+            let disk_full = std::io::Error::from(std::io::ErrorKind::StorageFull);
+            let error = TaskError::from_bot_error(stage, BotError::FileSystemError(disk_full));
+            assert_eq!(error.kind, TaskErrorKind::StorageUnavailable);
+
+            let read_only = std::io::Error::from(std::io::ErrorKind::ReadOnlyFilesystem);
+            let error = TaskError::from_bot_error(stage, BotError::ConversionError(ConversionError::IOError(read_only)));
+            assert_eq!(error.kind, TaskErrorKind::StorageUnavailable);
+
+            let error = TaskError::from_bot_error(
+                stage,
+                BotError::YoutubeError("ERROR: unable to write data: No space left on device".to_string()),
+            );
+            assert_eq!(error.kind, TaskErrorKind::StorageUnavailable);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_or_empty_thumbnails_are_not_attached() {
+        let dir = std::env::temp_dir().join(format!("thumbnail-test-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let missing = dir.join("missing.jpg");
+        let empty = dir.join("empty.jpg");
+        let present = dir.join("present.jpg");
+        tokio::fs::write(&empty, b"").await.unwrap();
+        tokio::fs::write(&present, b"\xFF\xD8\xFF").await.unwrap();
+
+        assert_eq!(existing_thumbnail(None).await, None);
+        assert_eq!(existing_thumbnail(missing.to_str()).await, None);
+        assert_eq!(existing_thumbnail(empty.to_str()).await, None);
+        assert_eq!(existing_thumbnail(dir.to_str()).await, None);
+        assert_eq!(existing_thumbnail(present.to_str()).await, present.to_str());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_thumbnail_without_a_video_sends_none() {
+        let dir = std::env::temp_dir().join(format!("thumbnail-test-{}", uuid::Uuid::new_v4()));
+        let missing = dir.join("missing.jpg");
+        let video = dir.join("missing.mp4");
+
+        assert_eq!(prepare_thumbnail(missing.to_str(), video.to_str().unwrap()).await, None);
+    }
+
+    fn task(task_type: TaskType) -> Task {
+        Task {
+            id: TaskId::new(),
+            task_type,
+            chat_id: ChatId(1),
+            message_id: MessageId(1),
+            reply_to: None,
+            unique_file_id: "video".to_string(),
+            options: Default::default(),
+            filters: Default::default(),
+            target_chat: None,
+            premium: false,
         }
     }
+
+    /// An uploaded video with its thumbnail, in a temporary folder
+    async fn upload() -> (std::path::PathBuf, String, String) {
+        let dir = std::env::temp_dir().join(format!("source-test-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let video = dir.join("video.mp4").to_string_lossy().into_owned();
+        let thumbnail = dir.join("video.jpg").to_string_lossy().into_owned();
+        tokio::fs::write(&video, b"video").await.unwrap();
+        tokio::fs::write(&thumbnail, b"thumbnail").await.unwrap();
+        (dir, video, thumbnail)
+    }
+
+    fn convert_task(video: &str, thumbnail: &str) -> Task {
+        task(TaskType::Convert {
+            filename: video.to_string(),
+            thumbnail_path: Some(thumbnail.to_string()),
+            format: MediaFormatType::Video,
+            max_height: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn failed_uploads_keep_their_source_for_a_retry() {
+        let (dir, video, thumbnail) = upload().await;
+        let task = convert_task(&video, &thumbnail);
+
+        remove_source(&task, &video, &Some(thumbnail.clone()), false).await;
+        assert!(is_non_empty_file(&video).await);
+        assert!(is_non_empty_file(&thumbnail).await);
+
+        remove_task_files(&task, true).await;
+        assert!(is_non_empty_file(&video).await);
+        assert!(is_non_empty_file(&thumbnail).await);
+
+        remove_source(&task, &video, &Some(thumbnail.clone()), true).await;
+        assert!(!is_non_empty_file(&video).await);
+        assert!(!is_non_empty_file(&thumbnail).await);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelled_uploads_are_removed() {
+        let (dir, video, thumbnail) = upload().await;
+        let task = convert_task(&video, &thumbnail);
+
+        remove_task_files(&task, false).await;
+        assert!(!is_non_empty_file(&video).await);
+        assert!(!is_non_empty_file(&thumbnail).await);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_downloads_are_removed() {
+        // Downloads can be fetched again, so a failed one isn't worth the disk space
+        let (dir, video, thumbnail) = upload().await;
+        let task = task(TaskType::Download {
+            url: "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+            quality: Some(720),
+            format: MediaFormatType::Video,
+            audio_language: None,
+            format_string: None,
+            size_budget: None,
+            filename_template: None,
+            password: None,
+        });
+
+        remove_source(&task, &video, &Some(thumbnail.clone()), false).await;
+        assert!(!is_non_empty_file(&video).await);
+        assert!(!is_non_empty_file(&thumbnail).await);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn categories_survive_the_tasks_table() {
+        for kind in [
+            TaskErrorKind::DownloadFailed,
+            TaskErrorKind::ConversionFailed,
+            TaskErrorKind::SendFailed,
+            TaskErrorKind::Timeout,
+            TaskErrorKind::TooLarge,
+            TaskErrorKind::Cancelled,
+            TaskErrorKind::StorageUnavailable,
+        ] {
+            assert_eq!(TaskErrorKind::from_str(kind.as_ref()), Ok(kind));
+        }
+        assert_eq!(TaskErrorKind::DownloadFailed.as_ref(), "download_failed");
+        assert!(TaskErrorKind::from_str("unknown").is_err());
+    }
+
+    #[tokio::test]
+    async fn a_failed_part_does_not_stop_the_rest() {
+        let mut attempted = Vec::new();
+        let sent = send_parts(3, |index| {
+            attempted.push(index);
+            async move {
+                match index {
+                    1 => Err(teloxide::RequestError::Api(teloxide::ApiError::RequestEntityTooLarge)),
+                    _ => Ok(MessageId(index as i32 + 10)),
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(attempted, [0, 1, 2]);
+        assert_eq!(sent.ids, [MessageId(10), MessageId(12)]);
+        assert_eq!(sent.failed, [1]);
+    }
+
+    #[tokio::test]
+    async fn nothing_sent_is_an_error() {
+        let result = send_parts(2, |_| async {
+            Err::<MessageId, _>(teloxide::RequestError::Api(teloxide::ApiError::RequestEntityTooLarge))
+        })
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(teloxide::RequestError::Api(teloxide::ApiError::RequestEntityTooLarge))
+        ));
+    }
 }
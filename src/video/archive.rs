@@ -0,0 +1,130 @@
+//! Archive mode for operators keeping a media library.
+//!
+//! With `ARCHIVE_DIR` set, a sent file is moved into the archive instead of being
+//! deleted with its task, at a path made from `ARCHIVE_TEMPLATE`. The archive lives
+//! outside the task folders, so neither the orphaned files cleanup nor
+//! `TaskDb::get_active_filenames` ever look at it: once moved, a file is the operator's.
+
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use tokio::fs;
+
+use crate::utils::{MAX_FILENAME_BYTES, sanitize_filename, truncate_bytes};
+use crate::video::workdir;
+
+/// Default `ARCHIVE_TEMPLATE`
+pub const DEFAULT_TEMPLATE: &str = "{date}/{user}/{title}.{ext}";
+
+/// Move a sent file into the archive. Returns where it ended up, None if archive mode
+/// is off or the file couldn't be moved (it's then deleted with its task as usual).
+pub async fn archive_file(path: &str, user: i64, title: &str) -> Option<PathBuf> {
+    let config = crate::config::get();
+    let root = Path::new(config.archive_dir.as_deref()?);
+
+    // The cleanup empties the task folders, an archive in there wouldn't last
+    if [workdir::SOURCE_ROOT, workdir::OUTPUT_ROOT].iter().any(|r| root.starts_with(r)) {
+        log::error!("ARCHIVE_DIR {} is inside a task folder, not archiving", root.display());
+        return None;
+    }
+
+    let target = free_path(root.join(archive_path(&config.archive_template, path, user, title))).await;
+    if let Some(parent) = target.parent()
+        && let Err(e) = fs::create_dir_all(parent).await
+    {
+        log::error!("Failed to create archive folder {}: {}", parent.display(), e);
+        return None;
+    }
+
+    // A rename can't cross filesystems, copy the file over then
+    let moved = match fs::rename(path, &target).await {
+        Ok(()) => Ok(()),
+        Err(_) => match fs::copy(path, &target).await {
+            Ok(_) => fs::remove_file(path).await,
+            Err(e) => Err(e),
+        },
+    };
+
+    match moved {
+        Ok(()) => {
+            log::info!("Archived {} as {}", path, target.display());
+            Some(target)
+        }
+        Err(e) => {
+            log::error!("Failed to archive {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Path inside the archive from the template. Each folder of the template is expanded
+/// and sanitized on its own, so neither a title nor the template can leave the archive.
+/// Only the title is shortened to fit the file name limit, so the extension and the
+/// rest of the template always survive.
+fn archive_path(template: &str, path: &str, user: i64, title: &str) -> PathBuf {
+    let ext = Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let date = Utc::now().format("%Y-%m-%d").to_string();
+    let title = sanitize_filename(title);
+
+    template
+        .split('/')
+        .filter(|part| !part.trim().is_empty())
+        .map(|part| {
+            let part = part
+                .replace("{date}", &date)
+                .replace("{user}", &user.to_string())
+                .replace("{ext}", &ext);
+
+            // The title goes in last, so placeholders in it stay as they are
+            let titles = part.matches("{title}").count();
+            let mut title = title.clone();
+            if titles > 0 {
+                let rest = part.len() - titles * "{title}".len();
+                truncate_bytes(&mut title, MAX_FILENAME_BYTES.saturating_sub(rest) / titles);
+            }
+            sanitize_filename(&part.replace("{title}", title.trim_end()))
+        })
+        .collect()
+}
+
+/// `path`, or `name (2).ext`, `name (3).ext`... if something is archived there already
+async fn free_path(path: PathBuf) -> PathBuf {
+    if !fs::try_exists(&path).await.unwrap_or(false) {
+        return path;
+    }
+
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    for n in 2.. {
+        let candidate = path.with_file_name(format!("{} ({}){}", stem, n, ext));
+        if !fs::try_exists(&candidate).await.unwrap_or(false) {
+            return candidate;
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_titles_keep_their_extension() {
+        let title = "Очень длинное название ".repeat(30);
+        let path = archive_path(DEFAULT_TEMPLATE, "/tmp/out/abc.mp4", 42, &title);
+
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.ends_with(".mp4"), "{}", name);
+        assert!(name.len() <= MAX_FILENAME_BYTES);
+        assert!(name.starts_with("Очень длинное название"));
+    }
+
+    #[test]
+    fn titles_cant_leave_the_archive() {
+        let path = archive_path("{user}/{title}.{ext}", "a.m4a", 7, "../../etc/passwd");
+        assert_eq!(path, PathBuf::from("7").join("_.._etc_passwd.m4a"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Small in-memory cache for yt-dlp metadata, so going back and forth
+//! between format/quality selection doesn't spawn yt-dlp every time.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Entry<V> {
+    value: V,
+    inserted_at: Instant,
+    last_used: Instant,
+}
+
+/// Bounded cache keyed by URL. Entries expire after `ttl`, and the least
+/// recently used entry is evicted once `capacity` is reached.
+pub struct TtlLruCache<V> {
+    entries: Mutex<HashMap<String, Entry<V>>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl<V: Clone> TtlLruCache<V> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Get a fresh value, dropping it if it has expired
+    pub fn get(&self, key: &str) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        match entries.get_mut(key) {
+            Some(entry) if now.duration_since(entry.inserted_at) < self.ttl => {
+                entry.last_used = now;
+                Some(entry.value.clone())
+            }
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: &str, value: V) {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        entries.retain(|_, entry| now.duration_since(entry.inserted_at) < self.ttl);
+
+        if !entries.contains_key(key) && entries.len() >= self.capacity {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            key.to_string(),
+            Entry {
+                value,
+                inserted_at: now,
+                last_used: now,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread::sleep;
+
+    const LONG_TTL: Duration = Duration::from_secs(60);
+
+    /// Make sure the next `last_used` differs from the previous one
+    fn tick() {
+        sleep(Duration::from_millis(2));
+    }
+
+    #[test]
+    fn entries_expire_after_ttl() {
+        let cache = TtlLruCache::new(4, Duration::from_millis(50));
+        cache.insert("a", 1);
+        assert_eq!(cache.get("a"), Some(1));
+
+        sleep(Duration::from_millis(80));
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn reading_does_not_extend_ttl() {
+        let cache = TtlLruCache::new(4, Duration::from_millis(80));
+        cache.insert("a", 1);
+        sleep(Duration::from_millis(50));
+        assert_eq!(cache.get("a"), Some(1));
+        sleep(Duration::from_millis(50));
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn least_recently_used_entry_is_evicted() {
+        let cache = TtlLruCache::new(2, LONG_TTL);
+        cache.insert("a", 1);
+        tick();
+        cache.insert("b", 2);
+        tick();
+        // "a" is now the most recently used
+        assert_eq!(cache.get("a"), Some(1));
+        tick();
+        cache.insert("c", 3);
+
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c"), Some(3));
+    }
+
+    #[test]
+    fn capacity_is_never_exceeded() {
+        let cache = TtlLruCache::new(3, LONG_TTL);
+        for (i, key) in ["a", "b", "c", "d", "e"].into_iter().enumerate() {
+            cache.insert(key, i);
+            tick();
+        }
+
+        assert_eq!(cache.entries.lock().unwrap().len(), 3);
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("e"), Some(4));
+    }
+
+    #[test]
+    fn replacing_a_key_at_capacity_evicts_nothing() {
+        let cache = TtlLruCache::new(2, LONG_TTL);
+        cache.insert("a", 1);
+        tick();
+        cache.insert("b", 2);
+        tick();
+        cache.insert("a", 10);
+
+        assert_eq!(cache.get("a"), Some(10));
+        assert_eq!(cache.get("b"), Some(2));
+    }
+
+    #[test]
+    fn expired_entries_make_room_before_eviction() {
+        let cache = TtlLruCache::new(2, Duration::from_millis(50));
+        cache.insert("a", 1);
+        sleep(Duration::from_millis(80));
+        cache.insert("b", 2);
+        tick();
+        cache.insert("c", 3);
+
+        // "a" expired, so "b" didn't have to go
+        assert_eq!(cache.get("b"), Some(2));
+        assert_eq!(cache.get("c"), Some(3));
+    }
+}
@@ -6,9 +6,14 @@ use std::{
 
 use tokio::{fs, process, sync::mpsc};
 
+use crate::config::BotApiMode;
 use crate::errors::{BotError, BotResult, ConversionError};
+use crate::settings::CustomFilters;
+use crate::video::{VideoInfo, workdir};
 
-const MAX_FILE_SIZE: u64 = 200 * 1024 * 1024; // 200MB in bytes
+/// Telegram video notes are square, up to 640px and 60 seconds long
+const VIDEO_NOTE_MAX_SIZE: u32 = 640;
+const VIDEO_NOTE_MAX_DURATION: &str = "60";
 
 #[derive(Debug, Clone)]
 pub struct ProgressInfo {
@@ -16,57 +21,576 @@ pub struct ProgressInfo {
     pub estimated_time_remaining: Option<Duration>,
 }
 
-pub async fn convert_video_note<P: AsRef<Path>>(file: P) -> BotResult<String> {
-    convert_with_progress(
+pub async fn convert_video_note<P: AsRef<Path>>(
+    file: P,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    let input_path = file.as_ref().to_string_lossy().into_owned();
+
+    // Already square H.264 video - no need to re-encode, just trim and remux
+    if let Ok(info) = VideoInfo::from_file(&input_path).await
+        && is_video_note_compatible(&info)
+    {
+        log::info!("Video note source is already compatible, using stream copy");
+
+        let audio_args = match info.audio_codec.as_deref() {
+            Some("aac") | None => ["-c:a", "copy"],
+            Some(_) => ["-c:a", "aac"],
+        };
+
+        // Video notes have no chapter UI, and the cut would leave dangling markers
+        let mut args = vec!["-t", VIDEO_NOTE_MAX_DURATION, "-c:v", "copy", "-map_chapters", "-1"];
+        args.extend(audio_args);
+
+        return convert_with_progress(file, "mp4", &args, progress_sender).await;
+    }
+
+    encode_h264(
         file,
-        "mp4",
-        &[
-            "-t",
-            "60",
-            "-vf",
-            "scale=(iw*sar)*max(512/(iw*sar)\\,512/ih):ih*max(512/(iw*sar)\\,512/ih), crop=512:512",
-        ],
-        None,
+        "scale=(iw*sar)*max(512/(iw*sar)\\,512/ih):ih*max(512/(iw*sar)\\,512/ih), crop=512:512",
+        "23",
+        "medium",
+        &["-t", VIDEO_NOTE_MAX_DURATION, "-map_chapters", "-1"],
+        progress_sender,
     )
     .await
 }
 
+/// Check if a video can be sent as a video note without re-encoding the video stream:
+/// square both in pixels and on screen, in the pixel format every player handles
+fn is_video_note_compatible(info: &VideoInfo) -> bool {
+    info.video_codec.as_deref() == Some("h264")
+        && info.width == info.height
+        && info.width <= VIDEO_NOTE_MAX_SIZE
+        && info.sample_aspect_ratio.as_deref().is_none_or(|sar| sar == "1:1")
+        && info.pixel_format.as_deref() == Some("yuv420p")
+}
+
+/// Telegram video stickers: VP9 WebM without sound, one side exactly 512px,
+/// at most 3 seconds, 30 fps and 256 KB
+const STICKER_SIZE: u32 = 512;
+const STICKER_MAX_DURATION: f64 = 3.0;
+const STICKER_MAX_BYTES: u64 = 256 * 1024;
+
+/// Qualities tried one after another until the sticker fits in 256 KB
+const STICKER_CRFS: [&str; 4] = ["30", "40", "50", "63"];
+
+/// Convert to a video sticker. The first 3 seconds are scaled to fit 512×512 and encoded
+/// at lower and lower quality until the file fits; `BotError::FileTooLarge` if even the
+/// lowest doesn't.
+pub async fn convert_video_sticker<P: AsRef<Path>>(
+    file: P,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    let filter = format!(
+        "scale={size}:{size}:force_original_aspect_ratio=decrease,fps=30",
+        size = STICKER_SIZE
+    );
+    let duration = STICKER_MAX_DURATION.to_string();
+
+    for crf in STICKER_CRFS {
+        let args = [
+            "-t", &duration,
+            "-vf", &filter,
+            "-an",
+            "-map_chapters", "-1",
+            "-c:v", "libvpx-vp9",
+            "-pix_fmt", "yuva420p",
+            "-b:v", "0",
+            "-crf", crf,
+        ];
+        let output = convert_with_progress(file.as_ref(), "webm", &args, progress_sender.clone()).await?;
+
+        let size = fs::metadata(&output).await?.len();
+        if size <= STICKER_MAX_BYTES {
+            verify_sticker(&output).await?;
+            return Ok(output);
+        }
+        log::info!("Sticker at CRF {} is {} bytes, trying lower quality", crf, size);
+    }
+
+    Err(BotError::file_too_large(format!(
+        "video sticker doesn't fit in {} bytes even at the lowest quality",
+        STICKER_MAX_BYTES
+    )))
+}
+
+/// Check the constraints Telegram enforces on video stickers, so a broken one fails here
+/// with a clear reason instead of being rejected by `sendSticker`
+async fn verify_sticker(path: &str) -> BotResult<()> {
+    let info = VideoInfo::from_file(path).await?;
+
+    let fits = info.video_codec.as_deref() == Some("vp9")
+        && info.audio_codec.is_none()
+        && info.width.max(info.height) == STICKER_SIZE
+        // Container durations are rounded up to whole frames
+        && info.duration <= STICKER_MAX_DURATION + 0.05;
+
+    if !fits {
+        let _ = fs::remove_file(path).await;
+        return Err(BotError::general(format!(
+            "Video sticker doesn't meet the requirements: {}x{}, {:.2}s, video {:?}, audio {:?}",
+            info.width, info.height, info.duration, info.video_codec, info.audio_codec
+        )));
+    }
+
+    Ok(())
+}
+
+/// Audio bitrate of size-targeted compression, kbit/s
+const TARGET_AUDIO_KBPS: u64 = 128;
+
+/// Share of the target size given to the streams, the rest covers container
+/// overhead and encoder overshoot
+const TARGET_SIZE_MARGIN: f64 = 0.95;
+
+/// Below this video bitrate the result is unwatchable, give up instead
+const MIN_VIDEO_KBPS: u64 = 150;
+
+/// Compression quality (CRF) and scale filter. The cloud Bot API only takes 50 MB,
+/// so compress much harder there. `max_height` scales down further, e.g. to the
+/// user's tier ceiling.
+fn compression_settings(mode: BotApiMode, max_height: Option<u32>) -> (&'static str, String) {
+    let (crf, height) = match mode {
+        BotApiMode::Cloud => ("36", 480),
+        BotApiMode::Local => ("32", 720),
+    };
+    let height = max_height.map_or(height, |max| height.min(max));
+    // 16:9 box, so portrait videos are limited by the same number of pixels
+    let width = (height * 16 / 9).next_multiple_of(2);
+    let scale = format!(
+        "scale=iw*min({w}/iw\\,{h}/ih):ih*min({w}/iw\\,{h}/ih)",
+        w = width,
+        h = height
+    );
+    (crf, scale)
+}
+
+/// Compress a video to fit the upload limit, at most `max_height` lines high. Two-pass
+/// encoding at a computed bitrate hits the size reliably; if it fails, falls back to
+/// faster single-pass CRF encoding.
 pub async fn compress_video_with_progress<P: AsRef<Path>>(
     file: P,
+    max_height: Option<u32>,
     progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
 ) -> BotResult<String> {
-    // Try compression with reduced quality
-    let compressed_file = convert_with_progress(
-        file,
-        "mp4",
-        &[
-            "-crf",
-            "32", // Higher CRF = lower quality, smaller file
-            "-preset",
-            "fast", // Encoding speed vs compression efficiency
-            "-vf",
-            "scale=iw*min(1280/iw\\,720/ih):ih*min(1280/iw\\,720/ih)", // Scale down if needed
-        ],
-        progress_sender,
-    )
-    .await?;
+    let upload_limit = crate::config::get().upload_limit;
+
+    match compress_video_to_size(&file, upload_limit, max_height, progress_sender.clone()).await {
+        Ok(compressed_file) => return Ok(compressed_file),
+        Err(e) => log::warn!("Size-targeted compression failed, falling back to CRF: {}", e),
+    }
+
+    compress_video_crf(file, max_height, progress_sender).await
+}
+
+/// Video bitrate (kbit/s) that makes a video of this length fit into `target_bytes`
+fn target_video_kbps(target_bytes: u64, duration_secs: f64, audio_kbps: u64) -> Option<u64> {
+    if duration_secs <= 0.0 {
+        return None;
+    }
+    let total_kbps = (target_bytes as f64 * 8.0 * TARGET_SIZE_MARGIN / 1000.0 / duration_secs) as u64;
+    total_kbps
+        .checked_sub(audio_kbps)
+        .filter(|&kbps| kbps >= MIN_VIDEO_KBPS)
+}
+
+/// Whether compression can bring a video this long under the upload limit at a watchable
+/// bitrate. Longer videos over the limit fail after downloading, whatever their quality.
+pub fn fits_after_compression(duration_secs: f64) -> bool {
+    let upload_limit = crate::config::get().upload_limit;
+    target_video_kbps(upload_limit, duration_secs, TARGET_AUDIO_KBPS).is_some()
+}
 
-    // Check if compressed file is still too big
-    let file_size = fs::metadata(&compressed_file).await?.len();
+/// Two-pass encode at the bitrate that fits `target_bytes`
+pub async fn compress_video_to_size<P: AsRef<Path>>(
+    file: P,
+    target_bytes: u64,
+    max_height: Option<u32>,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    let input_path = file.as_ref();
+    let input_str = input_path.to_str().ok_or(ConversionError::NonUtf8Path)?;
+    let info = VideoInfo::from_file(input_str).await?;
+
+    let audio_kbps = if info.audio_codec.is_some() { TARGET_AUDIO_KBPS } else { 0 };
+    let video_kbps = target_video_kbps(target_bytes, info.duration, audio_kbps).ok_or_else(|| {
+        BotError::file_too_large(format!(
+            "{:.0}s of video can't fit into {} bytes",
+            info.duration, target_bytes
+        ))
+    })?;
+    let video_bitrate = format!("{}k", video_kbps);
+    let audio_bitrate = format!("{}k", TARGET_AUDIO_KBPS);
+    let (_, scale) = compression_settings(crate::config::get().bot_api_mode, max_height);
+
+    log::info!(
+        "Compressing {} to {} bytes: {:.0}s at {} video + {} audio",
+        input_str, target_bytes, info.duration, video_bitrate, audio_bitrate
+    );
+
+    // ffmpeg adds -0.log (and -0.log.mbtree for x264) to the prefix
+    let passlog = format!(
+        "/tmp/ffmpeg2pass_{}_{}",
+        std::process::id(),
+        input_path.file_stem().and_then(|s| s.to_str()).unwrap_or("video")
+    );
 
-    if file_size > MAX_FILE_SIZE {
-        fs::remove_file(&compressed_file).await?;
+    let result = async {
+        // First pass only analyses the video, its output is thrown away
+        let output = process::Command::new("ffmpeg")
+            .kill_on_drop(true)
+            .args(["-y", "-i"])
+            .arg(input_path)
+            .args(["-vf", &scale, "-c:v", "libx264", "-preset", "fast"])
+            .args(["-b:v", &video_bitrate, "-pass", "1", "-passlogfile", &passlog])
+            .args(["-an", "-f", "null", "/dev/null"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Err(ConversionError::FfmpegFailed(
+                output.status,
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            )
+            .into());
+        }
+
+        convert_with_progress(
+            input_path,
+            "mp4",
+            &[
+                "-vf", &scale,
+                "-c:v", "libx264",
+                "-preset", "fast",
+                "-b:v", &video_bitrate,
+                "-pass", "2",
+                "-passlogfile", &passlog,
+                "-c:a", "aac",
+                "-b:a", &audio_bitrate,
+                "-map_chapters", "0",
+            ],
+            progress_sender,
+        )
+        .await
+    }
+    .await;
+
+    for suffix in ["-0.log", "-0.log.temp", "-0.log.mbtree", "-0.log.mbtree.temp"] {
+        let _ = fs::remove_file(format!("{}{}", passlog, suffix)).await;
+    }
+
+    let compressed_file = result?;
+    check_compressed_size(&compressed_file, target_bytes).await?;
+    Ok(compressed_file)
+}
+
+/// Single-pass CRF encode: faster, but the resulting size is only roughly predictable
+async fn compress_video_crf<P: AsRef<Path>>(
+    file: P,
+    max_height: Option<u32>,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    let config = crate::config::get();
+    let (crf, scale) = compression_settings(config.bot_api_mode, max_height);
+
+    // Try compression with reduced quality (higher CRF = lower quality, smaller file),
+    // scaled down if needed, keeping chapter markers from the source
+    let compressed_file =
+        encode_h264(file, &scale, crf, "fast", &["-map_chapters", "0"], progress_sender).await?;
+
+    check_compressed_size(&compressed_file, config.upload_limit).await?;
+    Ok(compressed_file)
+}
+
+/// Remove the compressed file if it's still too big
+async fn check_compressed_size(compressed_file: &str, limit: u64) -> BotResult<()> {
+    let file_size = fs::metadata(compressed_file).await?.len();
+
+    if file_size > limit {
+        fs::remove_file(compressed_file).await?;
         return Err(BotError::file_too_large(format!(
             "Even compressed file size {} bytes exceeds {} bytes limit",
-            file_size, MAX_FILE_SIZE
+            file_size, limit
         )));
     }
 
-    Ok(compressed_file)
+    Ok(())
+}
+
+/// Check that a conversion produced something worth sending before the source is thrown
+/// away: ffmpeg can exit successfully and still leave an empty or unreadable file.
+/// A bad output is removed.
+pub async fn verify_output(path: &str) -> BotResult<()> {
+    let size = fs::metadata(path).await?.len();
+    // Transcripts are plain text, everything else has to be readable media
+    let is_text = Path::new(path).extension().is_some_and(|ext| ext == "txt");
+    let readable = size > 0 && (is_text || VideoInfo::get_duration(path).await.is_ok());
+
+    if !readable {
+        let _ = fs::remove_file(path).await;
+        return Err(BotError::general(format!("Conversion produced an unusable file: {}", path)));
+    }
+
+    Ok(())
+}
+
+/// Subtitle look for burned-in subtitles: readable on phones, kept off the very bottom edge
+const SUBTITLE_STYLE: &str = "FontSize=20,Outline=1,Shadow=0,MarginV=24";
+
+/// Re-encode a video with the subtitles drawn into the picture.
+/// The audio is copied as is.
+pub async fn burn_subtitles<P: AsRef<Path>>(
+    file: P,
+    subtitles_path: &str,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    // The path goes into a filter graph, where ':' and quotes are special;
+    // the subtitle files we write never contain them
+    let filter = format!(
+        "subtitles=filename={}:force_style='{}'",
+        subtitles_path, SUBTITLE_STYLE
+    );
+
+    encode_h264(file, &filter, "23", "veryfast", &["-c:a", "copy"], progress_sender).await
+}
+
+/// Re-encode the video stream to H.264 through `filter` at roughly x264 quality `crf`.
+/// Uses the hardware encoder if one is active; if it fails (driver trouble, unsupported
+/// resolution), the conversion is redone with libx264 at `preset`.
+async fn encode_h264<P: AsRef<Path>>(
+    file: P,
+    filter: &str,
+    crf: &str,
+    preset: &str,
+    extra_args: &[&str],
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    if let Some(encoder) = crate::video::hwaccel::active() {
+        let input_args = encoder.input_args();
+        let input_args: Vec<&str> = input_args.iter().map(String::as_str).collect();
+        let hw_filter = encoder.filter(filter);
+        let codec_args = encoder.codec_args(crf);
+        let mut args = vec!["-vf", hw_filter.as_str()];
+        args.extend(codec_args.iter().map(String::as_str));
+        args.extend_from_slice(extra_args);
+
+        match convert_with_input_args(&file, &input_args, "mp4", &args, progress_sender.clone()).await {
+            Ok(output) => return Ok(output),
+            Err(BotError::ConversionError(ConversionError::FfmpegFailed(status, stderr))) => {
+                log::warn!(
+                    "{} encode failed ({}), retrying with libx264: {}",
+                    encoder.codec(),
+                    status,
+                    stderr.lines().last().unwrap_or_default()
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let mut args = vec!["-vf", filter, "-c:v", "libx264", "-crf", crf, "-preset", preset];
+    args.extend_from_slice(extra_args);
+    convert_with_progress(file, "mp4", &args, progress_sender).await
+}
+
+/// Run a user's own ffmpeg filters (see /filter) over a file before it's converted.
+/// Filtered streams are re-encoded to H.264/AAC, the others copied, so the result is
+/// an MP4 the usual conversions and sends take from there. It's written next to the
+/// source as `<name>_filtered.mp4`, where the task's cleanup finds it.
+/// A graph ffmpeg rejects fails with ffmpeg's own explanation.
+pub async fn apply_custom_filters<P: AsRef<Path>>(
+    file: P,
+    filters: &CustomFilters,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    // Graphs saved before the current rules were in place are checked again
+    for graph in filters.video.iter().chain(&filters.audio) {
+        crate::settings::validate_filter(graph)
+            .map_err(|e| BotError::general(format!("Фильтр «{}» отклонён: {}", graph, e)))?;
+    }
+
+    let mut args = Vec::new();
+    match &filters.video {
+        Some(graph) => args.extend(["-vf", graph, "-c:v", "libx264", "-crf", "20", "-preset", "veryfast"]),
+        None => args.extend(["-c:v", "copy"]),
+    }
+    match &filters.audio {
+        Some(graph) => args.extend(["-af", graph, "-c:a", "aac"]),
+        None => args.extend(["-c:a", "copy"]),
+    }
+
+    let output = match convert_with_progress(file.as_ref(), "mp4", &args, progress_sender).await {
+        Ok(output) => output,
+        Err(BotError::ConversionError(ConversionError::FfmpegFailed(_, stderr))) => {
+            return Err(BotError::general(filter_error(&stderr)));
+        }
+        Err(e) => return Err(e),
+    };
+
+    // Conversions write to the output folder under the input's name, so keep the
+    // filtered file out of their way
+    let stem = file.as_ref().file_stem().unwrap_or_default().to_string_lossy();
+    let filtered = file.as_ref().with_file_name(format!("{}_filtered.mp4", stem));
+    fs::rename(&output, &filtered).await?;
+
+    let path = filtered.to_str().ok_or(ConversionError::NonUtf8Path)?;
+    Ok(path.to_owned())
 }
 
-pub async fn convert_audio<P: AsRef<Path>>(file: P) -> BotResult<String> {
-    convert_with_progress(file, "mp3", &[], None).await
+/// Drop the audio track of a video, copying the picture as is. Written next to the
+/// source as `<name>_muted.mp4`, like `apply_custom_filters`, so later conversions
+/// and compression don't write over their own input.
+pub async fn strip_audio<P: AsRef<Path>>(
+    file: P,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    let output = convert_with_progress(file.as_ref(), "mp4", &["-c:v", "copy", "-an"], progress_sender).await?;
+
+    let stem = file.as_ref().file_stem().unwrap_or_default().to_string_lossy();
+    let muted = file.as_ref().with_file_name(format!("{}_muted.mp4", stem));
+    fs::rename(&output, &muted).await?;
+
+    let path = muted.to_str().ok_or(ConversionError::NonUtf8Path)?;
+    Ok(path.to_owned())
+}
+
+/// Scale a video down to `height` lines, keeping its aspect ratio. Written next to
+/// the source as `<name>_<height>p.mp4`, like `strip_audio`.
+pub async fn downscale_video<P: AsRef<Path>>(
+    file: P,
+    height: u32,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    let scale = format!("scale=-2:{}", height);
+    let args = [
+        "-vf", &scale,
+        "-c:v", "libx264", "-preset", "veryfast", "-crf", "23", "-pix_fmt", "yuv420p",
+        "-c:a", "copy",
+    ];
+    let output = convert_with_progress(file.as_ref(), "mp4", &args, progress_sender).await?;
+
+    let stem = file.as_ref().file_stem().unwrap_or_default().to_string_lossy();
+    let scaled = file.as_ref().with_file_name(format!("{}_{}p.mp4", stem, height));
+    fs::rename(&output, &scaled).await?;
+
+    let path = scaled.to_str().ok_or(ConversionError::NonUtf8Path)?;
+    Ok(path.to_owned())
+}
+
+/// The part of ffmpeg's output that explains why a filter graph was rejected
+fn filter_error(stderr: &str) -> String {
+    let lines: Vec<&str> = stderr.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+    let explanation = lines
+        .iter()
+        .find(|line| {
+            let line = line.to_lowercase();
+            line.contains("filter") || line.contains("option") || line.contains("invalid")
+        })
+        .or(lines.last())
+        .copied()
+        .unwrap_or("unknown error");
+
+    // "[Parsed_eq_0 @ 0x55d0c3a8] ..." - the address means nothing to the user
+    let explanation = match explanation.split_once("] ") {
+        Some((prefix, rest)) if prefix.starts_with('[') => rest,
+        _ => explanation,
+    };
+    format!("ffmpeg: {}", explanation)
+}
+
+/// Audio parts are cut for this share of the upload limit: the bitrate varies along
+/// the file and cuts only land on frame boundaries
+const AUDIO_PART_MARGIN: f64 = 0.9;
+
+/// Split an audio file bigger than `max_bytes` into parts that fit, cut by time. The
+/// part length comes from the average bitrate. Parts are written next to the file as
+/// `<name>_part001.mp3`, ... and keep its tags. A file that fits is its own only part.
+pub async fn split_audio<P: AsRef<Path>>(file: P, max_bytes: u64) -> BotResult<Vec<String>> {
+    let path = file.as_ref().to_str().ok_or(ConversionError::NonUtf8Path)?;
+    let size = fs::metadata(path).await?.len();
+    if size <= max_bytes {
+        return Ok(vec![path.to_owned()]);
+    }
+
+    let duration = VideoInfo::get_duration(path).await?;
+    let bytes_per_second = size as f64 / duration.max(1.0);
+    let part_seconds = (max_bytes as f64 * AUDIO_PART_MARGIN / bytes_per_second).floor().max(1.0);
+    log::info!(
+        "Splitting {} ({} bytes, {:.0}s) into parts of {}s",
+        path,
+        size,
+        duration,
+        part_seconds
+    );
+
+    let stem = file.as_ref().file_stem().unwrap_or_default().to_string_lossy();
+    let ext = file.as_ref().extension().unwrap_or_default().to_string_lossy();
+    let prefix = format!("{}_part", stem);
+    let pattern = file.as_ref().with_file_name(format!("{}%03d.{}", prefix, ext));
+
+    let output = process::Command::new("ffmpeg")
+        .args(["-y", "-i", path])
+        .args([
+            "-map", "0:a",
+            "-c", "copy",
+            "-map_metadata", "0",
+            "-f", "segment",
+            "-segment_time", &part_seconds.to_string(),
+            "-reset_timestamps", "1",
+        ])
+        .arg(&pattern)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(ConversionError::FfmpegFailed(
+            output.status,
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+        .into());
+    }
+
+    let dir = file.as_ref().parent().unwrap_or(Path::new("."));
+    let mut parts = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_name().to_string_lossy().starts_with(&prefix)
+            && let Some(part) = entry.path().to_str()
+        {
+            parts.push(part.to_owned());
+        }
+    }
+    // _part000, _part001, ... sort in playback order
+    parts.sort();
+
+    Ok(parts)
+}
+
+/// Silence below -50 dB: cut at the start, and pauses over a second shrink to half a second.
+/// Stops are kept short rather than removed so speech doesn't run together
+const TRIM_SILENCE_FILTER: &str = "silenceremove=start_periods=1:start_threshold=-50dB:start_silence=0.2:\
+stop_periods=-1:stop_duration=1:stop_threshold=-50dB:stop_silence=0.5";
+
+/// MP3 of the file's audio; `trim_silence` cuts leading silence and long pauses (speech only,
+/// it would eat quiet music intros)
+pub async fn convert_audio<P: AsRef<Path>>(
+    file: P,
+    trim_silence: bool,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    let args: &[&str] = if trim_silence { &["-af", TRIM_SILENCE_FILTER] } else { &[] };
+    convert_with_progress(file, "mp3", args, progress_sender).await
+}
+
+/// Like `convert_audio`, but downmixed to mono: voice messages are speech,
+/// a second channel only doubles the size
+pub async fn convert_voice<P: AsRef<Path>>(
+    file: P,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    convert_with_progress(file, "mp3", &["-ac", "1"], progress_sender).await
 }
 
 pub async fn convert_with_progress<P: AsRef<Path>>(
@@ -74,18 +598,33 @@ pub async fn convert_with_progress<P: AsRef<Path>>(
     ext: &str,
     args: &[&str],
     progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<String> {
+    convert_with_input_args(file, &[], ext, args, progress_sender).await
+}
+
+/// `convert_with_progress` with options placed before the input (e.g. a hardware device)
+async fn convert_with_input_args<P: AsRef<Path>>(
+    file: P,
+    input_args: &[&str],
+    ext: &str,
+    args: &[&str],
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
 ) -> BotResult<String> {
     let input_path = file.as_ref();
 
-    fs::create_dir_all("converted").await?;
-    let output_path = move_to_new_folder(&input_path.with_extension(ext), "converted");
+    let output_dir = workdir::output_dir(input_path).await?;
+    let output_path = move_to_new_folder(&input_path.with_extension(ext), &output_dir);
 
     // Create progress file for ffmpeg progress reporting
     let progress_file = format!("/tmp/ffmpeg_progress_{}.txt", std::process::id());
 
     let mut cmd = process::Command::new("ffmpeg");
-    cmd.args(["-y", "-i"])
-        .arg(&input_path)
+    // Stop ffmpeg when its task is cancelled
+    cmd.kill_on_drop(true)
+        .arg("-y")
+        .args(input_args)
+        .arg("-i")
+        .arg(input_path)
         .args(args);
 
     // Add faststart flag for MP4 files to enable streaming before full download
@@ -106,15 +645,10 @@ pub async fn convert_with_progress<P: AsRef<Path>>(
         let progress_file_clone = progress_file.clone();
         let input_path_str = input_path.to_string_lossy().to_string();
 
-        // Get video duration for video formats only
-        let video_duration = if ext == "mp4" {
-            // Try to get duration, but don't fail if we can't
-            match crate::video::VideoInfo::get_duration(&input_path_str).await {
-                Ok(duration) => Some(Duration::from_secs_f64(duration)),
-                Err(_) => None,
-            }
-        } else {
-            None
+        // Try to get input duration, but don't fail if we can't
+        let video_duration = match crate::video::VideoInfo::get_duration(&input_path_str).await {
+            Ok(duration) => Some(Duration::from_secs_f64(duration)),
+            Err(_) => None,
         };
 
         Some(tokio::spawn(async move {
@@ -197,7 +731,7 @@ async fn monitor_progress(
                 break;
             }
 
-            if let Some(_) = out_time_us {
+            if out_time_us.is_some() {
                 if current_time > last_time {
                     no_update_count = 0; // Reset counter on progress update
                     let elapsed = start_time.elapsed();
@@ -267,66 +801,288 @@ async fn monitor_progress(
     });
 }
 
-fn move_to_new_folder(path: &Path, new_folder: &str) -> PathBuf {
+fn move_to_new_folder(path: &Path, new_folder: &Path) -> PathBuf {
     let filename = match path.file_name() {
         Some(name) => name,
-        None => return PathBuf::from(new_folder), // fallback, если путь не содержит файла
+        None => return new_folder.to_path_buf(), // fallback, если путь не содержит файла
     };
 
-    Path::new(new_folder).join(filename)
+    new_folder.join(filename)
+}
+
+/// Telegram drops thumbnails larger than 320px on either side or 200KB
+pub const THUMBNAIL_MAX_SIDE: u32 = 320;
+pub const THUMBNAIL_MAX_BYTES: u64 = 200 * 1024;
+
+/// Fit into the thumbnail box keeping aspect ratio, never upscale
+const THUMBNAIL_SCALE: &str =
+    "scale='min(320,iw)':'min(320,ih)':force_original_aspect_ratio=decrease";
+
+/// JPEG qualities to try, best first (2-31, lower is better)
+const THUMBNAIL_QUALITIES: [&str; 5] = ["5", "10", "15", "23", "31"];
+
+/// Filters that turn a frame stored with a clockwise `rotation` upright
+fn rotation_filter(rotation: u32) -> Option<&'static str> {
+    match rotation {
+        90 => Some("transpose=clock"),
+        180 => Some("hflip,vflip"),
+        270 => Some("transpose=cclock"),
+        _ => None,
+    }
 }
 
 /// Generate a thumbnail from a video file
-/// Returns the path to the generated thumbnail (JPEG)
+/// Returns the path to a Telegram-compliant thumbnail (JPEG)
 pub async fn generate_thumbnail<P: AsRef<Path>>(video_path: P) -> BotResult<String> {
     let input_path = video_path.as_ref();
 
-    // Create thumbnail path with .jpg extension
-    let thumb_filename = format!(
-        "{}_thumb.jpg",
+    let frame_path = workdir::output_dir(input_path).await?.join(format!(
+        "{}_frame.jpg",
         input_path.file_stem()
             .and_then(|s| s.to_str())
             .unwrap_or("video")
-    );
-    let thumb_path = Path::new("converted").join(&thumb_filename);
+    ));
 
-    fs::create_dir_all("converted").await?;
+    // Rotated (phone) videos are stored sideways with a rotation flag, and JPEG has no
+    // such flag, so the rotation has to be applied to the frame itself. ffmpeg's own
+    // autorotation differs between versions and gives up on some flags, so it's turned
+    // off and the rotation ffprobe reports is applied explicitly.
+    let rotation = match input_path.to_str() {
+        Some(path) => VideoInfo::from_file(path).await.map(|info| info.rotation).unwrap_or(0),
+        None => 0,
+    };
+    let mut frame_args = vec!["-vframes", "1", "-q:v", "2"];
+    if let Some(filter) = rotation_filter(rotation) {
+        frame_args.extend(["-vf", filter]);
+    }
 
     // Extract frame at 1 second (or first frame if video is shorter)
     let output = process::Command::new("ffmpeg")
-        .args(["-y", "-i"])
+        .args(["-y", "-noautorotate", "-i"])
         .arg(input_path)
-        .args([
-            "-ss", "1",           // Seek to 1 second
-            "-vframes", "1",      // Extract 1 frame
-            "-vf", "scale=320:-1", // Scale to 320px width, keep aspect ratio
-            "-q:v", "5",          // JPEG quality (2-31, lower is better)
-        ])
-        .arg(&thumb_path)
+        .args(["-ss", "1"])
+        .args(&frame_args)
+        .arg(&frame_path)
         .output()
         .await?;
 
     if !output.status.success() {
         // Try extracting first frame if 1 second seek failed
+        let output = process::Command::new("ffmpeg")
+            .args(["-y", "-noautorotate", "-i"])
+            .arg(input_path)
+            .args(&frame_args)
+            .arg(&frame_path)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            return Err(crate::errors::ConversionError::FfmpegFailed(
+                output.status,
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ).into());
+        }
+    }
+
+    let result = normalize_thumbnail(&frame_path).await;
+    let _ = fs::remove_file(&frame_path).await;
+    result
+}
+
+/// Scale and recompress an image (e.g. a YouTube thumbnail) into a Telegram-compliant
+/// JPEG thumbnail, stepping down quality until it fits the size limit.
+/// Always writes a new file, the original is left untouched.
+pub async fn normalize_thumbnail<P: AsRef<Path>>(image_path: P) -> BotResult<String> {
+    let input_path = image_path.as_ref();
+
+    // Create thumbnail path with .jpg extension
+    let thumb_filename = format!(
+        "{}_thumb.jpg",
+        input_path.file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("video")
+    );
+    let thumb_path = workdir::output_dir(input_path).await?.join(&thumb_filename);
+
+    for quality in THUMBNAIL_QUALITIES {
         let output = process::Command::new("ffmpeg")
             .args(["-y", "-i"])
             .arg(input_path)
-            .args([
-                "-vframes", "1",
-                "-vf", "scale=320:-1",
-                "-q:v", "5",
-            ])
+            .args(["-vframes", "1", "-vf", THUMBNAIL_SCALE, "-q:v", quality])
             .arg(&thumb_path)
             .output()
             .await?;
 
         if !output.status.success() {
-            return Err(crate::errors::ConversionError::FfmpegFailed(
+            return Err(ConversionError::FfmpegFailed(
                 output.status,
                 String::from_utf8_lossy(&output.stderr).into_owned(),
             ).into());
         }
+
+        if fs::metadata(&thumb_path).await?.len() <= THUMBNAIL_MAX_BYTES {
+            return Ok(thumb_path.to_string_lossy().into_owned());
+        }
+    }
+
+    let _ = fs::remove_file(&thumb_path).await;
+    Err(BotError::FileTooLarge(format!(
+        "thumbnail {} doesn't fit into {} bytes",
+        input_path.display(),
+        THUMBNAIL_MAX_BYTES
+    )))
+}
+
+/// Check that a thumbnail satisfies Telegram's limits before attaching it
+pub async fn is_valid_thumbnail<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+
+    let Ok(metadata) = fs::metadata(path).await else {
+        return false;
+    };
+    if metadata.len() > THUMBNAIL_MAX_BYTES {
+        return false;
+    }
+
+    image_size(path)
+        .await
+        .is_some_and(|(w, h)| w <= THUMBNAIL_MAX_SIDE && h <= THUMBNAIL_MAX_SIDE)
+}
+
+/// Width and height of an image, None if ffprobe can't read it
+pub async fn image_size<P: AsRef<Path>>(path: P) -> Option<(u32, u32)> {
+    let output = process::Command::new("ffprobe")
+        .args([
+            "-v", "quiet",
+            "-select_streams", "v:0",
+            "-show_entries", "stream=width,height",
+            "-of", "csv=p=0:s=x",
+        ])
+        .arg(path.as_ref())
+        .output()
+        .await
+        .ok()?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square_h264() -> VideoInfo {
+        VideoInfo {
+            width: 480,
+            height: 480,
+            duration: 30.0,
+            video_codec: Some("h264".to_string()),
+            audio_codec: Some("aac".to_string()),
+            sample_aspect_ratio: Some("1:1".to_string()),
+            pixel_format: Some("yuv420p".to_string()),
+            rotation: 0,
+        }
+    }
+
+    #[test]
+    fn square_h264_is_copied_into_video_notes() {
+        assert!(is_video_note_compatible(&square_h264()));
+        assert!(is_video_note_compatible(&VideoInfo { sample_aspect_ratio: None, ..square_h264() }));
+    }
+
+    #[test]
+    fn video_notes_reencode_what_would_show_wrong() {
+        for info in [
+            VideoInfo { video_codec: Some("hevc".to_string()), ..square_h264() },
+            VideoInfo { width: 640, height: 480, ..square_h264() },
+            VideoInfo { width: 720, height: 720, ..square_h264() },
+            // Square in pixels, but stretched on screen
+            VideoInfo { sample_aspect_ratio: Some("4:3".to_string()), ..square_h264() },
+            VideoInfo { pixel_format: Some("yuv444p".to_string()), ..square_h264() },
+            VideoInfo { pixel_format: Some("yuv420p10le".to_string()), ..square_h264() },
+            VideoInfo { pixel_format: None, ..square_h264() },
+        ] {
+            assert!(!is_video_note_compatible(&info), "{:?} was copied", info);
+        }
+    }
+
+    #[test]
+    fn rotation_filters_turn_frames_upright() {
+        assert_eq!(rotation_filter(0), None);
+        assert_eq!(rotation_filter(90), Some("transpose=clock"));
+        assert_eq!(rotation_filter(180), Some("hflip,vflip"));
+        assert_eq!(rotation_filter(270), Some("transpose=cclock"));
+    }
+
+    /// Size of the streams encoded at these bitrates, in bytes
+    fn streams_bytes(video_kbps: u64, audio_kbps: u64, duration_secs: f64) -> f64 {
+        (video_kbps + audio_kbps) as f64 * 1000.0 / 8.0 * duration_secs
+    }
+
+    #[test]
+    fn target_bitrate_leaves_room_for_overhead() {
+        let target = 50 * 1024 * 1024;
+        for duration in [60.0, 300.0, 599.5, 1200.0] {
+            let video_kbps = target_video_kbps(target, duration, TARGET_AUDIO_KBPS).unwrap();
+            let bytes = streams_bytes(video_kbps, TARGET_AUDIO_KBPS, duration);
+            assert!(bytes <= target as f64 * TARGET_SIZE_MARGIN, "{}s: {} bytes", duration, bytes);
+            // Not wasting the limit either: within a kbit/s of the margin
+            let next = streams_bytes(video_kbps + 1, TARGET_AUDIO_KBPS, duration);
+            assert!(next > target as f64 * TARGET_SIZE_MARGIN, "{}s: {} bytes", duration, next);
+        }
+    }
+
+    #[test]
+    fn target_bitrate_goes_to_video_without_audio() {
+        let target = 10 * 1024 * 1024;
+        let with_audio = target_video_kbps(target, 100.0, TARGET_AUDIO_KBPS).unwrap();
+        let without_audio = target_video_kbps(target, 100.0, 0).unwrap();
+        assert_eq!(without_audio - with_audio, TARGET_AUDIO_KBPS);
     }
 
-    Ok(thumb_path.to_string_lossy().into_owned())
+    #[test]
+    fn target_bitrate_gives_up_on_unwatchable_or_broken_input() {
+        let target = 50 * 1024 * 1024;
+        assert_eq!(target_video_kbps(target, 0.0, TARGET_AUDIO_KBPS), None);
+        assert_eq!(target_video_kbps(target, -5.0, TARGET_AUDIO_KBPS), None);
+        // 3 hours leave less than the audio's share
+        assert_eq!(target_video_kbps(target, 3.0 * 3600.0, TARGET_AUDIO_KBPS), None);
+
+        // Just above and below the lowest watchable bitrate
+        let duration = |total_kbps: f64| target as f64 * 8.0 * TARGET_SIZE_MARGIN / 1000.0 / total_kbps;
+        let at_minimum = duration((MIN_VIDEO_KBPS + TARGET_AUDIO_KBPS) as f64 + 0.5);
+        assert_eq!(target_video_kbps(target, at_minimum, TARGET_AUDIO_KBPS), Some(MIN_VIDEO_KBPS));
+        let below_minimum = duration((MIN_VIDEO_KBPS + TARGET_AUDIO_KBPS) as f64 - 0.5);
+        assert_eq!(target_video_kbps(target, below_minimum, TARGET_AUDIO_KBPS), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs ffmpeg and ffprobe"]
+    async fn two_pass_output_fits_the_target() {
+        let id = format!("compress-test-{}", uuid::Uuid::new_v4());
+        let sample = workdir::source_dir(&id).await.unwrap().join("sample.mp4");
+        let output = process::Command::new("ffmpeg")
+            .args(["-y", "-f", "lavfi", "-i", "testsrc2=duration=20:size=1280x720:rate=30"])
+            .args(["-f", "lavfi", "-i", "sine=frequency=440:duration=20"])
+            .args(["-c:v", "libx264", "-b:v", "8M", "-pix_fmt", "yuv420p", "-c:a", "aac", "-shortest"])
+            .arg(&sample)
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        let target = 2 * 1024 * 1024;
+        assert!(fs::metadata(&sample).await.unwrap().len() > target);
+        let compressed = compress_video_to_size(&sample, target, None, None).await;
+        let size = match &compressed {
+            Ok(path) => fs::metadata(path).await.unwrap().len(),
+            Err(_) => u64::MAX,
+        };
+        workdir::remove_task(&id).await;
+
+        compressed.unwrap();
+        assert!(size <= target, "{} bytes", size);
+    }
 }
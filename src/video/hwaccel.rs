@@ -0,0 +1,108 @@
+//! Optional hardware H.264 encoding.
+//!
+//! The encoder is picked with `FFMPEG_HWACCEL` and checked against `ffmpeg -encoders`
+//! once at startup. Decoding and filters (scale, crop, subtitles) stay on the CPU,
+//! so every filter graph works unchanged; frames are only handed to the GPU for encoding.
+//! Two-pass compression to a target size has no hardware equivalent and stays on libx264.
+
+use std::sync::OnceLock;
+
+use tokio::process;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HwEncoder {
+    /// NVIDIA (`h264_nvenc`)
+    Nvenc,
+    /// VA-API, Intel and AMD on Linux (`h264_vaapi`), with the render device to use
+    Vaapi { device: String },
+    /// Intel Quick Sync (`h264_qsv`)
+    Qsv,
+}
+
+impl HwEncoder {
+    /// ffmpeg encoder name
+    pub fn codec(&self) -> &'static str {
+        match self {
+            HwEncoder::Nvenc => "h264_nvenc",
+            HwEncoder::Vaapi { .. } => "h264_vaapi",
+            HwEncoder::Qsv => "h264_qsv",
+        }
+    }
+
+    /// Options that go before the input
+    pub fn input_args(&self) -> Vec<String> {
+        match self {
+            HwEncoder::Vaapi { device } => vec!["-vaapi_device".to_string(), device.clone()],
+            HwEncoder::Nvenc | HwEncoder::Qsv => Vec::new(),
+        }
+    }
+
+    /// The filter graph extended with the upload to the encoder's frame format
+    pub fn filter(&self, filter: &str) -> String {
+        match self {
+            // VA-API only encodes frames in GPU memory
+            HwEncoder::Vaapi { .. } => format!("{},format=nv12,hwupload", filter),
+            HwEncoder::Qsv => format!("{},format=nv12", filter),
+            HwEncoder::Nvenc => filter.to_string(),
+        }
+    }
+
+    /// Encoder and constant-quality options roughly matching x264 at `crf`
+    pub fn codec_args(&self, crf: &str) -> Vec<String> {
+        let args: Vec<&str> = match self {
+            HwEncoder::Nvenc => vec!["-c:v", "h264_nvenc", "-preset", "p4", "-rc", "vbr", "-cq", crf],
+            HwEncoder::Vaapi { .. } => vec!["-c:v", "h264_vaapi", "-qp", crf],
+            HwEncoder::Qsv => vec!["-c:v", "h264_qsv", "-global_quality", crf],
+        };
+        args.into_iter().map(String::from).collect()
+    }
+}
+
+/// Hardware encoder found at startup, None for software encoding
+static ACTIVE: OnceLock<Option<HwEncoder>> = OnceLock::new();
+
+/// The hardware encoder to use, if one is configured and available
+pub fn active() -> Option<&'static HwEncoder> {
+    ACTIVE.get().and_then(Option::as_ref)
+}
+
+/// Check that the configured encoder is built into ffmpeg and remember the result.
+/// Called once at startup, later `FFMPEG_HWACCEL` changes need a restart.
+pub async fn probe() {
+    let encoder = match &crate::config::get().hw_encoder {
+        Some(encoder) => {
+            if encoder_available(encoder.codec()).await {
+                log::info!("Hardware encoding enabled: {}", encoder.codec());
+                Some(encoder.clone())
+            } else {
+                log::warn!(
+                    "{} is not available in this ffmpeg build, using software encoding",
+                    encoder.codec()
+                );
+                None
+            }
+        }
+        None => {
+            log::info!("Hardware encoding disabled, using libx264");
+            None
+        }
+    };
+
+    let _ = ACTIVE.set(encoder);
+}
+
+async fn encoder_available(codec: &str) -> bool {
+    match process::Command::new("ffmpeg")
+        .args(["-hide_banner", "-encoders"])
+        .output()
+        .await
+    {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line.split_whitespace().nth(1) == Some(codec)),
+        Err(e) => {
+            log::warn!("Failed to list ffmpeg encoders: {}", e);
+            false
+        }
+    }
+}
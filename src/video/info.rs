@@ -8,6 +8,16 @@ pub struct VideoInfo {
     pub width: u32,
     pub height: u32,
     pub duration: f64,
+    /// Codec of the first video stream (e.g. "h264")
+    pub video_codec: Option<String>,
+    /// Codec of the first audio stream (e.g. "aac"), None if there's no audio
+    pub audio_codec: Option<String>,
+    /// Shape of the video's pixels (e.g. "1:1"), None if ffprobe doesn't know
+    pub sample_aspect_ratio: Option<String>,
+    /// Pixel format of the first video stream (e.g. "yuv420p")
+    pub pixel_format: Option<String>,
+    /// Clockwise rotation the video is displayed with, in degrees (0, 90, 180 or 270)
+    pub rotation: u32,
 }
 
 impl VideoInfo {
@@ -99,6 +109,15 @@ impl VideoInfo {
             .ok_or_else(|| BotError::ParseError("Height not found in video stream".to_string()))?
             as u32;
 
+        // Phone videos are often stored landscape with a rotation flag,
+        // report the dimensions as they are displayed
+        let rotation = Self::rotation(video_stream);
+        let (width, height) = if rotation % 180 == 0 {
+            (width, height)
+        } else {
+            (height, width)
+        };
+
         // Get duration from format section
         let duration_str = json["format"]["duration"].as_str().ok_or_else(|| {
             BotError::ParseError("Duration not found in format section".to_string())
@@ -111,10 +130,116 @@ impl VideoInfo {
             ))
         })?;
 
+        let video_codec = video_stream["codec_name"].as_str().map(str::to_string);
+        let audio_codec = streams
+            .iter()
+            .find(|s| s["codec_type"] == "audio")
+            .and_then(|s| s["codec_name"].as_str())
+            .map(str::to_string);
+        // "0:1" and "N/A" both mean the ratio isn't set
+        let sample_aspect_ratio = video_stream["sample_aspect_ratio"]
+            .as_str()
+            .filter(|sar| !matches!(*sar, "0:1" | "N/A"))
+            .map(str::to_string);
+        let pixel_format = video_stream["pix_fmt"].as_str().map(str::to_string);
+
         Ok(VideoInfo {
             width,
             height,
             duration,
+            video_codec,
+            audio_codec,
+            sample_aspect_ratio,
+            pixel_format,
+            rotation,
         })
     }
+
+    /// Clockwise display rotation of a video stream in degrees, from the display matrix
+    /// side data (newer ffmpeg, counterclockwise) or the `rotate` tag (older ffmpeg,
+    /// clockwise), 0 if there is none
+    fn rotation(video_stream: &Value) -> u32 {
+        let from_side_data = video_stream["side_data_list"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find_map(|data| data["rotation"].as_f64())
+            .map(|degrees| -degrees);
+
+        let from_tag = || {
+            video_stream["tags"]["rotate"]
+                .as_str()
+                .and_then(|rotate| rotate.trim().parse::<f64>().ok())
+        };
+
+        from_side_data
+            .or_else(from_tag)
+            .map(|degrees| (degrees.round() as i64).rem_euclid(360) as u32)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn probe(stream: Value) -> VideoInfo {
+        VideoInfo::parse_json(json!({
+            "streams": [stream],
+            "format": { "duration": "12.5" },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn display_matrix_rotation_swaps_dimensions() {
+        let info = probe(json!({
+            "codec_type": "video",
+            "width": 1920,
+            "height": 1080,
+            "side_data_list": [{ "side_data_type": "Display Matrix", "rotation": -90 }],
+        }));
+        assert_eq!((info.width, info.height, info.rotation), (1080, 1920, 90));
+    }
+
+    #[test]
+    fn rotate_tag_is_clockwise() {
+        let info = probe(json!({
+            "codec_type": "video",
+            "width": 1920,
+            "height": 1080,
+            "tags": { "rotate": "270" },
+        }));
+        assert_eq!((info.width, info.height, info.rotation), (1080, 1920, 270));
+    }
+
+    #[test]
+    fn upside_down_keeps_dimensions() {
+        let info = probe(json!({
+            "codec_type": "video",
+            "width": 1920,
+            "height": 1080,
+            "side_data_list": [{ "rotation": 180 }],
+        }));
+        assert_eq!((info.width, info.height, info.rotation), (1920, 1080, 180));
+    }
+
+    #[test]
+    fn no_rotation() {
+        let info = probe(json!({ "codec_type": "video", "width": 640, "height": 360 }));
+        assert_eq!((info.width, info.height, info.rotation), (640, 360, 0));
+    }
+
+    #[test]
+    fn unset_sample_aspect_ratio_is_none() {
+        let sar = |sar: &str| {
+            probe(json!({ "codec_type": "video", "width": 640, "height": 640, "sample_aspect_ratio": sar }))
+                .sample_aspect_ratio
+        };
+        assert_eq!(sar("0:1"), None);
+        assert_eq!(sar("N/A"), None);
+        assert_eq!(sar("4:3").as_deref(), Some("4:3"));
+    }
 }
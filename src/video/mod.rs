@@ -1,5 +1,11 @@
+pub mod archive;
+pub mod cache;
 pub mod convert;
+pub mod hwaccel;
 pub mod info;
+pub mod toolchain;
+pub mod transcribe;
+pub mod workdir;
 pub mod youtube;
 
 pub use convert::{ProgressInfo, compress_video_with_progress, generate_thumbnail};
@@ -0,0 +1,361 @@
+//! Checks of the external tools every task depends on.
+//!
+//! yt-dlp, ffmpeg and ffprobe are plain executables on `PATH`. Without them the bot
+//! starts fine and then fails every task with a cryptic error, so `preflight` looks
+//! for them at startup, and `/selftest` runs a tiny conversion end to end.
+//!
+//! YouTube changes often enough to break older yt-dlp releases, so download errors
+//! that look like that are called out in the log, and with `YTDLP_SELF_UPDATE` the
+//! bot runs `yt-dlp -U` once and retries.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::{process, sync::OnceCell};
+
+use crate::errors::{BotError, BotResult};
+use crate::video::{VideoInfo, convert, workdir};
+
+/// An external tool and where to get it
+struct Tool {
+    name: &'static str,
+    version_arg: &'static str,
+    install: &'static str,
+}
+
+const TOOLS: &[Tool] = &[
+    Tool {
+        name: "yt-dlp",
+        version_arg: "--version",
+        install: "`pip install -U yt-dlp` or a release from https://github.com/yt-dlp/yt-dlp/releases",
+    },
+    Tool {
+        name: "ffmpeg",
+        version_arg: "-version",
+        install: "the `ffmpeg` package of your distribution or a build from https://ffmpeg.org/download.html",
+    },
+    Tool {
+        name: "ffprobe",
+        version_arg: "-version",
+        install: "comes with ffmpeg, install ffmpeg",
+    },
+];
+
+/// yt-dlp errors that usually mean the site changed and yt-dlp needs an update
+const OUTDATED_YTDLP_SIGNATURES: &[&str] = &[
+    "Signature extraction failed",
+    "nsig extraction failed",
+    "Unable to extract",
+    "Some formats may be missing",
+];
+
+/// How long the result of a self-update attempt is reused instead of trying again
+const SELF_UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Longest `yt-dlp -U` may take, a stuck one shouldn't hold the downloads waiting for it
+const SELF_UPDATE_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/// A self-update attempt: when it started, and once it's done, when yt-dlp's version
+/// changed (`None` if it didn't). Downloads failing together wait on the same attempt
+/// instead of starting several.
+struct SelfUpdate {
+    started: Instant,
+    updated_at: Arc<OnceCell<Option<Instant>>>,
+}
+
+/// The last self-update attempt, only locked to look it up or replace it
+static LAST_SELF_UPDATE: Mutex<Option<SelfUpdate>> = Mutex::new(None);
+
+/// Task folder the self-test works in, see `workdir`
+const SELFTEST_ID: &str = "selftest";
+
+/// First line of each tool's version output, or why it couldn't be run
+pub async fn versions() -> Vec<(&'static str, Result<String, String>)> {
+    let mut versions = Vec::with_capacity(TOOLS.len());
+    for tool in TOOLS {
+        versions.push((tool.name, version(tool).await));
+    }
+    versions
+}
+
+async fn version(tool: &Tool) -> Result<String, String> {
+    match process::Command::new(tool.name).arg(tool.version_arg).output().await {
+        Ok(output) if output.status.success() => Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .unwrap_or("unknown")
+            .trim()
+            .to_string()),
+        Ok(output) => Err(format!("exited with {}", output.status)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Check that every tool can be run, logging its version or how to install it.
+/// Returns false if any is missing.
+pub async fn preflight() -> bool {
+    let mut all_found = true;
+
+    for (tool, (name, version)) in TOOLS.iter().zip(versions().await) {
+        match version {
+            Ok(version) => log::info!("Found {}: {}", name, version),
+            Err(e) => {
+                log::error!(
+                    "{} is not available ({}). Install it and make sure it's on PATH: {}",
+                    name,
+                    e,
+                    tool.install
+                );
+                all_found = false;
+            }
+        }
+    }
+
+    all_found
+}
+
+/// Whether a failed yt-dlp run looks like yt-dlp is too old for the site.
+/// Logs a warning for the operator if so.
+pub fn is_outdated_ytdlp_error(error: &BotError) -> bool {
+    let BotError::YoutubeError(stderr) = error else {
+        return false;
+    };
+    let Some(signature) = OUTDATED_YTDLP_SIGNATURES.iter().find(|s| stderr.contains(*s)) else {
+        return false;
+    };
+
+    log::warn!(
+        "!!! yt-dlp failed with \"{}\", which usually means it is outdated. \
+         Update it (`yt-dlp -U`, `pip install -U yt-dlp` or your package manager){}",
+        signature,
+        if crate::config::get().ytdlp_self_update {
+            ""
+        } else {
+            ", or set YTDLP_SELF_UPDATE=true to let the bot try it"
+        }
+    );
+    true
+}
+
+/// Run `yt-dlp -U` if `YTDLP_SELF_UPDATE` allows it. Returns whether yt-dlp has been
+/// updated since `started`, when the failed download began, in which case it's worth
+/// retrying. Attempts are at most once per `SELF_UPDATE_INTERVAL`; a download that began
+/// after the last update already ran with the current version and isn't retried.
+pub async fn self_update_ytdlp(started: Instant) -> bool {
+    if !crate::config::get().ytdlp_self_update {
+        return false;
+    }
+
+    let updated_at = {
+        let mut last = LAST_SELF_UPDATE.lock().unwrap();
+        match &*last {
+            Some(attempt) if attempt.started.elapsed() < SELF_UPDATE_INTERVAL => attempt.updated_at.clone(),
+            _ => {
+                let updated_at = Arc::new(OnceCell::new());
+                *last = Some(SelfUpdate { started: Instant::now(), updated_at: updated_at.clone() });
+                updated_at
+            }
+        }
+    };
+
+    let updated_at = *updated_at
+        .get_or_init(|| async { run_self_update().await.then(Instant::now) })
+        .await;
+    updated_at.is_some_and(|at| at > started)
+}
+
+/// Run `yt-dlp -U`, returns whether the version changed
+async fn run_self_update() -> bool {
+    // `yt-dlp -U` succeeds when there's nothing to update, only a new version counts
+    let before = ytdlp_version().await;
+    log::info!("Updating yt-dlp from {}...", before.as_deref().unwrap_or("unknown version"));
+    // The shared command, so the update goes through `YTDLP_PROXY` like the downloads do
+    let mut cmd = crate::video::youtube::yt_dlp_command();
+    cmd.arg("-U");
+    let output = match tokio::time::timeout(SELF_UPDATE_TIMEOUT, cmd.output()).await {
+        Ok(output) => output,
+        Err(_) => {
+            log::error!("yt-dlp -U didn't finish within {:?}, gave up", SELF_UPDATE_TIMEOUT);
+            return false;
+        }
+    };
+    match output {
+        Ok(output) if output.status.success() => {
+            let after = ytdlp_version().await;
+            match (&before, &after) {
+                (Some(before), Some(after)) if before != after => {
+                    log::info!("yt-dlp updated from {} to {}", before, after);
+                    true
+                }
+                _ => {
+                    log::warn!(
+                        "yt-dlp is already the latest version ({}), the failure isn't fixed by an update",
+                        after.as_deref().unwrap_or("unknown")
+                    );
+                    false
+                }
+            }
+        }
+        Ok(output) => {
+            // Installs from pip or a distribution package refuse to update themselves
+            log::error!(
+                "yt-dlp couldn't update itself ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            false
+        }
+        Err(e) => {
+            log::error!("Failed to run yt-dlp -U: {}", e);
+            false
+        }
+    }
+}
+
+/// Installed yt-dlp version, `None` if it can't be run
+async fn ytdlp_version() -> Option<String> {
+    version(&TOOLS[0]).await.ok()
+}
+
+/// Generate a two-second test clip and run it through probing and every conversion.
+/// Returns each step with how long it took or why it failed; stops at the first failure.
+pub async fn self_test() -> Vec<(&'static str, Result<Duration, String>)> {
+    let mut steps = Vec::new();
+    run_steps(&mut steps).await;
+    workdir::remove_task(SELFTEST_ID).await;
+    steps
+}
+
+async fn run_steps(steps: &mut Vec<(&'static str, Result<Duration, String>)>) {
+    let sample = match step(steps, "generate sample", generate_sample()).await {
+        Some(sample) => sample,
+        None => return,
+    };
+
+    let passed = step(steps, "probe", VideoInfo::from_file(&sample)).await.is_some()
+        && step(steps, "video note", verified(convert::convert_video_note(&sample, None)))
+            .await
+            .is_some()
+        && step(steps, "audio", verified(convert::convert_audio(&sample, false, None)))
+            .await
+            .is_some()
+        && step(steps, "voice", verified(convert::convert_voice(&sample, None)))
+            .await
+            .is_some();
+
+    if passed
+        && step(steps, "thumbnail", convert::generate_thumbnail(&sample)).await.is_some()
+    {
+        step(steps, "rotated thumbnail", rotated_thumbnail(&sample)).await;
+    }
+}
+
+/// Flag a copy of the landscape sample as rotated by 90° the way phones do, and check
+/// that its thumbnail comes out portrait
+async fn rotated_thumbnail(sample: &str) -> BotResult<()> {
+    let rotated = workdir::source_dir(SELFTEST_ID).await?.join("rotated.mp4");
+    let rotated = rotated.to_string_lossy().into_owned();
+
+    // `-display_rotation` needs ffmpeg 6.1+, older versions take the `rotate` tag instead
+    let mut output = process::Command::new("ffmpeg")
+        .args(["-y", "-display_rotation", "90", "-i", sample, "-c", "copy", &rotated])
+        .output()
+        .await?;
+    if !output.status.success() {
+        output = process::Command::new("ffmpeg")
+            .args(["-y", "-i", sample, "-c", "copy", "-metadata:s:v:0", "rotate=90", &rotated])
+            .output()
+            .await?;
+    }
+    if !output.status.success() {
+        return Err(BotError::general(format!(
+            "ffmpeg failed to rotate the sample: {}",
+            String::from_utf8_lossy(&output.stderr).lines().last().unwrap_or("")
+        )));
+    }
+
+    let info = VideoInfo::from_file(&rotated).await?;
+    if info.rotation % 180 == 0 {
+        return Err(BotError::general("ffprobe doesn't see the sample's rotation"));
+    }
+
+    let thumbnail = convert::generate_thumbnail(&rotated).await?;
+    let size = convert::image_size(&thumbnail).await;
+    let _ = tokio::fs::remove_file(&thumbnail).await;
+    match size {
+        Some((width, height)) if height > width => Ok(()),
+        Some((width, height)) => Err(BotError::general(format!(
+            "thumbnail of a portrait video is {}x{}",
+            width, height
+        ))),
+        None => Err(BotError::general("ffprobe can't read the thumbnail")),
+    }
+}
+
+/// A conversion followed by the same output check tasks use
+async fn verified(conversion: impl Future<Output = BotResult<String>>) -> BotResult<()> {
+    let output = conversion.await?;
+    convert::verify_output(&output).await
+}
+
+/// Run one step and record the result, returning its output if it succeeded
+async fn step<T>(
+    steps: &mut Vec<(&'static str, Result<Duration, String>)>,
+    name: &'static str,
+    future: impl Future<Output = BotResult<T>>,
+) -> Option<T> {
+    let started = Instant::now();
+    match future.await {
+        Ok(output) => {
+            steps.push((name, Ok(started.elapsed())));
+            Some(output)
+        }
+        Err(e) => {
+            steps.push((name, Err(e.to_string())));
+            None
+        }
+    }
+}
+
+/// Test pattern with a sine tone, encoded the way downloads usually arrive
+async fn generate_sample() -> BotResult<String> {
+    let path = workdir::source_dir(SELFTEST_ID).await?.join("sample.mp4");
+    let path = path.to_string_lossy().into_owned();
+
+    let output = process::Command::new("ffmpeg")
+        .args([
+            "-y",
+            "-f", "lavfi", "-i", "testsrc=duration=2:size=640x360:rate=25",
+            "-f", "lavfi", "-i", "sine=frequency=440:duration=2",
+            "-c:v", "libx264", "-pix_fmt", "yuv420p",
+            "-c:a", "aac",
+            "-shortest",
+            &path,
+        ])
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(BotError::general(format!(
+            "ffmpeg failed to generate the sample: {}",
+            String::from_utf8_lossy(&output.stderr).lines().last().unwrap_or("")
+        )));
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "needs ffmpeg and ffprobe"]
+    async fn self_test_passes() {
+        let steps = self_test().await;
+        for (name, result) in &steps {
+            assert!(result.is_ok(), "{} failed: {:?}", name, result);
+        }
+        assert!(steps.iter().any(|(name, _)| *name == "rotated thumbnail"));
+    }
+}
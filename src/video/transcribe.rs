@@ -0,0 +1,210 @@
+//! Speech-to-text for the "Текст" format.
+//!
+//! The backend is chosen by the operator: a local whisper.cpp binary or an
+//! OpenAI-compatible transcription API. Long audio is split into chunks that
+//! are transcribed one by one and stitched back together.
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tokio::{fs, process};
+
+use crate::errors::{BotError, BotResult, ConversionError};
+use crate::video::workdir;
+
+/// Length of a single chunk sent to the backend (whisper.cpp and most APIs
+/// cope poorly with very long inputs, APIs also limit upload size)
+const CHUNK_SECONDS: &str = "600";
+
+#[derive(Debug, Clone)]
+pub enum TranscriptionBackend {
+    /// Local whisper.cpp binary (`whisper-cli`) with a ggml model file
+    WhisperCpp { binary: String, model: String },
+    /// OpenAI-compatible `/audio/transcriptions` endpoint
+    Api {
+        url: String,
+        api_key: String,
+        model: String,
+    },
+}
+
+/// The backend configured via `TRANSCRIPTION_BACKEND`, if transcription is enabled
+pub fn configured_backend() -> Option<&'static TranscriptionBackend> {
+    crate::config::get().transcription.as_ref()
+}
+
+/// Transcribe a downloaded file with the configured backend and save the text
+/// to the task's output folder, like other conversions. Returns the path of the `.txt` file.
+pub async fn transcribe_to_file<P: AsRef<Path>>(file: P) -> BotResult<String> {
+    let input_path = file.as_ref();
+    let backend = configured_backend()
+        .ok_or_else(|| BotError::general("Transcription backend is not configured"))?;
+
+    let text = transcribe_audio(input_path, backend).await?;
+
+    let output_path = workdir::output_dir(input_path).await?.join(format!(
+        "{}.txt",
+        input_path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("transcript")
+    ));
+    fs::write(&output_path, text).await?;
+
+    Ok(output_path.to_string_lossy().into_owned())
+}
+
+/// Transcribe the speech in an audio file to text
+pub async fn transcribe_audio<P: AsRef<Path>>(
+    file: P,
+    backend: &TranscriptionBackend,
+) -> BotResult<String> {
+    let input_path = file.as_ref();
+    let chunks_dir = workdir::output_dir(input_path).await?.join(format!(
+        "{}_chunks",
+        input_path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("audio")
+    ));
+
+    let result = transcribe_chunks(input_path, &chunks_dir, backend).await;
+    let _ = fs::remove_dir_all(&chunks_dir).await;
+    result
+}
+
+async fn transcribe_chunks(
+    input_path: &Path,
+    chunks_dir: &Path,
+    backend: &TranscriptionBackend,
+) -> BotResult<String> {
+    let chunks = split_audio(input_path, chunks_dir).await?;
+    log::info!(
+        "Transcribing {} in {} chunk(s)",
+        input_path.display(),
+        chunks.len()
+    );
+
+    // One chunk at a time, so the parts are joined in order
+    let mut parts = Vec::with_capacity(chunks.len());
+    for chunk in &chunks {
+        let text = match backend {
+            TranscriptionBackend::WhisperCpp { binary, model } => {
+                transcribe_whisper_cpp(chunk, binary, model).await?
+            }
+            TranscriptionBackend::Api {
+                url,
+                api_key,
+                model,
+            } => transcribe_api(chunk, url, api_key, model).await?,
+        };
+
+        let text = text.trim();
+        if !text.is_empty() {
+            parts.push(text.to_string());
+        }
+    }
+
+    Ok(parts.join("\n"))
+}
+
+/// Split audio into 16kHz mono WAV chunks (the format whisper expects)
+async fn split_audio(input_path: &Path, chunks_dir: &Path) -> BotResult<Vec<PathBuf>> {
+    fs::create_dir_all(chunks_dir).await?;
+
+    let output = process::Command::new("ffmpeg")
+        .args(["-y", "-i"])
+        .arg(input_path)
+        .args([
+            "-vn",
+            "-ac", "1",
+            "-ar", "16000",
+            "-f", "segment",
+            "-segment_time", CHUNK_SECONDS,
+        ])
+        .arg(chunks_dir.join("chunk_%04d.wav"))
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(ConversionError::FfmpegFailed(
+            output.status,
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+        .into());
+    }
+
+    let mut chunks = Vec::new();
+    let mut entries = fs::read_dir(chunks_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        chunks.push(entry.path());
+    }
+    // chunk_0000.wav, chunk_0001.wav, ... sort in playback order
+    chunks.sort();
+
+    Ok(chunks)
+}
+
+async fn transcribe_whisper_cpp(chunk: &Path, binary: &str, model: &str) -> BotResult<String> {
+    let output = process::Command::new(binary)
+        .args(["-m", model, "-l", "auto"])
+        // No timestamps and no progress/system info, only the text on stdout
+        .args(["-nt", "-np"])
+        .arg("-f")
+        .arg(chunk)
+        .output()
+        .await
+        .map_err(|e| BotError::external_command_error(binary, e.to_string()))?;
+
+    if !output.status.success() {
+        let error_msg = String::from_utf8_lossy(&output.stderr);
+        return Err(BotError::external_command_error(binary, error_msg));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[derive(Deserialize)]
+struct ApiTranscription {
+    text: String,
+}
+
+async fn transcribe_api(chunk: &Path, url: &str, api_key: &str, model: &str) -> BotResult<String> {
+    let bytes = fs::read(chunk).await?;
+    let file_name = chunk
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "audio.wav".to_string());
+
+    let file_part = reqwest::multipart::Part::bytes(bytes)
+        .file_name(file_name)
+        .mime_str("audio/wav")
+        .map_err(|e| BotError::general(format!("Invalid transcription upload: {}", e)))?;
+    let form = reqwest::multipart::Form::new()
+        .text("model", model.to_string())
+        .part("file", file_part);
+
+    let response = reqwest::Client::new()
+        .post(url)
+        .bearer_auth(api_key)
+        .multipart(form)
+        .send()
+        .await
+        .map_err(|e| BotError::general(format!("Transcription request failed: {}", e)))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(BotError::general(format!(
+            "Transcription API returned {}: {}",
+            status, body
+        )));
+    }
+
+    let transcription: ApiTranscription = response
+        .json()
+        .await
+        .map_err(|e| BotError::ParseError(format!("Failed to parse transcription: {}", e)))?;
+
+    Ok(transcription.text)
+}
@@ -0,0 +1,92 @@
+//! Per-task working directories.
+//!
+//! Every task keeps its files in a folder of its own: sources (downloads, uploads,
+//! subtitles, thumbnails from yt-dlp) in `videos/<id>/`, and everything made from
+//! them (conversions, generated thumbnails, transcript chunks) in `converted/<id>/`.
+//! Tasks can't clash over file names, and cleaning up after a task means deleting
+//! its two folders. Files from the older flat layout (directly in `videos/` or
+//! `converted/`) are still handled, they just don't belong to a folder.
+
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use crate::errors::BotResult;
+
+/// Downloaded and uploaded source files
+pub const SOURCE_ROOT: &str = "videos";
+
+/// Files produced from the sources
+pub const OUTPUT_ROOT: &str = "converted";
+
+/// Source folder of a task, `videos/<id>/`, created if missing
+pub async fn source_dir(id: &str) -> BotResult<PathBuf> {
+    let dir = Path::new(SOURCE_ROOT).join(id);
+    fs::create_dir_all(&dir).await?;
+    Ok(dir)
+}
+
+/// Folder for files made from `input`: the output folder of the task `input` belongs to,
+/// or `converted/` itself for files from the flat layout. Created if missing.
+pub async fn output_dir(input: &Path) -> BotResult<PathBuf> {
+    let dir = match task_id_of(input) {
+        Some(id) => Path::new(OUTPUT_ROOT).join(id),
+        None => PathBuf::from(OUTPUT_ROOT),
+    };
+    fs::create_dir_all(&dir).await?;
+    Ok(dir)
+}
+
+/// ID of the task folder a file is in, None for files from the flat layout
+pub fn task_id_of(path: &Path) -> Option<String> {
+    let dir = path.parent()?;
+    let root = dir.parent()?.file_name()?;
+    if root != SOURCE_ROOT && root != OUTPUT_ROOT {
+        return None;
+    }
+    dir.file_name().map(|id| id.to_string_lossy().into_owned())
+}
+
+/// Delete both folders of a task with everything in them
+pub async fn remove_task(id: &str) {
+    for root in [SOURCE_ROOT, OUTPUT_ROOT] {
+        let dir = Path::new(root).join(id);
+        if let Err(e) = fs::remove_dir_all(&dir).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            log::warn!("Failed to remove task folder {}: {}", dir.display(), e);
+        }
+    }
+}
+
+/// Bytes taken by the task a file belongs to, or by just the file if it's from the flat layout
+pub async fn task_size_of(path: &str) -> u64 {
+    let Some(id) = task_id_of(Path::new(path)) else {
+        return fs::metadata(path).await.map(|m| m.len()).unwrap_or(0);
+    };
+
+    let mut size = 0;
+    for root in [SOURCE_ROOT, OUTPUT_ROOT] {
+        let Ok(mut entries) = fs::read_dir(Path::new(root).join(&id)).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if let Ok(metadata) = entry.metadata().await
+                && metadata.is_file()
+            {
+                size += metadata.len();
+            }
+        }
+    }
+    size
+}
+
+/// Delete the task a file belongs to, or just the file if it's from the flat layout
+pub async fn remove_task_of(path: &str) {
+    match task_id_of(Path::new(path)) {
+        Some(id) => remove_task(&id).await,
+        None => {
+            let _ = fs::remove_file(path).await;
+        }
+    }
+}
@@ -1,43 +1,345 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::process::Stdio;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
 use log::info;
 use serde::Deserialize;
-use tokio::{fs, process};
+use tokio::{
+    fs,
+    io::{AsyncBufReadExt, BufReader},
+    process,
+    sync::{OnceCell, Semaphore, mpsc},
+};
 
 use crate::errors::{BotError, BotResult};
 use crate::utils::MediaFormatType;
+use crate::video::ProgressInfo;
+use crate::video::cache::TtlLruCache;
+use crate::video::{toolchain, workdir};
+
+pub const MAX_VIDEO_DURATION_SECONDS: u32 = 3600; // 1 hour, for users without a subscription tier
 
-pub const MAX_VIDEO_DURATION_SECONDS: u32 = 3600; // 1 hour
+/// Resolutions offered for videos, as long as the source is at least that big
+pub const STANDARD_HEIGHTS: [u32; 6] = [360, 480, 720, 1080, 1440, 2160];
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct VideoQuality {
+    /// Height limit the download is made with
     pub height: u32,
     pub label: String,
+    /// Frame rate of the stream, rounded, if yt-dlp reported one
+    pub fps: Option<u32>,
+    /// Approximate download size in bytes, from yt-dlp's (estimated) stream sizes
+    pub estimated_size: Option<u64>,
 }
 
 impl VideoQuality {
     pub fn new(height: u32) -> Self {
         let label = format!("{}p", height);
-        Self { height, label }
+        Self { height, label, fps: None, estimated_size: None }
+    }
+
+    /// Quality labelled with the stream that will actually be downloaded, e.g. "1080p60 (VP9)".
+    /// `height` is the selection sent back in the callback, the label uses the stream's own height.
+    /// `audio_size` is the size of the audio stream merged into video-only streams.
+    fn for_stream(height: u32, stream: &YtDlpFormat, audio_size: Option<u64>) -> Self {
+        let mut label = format!("{}p", stream.height.unwrap_or(height));
+        let fps = stream.fps.map(|fps| fps.round() as u32);
+        // 30 fps and below is the norm, only call out smoother video
+        if let Some(fps) = fps.filter(|&fps| fps > 30) {
+            label.push_str(&format!("{}", fps));
+        }
+        if let Some(codec) = stream.vcodec.as_deref().and_then(codec_name) {
+            label.push_str(&format!(" ({})", codec));
+        }
+        let estimated_size = if stream.has_audio() {
+            stream.size()
+        } else {
+            stream.size().map(|size| size + audio_size.unwrap_or(0))
+        };
+        Self { height, label, fps, estimated_size }
     }
 }
 
+/// Human-readable name of a yt-dlp video codec string (e.g. "avc1.640028")
+fn codec_name(vcodec: &str) -> Option<&'static str> {
+    let family = vcodec.split('.').next().unwrap_or_default();
+    match family {
+        "avc1" | "avc3" | "h264" => Some("H.264"),
+        "hev1" | "hvc1" | "h265" => Some("H.265"),
+        "vp9" | "vp09" => Some("VP9"),
+        "av01" => Some("AV1"),
+        _ => None,
+    }
+}
+
+/// Audio track of a video (dubbed videos have one per language)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioTrack {
+    /// Language code as reported by yt-dlp (e.g. "en-US", "ja")
+    pub language: String,
+    pub label: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct YtDlpFormat {
     height: Option<u32>,
+    fps: Option<f64>,
     vcodec: Option<String>,
+    acodec: Option<String>,
+    language: Option<String>,
+    format_note: Option<String>,
+    filesize: Option<u64>,
+    /// yt-dlp's estimate from the bitrate and duration when the exact size isn't known
+    filesize_approx: Option<u64>,
+}
+
+impl YtDlpFormat {
+    fn size(&self) -> Option<u64> {
+        self.filesize.or(self.filesize_approx)
+    }
+
+    fn has_audio(&self) -> bool {
+        self.acodec.as_deref().is_some_and(|a| a != "none")
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct YtDlpInfo {
+    /// The site's own ID of the video
+    id: Option<String>,
+    title: Option<String>,
+    /// Song name, set by music sites and YouTube Music
+    track: Option<String>,
+    artist: Option<String>,
+    uploader: Option<String>,
+    duration: Option<f64>,
+    description: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    view_count: Option<u64>,
+    /// YYYYMMDD
+    upload_date: Option<String>,
     formats: Vec<YtDlpFormat>,
+    /// Uploaded subtitles by language code
+    #[serde(default)]
+    subtitles: HashMap<String, serde_json::Value>,
+    /// Auto-generated (and auto-translated) captions by language code
+    #[serde(default)]
+    automatic_captions: HashMap<String, serde_json::Value>,
+    /// Chapters set by the uploader, null when there are none
+    #[serde(default)]
+    chapters: Option<Vec<YtDlpChapter>>,
 }
 
-/// Get available video qualities for a YouTube URL
-pub async fn get_available_qualities(url: &str) -> BotResult<Vec<VideoQuality>> {
+#[derive(Debug, Deserialize)]
+struct YtDlpChapter {
+    start_time: f64,
+    title: Option<String>,
+}
+
+/// Subtitle languages tried in order after the selected audio track's language
+const SUBTITLE_LANGUAGES: [&str; 2] = ["ru", "en"];
+
+impl YtDlpInfo {
+    /// Pick the subtitle language to burn in: uploaded subtitles in a preferred
+    /// language, then any uploaded ones, then auto-generated captions.
+    /// Returns the language code and whether it's an auto-generated track.
+    fn subtitle_language(&self, preferred: Option<&str>) -> Option<(String, bool)> {
+        let languages: Vec<&str> = preferred.into_iter().chain(SUBTITLE_LANGUAGES).collect();
+
+        // Live streams list their chat replay as a subtitle track
+        let uploaded: Vec<&String> = self
+            .subtitles
+            .keys()
+            .filter(|lang| lang.as_str() != "live_chat")
+            .collect();
+
+        if let Some(lang) = languages
+            .iter()
+            .find_map(|pref| uploaded.iter().find(|lang| lang.split('-').next() == Some(pref)))
+        {
+            return Some(((*lang).clone(), false));
+        }
+        if let Some(lang) = uploaded.iter().min() {
+            return Some(((*lang).clone(), false));
+        }
+
+        languages
+            .iter()
+            .find(|lang| self.automatic_captions.contains_key(**lang))
+            .map(|lang| (lang.to_string(), true))
+    }
+}
+
+/// Password of a protected video, passed to yt-dlp as `--video-password`.
+/// Only ever held in memory; `Debug` hides it so it can't end up in logs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct VideoPassword(String);
+
+impl VideoPassword {
+    pub fn new(password: &str) -> Self {
+        Self(password.to_string())
+    }
+}
+
+impl std::fmt::Debug for VideoPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("VideoPassword(***)")
+    }
+}
+
+/// yt-dlp errors asking for a video password, or rejecting the one given
+const PASSWORD_SIGNATURES: &[&str] = &[
+    "--video-password",
+    "Wrong password",
+    "wrong password",
+    "Wrong video password",
+    "This video is protected by a password",
+];
+
+/// Whether yt-dlp failed because the video needs a (different) password
+pub fn is_password_error(error: &BotError) -> bool {
+    let BotError::YoutubeError(stderr) = error else {
+        return false;
+    };
+    PASSWORD_SIGNATURES.iter().any(|s| stderr.contains(s))
+}
+
+/// Add the video's password to a yt-dlp command, as a single argument
+fn add_password(cmd: &mut process::Command, password: Option<&VideoPassword>) {
+    if let Some(password) = password {
+        cmd.args(["--video-password", &password.0]);
+    }
+}
+
+/// A yt-dlp command with the options shared by all invocations
+pub fn yt_dlp_command() -> process::Command {
+    let config = crate::config::get();
     let mut cmd = process::Command::new("yt-dlp");
+    // Stop yt-dlp when its task is cancelled
+    cmd.kill_on_drop(true);
+    cmd.args(["--socket-timeout", &config.ytdlp_socket_timeout.to_string()])
+        .args(["--retries", &config.ytdlp_retries.to_string()]);
+    if let Some(proxy) = &config.ytdlp_proxy {
+        cmd.args(["--proxy", proxy]);
+    }
+    cmd
+}
+
+/// Metadata of recently requested videos, keyed by URL (and password, see `info_key`)
+static INFO_CACHE: LazyLock<TtlLruCache<Arc<YtDlpInfo>>> =
+    LazyLock::new(|| TtlLruCache::new(64, Duration::from_secs(5 * 60)));
+
+/// A metadata fetch that concurrent requests for the same URL can wait on, with its
+/// outcome: a failure reaches everyone who waited for it, not just the first
+type InFlightFetch = Arc<OnceCell<BotResult<Arc<YtDlpInfo>>>>;
+
+/// Metadata fetches in progress, keyed by URL, so a popular link spawns a single yt-dlp process
+static IN_FLIGHT: LazyLock<Mutex<HashMap<String, InFlightFetch>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Slots for metadata fetches, so a burst of links can't start unlimited yt-dlp
+/// processes on the handlers' path. Sized at first use, `METADATA_CONCURRENCY`.
+static METADATA_SLOTS: LazyLock<Semaphore> =
+    LazyLock::new(|| Semaphore::new(crate::config::get().metadata_concurrency));
+
+/// Shown when video metadata couldn't be fetched in time (`BotError::Timeout`)
+pub const METADATA_TIMEOUT_MESSAGE: &str = "⏳ Не удалось получить информацию о видео, попробуйте ещё раз.";
+
+/// Hashes passwords for `info_key`, seeded per process
+static PASSWORD_HASHER: LazyLock<RandomState> = LazyLock::new(RandomState::new);
+
+/// Cache key of a video's metadata. Protected videos fetched with a password are kept
+/// apart, so they don't open up to whoever sends the same link without it. Only a hash
+/// of the password goes into the key, the password itself isn't kept after its flow.
+fn info_key(url: &str, password: Option<&VideoPassword>) -> String {
+    match password {
+        Some(password) => format!("{}\n{:x}", url, PASSWORD_HASHER.hash_one(&password.0)),
+        None => url.to_string(),
+    }
+}
+
+/// Fetch video metadata with all available formats, served from cache when possible
+async fn fetch_info(url: &str, password: Option<&VideoPassword>) -> BotResult<Arc<YtDlpInfo>> {
+    let key = info_key(url, password);
+    if let Some(info) = INFO_CACHE.get(&key) {
+        log::debug!("Metadata cache hit: {}", url);
+        return Ok(info);
+    }
+
+    let cell = IN_FLIGHT
+        .lock()
+        .unwrap()
+        .entry(key.clone())
+        .or_default()
+        .clone();
+
+    let result = match cell
+        .get_or_init(|| async {
+            log::debug!("Metadata cache miss: {}", url);
+            fetch_info_uncached(url, password).await.map(Arc::new)
+        })
+        .await
+    {
+        Ok(info) => Ok(info.clone()),
+        Err(e) => Err(shared_error(e)),
+    };
+
+    // Cache before dropping the in-flight entry, so no new fetch can sneak in between.
+    // Failures aren't cached, the next request after this fetch tries again.
+    if let Ok(info) = &result {
+        INFO_CACHE.insert(&key, info.clone());
+    }
+    let mut in_flight = IN_FLIGHT.lock().unwrap();
+    if in_flight.get(&key).is_some_and(|current| Arc::ptr_eq(current, &cell)) {
+        in_flight.remove(&key);
+    }
+
+    result
+}
+
+/// A copy of a metadata fetch error for each of its waiters. Keeps the variants
+/// callers tell apart (timeouts, password errors), the rest only as text.
+fn shared_error(error: &BotError) -> BotError {
+    match error {
+        BotError::YoutubeError(stderr) => BotError::YoutubeError(stderr.clone()),
+        BotError::Timeout(what) => BotError::Timeout(what.clone()),
+        BotError::ParseError(message) => BotError::ParseError(message.clone()),
+        BotError::ExternalCommandError { command, stderr } => BotError::ExternalCommandError {
+            command: command.clone(),
+            stderr: stderr.clone(),
+        },
+        other => BotError::General(other.to_string()),
+    }
+}
+
+/// Run yt-dlp for the metadata in one of `METADATA_SLOTS`, giving up after
+/// `METADATA_TIMEOUT_SECS`. yt-dlp is killed when the timeout drops it.
+async fn fetch_info_uncached(url: &str, password: Option<&VideoPassword>) -> BotResult<YtDlpInfo> {
+    let timeout = crate::config::get().metadata_timeout;
+    let fetch = async {
+        let _slot = METADATA_SLOTS
+            .acquire()
+            .await
+            .map_err(|e| BotError::general(e.to_string()))?;
+        run_info_command(url, password).await
+    };
+
+    tokio::time::timeout(timeout, fetch).await.unwrap_or_else(|_| {
+        log::warn!("Metadata fetch for {} timed out after {:?}", url, timeout);
+        Err(BotError::Timeout(format!("получение информации о {}", url)))
+    })
+}
+
+async fn run_info_command(url: &str, password: Option<&VideoPassword>) -> BotResult<YtDlpInfo> {
+    let mut cmd = yt_dlp_command();
     cmd.arg("--no-playlist")
-        .args(["--socket-timeout", "5", "--retries", "3"])
-        .args(["-J"]) // JSON output
-        .arg(url);
+        .args(["-J"]); // JSON output
+    add_password(&mut cmd, password);
+    cmd.arg(url);
 
     let output = cmd
         .output()
@@ -50,62 +352,173 @@ pub async fn get_available_qualities(url: &str) -> BotResult<Vec<VideoQuality>>
     }
 
     let json_str = String::from_utf8_lossy(&output.stdout);
-    let info: YtDlpInfo = serde_json::from_str(&json_str)
-        .map_err(|e| BotError::ParseError(format!("Failed to parse yt-dlp output: {}", e)))?;
+    serde_json::from_str(&json_str)
+        .map_err(|e| BotError::ParseError(format!("Failed to parse yt-dlp output: {}", e)))
+}
 
-    // Collect unique heights from video formats
-    let mut heights: Vec<u32> = info
+/// Get available video qualities for a YouTube URL
+pub async fn get_available_qualities(url: &str, password: Option<&VideoPassword>) -> BotResult<Vec<VideoQuality>> {
+    let info = fetch_info(url, password).await?;
+
+    let video_formats: Vec<&YtDlpFormat> = info
         .formats
         .iter()
         .filter(|f| {
-            f.vcodec.as_ref().map_or(false, |v| v != "none")
-                && f.height.map_or(false, |h| h > 0)
+            f.vcodec.as_ref().is_some_and(|v| v != "none")
+                && f.height.is_some_and(|h| h > 0)
         })
-        .filter_map(|f| f.height)
         .collect();
 
-    heights.sort_unstable();
-    heights.dedup();
+    let Some(max_height) = video_formats.iter().filter_map(|f| f.height).max() else {
+        return Err(BotError::youtube_error(
+            "No video formats available".to_string(),
+        ));
+    };
+
+    // build_video_command merges the best AAC audio into video-only streams
+    let audio_only = || {
+        info.formats
+            .iter()
+            .filter(|f| f.has_audio() && f.vcodec.as_deref().is_none_or(|v| v == "none"))
+    };
+    let audio_size = audio_only()
+        .filter(|f| f.acodec.as_deref().is_some_and(|a| a.starts_with("mp4a")))
+        .filter_map(YtDlpFormat::size)
+        .max()
+        .or_else(|| audio_only().filter_map(YtDlpFormat::size).max());
 
     // Standard qualities to offer (filter by what's actually available)
-    let standard_qualities = [360, 480, 720, 1080, 1440, 2160];
-    let available: Vec<VideoQuality> = standard_qualities
-        .iter()
-        .filter(|&&h| heights.iter().any(|&available_h| available_h >= h))
-        .map(|&h| VideoQuality::new(h))
-        .collect();
+    let mut available: Vec<VideoQuality> = Vec::new();
+    for height in STANDARD_HEIGHTS.into_iter().filter(|&h| max_height >= h) {
+        let Some(stream) = selected_stream(&video_formats, height) else {
+            continue;
+        };
+        // Several limits can end up with the same stream (e.g. no H.264 above 1080p),
+        // offer it once
+        let quality = VideoQuality::for_stream(height, stream, audio_size);
+        if !available.iter().any(|q| q.label == quality.label) {
+            available.push(quality);
+        }
+    }
 
     if available.is_empty() {
         // If no standard qualities match, return the best available
-        if let Some(&max_height) = heights.last() {
-            return Ok(vec![VideoQuality::new(max_height)]);
+        return Ok(vec![
+            selected_stream(&video_formats, max_height)
+                .map(|stream| VideoQuality::for_stream(max_height, stream, audio_size))
+                .unwrap_or_else(|| VideoQuality::new(max_height)),
+        ]);
+    }
+
+    Ok(available)
+}
+
+/// The video stream `build_video_command` is expected to pick for a height limit:
+/// the highest (then smoothest) H.264 stream, or any codec if there's no H.264
+fn selected_stream<'a>(formats: &[&'a YtDlpFormat], max_height: u32) -> Option<&'a YtDlpFormat> {
+    let fitting: Vec<&YtDlpFormat> = formats
+        .iter()
+        .copied()
+        .filter(|f| f.height.is_some_and(|h| h <= max_height))
+        .collect();
+    let is_h264 = |f: &&YtDlpFormat| f.vcodec.as_deref().is_some_and(|v| v.starts_with("avc1"));
+    let best = |f: &&YtDlpFormat| (f.height.unwrap_or(0), f.fps.unwrap_or(0.0).round() as u32);
+
+    fitting
+        .iter()
+        .copied()
+        .filter(is_h264)
+        .max_by_key(best)
+        .or_else(|| fitting.iter().copied().max_by_key(best))
+}
+
+/// Get the distinct audio tracks of a video, default track first.
+/// Videos without dubs have a single track (or none with a language set).
+pub async fn get_audio_tracks(url: &str, password: Option<&VideoPassword>) -> BotResult<Vec<AudioTrack>> {
+    let info = fetch_info(url, password).await?;
+
+    let mut tracks: Vec<(bool, AudioTrack)> = Vec::new();
+    for f in &info.formats {
+        let is_audio_only = f.vcodec.as_deref().is_none_or(|v| v == "none")
+            && f.acodec.as_deref().is_some_and(|a| a != "none");
+        let Some(language) = f.language.as_deref().filter(|l| is_valid_language(l)) else {
+            continue;
+        };
+        if !is_audio_only || tracks.iter().any(|(_, t)| t.language == language) {
+            continue;
         }
-        return Err(BotError::youtube_error(
-            "No video formats available".to_string(),
+
+        // format_note looks like "English (United States) original (default), medium"
+        let note = f.format_note.as_deref().unwrap_or_default();
+        let label = note
+            .split(',')
+            .next()
+            .map(|l| l.replace("(default)", "").trim().to_string())
+            .filter(|l| !l.is_empty())
+            .unwrap_or_else(|| language.to_string());
+
+        tracks.push((
+            note.contains("default"),
+            AudioTrack {
+                language: language.to_string(),
+                label,
+            },
         ));
     }
 
-    Ok(available)
+    // Default (usually original) track first
+    tracks.sort_by_key(|(is_default, _)| !is_default);
+
+    Ok(tracks.into_iter().map(|(_, track)| track).collect())
+}
+
+/// Language codes are passed into the yt-dlp format selector, so only allow simple ones
+fn is_valid_language(language: &str) -> bool {
+    !language.is_empty()
+        && language.len() <= 16
+        && language.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
 }
 
 fn get_output_format(unique_id: &str) -> String {
-    format!("videos/%(id)s_{unique_id}.%(ext)s")
+    format!("{}/{unique_id}/%(id)s.%(ext)s", workdir::SOURCE_ROOT)
 }
 
-fn build_video_command(url: &str, max_height: Option<u32>) -> process::Command {
-    let mut cmd = process::Command::new("yt-dlp");
+/// Part of a size budget (1/N) left for the audio stream merged into the video
+const SIZE_BUDGET_AUDIO_SHARE: u64 = 10;
+
+/// How yt-dlp picks the video streams to download
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoSelection<'a> {
+    /// Best stream up to this height, preferring Telegram-friendly codecs
+    /// (any height if `None`)
+    MaxHeight(Option<u32>),
+    /// Best streams that fit `bytes` by yt-dlp's (estimated) sizes, up to `max_height`.
+    /// The smallest streams if nothing fits.
+    MaxSize { bytes: u64, max_height: Option<u32> },
+    /// The user's own `-f` expression (see `settings::validate_format_string`)
+    Custom(&'a str),
+}
+
+fn build_video_command(url: &str, selection: VideoSelection<'_>) -> process::Command {
+    let mut cmd = yt_dlp_command();
     cmd.arg("--no-playlist")
-        .args(["--socket-timeout", "5", "--retries", "3"])
         // Download fragments concurrently to bypass YouTube throttling
         .args(["-N", "4"])
         // Always remux to mp4 to ensure faststart is applied
         .args(["--remux-video", "mp4"])
         // Add faststart for streaming compatibility (allows playback before full download)
-        .args(["--ppa", "FFmpegVideoRemuxer:-movflags +faststart"]);
+        .args(["--ppa", "FFmpegVideoRemuxer:-movflags +faststart"])
+        // Keep YouTube chapters as mp4 chapter markers (runs after the remux,
+        // keep faststart since this rewrites the file)
+        .arg("--embed-chapters")
+        .args(["--ppa", "FFmpegMetadata:-movflags +faststart"]);
 
     // Apply quality filter - prefer H.264 (avc1) and AAC for Telegram compatibility
     // This avoids re-encoding since these codecs are natively supported
-    if let Some(height) = max_height {
+    if let VideoSelection::Custom(format) = selection {
+        // Passed as a single argument, a malformed expression fails with yt-dlp's own error
+        cmd.args(["-f", format]);
+    } else if let VideoSelection::MaxHeight(Some(height)) = selection {
         // Prefer h264 video + aac/m4a audio, fall back to best available
         let format = format!(
             "bestvideo[height<={}][vcodec^=avc1]+bestaudio[acodec^=mp4a]/\
@@ -115,6 +528,22 @@ fn build_video_command(url: &str, max_height: Option<u32>) -> process::Command {
             height, height, height, height
         );
         cmd.args(["-f", &format]);
+    } else if let VideoSelection::MaxSize { bytes, max_height } = selection {
+        // Filters apply per stream, so leave the audio stream a share of the budget.
+        // Formats without a known size don't pass the filters.
+        let video = bytes - bytes / SIZE_BUDGET_AUDIO_SHARE;
+        let height = max_height.map(|h| format!("[height<={}]", h)).unwrap_or_default();
+        let format = format!(
+            "bestvideo*{h}[filesize<{v}]+bestaudio/\
+             bestvideo*{h}[filesize_approx<{v}]+bestaudio/\
+             best{h}[filesize<{b}]/\
+             best{h}[filesize_approx<{b}]/\
+             worstvideo*{h}+worstaudio/worst",
+            h = height,
+            v = video,
+            b = bytes
+        );
+        cmd.args(["-f", &format]);
     } else {
         // No height limit - prefer h264 + aac for compatibility
         cmd.args(["-f",
@@ -128,16 +557,49 @@ fn build_video_command(url: &str, max_height: Option<u32>) -> process::Command {
     cmd
 }
 
-fn build_audio_command(url: &str) -> process::Command {
-    let mut cmd = process::Command::new("yt-dlp");
+/// yt-dlp command fetching the streams `format` needs. Audio formats skip the quality
+/// step and must not pull video streams.
+fn build_download_command(
+    url: &str,
+    selection: VideoSelection<'_>,
+    format: &MediaFormatType,
+    audio_language: Option<&str>,
+) -> process::Command {
+    if format.is_audio_only() {
+        build_audio_command(url, audio_language)
+    } else {
+        build_video_command(url, selection)
+    }
+}
+
+/// yt-dlp command for audio formats: only an audio stream is fetched, never the video.
+/// The trailing `best` is for sites without separate audio streams, where the
+/// audio can't be had without downloading the video anyway.
+fn build_audio_command(url: &str, language: Option<&str>) -> process::Command {
+    let mut cmd = yt_dlp_command();
     cmd.arg("--no-playlist")
-        .args(["--socket-timeout", "5", "--retries", "3"])
         // Download fragments concurrently
-        .args(["-N", "4"])
-        // Download only audio - prefer AAC for Telegram compatibility
-        .args(["-f", "bestaudio[acodec^=mp4a]/bestaudio/best"])
-        // Extract audio and convert to m4a (AAC container)
-        .args(["-x", "--audio-format", "m4a"]);
+        .args(["-N", "4"]);
+
+    // Download only audio - prefer AAC for Telegram compatibility
+    match language.filter(|l| is_valid_language(l)) {
+        Some(language) => {
+            // Pick the requested dub, fall back to the default track if it's gone
+            let format = format!(
+                "bestaudio[language={}][acodec^=mp4a]/\
+                 bestaudio[language={}]/\
+                 bestaudio[acodec^=mp4a]/bestaudio/best",
+                language, language
+            );
+            cmd.args(["-f", &format]);
+        }
+        None => {
+            cmd.args(["-f", "bestaudio[acodec^=mp4a]/bestaudio/best"]);
+        }
+    }
+
+    // Extract audio and convert to m4a (AAC container)
+    cmd.args(["-x", "--audio-format", "m4a"]);
 
     cmd.arg(url);
     cmd
@@ -159,6 +621,17 @@ fn build_audio_command(url: &str) -> process::Command {
 //     }
 // }
 
+/// SponsorBlock categories cut out of downloads with the SponsorBlock option
+const SPONSORBLOCK_CATEGORIES: &str = "sponsor,selfpromo,intro";
+
+/// Which parts of a video to leave out of a download
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DownloadCuts {
+    /// Remove `SPONSORBLOCK_CATEGORIES` segments. Videos nobody submitted segments
+    /// for are downloaded whole; the file's duration changes, so probe it afterwards.
+    pub sponsorblock: bool,
+}
+
 /// Result of video download containing video path and optional thumbnail path
 #[derive(Debug, Clone)]
 pub struct DownloadResult {
@@ -172,25 +645,86 @@ impl std::fmt::Display for DownloadResult {
     }
 }
 
+/// Download a video (or its audio)
+#[allow(clippy::too_many_arguments)]
 pub async fn download_video(
     url: &str,
+    password: Option<&VideoPassword>,
     unique_id: &str,
-    max_height: Option<u32>,
+    selection: VideoSelection<'_>,
     format: &MediaFormatType,
+    audio_language: Option<&str>,
+    cuts: DownloadCuts,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
 ) -> BotResult<DownloadResult> {
-    fs::create_dir_all("videos").await?;
+    // Links were checked when they came in, this covers tasks restored from the database
+    crate::utils::validate_url_safe(url).await?;
 
-    let is_audio_only = matches!(format, MediaFormatType::Audio | MediaFormatType::Voice);
+    let started = Instant::now();
+    let result =
+        run_download(url, password, unique_id, selection, format, audio_language, cuts, progress_sender.clone()).await;
 
-    let mut cmd = if is_audio_only {
-        build_audio_command(url)
-    } else {
-        build_video_command(url, max_height)
+    // An outdated yt-dlp gets one update and one more try, see `toolchain`
+    match result {
+        Err(e) if toolchain::is_outdated_ytdlp_error(&e) && toolchain::self_update_ytdlp(started).await => {
+            info!("Retrying download of {} with the updated yt-dlp", url);
+            run_download(url, password, unique_id, selection, format, audio_language, cuts, progress_sender).await
+        }
+        result => result,
+    }
+}
+
+/// yt-dlp errors about the video streams, where the audio may still download fine
+const VIDEO_UNAVAILABLE_SIGNATURES: &[&str] = &[
+    "Requested format is not available",
+    "No video formats found",
+    "Requested formats are incompatible for merge",
+];
+
+/// Whether a failed download failed over its video streams rather than the link,
+/// so downloading just the audio is worth a try
+pub fn is_video_unavailable_error(error: &BotError) -> bool {
+    let BotError::YoutubeError(stderr) = error else {
+        return false;
     };
+    VIDEO_UNAVAILABLE_SIGNATURES.iter().any(|s| stderr.contains(s))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_download(
+    url: &str,
+    password: Option<&VideoPassword>,
+    unique_id: &str,
+    selection: VideoSelection<'_>,
+    format: &MediaFormatType,
+    audio_language: Option<&str>,
+    cuts: DownloadCuts,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> BotResult<DownloadResult> {
+    workdir::source_dir(unique_id).await?;
+
+    let is_audio_only = format.is_audio_only();
+    let mut cmd = build_download_command(url, selection, format, audio_language);
 
     cmd.args(["--no-simulate"])
         .args(["-o", &get_output_format(unique_id)])
-        .args(["--print", "after_move:filepath"]);
+        .args(["--print", "after_move:filepath"])
+        // --print implies quiet mode, force progress lines so we can report them
+        .args(["--progress", "--newline"])
+        .args([
+            "--progress-template",
+            &format!(
+                "download:{}%(progress.downloaded_bytes)s/%(progress.total_bytes,progress.total_bytes_estimate)s",
+                PROGRESS_PREFIX
+            ),
+        ]);
+
+    // A no-op for videos without SponsorBlock segments
+    if cuts.sponsorblock {
+        cmd.args(["--sponsorblock-remove", SPONSORBLOCK_CATEGORIES]);
+    }
+
+    add_password(&mut cmd, password);
 
     // Download thumbnail only for video formats
     if !is_audio_only {
@@ -199,19 +733,48 @@ pub async fn download_video(
     }
 
     info!(
-        "Starting download: {} (quality: {:?}, format: {:?}, audio_only: {})",
-        url, max_height, format, is_audio_only
+        "Starting download: {} (selection: {:?}, format: {:?}, audio_only: {}, language: {:?}, cuts: {:?})",
+        url, selection, format, is_audio_only, audio_language, cuts
     );
 
-    let output = cmd
-        .output()
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| BotError::external_command_error("yt-dlp", e.to_string()))?;
+
+    // Progress lines may end up on either stream depending on yt-dlp version
+    let stdout = child.stdout.take().map(|s| read_output_lines(s, progress_sender.clone()));
+    let stderr = child.stderr.take().map(|s| read_output_lines(s, progress_sender));
+
+    let status = child
+        .wait()
         .await
         .map_err(|e| BotError::external_command_error("yt-dlp", e.to_string()))?;
 
-    info!("yt-dlp exit code: {:?}", output.status.code());
+    let stdout = match stdout {
+        Some(task) => task.await.unwrap_or_default(),
+        None => Vec::new(),
+    };
+    let stderr = match stderr {
+        Some(task) => task.await.unwrap_or_default(),
+        None => Vec::new(),
+    };
+
+    info!("yt-dlp exit code: {:?}", status.code());
+
+    if status.success() {
+        let file_path = stdout.last().cloned().unwrap_or_default();
+
+        // The path comes from yt-dlp output, make sure it stays inside the download dir
+        if !is_safe_download_path(&file_path, unique_id) {
+            log::error!("yt-dlp returned unexpected file path: {:?}", file_path);
+            return Err(BotError::youtube_error(format!(
+                "Unexpected output file path: {:?}",
+                file_path
+            )));
+        }
 
-    if output.status.success() {
-        let file_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
         info!("Download successful: {}", file_path);
 
         // Find thumbnail file only for video formats
@@ -226,12 +789,74 @@ pub async fn download_video(
             thumbnail_path,
         })
     } else {
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let stderr = stderr.join("\n");
         log::error!("yt-dlp failed: {}", stderr);
         Err(BotError::youtube_error(stderr))
     }
 }
 
+/// Check that a downloaded file path is a plain file directly inside the task's `videos/<id>/`
+fn is_safe_download_path(file_path: &str, unique_id: &str) -> bool {
+    use std::path::{Component, Path};
+
+    // yt-dlp may print either a relative or an absolute path
+    let path = Path::new(file_path);
+    let file_name = path.file_name().and_then(|n| n.to_str());
+    let dir = path.parent();
+
+    dir.and_then(|d| d.file_name()).is_some_and(|d| d == unique_id)
+        && dir.and_then(|d| d.parent()).and_then(|r| r.file_name()).is_some_and(|r| r == workdir::SOURCE_ROOT)
+        && !path.components().any(|c| matches!(c, Component::ParentDir))
+        && file_name.is_some_and(|n| crate::utils::sanitize_filename(n) == n)
+}
+
+/// Marker for progress lines printed via --progress-template
+const PROGRESS_PREFIX: &str = "[progress]";
+
+/// Read yt-dlp output line by line, forwarding progress lines to the sender.
+/// Returns all other non-empty lines.
+fn read_output_lines<R>(
+    reader: R,
+    progress_sender: Option<mpsc::UnboundedSender<ProgressInfo>>,
+) -> tokio::task::JoinHandle<Vec<String>>
+where
+    R: tokio::io::AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        let mut output = Vec::new();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            let line = line.trim();
+            if let Some(progress) = line.strip_prefix(PROGRESS_PREFIX) {
+                if let (Some(sender), Some(percentage)) = (&progress_sender, parse_progress(progress)) {
+                    let _ = sender.send(ProgressInfo {
+                        percentage,
+                        estimated_time_remaining: None,
+                    });
+                }
+            } else if !line.is_empty() {
+                output.push(line.to_string());
+            }
+        }
+
+        output
+    })
+}
+
+/// Parse "downloaded/total" byte counts into a percentage
+fn parse_progress(progress: &str) -> Option<f32> {
+    let (downloaded, total) = progress.split_once('/')?;
+    let downloaded: f64 = downloaded.trim().parse().ok()?;
+    let total: f64 = total.trim().parse().ok()?;
+
+    if total <= 0.0 {
+        return None;
+    }
+
+    Some((downloaded / total * 100.0).min(100.0) as f32)
+}
+
 /// Find thumbnail file for a video (yt-dlp saves it with same name but .jpg extension)
 async fn find_thumbnail(video_path: &str) -> Option<String> {
     use std::path::Path;
@@ -249,41 +874,172 @@ async fn find_thumbnail(video_path: &str) -> Option<String> {
     None
 }
 
-pub async fn get_video_duration(url: &str) -> BotResult<u32> {
-    let mut cmd = process::Command::new("yt-dlp");
+pub async fn get_video_duration(url: &str, password: Option<&VideoPassword>) -> BotResult<u32> {
+    let info = fetch_info(url, password).await?;
+
+    // yt-dlp has no duration for e.g. live streams
+    let duration = info.duration.ok_or_else(|| {
+        BotError::youtube_error("Video duration is not available".to_string())
+    })?;
+
+    Ok(duration as u32)
+}
+
+/// Get the video title
+pub async fn get_video_title(url: &str, password: Option<&VideoPassword>) -> BotResult<String> {
+    let info = fetch_info(url, password).await?;
+
+    info.title
+        .clone()
+        .ok_or_else(|| BotError::youtube_error("Video title is not available".to_string()))
+}
+
+/// Whether the video has subtitles that can be burned in
+pub async fn has_subtitles(url: &str, password: Option<&VideoPassword>) -> bool {
+    match fetch_info(url, password).await {
+        Ok(info) => info.subtitle_language(None).is_some(),
+        Err(_) => false,
+    }
+}
+
+/// A chapter of a video
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    /// Start of the chapter in seconds
+    pub start: u32,
+    pub title: String,
+}
+
+/// Chapters of the video, empty if it has none
+pub async fn get_chapters(url: &str, password: Option<&VideoPassword>) -> BotResult<Vec<Chapter>> {
+    let info = fetch_info(url, password).await?;
+
+    Ok(info
+        .chapters
+        .iter()
+        .flatten()
+        .map(|c| Chapter {
+            start: c.start_time as u32,
+            title: c.title.clone().unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Whether the video has chapters to list
+pub async fn has_chapters(url: &str, password: Option<&VideoPassword>) -> bool {
+    get_chapters(url, password).await.is_ok_and(|chapters| !chapters.is_empty())
+}
+
+/// Download the video's subtitles as SRT, preferring `preferred_language`.
+/// Returns None if the video has no suitable subtitles.
+pub async fn download_subtitles(
+    url: &str,
+    password: Option<&VideoPassword>,
+    unique_id: &str,
+    preferred_language: Option<&str>,
+) -> BotResult<Option<String>> {
+    let info = fetch_info(url, password).await?;
+    let Some((language, automatic)) = info.subtitle_language(preferred_language) else {
+        return Ok(None);
+    };
+
+    let dir = workdir::source_dir(unique_id).await?;
+
+    let mut cmd = yt_dlp_command();
     cmd.arg("--no-playlist")
-        .args(["--socket-timeout", "5", "--retries", "3"])
-        .args(["--print", "duration"])
-        .arg(url);
+        .arg("--skip-download")
+        .arg(if automatic { "--write-auto-subs" } else { "--write-subs" })
+        .args(["--sub-langs", &language])
+        .args(["--convert-subs", "srt"])
+        .args(["-o", &format!("{}/subs.%(ext)s", dir.display())]);
+    add_password(&mut cmd, password);
+    cmd.arg(url);
+
+    info!("Downloading {} subtitles ({}) for {}", language, if automatic { "auto" } else { "uploaded" }, url);
 
     let output = cmd
         .output()
         .await
         .map_err(|e| BotError::external_command_error("yt-dlp", e.to_string()))?;
 
-    if output.status.success() {
-        let duration_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() {
+        let stderr_str = String::from_utf8_lossy(&output.stderr).to_string();
+        return Err(BotError::youtube_error(stderr_str));
+    }
 
-        // yt-dlp can return "NA" for duration if it's not available
-        if duration_str == "NA" || duration_str.is_empty() {
-            return Err(BotError::youtube_error(
-                "Video duration is not available".to_string(),
-            ));
-        }
+    // yt-dlp names the file after the language: subs.<lang>.srt
+    let path = format!("{}/subs.{language}.srt", dir.display());
+    if fs::metadata(&path).await.is_err() {
+        return Err(BotError::youtube_error(format!("Subtitle file {} was not written", path)));
+    }
 
-        let duration = duration_str.parse::<f64>().map_err(|_| {
-            BotError::youtube_error(format!("Invalid duration format: {}", duration_str))
-        })?;
+    Ok(Some(path))
+}
 
-        Ok(duration as u32)
-    } else {
-        let stderr_str = String::from_utf8_lossy(&output.stderr).to_string();
-        Err(BotError::youtube_error(stderr_str))
-    }
+/// Title and performer shown in Telegram's audio player
+#[derive(Debug, Clone, Default)]
+pub struct AudioMetadata {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+}
+
+/// Get audio player metadata, preferring the music fields over the generic video ones
+pub async fn get_audio_metadata(url: &str, password: Option<&VideoPassword>) -> BotResult<AudioMetadata> {
+    let info = fetch_info(url, password).await?;
+
+    Ok(AudioMetadata {
+        title: info.track.clone().or_else(|| info.title.clone()),
+        performer: info.artist.clone().or_else(|| info.uploader.clone()),
+    })
 }
 
-pub fn is_video_too_long(duration_seconds: u32) -> bool {
-    duration_seconds > MAX_VIDEO_DURATION_SECONDS
+/// Description and details of a video, sent on request next to the media
+#[derive(Debug, Clone)]
+pub struct VideoDetails {
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    /// Upload date as YYYYMMDD
+    pub upload_date: Option<String>,
+    pub view_count: Option<u64>,
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+}
+
+/// Get the video's description, tags, view count and upload date
+pub async fn get_video_details(url: &str) -> BotResult<VideoDetails> {
+    let info = fetch_info(url, None).await?;
+
+    Ok(VideoDetails {
+        title: info.title.clone(),
+        uploader: info.uploader.clone(),
+        upload_date: info.upload_date.clone(),
+        view_count: info.view_count,
+        tags: info.tags.clone(),
+        description: info.description.clone().filter(|d| !d.trim().is_empty()),
+    })
+}
+
+/// Expand a naming template (see `settings::FILENAME_PLACEHOLDERS`) with the video's
+/// metadata into a file name without extension. Unknown fields expand to nothing.
+pub async fn expand_filename_template(
+    url: &str,
+    password: Option<&VideoPassword>,
+    template: &str,
+    quality: Option<u32>,
+) -> BotResult<String> {
+    let info = fetch_info(url, password).await?;
+
+    let name = template
+        .replace("{title}", info.title.as_deref().unwrap_or_default())
+        .replace("{uploader}", info.uploader.as_deref().unwrap_or_default())
+        .replace("{quality}", &quality.map(|h| format!("{}p", h)).unwrap_or_default())
+        .replace("{id}", info.id.as_deref().unwrap_or_default());
+
+    Ok(crate::utils::sanitize_filename(&name))
+}
+
+pub fn is_video_too_long(duration_seconds: u32, max_duration_seconds: u32) -> bool {
+    duration_seconds > max_duration_seconds
 }
 
 pub fn format_duration(seconds: u32) -> String {
@@ -297,3 +1053,162 @@ pub fn format_duration(seconds: u32) -> String {
         format!("{}:{:02}", minutes, secs)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+
+    fn command_args(cmd: &process::Command) -> Vec<String> {
+        cmd.as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// The `-f` expression of a yt-dlp command
+    fn format_selector(args: &[String]) -> &str {
+        let index = args.iter().position(|arg| arg == "-f").expect("no -f argument");
+        &args[index + 1]
+    }
+
+    fn stream(height: Option<u32>, fps: Option<f64>, vcodec: &str, acodec: &str, filesize: Option<u64>) -> YtDlpFormat {
+        YtDlpFormat {
+            height,
+            fps,
+            vcodec: Some(vcodec.to_string()),
+            acodec: Some(acodec.to_string()),
+            language: None,
+            format_note: None,
+            filesize,
+            filesize_approx: None,
+        }
+    }
+
+    #[test]
+    fn quality_labels_describe_the_stream() {
+        let quality = VideoQuality::for_stream(1080, &stream(Some(1080), Some(59.94), "vp09.00.40.08", "none", None), None);
+        assert_eq!(quality.label, "1080p60 (VP9)");
+        assert_eq!(quality.fps, Some(60));
+        assert_eq!(quality.height, 1080);
+
+        // 30 fps is the norm, not worth mentioning
+        let quality = VideoQuality::for_stream(720, &stream(Some(720), Some(30.0), "avc1.64001F", "none", None), None);
+        assert_eq!(quality.label, "720p (H.264)");
+        assert_eq!(quality.fps, Some(30));
+
+        // A portrait stream keeps its own height in the label, the selection in `height`
+        let quality = VideoQuality::for_stream(1080, &stream(Some(1920), None, "unknown", "none", None), None);
+        assert_eq!(quality.label, "1920p");
+        assert_eq!(quality.height, 1080);
+        assert_eq!(quality.fps, None);
+
+        let quality = VideoQuality::for_stream(480, &stream(None, None, "none", "none", None), None);
+        assert_eq!(quality.label, "480p");
+        assert_eq!(VideoQuality::new(480).label, "480p");
+    }
+
+    #[test]
+    fn quality_sizes_include_the_merged_audio() {
+        let video_only = stream(Some(720), None, "avc1", "none", Some(1000));
+        assert_eq!(VideoQuality::for_stream(720, &video_only, Some(200)).estimated_size, Some(1200));
+        assert_eq!(VideoQuality::for_stream(720, &video_only, None).estimated_size, Some(1000));
+
+        let muxed = stream(Some(720), None, "avc1", "mp4a.40.2", Some(1000));
+        assert_eq!(VideoQuality::for_stream(720, &muxed, Some(200)).estimated_size, Some(1000));
+
+        let unknown = stream(Some(720), None, "avc1", "none", None);
+        assert_eq!(VideoQuality::for_stream(720, &unknown, Some(200)).estimated_size, None);
+    }
+
+    #[test]
+    fn quality_buttons_round_trip() {
+        use crate::callback::Callback;
+
+        let mut qualities: Vec<VideoQuality> = STANDARD_HEIGHTS.into_iter().map(VideoQuality::new).collect();
+        qualities.push(VideoQuality::for_stream(1080, &stream(Some(1080), Some(60.0), "av01", "none", None), None));
+
+        for quality in qualities {
+            let data = Callback::SelectQuality { short_id: "a1B2-c3D".to_string(), height: quality.height }.to_string();
+            match Callback::parse(&data) {
+                Ok(Callback::SelectQuality { short_id, height }) => {
+                    assert_eq!(short_id, "a1B2-c3D");
+                    assert_eq!(height, quality.height);
+                }
+                other => panic!("{} parsed as {:?}", data, other),
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_quality_buttons_are_rejected() {
+        use crate::callback::Callback;
+
+        for data in [
+            "q:",
+            "q:abc",
+            "q::720",
+            "q:abc:",
+            "q:abc:0",
+            "q:abc:+720",
+            "q:abc:-720",
+            "q:abc:720p",
+            "q:abc: 720",
+            "q:abc:720:1",
+            "q:a b:720",
+            "q:abc:4294967296",
+        ] {
+            assert!(
+                !matches!(Callback::parse(data), Ok(Callback::SelectQuality { .. })),
+                "{:?} was accepted",
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn audio_downloads_fetch_only_audio() {
+        let selections = [
+            VideoSelection::MaxHeight(None),
+            VideoSelection::MaxHeight(Some(1080)),
+            VideoSelection::MaxSize { bytes: 50_000_000, max_height: Some(720) },
+            VideoSelection::Custom("bestvideo+bestaudio"),
+        ];
+        for format in [MediaFormatType::Audio, MediaFormatType::Voice, MediaFormatType::Transcript] {
+            for selection in selections {
+                for language in [None, Some("de"), Some("bad language")] {
+                    let args = command_args(&build_download_command(URL, selection, &format, language));
+                    let selector = format_selector(&args);
+                    assert!(selector.contains("bestaudio"), "{:?}: {}", format, selector);
+                    assert!(!selector.contains("video"), "{:?}: {}", format, selector);
+                    assert!(!selector.contains("height"), "{:?}: {}", format, selector);
+                    // Only sites without separate audio streams fall through to `best`
+                    assert!(selector.ends_with("/bestaudio/best"), "{:?}: {}", format, selector);
+                    assert!(args.iter().any(|arg| arg == "-x"));
+                    assert_eq!(args.last().map(String::as_str), Some(URL));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn audio_downloads_pick_the_requested_dub() {
+        let args = command_args(&build_audio_command(URL, Some("de")));
+        assert!(format_selector(&args).starts_with("bestaudio[language=de]"));
+
+        // Not passed into the expression
+        let args = command_args(&build_audio_command(URL, Some("de]/bestvideo")));
+        assert_eq!(format_selector(&args), "bestaudio[acodec^=mp4a]/bestaudio/best");
+    }
+
+    #[test]
+    fn video_downloads_fetch_video() {
+        for format in [MediaFormatType::Video, MediaFormatType::VideoNote, MediaFormatType::VideoSticker] {
+            let args = command_args(&build_download_command(URL, VideoSelection::MaxHeight(Some(720)), &format, None));
+            let selector = format_selector(&args);
+            assert!(selector.starts_with("bestvideo[height<=720]"), "{:?}: {}", format, selector);
+            assert!(!args.iter().any(|arg| arg == "-x"));
+        }
+    }
+}